@@ -0,0 +1,274 @@
+//! Local alerting engine.
+//!
+//! Alerts are evaluated on a dedicated sampling task, so they keep working while the
+//! Komari server is unreachable. When a rule has been breached for its whole duration
+//! the configured webhook is called and / or the local command is run, and again once
+//! the rule recovers.
+
+use crate::get_info::collector::MetricsCollector;
+use crate::get_info::cpu::realtime_cpu;
+use crate::get_info::mem::{realtime_disk, realtime_mem, realtime_swap};
+use log::{error, info, warn};
+use miniserde::{Serialize, json};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+/// Number of rules currently firing, read by other subsystems (e.g. quiet hours)
+static FIRING: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// CPU usage (%)
+    Cpu,
+    /// RAM usage (%)
+    Ram,
+    /// Swap usage (%)
+    Swap,
+    /// Disk usage (%)
+    Disk,
+    /// Upload speed (bytes/s)
+    Up,
+    /// Download speed (bytes/s)
+    Down,
+    /// Traffic of the current cycle, up + down (bytes)
+    Traffic,
+}
+
+impl Metric {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cpu" => Some(Self::Cpu),
+            "ram" | "mem" => Some(Self::Ram),
+            "swap" => Some(Self::Swap),
+            "disk" => Some(Self::Disk),
+            "up" => Some(Self::Up),
+            "down" => Some(Self::Down),
+            "traffic" => Some(Self::Traffic),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlertRule {
+    pub name: String,
+    pub metric: Metric,
+    pub threshold: f64,
+    pub duration: Duration,
+}
+
+/// Where a breached rule is reported to
+#[derive(Debug, Clone)]
+pub struct AlertActions {
+    pub webhook: Option<String>,
+    pub command: Option<String>,
+    pub ignore_unsafe_cert: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct AlertEvent {
+    alert: String,
+    state: String,
+    value: f64,
+    threshold: f64,
+    time: String,
+}
+
+/// Parse `--alert` values in `METRIC>THRESHOLD[:DURATION]` format, duration in seconds
+pub fn parse_alert_rules(specs: &[String]) -> Result<Vec<AlertRule>, String> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (metric, rest) = spec.split_once('>').ok_or_else(|| {
+                format!("Invalid alert '{spec}', expected METRIC>THRESHOLD[:DURATION]")
+            })?;
+            let metric = Metric::parse(metric).ok_or_else(|| {
+                format!(
+                    "Invalid alert metric in '{spec}', expected one of cpu, ram, swap, disk, up, down, traffic"
+                )
+            })?;
+            let (threshold, duration) = rest.split_once(':').unwrap_or((rest, "0"));
+            let threshold = threshold
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid alert threshold in '{spec}'"))?;
+            let duration = duration
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid alert duration in '{spec}'"))?;
+
+            Ok(AlertRule {
+                name: spec.trim().to_string(),
+                metric,
+                threshold,
+                duration: Duration::from_secs(duration),
+            })
+        })
+        .collect()
+}
+
+/// Whether any alert rule is currently firing
+pub fn is_firing() -> bool {
+    FIRING.load(Ordering::Relaxed) > 0
+}
+
+fn percent(used: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        used as f64 / total as f64 * 100.0
+    }
+}
+
+fn value(collector: &MetricsCollector, metric: Metric) -> f64 {
+    match metric {
+        Metric::Cpu => realtime_cpu(&collector.sysinfo_sys).usage,
+        Metric::Ram => {
+            let ram = realtime_mem(&collector.sysinfo_sys);
+            percent(ram.used, ram.total)
+        }
+        Metric::Swap => {
+            let swap = realtime_swap(&collector.sysinfo_sys);
+            percent(swap.used, swap.total)
+        }
+        Metric::Disk => {
+            let disk = realtime_disk(&collector.disks);
+            percent(disk.used, disk.total)
+        }
+        Metric::Up => collector.network().up as f64,
+        Metric::Down => collector.network().down as f64,
+        Metric::Traffic => {
+            let network = collector.network();
+            (network.total_up + network.total_down) as f64
+        }
+    }
+}
+
+/// Sample the metrics every `interval_ms` and evaluate the rules, runs forever
+pub async fn alert_loop(
+    rules: Vec<AlertRule>,
+    interval_ms: u64,
+    mut collector: MetricsCollector,
+    actions: AlertActions,
+) {
+    // (breached since, firing)
+    let mut states: Vec<(Option<Instant>, bool)> = vec![(None, false); rules.len()];
+
+    loop {
+        tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+        collector.refresh();
+
+        for (rule, (breached_since, firing)) in rules.iter().zip(states.iter_mut()) {
+            let value = value(&collector, rule.metric);
+
+            if value > rule.threshold {
+                let since = *breached_since.get_or_insert_with(Instant::now);
+                if !*firing && since.elapsed() >= rule.duration {
+                    *firing = true;
+                    FIRING.fetch_add(1, Ordering::Relaxed);
+                    warn!("Alert '{}' firing: {value:.2}", rule.name);
+                    fire(rule, "firing", value, &actions);
+                }
+            } else {
+                *breached_since = None;
+                if *firing {
+                    *firing = false;
+                    FIRING.fetch_sub(1, Ordering::Relaxed);
+                    info!("Alert '{}' resolved: {value:.2}", rule.name);
+                    fire(rule, "resolved", value, &actions);
+                }
+            }
+        }
+    }
+}
+
+fn fire(rule: &AlertRule, state: &str, value: f64, actions: &AlertActions) {
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let event = AlertEvent {
+        alert: rule.name.clone(),
+        state: state.to_string(),
+        value,
+        threshold: rule.threshold,
+        time: now.format(&Rfc3339).unwrap_or_default(),
+    };
+
+    if let Some(command) = &actions.command {
+        run_command(command, &event);
+    }
+
+    if let Some(webhook) = &actions.webhook {
+        let webhook = webhook.clone();
+        let ignore_unsafe_cert = actions.ignore_unsafe_cert;
+        let json_string = json::to_string(&event);
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = post_webhook(&webhook, &json_string, ignore_unsafe_cert) {
+                error!("Failed to call alert webhook: {e}");
+            }
+        });
+    }
+}
+
+fn run_command(command: &str, event: &AlertEvent) {
+    #[cfg(target_os = "windows")]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    };
+    #[cfg(not(target_os = "windows"))]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+
+    cmd.env("KOMARI_ALERT", &event.alert)
+        .env("KOMARI_ALERT_STATE", &event.state)
+        .env("KOMARI_ALERT_VALUE", format!("{:.2}", event.value))
+        .env("KOMARI_ALERT_THRESHOLD", event.threshold.to_string());
+
+    match cmd.spawn() {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                if let Ok(status) = child.wait().await
+                    && !status.success()
+                {
+                    warn!("Alert command exited with {status}");
+                }
+            });
+        }
+        Err(e) => error!("Failed to run alert command: {e}"),
+    }
+}
+
+fn post_webhook(url: &str, json_string: &str, ignore_unsafe_cert: bool) -> Result<(), String> {
+    #[cfg(feature = "ureq-support")]
+    {
+        use crate::utils::create_ureq_agent;
+        let agent = create_ureq_agent(ignore_unsafe_cert);
+        let resp = agent
+            .post(url)
+            .header("Content-Type", "application/json")
+            .send(json_string)
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("HTTP status code: {}", resp.status()));
+        }
+    }
+    #[cfg(feature = "nyquest-support")]
+    {
+        use nyquest::Body;
+        use nyquest::Request;
+        let client = crate::utils::create_nyquest_client(ignore_unsafe_cert);
+        let body = Body::text(json_string.to_string(), "application/json");
+        let resp = client
+            .request(Request::post(url.to_string()).with_body(body))
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_successful() {
+            return Err(format!("HTTP status code: {}", resp.status()));
+        }
+    }
+    Ok(())
+}
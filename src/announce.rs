@@ -0,0 +1,127 @@
+//! Version announcement after an upgrade.
+//!
+//! The version that ran last is kept in `--version-file`. When it differs from the
+//! running one (or there is none yet), a single event is queued for the next Real-Time
+//! Info, a JSON object after the `[agent-version]` tag:
+//! `{"from":"0.3.3","to":"0.3.4","build":["ureq-support"],"features":["terminal",...]}`.
+
+use crate::command_parser::{Args, TerminalApproval};
+use crate::events::push_event;
+use log::{info, warn};
+use miniserde::{Serialize, json};
+
+#[derive(Serialize)]
+struct VersionAnnouncement {
+    /// `None` on the first run
+    from: Option<String>,
+    to: String,
+    /// Cargo features the binary was built with
+    build: Vec<String>,
+    /// Optional features enabled on the command line
+    features: Vec<String>,
+}
+
+fn build_features() -> Vec<String> {
+    let mut build = Vec::new();
+    if cfg!(feature = "ureq-support") {
+        build.push("ureq-support".to_string());
+    }
+    if cfg!(feature = "nyquest-support") {
+        build.push("nyquest-support".to_string());
+    }
+    if cfg!(feature = "winxp-support") {
+        build.push("winxp-support".to_string());
+    }
+    if cfg!(feature = "metrics-only") {
+        build.push("metrics-only".to_string());
+    } else {
+        if cfg!(feature = "no-terminal") {
+            build.push("no-terminal".to_string());
+        }
+        if cfg!(feature = "no-exec") {
+            build.push("no-exec".to_string());
+        }
+    }
+    build
+}
+
+fn enabled_features(args: &Args) -> Vec<String> {
+    let features = [
+        ("terminal", args.terminal),
+        ("terminal-restricted", args.restricted_terminal()),
+        (
+            "terminal-approval",
+            !matches!(args.terminal_approval, TerminalApproval::None),
+        ),
+        ("terminal-record", args.terminal_record_dir.is_some()),
+        ("speedtest", args.speedtest),
+        ("fetch", !args.fetch_dir.is_empty()),
+        ("ws-compression", args.ws_compression),
+        ("tenants", !args.tenant.is_empty()),
+        ("delta-mode", args.delta_mode),
+        ("network-statistics", !args.disable_network_statistics),
+        ("traffic-account", !args.traffic_account.is_empty()),
+        ("ebpf-process-network", args.ebpf_process_network),
+        ("ebpf-connections", args.ebpf_connections),
+        ("vpn-stats", args.vpn_stats || args.openvpn_status.is_some()),
+        ("ip-family-stats", args.ip_family_stats),
+        ("temperatures", args.temperatures),
+        ("power-stats", args.power_stats || !args.nut_ups.is_empty()),
+        ("thermal-throttle", args.thermal_throttle),
+        ("sbc-stats", args.sbc_stats),
+        ("wifi-stats", args.wifi_stats),
+        (
+            "watch",
+            !args.watch_process.is_empty() || !args.watch_service.is_empty(),
+        ),
+        ("cgroups", !args.cgroup.is_empty()),
+        ("geoip", args.geoip || !args.geoip_mmdb.is_empty()),
+        ("ping-targets", !args.ping_target.is_empty()),
+        ("http-checks", !args.http_check.is_empty()),
+        ("clock-check", args.clock_check.is_some()),
+        ("shed-pressure", args.shed_pressure > 0.0),
+        ("health-check", args.health_check_interval > 0),
+        ("alerts", !args.alert.is_empty()),
+        ("syslog", args.syslog_listen.is_some()),
+        ("tail", !args.tail_file.is_empty()),
+        ("record", args.record_path.is_some()),
+        ("privacy-mode", args.privacy_mode),
+    ];
+    features
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Queue the announcement when the version changed since the last run and remember the
+/// running one
+pub fn announce_version(args: &Args, version_file: &str) {
+    let running = env!("CARGO_PKG_VERSION");
+    let previous = std::fs::read_to_string(version_file)
+        .ok()
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty());
+    if previous.as_deref() == Some(running) {
+        return;
+    }
+
+    match &previous {
+        Some(previous) => info!("Upgraded from {previous} to {running}, announcing it"),
+        None => info!("First run of {running}, announcing it"),
+    }
+    let announcement = VersionAnnouncement {
+        from: previous,
+        to: running.to_string(),
+        build: build_features(),
+        features: enabled_features(args),
+    };
+    push_event(format!(
+        "[agent-version] {}",
+        json::to_string(&announcement)
+    ));
+
+    if let Err(e) = std::fs::write(version_file, running) {
+        warn!("Failed to save the agent version to {version_file}: {e}");
+    }
+}
@@ -0,0 +1,163 @@
+//! How the agent authenticates to the panel (`--auth-mode`).
+//!
+//! `query` appends `?token=` to every URL, the way Komari expects it. Reverse proxies that
+//! strip query strings get the token in an `Authorization: Bearer` header instead
+//! (`bearer`), or a short-lived HS256 JWT signed with `--auth-jwt-key` whose `sub` is the
+//! token (`jwt`). The header goes with the WebSocket handshakes and every HTTP request
+//! to the panel, together with the `--header` ones, e.g. for an access proxy in front of it.
+
+use crate::command_parser::AuthMode;
+use miniserde::{Serialize, json};
+use ring::hmac;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+
+struct Auth {
+    mode: AuthMode,
+    token: String,
+    /// HMAC-SHA256 key, `jwt` mode only
+    key: Option<hmac::Key>,
+    ttl: Duration,
+}
+
+static AUTH: OnceLock<Auth> = OnceLock::new();
+/// `--header` lines split into name and value
+static HEADERS: OnceLock<Vec<(String, String)>> = OnceLock::new();
+/// The JWT handed out last and its expiry (Unix s)
+static JWT: Mutex<Option<(String, u64)>> = Mutex::new(None);
+
+#[derive(Serialize)]
+struct Claims {
+    sub: String,
+    iat: u64,
+    exp: u64,
+}
+
+/// Check the settings for `mode` and keep them, the JWT key is read from `jwt_key_file`
+pub fn set_auth(
+    mode: AuthMode,
+    token: &str,
+    jwt_key_file: Option<&str>,
+    jwt_ttl: u64,
+) -> Result<(), String> {
+    let key = match (&mode, jwt_key_file) {
+        (AuthMode::Jwt, Some(path)) => {
+            let secret =
+                std::fs::read(path).map_err(|e| format!("Failed to read JWT key {path}: {e}"))?;
+            let secret = secret.trim_ascii();
+            if secret.is_empty() {
+                return Err(format!("JWT key {path} is empty"));
+            }
+            Some(hmac::Key::new(hmac::HMAC_SHA256, secret))
+        }
+        (AuthMode::Jwt, None) => {
+            return Err("`--auth-mode jwt` requires `--auth-jwt-key`".to_string());
+        }
+        _ => None,
+    };
+    let _ = AUTH.set(Auth {
+        mode,
+        token: token.to_string(),
+        key,
+        ttl: Duration::from_secs(jwt_ttl.max(30)),
+    });
+    Ok(())
+}
+
+/// Whether the token goes in the query string of the panel URLs
+pub fn token_in_query() -> bool {
+    AUTH.get()
+        .is_none_or(|auth| matches!(auth.mode, AuthMode::Query))
+}
+
+/// Check the `Name: value` lines of `--header` and keep them, `Authorization` is only free
+/// in `query` mode
+pub fn set_headers(lines: &[String], mode: &AuthMode) -> Result<(), String> {
+    let mut headers = Vec::with_capacity(lines.len());
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("Format error: expected 'Name: value', got '{line}'"))?;
+        let (name, value) = (name.trim(), value.trim());
+        HeaderName::try_from(name).map_err(|e| format!("Invalid header name '{name}': {e}"))?;
+        HeaderValue::from_str(value).map_err(|e| format!("Invalid value of header {name}: {e}"))?;
+        if name.eq_ignore_ascii_case("authorization") && !matches!(mode, AuthMode::Query) {
+            return Err(format!(
+                "Header {name} is already sent by --auth-mode {mode:?}"
+            ));
+        }
+        headers.push((name.to_string(), value.to_string()));
+    }
+    let _ = HEADERS.set(headers);
+    Ok(())
+}
+
+/// The `--header` ones alone, for the panels of `--tenant` whose token is in the query
+pub fn tenant_headers() -> Vec<(String, String)> {
+    HEADERS.get().cloned().unwrap_or_default()
+}
+
+/// Headers of every WebSocket handshake and HTTP request to the panel
+pub fn panel_headers() -> Vec<(String, String)> {
+    let mut headers = HEADERS.get().cloned().unwrap_or_default();
+    if let Some(authorization) = auth_header() {
+        headers.push(("Authorization".to_string(), authorization));
+    }
+    headers
+}
+
+/// Value of the `Authorization` header for the panel, `None` in `query` mode
+fn auth_header() -> Option<String> {
+    let auth = AUTH.get()?;
+    match auth.mode {
+        AuthMode::Query => None,
+        AuthMode::Bearer => Some(format!("Bearer {}", auth.token)),
+        AuthMode::Jwt => Some(format!("Bearer {}", jwt(auth)?)),
+    }
+}
+
+/// A cached JWT while it has more than half of its lifetime left, a new one otherwise
+fn jwt(auth: &Auth) -> Option<String> {
+    let key = auth.key.as_ref()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let mut cached = JWT.lock().ok()?;
+    if let Some((jwt, exp)) = cached.as_ref()
+        && exp.saturating_sub(now) > auth.ttl.as_secs() / 2
+    {
+        return Some(jwt.clone());
+    }
+
+    let exp = now + auth.ttl.as_secs();
+    let claims = Claims {
+        sub: auth.token.clone(),
+        iat: now,
+        exp,
+    };
+    let signing_input = format!(
+        "{}.{}",
+        base64url(br#"{"alg":"HS256","typ":"JWT"}"#),
+        base64url(json::to_string(&claims).as_bytes())
+    );
+    let signature = hmac::sign(key, signing_input.as_bytes());
+    let jwt = format!("{signing_input}.{}", base64url(signature.as_ref()));
+    *cached = Some((jwt.clone(), exp));
+    Some(jwt)
+}
+
+/// Base64url without padding (RFC 7515)
+fn base64url(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    let mut output = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (u32::from(*byte) << (16 - 8 * i)));
+        for i in 0..=chunk.len() {
+            output.push(char::from(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3F]));
+        }
+    }
+    output
+}
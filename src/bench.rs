@@ -0,0 +1,239 @@
+//! The `bench` subcommand (`bench-collectors` before).
+//!
+//! Every collector is called `iterations` times in a row and timed. The first call is
+//! listed on its own, it fills the caches the later ones read from (temperatures and VPN
+//! are refreshed every 10 s only). The sysinfo refresh every tick starts with is also timed
+//! part by part, the collectors reading its snapshot cost little themselves. Optional
+//! collectors are started the way their flag would, the ones that cannot run on this host
+//! are listed as unavailable. The heap allocations per call are counted by the global
+//! allocator below, which otherwise only hands over to the system allocator. The report
+//! ends with the shortest `--realtime-info-interval` keeping a tick of the metrics loop
+//! under 1 % of the time.
+
+use crate::data_struct::RealTimeInfo;
+use crate::get_info::balloon::balloon_bytes;
+use crate::get_info::collector::MetricsCollector;
+use crate::get_info::cpu::realtime_cpu;
+use crate::get_info::filters::{set_disk_filter, set_net_filter};
+use crate::get_info::load::realtime_load;
+use crate::get_info::mem::{realtime_disk, realtime_mem, realtime_swap};
+use crate::get_info::network::TrafficOffset;
+use crate::get_info::network::ebpf::{
+    ebpf_tcp_connections, init_ebpf_connections, init_process_network, realtime_process_network,
+};
+use crate::get_info::network::family::{init_ip_family, realtime_ip_family};
+use crate::get_info::network::link::link_info;
+use crate::get_info::network::realtime_connections;
+use crate::get_info::network::vpn::{init_vpn, realtime_vpn};
+use crate::get_info::sbc::{init_sbc, realtime_sbc};
+use crate::get_info::temperature::{init_temperatures, realtime_temperatures};
+use crate::get_info::thermal::{init_thermal_throttle, realtime_thermal_throttle};
+use crate::get_info::wifi::{init_wifi, realtime_wifi};
+use crate::get_info::{realtime_process, realtime_uptime};
+use miniserde::json;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use sysinfo::{CpuRefreshKind, DiskRefreshKind};
+
+/// Share of the time a tick of the metrics loop may take at the suggested interval
+const TICK_BUDGET: f64 = 0.01;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+struct Timing {
+    name: &'static str,
+    /// `None` when the collector gave nothing on its first call
+    times: Option<(Duration, Duration, Duration, Duration)>,
+    /// Average allocations and reallocations per call after the first
+    allocations: u64,
+}
+
+/// `(first, min, avg, max)` of `iterations` calls
+fn time<T>(name: &'static str, iterations: u32, mut collect: impl FnMut() -> Option<T>) -> Timing {
+    let start = Instant::now();
+    if collect().is_none() {
+        return Timing {
+            name,
+            times: None,
+            allocations: 0,
+        };
+    }
+    let first = start.elapsed();
+
+    let (mut min, mut max, mut total) = (Duration::MAX, Duration::ZERO, Duration::ZERO);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        collect();
+        let elapsed = start.elapsed();
+        min = min.min(elapsed);
+        max = max.max(elapsed);
+        total += elapsed;
+    }
+    Timing {
+        name,
+        times: Some((first, min, total / iterations, max)),
+        allocations: (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / u64::from(iterations),
+    }
+}
+
+fn micros(duration: Duration) -> String {
+    format!("{:.1}", duration.as_secs_f64() * 1_000_000.0)
+}
+
+pub fn bench(iterations: u32) -> std::io::Result<()> {
+    let iterations = iterations.max(1);
+
+    // Parsed once at startup by the agent, not on every call
+    set_disk_filter(None, None);
+    set_net_filter(None, None);
+
+    // An optional collector that fails to start gives `None` and shows as unavailable
+    let _ = init_process_network(10);
+    init_vpn(None);
+    init_ip_family();
+    init_temperatures();
+    init_thermal_throttle();
+    init_sbc();
+    init_wifi();
+
+    let mut collector = MetricsCollector::new(Arc::new(TrafficOffset::default()));
+    collector.refresh();
+
+    let mut timings = vec![
+        time("refresh_cpu", iterations, || {
+            collector
+                .sysinfo_sys
+                .refresh_cpu_specifics(CpuRefreshKind::everything().without_frequency());
+            Some(())
+        }),
+        time("refresh_mem", iterations, || {
+            collector.sysinfo_sys.refresh_memory();
+            Some(())
+        }),
+        time("refresh_net", iterations, || {
+            collector.networks.refresh(true);
+            Some(())
+        }),
+        time("refresh_disk", iterations, || {
+            collector
+                .disks
+                .refresh_specifics(true, DiskRefreshKind::nothing().with_storage());
+            Some(())
+        }),
+        time("refresh", iterations, || {
+            collector.refresh();
+            Some(())
+        }),
+    ];
+    let collector = &collector;
+    timings.extend([
+        time("cpu", iterations, || {
+            Some(realtime_cpu(&collector.sysinfo_sys))
+        }),
+        time("ram", iterations, || {
+            Some(realtime_mem(&collector.sysinfo_sys))
+        }),
+        time("swap", iterations, || {
+            Some(realtime_swap(&collector.sysinfo_sys))
+        }),
+        time("disk", iterations, || Some(realtime_disk(&collector.disks))),
+        time("load", iterations, || Some(realtime_load())),
+        time("network", iterations, || Some(collector.network())),
+        time("connections", iterations, || Some(realtime_connections())),
+        time("process", iterations, || Some(realtime_process())),
+        time("uptime", iterations, || Some(realtime_uptime())),
+        time("interfaces", iterations, || Some(link_info())),
+        time("balloon", iterations, || Some(balloon_bytes())),
+        time("top", iterations, realtime_process_network),
+        time("vpn", iterations, realtime_vpn),
+        time("ip_family", iterations, realtime_ip_family),
+        time("temperatures", iterations, realtime_temperatures),
+        time("throttle", iterations, realtime_thermal_throttle),
+        time("sbc", iterations, realtime_sbc),
+        time("wifi", iterations, realtime_wifi),
+    ]);
+    // Started last, `connections` above times the netlink dump it replaces
+    let _ = init_ebpf_connections();
+    timings.push(time("tcp_ebpf", iterations, ebpf_tcp_connections));
+
+    // One tick of the metrics loop: every collector above, then the frame sent
+    timings.push(time("real_time", iterations, || {
+        Some(RealTimeInfo::build(collector, 1.0))
+    }));
+    let real_time = RealTimeInfo::build(collector, 1.0);
+    timings.push(time("json", iterations, || {
+        Some(json::to_string(&real_time))
+    }));
+
+    let mut stdout = std::io::stdout().lock();
+    writeln!(
+        stdout,
+        "Collector timings over {iterations} iterations (µs)"
+    )?;
+    writeln!(
+        stdout,
+        "{:<14}{:>12}{:>12}{:>12}{:>12}{:>12}",
+        "Collector", "First", "Min", "Avg", "Max", "Allocs"
+    )?;
+    for timing in &timings {
+        match timing.times {
+            Some((first, min, avg, max)) => writeln!(
+                stdout,
+                "{:<14}{:>12}{:>12}{:>12}{:>12}{:>12}",
+                timing.name,
+                micros(first),
+                micros(min),
+                micros(avg),
+                micros(max),
+                timing.allocations
+            )?,
+            None => writeln!(stdout, "{:<14}{:>12}", timing.name, "unavailable")?,
+        }
+    }
+
+    // A tick refreshes, then builds the frame and encodes it
+    let tick = ["refresh", "real_time", "json"]
+        .iter()
+        .filter_map(|name| timings.iter().find(|timing| timing.name == *name))
+        .filter_map(|timing| timing.times.map(|(_, _, avg, _)| avg))
+        .sum::<Duration>();
+    let suggested = (tick.as_secs_f64() / TICK_BUDGET * 1000.0 / 100.0).ceil() as u64 * 100;
+    writeln!(stdout)?;
+    writeln!(
+        stdout,
+        "One tick takes {} ms on average, suggested --realtime-info-interval: {} ms or more",
+        tick.as_millis(),
+        suggested.max(100)
+    )?;
+    Ok(())
+}
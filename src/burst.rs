@@ -0,0 +1,76 @@
+use crate::alerts::is_firing;
+use crate::data_struct::RealTimeInfo;
+use log::info;
+use std::time::{Duration, Instant};
+
+/// Shortens the reporting interval for a while after a spike so the dashboard captures
+/// the incident at high resolution.
+///
+/// A spike is a CPU usage above the threshold, a sudden load increase (load1 jumping
+/// past a multiple of load5) or any local alert rule firing.
+#[derive(Debug, Clone)]
+pub struct Burst {
+    interval_ms: u64,
+    duration: Duration,
+    cpu_threshold: f64,
+    load_jump: f64,
+    until: Option<Instant>,
+}
+
+impl Burst {
+    pub fn new(interval_ms: u64, duration_s: u64, cpu_threshold: f64, load_jump: f64) -> Self {
+        Self {
+            interval_ms,
+            duration: Duration::from_secs(duration_s),
+            cpu_threshold,
+            load_jump,
+            until: None,
+        }
+    }
+
+    pub const fn interval_ms(&self) -> u64 {
+        self.interval_ms
+    }
+
+    fn spike(&self, real_time: &RealTimeInfo) -> Option<String> {
+        if real_time.cpu.usage >= self.cpu_threshold {
+            return Some(format!("CPU usage {:.1}%", real_time.cpu.usage));
+        }
+        // Below 1 the ratio is mostly noise from an idle machine
+        let load = &real_time.load;
+        if load.load1 >= 1.0 && load.load1 >= load.load5 * self.load_jump {
+            return Some(format!(
+                "load jumped to {:.2} (5 min: {:.2})",
+                load.load1, load.load5
+            ));
+        }
+        if is_firing() {
+            return Some("alert firing".to_string());
+        }
+        None
+    }
+
+    /// Check the latest sample, returns `true` while burst mode is active
+    pub fn update(&mut self, real_time: &RealTimeInfo) -> bool {
+        if let Some(reason) = self.spike(real_time) {
+            if self.until.is_none() {
+                info!(
+                    "Entering burst mode ({reason}), Real-Time Info will be pushed every {} ms",
+                    self.interval_ms
+                );
+            }
+            self.until = Some(Instant::now() + self.duration);
+            return true;
+        }
+
+        match self.until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                info!("Leaving burst mode, returning to the normal interval");
+                self.until = None;
+                false
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Approval gate for terminal sessions (`--terminal-approval`).
+//!
+//! With `local` a terminal request waits until someone on the host approves it over the
+//! control socket (`ctl pending`, `ctl approve <id>`, `ctl deny <id>`) and is refused
+//! after `--terminal-approval-timeout`. With `totp` the request has to carry a `totp`
+//! field with the current code of `--terminal-totp-secret` (RFC 6238: SHA-1, 6 digits,
+//! 30 s steps, one step of clock drift either way), each code opens one session only.
+
+use crate::callbacks::message::parse_task;
+use crate::command_parser::TerminalApproval;
+use log::{info, warn};
+use miniserde::{Deserialize, Serialize, json};
+use ring::hmac;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+
+const TOTP_STEP: u64 = 30;
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Deserialize)]
+struct TerminalRequest {
+    request_id: String,
+    totp: Option<String>,
+}
+
+struct Pending {
+    id: String,
+    since: Instant,
+    decision: oneshot::Sender<bool>,
+}
+
+#[derive(Serialize)]
+struct PendingSession {
+    id: String,
+    /// Seconds waited so far
+    waiting: u64,
+}
+
+static TOTP_SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+/// Time step of the latest accepted code, a code is not accepted twice
+static LAST_STEP: Mutex<u64> = Mutex::new(0);
+static PENDING: Mutex<Vec<Pending>> = Mutex::new(Vec::new());
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in encoded.chars().filter(|c| !matches!(c, ' ' | '-' | '=')) {
+        let value = BASE32
+            .iter()
+            .position(|b| char::from(*b) == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Set the base32 shared secret of the authenticator app
+pub fn set_totp_secret(secret: &str) -> Result<(), String> {
+    let secret = base32_decode(secret)
+        .filter(|secret| secret.len() >= 10)
+        .ok_or_else(|| "The TOTP secret must be base32 with at least 80 bits".to_string())?;
+    let _ = TOTP_SECRET.set(secret);
+    Ok(())
+}
+
+fn totp(secret: &[u8], step: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &step.to_be_bytes());
+    let digest = tag.as_ref();
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let code = u32::from_be_bytes([
+        digest[offset],
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]) & 0x7fff_ffff;
+    code % 1_000_000
+}
+
+fn verify_totp(code: Option<&str>) -> Result<(), String> {
+    let secret = TOTP_SECRET
+        .get()
+        .ok_or_else(|| "No `--terminal-totp-secret` configured".to_string())?;
+    let code = code
+        .and_then(|code| code.trim().parse::<u32>().ok())
+        .ok_or_else(|| "The request carries no TOTP code".to_string())?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs()
+        / TOTP_STEP;
+
+    let step = [now.saturating_sub(1), now, now + 1]
+        .into_iter()
+        .find(|step| totp(secret, *step) == code)
+        .ok_or_else(|| "Wrong TOTP code".to_string())?;
+    let mut last_step = LAST_STEP.lock().map_err(|e| e.to_string())?;
+    if step <= *last_step {
+        return Err("TOTP code already used".to_string());
+    }
+    *last_step = step;
+    Ok(())
+}
+
+async fn wait_for_approval(id: String, timeout: Duration) -> Result<(), String> {
+    let (decision, decided) = oneshot::channel();
+    PENDING.lock().map_err(|e| e.to_string())?.push(Pending {
+        id: id.clone(),
+        since: Instant::now(),
+        decision,
+    });
+    warn!(
+        "Terminal session {id} waits for approval: `ctl approve {id}` or `ctl deny {id}` within {} s",
+        timeout.as_secs()
+    );
+
+    let result = tokio::time::timeout(timeout, decided).await;
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.retain(|pending| pending.id != id);
+    }
+    match result {
+        Ok(Ok(true)) => {
+            info!("Terminal session {id} approved locally");
+            Ok(())
+        }
+        Ok(Ok(false)) => Err("denied locally".to_string()),
+        Ok(Err(_)) => Err("approval was cancelled".to_string()),
+        Err(_) => Err(format!("not approved within {} s", timeout.as_secs())),
+    }
+}
+
+/// Wait for the terminal request `utf8_str` to be approved, `Err` when it is refused
+pub async fn approve_session(
+    utf8_str: &str,
+    approval: &TerminalApproval,
+    timeout: Duration,
+) -> Result<(), String> {
+    let request: TerminalRequest = parse_task(utf8_str, "TerminalEvent")?;
+    match approval {
+        TerminalApproval::None => Ok(()),
+        TerminalApproval::Totp => verify_totp(request.totp.as_deref()),
+        TerminalApproval::Local => wait_for_approval(request.request_id, timeout).await,
+    }
+}
+
+/// The sessions waiting for a local decision (JSON)
+pub fn pending_sessions() -> String {
+    let pending = PENDING.lock().map_or_else(
+        |_| Vec::new(),
+        |pending| {
+            pending
+                .iter()
+                .map(|pending| PendingSession {
+                    id: pending.id.clone(),
+                    waiting: pending.since.elapsed().as_secs(),
+                })
+                .collect()
+        },
+    );
+    json::to_string(&pending)
+}
+
+/// Approve or deny the waiting session `id`
+pub fn decide(id: &str, approve: bool) -> Result<(), String> {
+    let mut pending = PENDING.lock().map_err(|e| e.to_string())?;
+    let index = pending
+        .iter()
+        .position(|pending| pending.id == id)
+        .ok_or_else(|| format!("no terminal session `{id}` is waiting"))?;
+    let _ = pending.remove(index).decision.send(approve);
+    Ok(())
+}
@@ -0,0 +1,330 @@
+//! Recurring commands registered by the server.
+//!
+//! A `cron_schedule` message registers the job `task_id`: `command` run with bash on the
+//! five-field cron `schedule` (minute, hour, day of month, month, day of week, local time;
+//! `*`, `a-b`, lists and `/step`, Sunday is 0 or 7, a job restricting both day fields runs
+//! on either). A job with the same id replaces the previous one, `cron_cancel` removes it.
+//! The jobs are kept in `komari-cron` beside the version file and run whether the panel is
+//! connected or not, from one start of the agent to the next. The output of every run is
+//! posted like an `exec` result under the job's `task_id`; a run still going when the job
+//! comes due again makes that run skipped. The jobs obey the same options as `exec` and are
+//! kept but not run while those refuse remote commands. The file is written with mode 0600
+//! and not loaded unless the agent's user owns it and nobody else can write to it; with
+//! `--command-public-key` the signature of every job is saved and verified again on load.
+
+use crate::callbacks::exec::{post_result, run_command};
+use crate::callbacks::message::parse_task;
+use crate::callbacks::policy::{cron_payload, verify_signature};
+use crate::state_dir::check_private;
+use log::{error, info, warn};
+use miniserde::{Deserialize, Serialize, json};
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write as _;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::OffsetDateTime;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CronSchedule {
+    message: String,
+    task_id: String,
+    command: Option<String>,
+    schedule: Option<String>,
+    issued_at: Option<u64>,
+    signature: Option<String>,
+}
+
+/// A job as it is saved
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CronJob {
+    task_id: String,
+    command: String,
+    schedule: String,
+    issued_at: Option<u64>,
+    signature: Option<String>,
+}
+
+/// Allowed values of the five fields, one bit each
+#[derive(Debug, Clone)]
+struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and the day of week are both restricted
+    either_day: bool,
+}
+
+struct Scheduler {
+    path: String,
+    callback_url: String,
+    ignore_unsafe_cert: bool,
+}
+
+static SCHEDULER: OnceLock<Scheduler> = OnceLock::new();
+static JOBS: Mutex<Vec<(CronJob, Schedule)>> = Mutex::new(Vec::new());
+/// Jobs with a run going on
+static RUNNING: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("invalid field '{field}', expected values in {min}-{max}");
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(invalid)?,
+            ),
+            None => (part, 1),
+        };
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some((first, last)) = range.split_once('-') {
+            (
+                first.parse().map_err(|_| invalid())?,
+                last.parse().map_err(|_| invalid())?,
+            )
+        } else {
+            let value = range.parse().map_err(|_| invalid())?;
+            // `a/n` runs from `a` to the end
+            (value, if part.contains('/') { max } else { value })
+        };
+        if first < min || last > max || first > last {
+            return Err(invalid());
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn parse_schedule(schedule: &str) -> Result<Schedule, String> {
+    let fields = schedule.split_whitespace().collect::<Vec<_>>();
+    let [minutes, hours, days, months, weekdays] = fields[..] else {
+        return Err(format!(
+            "expected 5 fields (minute hour day month weekday), got '{schedule}'"
+        ));
+    };
+    let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+    // Sunday is 0 or 7
+    if weekday_bits & (1 << 7) != 0 {
+        weekday_bits |= 1;
+    }
+    Ok(Schedule {
+        minutes: parse_field(minutes, 0, 59)?,
+        hours: parse_field(hours, 0, 23)?,
+        days: parse_field(days, 1, 31)?,
+        months: parse_field(months, 1, 12)?,
+        weekdays: weekday_bits,
+        either_day: days != "*" && weekdays != "*",
+    })
+}
+
+impl Schedule {
+    fn matches(&self, now: OffsetDateTime) -> bool {
+        let bit = |bits: u64, value: u8| bits & (1 << value) != 0;
+        let day = bit(self.days, now.day());
+        let weekday = bit(self.weekdays, now.weekday().number_days_from_sunday());
+        bit(self.minutes, now.minute())
+            && bit(self.hours, now.hour())
+            && bit(self.months, u8::from(now.month()))
+            && if self.either_day {
+                day || weekday
+            } else {
+                day && weekday
+            }
+    }
+}
+
+fn save(jobs: &[(CronJob, Schedule)]) {
+    let Some(scheduler) = SCHEDULER.get() else {
+        return;
+    };
+    let jobs = jobs.iter().map(|(job, _)| job.clone()).collect::<Vec<_>>();
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let result = options
+        .open(&scheduler.path)
+        .and_then(|mut file| file.write_all(json::to_string(&jobs).as_bytes()));
+    if let Err(e) = result {
+        error!(
+            "Failed to save the scheduled jobs to {}: {e}",
+            scheduler.path
+        );
+    }
+}
+
+/// Handle `cron_schedule` and `cron_cancel` messages
+pub fn schedule_cron(utf8_str: &str) -> Result<(), String> {
+    let task: CronSchedule = parse_task(utf8_str, "CronSchedule")?;
+    let mut jobs = JOBS.lock().unwrap();
+
+    if task.message == "cron_cancel" {
+        let before = jobs.len();
+        jobs.retain(|(job, _)| job.task_id != task.task_id);
+        if jobs.len() != before {
+            info!("Scheduled job {} cancelled", task.task_id);
+            save(&jobs);
+        }
+        return Ok(());
+    }
+
+    let command = task
+        .command
+        .ok_or_else(|| "Scheduled job without `command`".to_string())?;
+    let schedule = task
+        .schedule
+        .ok_or_else(|| "Scheduled job without `schedule`".to_string())?;
+    let parsed = parse_schedule(&schedule).map_err(|e| format!("Invalid `schedule`: {e}"))?;
+
+    info!(
+        "Scheduled job {}: '{command}' at '{schedule}'",
+        task.task_id
+    );
+    jobs.retain(|(job, _)| job.task_id != task.task_id);
+    jobs.push((
+        CronJob {
+            task_id: task.task_id,
+            command,
+            schedule,
+            issued_at: task.issued_at,
+            signature: task.signature,
+        },
+        parsed,
+    ));
+    save(&jobs);
+    Ok(())
+}
+
+async fn run(job: CronJob) {
+    let Some(scheduler) = SCHEDULER.get() else {
+        return;
+    };
+    info!("Running scheduled job {}", job.task_id);
+    let result = match run_command(job.command).await {
+        Ok((status, output)) => {
+            let callback_url = scheduler.callback_url.clone();
+            let ignore_unsafe_cert = scheduler.ignore_unsafe_cert;
+            let task_id = job.task_id.clone();
+            tokio::task::spawn_blocking(move || {
+                post_result(task_id, status, output, callback_url, ignore_unsafe_cert)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!("Scheduled job {}: {e}", job.task_id);
+    }
+    if let Some(running) = RUNNING.lock().unwrap().as_mut() {
+        running.remove(&job.task_id);
+    }
+}
+
+/// The saved jobs, none when the file is missing or others could have written it
+fn load(path: &str) -> Vec<CronJob> {
+    if let Err(e) = check_private(Path::new(path)) {
+        error!("Not loading the scheduled jobs: {e}");
+        return Vec::new();
+    }
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|saved| json::from_str::<Vec<CronJob>>(&saved).ok())
+        .unwrap_or_default()
+}
+
+/// Load the saved jobs and run them from now on. `allowed` is whether remote commands are,
+/// the jobs are still taken and kept otherwise. With `public_key` a saved job runs only
+/// with a valid signature
+pub fn start_cron(
+    path: String,
+    callback_url: String,
+    ignore_unsafe_cert: bool,
+    allowed: bool,
+    public_key: Option<[u8; 32]>,
+) {
+    let jobs = load(&path);
+    {
+        let mut loaded = JOBS.lock().unwrap();
+        for job in jobs {
+            let verified = public_key.map_or(Ok(()), |key| {
+                verify_signature(
+                    &key,
+                    "cron_schedule",
+                    &cron_payload(
+                        &job.task_id,
+                        job.issued_at.unwrap_or_default(),
+                        &job.schedule,
+                        &job.command,
+                    ),
+                    job.signature.as_deref(),
+                )
+            });
+            match verified.and_then(|()| parse_schedule(&job.schedule)) {
+                Ok(schedule) => loaded.push((job, schedule)),
+                Err(e) => warn!("Dropping saved job {}: {e}", job.task_id),
+            }
+        }
+        if !loaded.is_empty() {
+            info!("Loaded {} scheduled job(s) from {path}", loaded.len());
+        }
+    }
+    let _ = SCHEDULER.set(Scheduler {
+        path,
+        callback_url,
+        ignore_unsafe_cert,
+    });
+    if !allowed {
+        if !JOBS.lock().unwrap().is_empty() {
+            warn!("Remote commands are refused, the scheduled jobs are not run");
+        }
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            // Just past the start of the next minute
+            tokio::time::sleep(Duration::from_millis(
+                60_000 - (now.as_millis() % 60_000) as u64 + 100,
+            ))
+            .await;
+
+            let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+            let due = JOBS
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, schedule)| schedule.matches(now))
+                .map(|(job, _)| job.clone())
+                .collect::<Vec<_>>();
+            for job in due {
+                let started = RUNNING
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_default()
+                    .insert(job.task_id.clone());
+                if started {
+                    tokio::spawn(run(job));
+                } else {
+                    warn!(
+                        "Scheduled job {} skipped, its previous run is still going",
+                        job.task_id
+                    );
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,226 @@
+//! Tasks the server sends on the Real-Time Info connection.
+//!
+//! Every message names its task type in `message` and is parsed again into the type of its
+//! handler. A task that is refused or fails, an unknown task type included, is answered
+//! with a `task_error` message echoing the task type and id (`task_id`, `ping_task_id` or
+//! `request_id`, whichever the task carries). The tasks running in the background are
+//! limited per type, one above the limit is refused as busy instead of queued. The task
+//! types stripped from the build (see `capabilities`) do not exist here at all.
+
+use crate::callbacks::LockedWriter;
+use futures::SinkExt;
+use log::error;
+use miniserde::Serialize;
+use miniserde::json::{self, Value};
+use std::future::Future;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskKind {
+    #[cfg(not(feature = "no-exec"))]
+    Exec,
+    #[cfg(not(feature = "no-exec"))]
+    CronSchedule,
+    #[cfg(not(feature = "no-exec"))]
+    CronCancel,
+    Ping,
+    Reload,
+    PingSchedule,
+    PingCancel,
+    MeshPeers,
+    Speedtest,
+    #[cfg(not(feature = "metrics-only"))]
+    FetchUrl,
+    #[cfg(not(feature = "no-terminal"))]
+    Terminal,
+}
+
+/// Every task type built in
+pub const KINDS: &[TaskKind] = &[
+    #[cfg(not(feature = "no-exec"))]
+    TaskKind::Exec,
+    #[cfg(not(feature = "no-exec"))]
+    TaskKind::CronSchedule,
+    #[cfg(not(feature = "no-exec"))]
+    TaskKind::CronCancel,
+    TaskKind::Ping,
+    TaskKind::Reload,
+    TaskKind::PingSchedule,
+    TaskKind::PingCancel,
+    TaskKind::MeshPeers,
+    TaskKind::Speedtest,
+    #[cfg(not(feature = "metrics-only"))]
+    TaskKind::FetchUrl,
+    #[cfg(not(feature = "no-terminal"))]
+    TaskKind::Terminal,
+];
+
+#[cfg(not(feature = "no-exec"))]
+const EXEC_LIMIT: usize = 4;
+const PING_LIMIT: usize = 32;
+const SPEEDTEST_LIMIT: usize = 1;
+#[cfg(not(feature = "metrics-only"))]
+const FETCH_URL_LIMIT: usize = 2;
+#[cfg(not(feature = "no-terminal"))]
+const TERMINAL_LIMIT: usize = 8;
+
+#[cfg(not(feature = "no-exec"))]
+static EXEC_SLOTS: Semaphore = Semaphore::const_new(EXEC_LIMIT);
+static PING_SLOTS: Semaphore = Semaphore::const_new(PING_LIMIT);
+static SPEEDTEST_SLOTS: Semaphore = Semaphore::const_new(SPEEDTEST_LIMIT);
+#[cfg(not(feature = "metrics-only"))]
+static FETCH_URL_SLOTS: Semaphore = Semaphore::const_new(FETCH_URL_LIMIT);
+#[cfg(not(feature = "no-terminal"))]
+static TERMINAL_SLOTS: Semaphore = Semaphore::const_new(TERMINAL_LIMIT);
+
+impl TaskKind {
+    pub fn parse(message: &str) -> Option<Self> {
+        KINDS.iter().copied().find(|kind| kind.name() == message)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(not(feature = "no-exec"))]
+            Self::Exec => "exec",
+            #[cfg(not(feature = "no-exec"))]
+            Self::CronSchedule => "cron_schedule",
+            #[cfg(not(feature = "no-exec"))]
+            Self::CronCancel => "cron_cancel",
+            Self::Ping => "ping",
+            Self::Reload => "reload",
+            Self::PingSchedule => "ping_schedule",
+            Self::PingCancel => "ping_cancel",
+            Self::MeshPeers => "mesh_peers",
+            Self::Speedtest => "speedtest",
+            #[cfg(not(feature = "metrics-only"))]
+            Self::FetchUrl => "fetch_url",
+            #[cfg(not(feature = "no-terminal"))]
+            Self::Terminal => "terminal",
+        }
+    }
+
+    /// Whether the task needs a signature with `--command-public-key`
+    pub fn signed(self) -> bool {
+        matches!(
+            self.name(),
+            "exec" | "cron_schedule" | "terminal" | "fetch_url" | "reload"
+        )
+    }
+
+    /// Slots of the tasks that run in the background, `None` for the ones handled right away
+    fn slots(self) -> Option<(&'static Semaphore, usize)> {
+        match self {
+            #[cfg(not(feature = "no-exec"))]
+            Self::Exec => Some((&EXEC_SLOTS, EXEC_LIMIT)),
+            Self::Ping => Some((&PING_SLOTS, PING_LIMIT)),
+            Self::Speedtest => Some((&SPEEDTEST_SLOTS, SPEEDTEST_LIMIT)),
+            #[cfg(not(feature = "metrics-only"))]
+            Self::FetchUrl => Some((&FETCH_URL_SLOTS, FETCH_URL_LIMIT)),
+            #[cfg(not(feature = "no-terminal"))]
+            Self::Terminal => Some((&TERMINAL_SLOTS, TERMINAL_LIMIT)),
+            #[cfg(not(feature = "no-exec"))]
+            Self::CronSchedule | Self::CronCancel => None,
+            Self::Reload | Self::PingSchedule | Self::PingCancel | Self::MeshPeers => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TaskError {
+    /// Always `task_error`
+    message: String,
+    task: String,
+    task_id: Option<Value>,
+    error: String,
+}
+
+/// A task received from the server
+#[derive(Clone)]
+pub struct Task {
+    pub kind: TaskKind,
+    /// Id the task was sent with, echoed in the error reply
+    id: Option<Value>,
+    /// The whole message, parsed into the task type by its handler
+    pub payload: Utf8Bytes,
+}
+
+/// Task type and id of a message, `None` when it is no task at all
+pub fn task_header(payload: &str) -> Option<(String, Option<Value>)> {
+    let Ok(Value::Object(object)) = json::from_str::<Value>(payload) else {
+        return None;
+    };
+    let Some(Value::String(message)) = object.get("message") else {
+        return None;
+    };
+    let id = ["task_id", "ping_task_id", "request_id"]
+        .into_iter()
+        .find_map(|key| object.get(key).cloned());
+    Some((message.clone(), id))
+}
+
+/// Answer a task of type `task` with an error
+pub async fn reply_error(writer: &LockedWriter, task: &str, id: Option<Value>, error: String) {
+    error!("{task} task failed: {error}");
+    let reply = json::to_string(&TaskError {
+        message: "task_error".to_string(),
+        task: task.to_string(),
+        task_id: id,
+        error,
+    });
+    let mut write = writer.lock().await;
+    if let Err(e) = write.send(Message::Text(Utf8Bytes::from(reply))).await {
+        error!("Error occurred while pushing task error: {e}");
+    }
+}
+
+impl Task {
+    pub fn new(kind: TaskKind, id: Option<Value>, payload: Utf8Bytes) -> Self {
+        Self { kind, id, payload }
+    }
+
+    /// Answer the task with an error
+    pub async fn fail(&self, writer: &LockedWriter, error: String) {
+        reply_error(writer, self.kind.name(), self.id.clone(), error).await;
+    }
+
+    fn acquire(&self) -> Result<Option<SemaphorePermit<'static>>, String> {
+        let Some((slots, limit)) = self.kind.slots() else {
+            return Ok(None);
+        };
+        slots.try_acquire().map(Some).map_err(|_| {
+            format!(
+                "busy, {limit} {} task(s) are already running",
+                self.kind.name()
+            )
+        })
+    }
+
+    /// Run the task in the background within the limit of its type. `run` gives the reply
+    /// to send, if any, its error is replied as a task error
+    pub fn spawn<F>(self, writer: &LockedWriter, run: F) -> Result<(), String>
+    where
+        F: Future<Output = Result<Option<String>, String>> + Send + 'static,
+    {
+        let permit = self.acquire()?;
+        let writer = writer.clone();
+        tokio::spawn(async move {
+            let result = run.await;
+            drop(permit);
+            match result {
+                Ok(Some(reply)) => {
+                    let mut write = writer.lock().await;
+                    if let Err(e) = write.send(Message::Text(Utf8Bytes::from(reply))).await {
+                        error!(
+                            "Error occurred while pushing {} result: {e}",
+                            self.kind.name()
+                        );
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => self.fail(&writer, e).await,
+            }
+        });
+        Ok(())
+    }
+}
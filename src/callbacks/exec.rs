@@ -1,3 +1,6 @@
+use crate::auth::panel_headers;
+use crate::callbacks::message::parse_task;
+use crate::signing::sign_json;
 use miniserde::{Deserialize, Serialize, json};
 use std::process::Stdio;
 use time::OffsetDateTime;
@@ -25,13 +28,23 @@ pub async fn exec_command(
     callback_url: String,
     ignore_unsafe_cert: &bool,
 ) -> Result<(), String> {
-    let remote_exec: RemoteExec =
-        json::from_str(utf8_str).map_err(|_| "Failed to parse RemoteExec".to_string())?;
+    let remote_exec: RemoteExec = parse_task(utf8_str, "RemoteExec")?;
+    let (status, output) = run_command(remote_exec.command).await?;
+    post_result(
+        remote_exec.task_id,
+        status,
+        output,
+        callback_url,
+        *ignore_unsafe_cert,
+    )
+}
 
+/// Run `command` with bash, its exit code and output
+pub async fn run_command(command: String) -> Result<(i32, String), String> {
     let exec = tokio::spawn(async move {
         let Ok(child) = Command::new("bash")
             .arg("-c")
-            .arg(&remote_exec.command) // Avoid cloning
+            .arg(&command) // Avoid cloning
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
@@ -54,23 +67,37 @@ pub async fn exec_command(
     let Ok(Ok((status, output))) = exec.await else {
         return Err("failed to execute process".to_string());
     };
+    Ok((status, output))
+}
 
+/// Post the result of a command to the panel, blocking
+pub fn post_result(
+    task_id: String,
+    status: i32,
+    output: String,
+    callback_url: String,
+    ignore_unsafe_cert: bool,
+) -> Result<(), String> {
     let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
     let finished_at = now.format(&Rfc3339).unwrap_or_default();
 
     let reply = RemoteExecCallback {
-        task_id: remote_exec.task_id,
+        task_id,
         result: output,
         exit_code: status,
         finished_at,
     };
 
-    let json_string = json::to_string(&reply);
+    let json_string = sign_json(json::to_string(&reply));
     #[cfg(feature = "ureq-support")]
     {
         use crate::utils::create_ureq_agent;
-        let agent = create_ureq_agent(*ignore_unsafe_cert);
-        if let Ok(req) = agent.post(callback_url).send(&json_string) {
+        let agent = create_ureq_agent(ignore_unsafe_cert);
+        let mut request = agent.post(callback_url);
+        for (name, value) in panel_headers() {
+            request = request.header(name, value);
+        }
+        if let Ok(req) = request.send(&json_string) {
             if req.status().is_success() {
                 Ok(())
             } else {
@@ -85,9 +112,12 @@ pub async fn exec_command(
     {
         use nyquest::Body;
         use nyquest::Request;
-        let client = crate::utils::create_nyquest_client(*ignore_unsafe_cert);
+        let client = crate::utils::create_nyquest_client(ignore_unsafe_cert);
         let body = Body::text(json_string, "application/json");
-        let request = Request::post(callback_url).with_body(body);
+        let mut request = Request::post(callback_url).with_body(body);
+        for (name, value) in panel_headers() {
+            request = request.with_header(name, value);
+        }
 
         if let Ok(res) = client.request(request) {
             if res.status().is_successful() {
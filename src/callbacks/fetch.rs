@@ -0,0 +1,317 @@
+//! Server-pushed file downloads (`fetch_url` message, `--fetch-dir`).
+//!
+//! The file is only written below one of the `--fetch-dir` directories, at most
+//! `rate_limit` bytes/s, into a temporary file next to the target. It replaces the target
+//! once complete and, when `sha256` is given, only if the digest matches.
+
+use crate::callbacks::message::parse_task;
+use crate::utils::timeouts;
+use log::info;
+use miniserde::{Deserialize, Serialize};
+use ring::digest::{Context, SHA256};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+/// Bundles and scripts, not disk images
+const MAX_SIZE: u64 = 512 * 1024 * 1024;
+/// The whole transfer, slow rate limits included
+const MAX_DURATION: Duration = Duration::from_hours(1);
+#[cfg(feature = "ureq-support")]
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FetchTask {
+    task_id: String,
+    url: String,
+    /// Absolute destination, below one of the `--fetch-dir` directories
+    path: String,
+    /// Expected digest, hex
+    sha256: Option<String>,
+    /// Bytes per second, unlimited when unset
+    rate_limit: Option<u64>,
+    /// Octal permissions applied to the file, e.g. `755` (Unix only). Setuid, setgid, sticky
+    /// and world-writable bits are refused
+    mode: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FetchCallback {
+    #[serde(rename = "type")]
+    pub type_str: String,
+    pub task_id: String,
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: Option<String>,
+    pub error: Option<String>,
+    pub finished_at: String,
+}
+
+/// The destination if it lies below an allowed directory, `..` is never accepted
+fn allowed_path(path: &str, fetch_dirs: &[String]) -> Result<PathBuf, String> {
+    let path = Path::new(path);
+    if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return Err(format!("{} is not a plain absolute path", path.display()));
+    }
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(format!("{} has no file name", path.display()));
+    };
+    let allowed = |dir: &Path| {
+        fetch_dirs.iter().any(|allowed| {
+            Path::new(allowed)
+                .canonicalize()
+                .is_ok_and(|allowed| dir.starts_with(allowed))
+        })
+    };
+    let outside = || {
+        format!(
+            "{} is outside the allowed fetch directories",
+            path.display()
+        )
+    };
+
+    // Checked before creating missing directories and again after, symlinked directories
+    // being resolved each time
+    let existing = parent
+        .ancestors()
+        .find(|dir| dir.exists())
+        .and_then(|dir| dir.canonicalize().ok());
+    if !existing.is_some_and(|dir| allowed(&dir)) {
+        return Err(outside());
+    }
+    std::fs::create_dir_all(parent)
+        .map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+    let parent = parent
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve {}: {e}", parent.display()))?;
+    if !allowed(&parent) {
+        return Err(outside());
+    }
+    Ok(parent.join(name))
+}
+
+/// Sleeps whenever the transfer gets ahead of the rate limit
+struct Pacer {
+    rate_limit: Option<u64>,
+    started: Instant,
+}
+
+impl Pacer {
+    fn pace(&self, bytes: u64) {
+        let Some(rate) = self.rate_limit.filter(|rate| *rate > 0) else {
+            return;
+        };
+        let due = Duration::from_secs_f64(bytes as f64 / rate as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+/// Write the body to `file`, returns its size and SHA-256
+#[cfg(feature = "ureq-support")]
+fn download(
+    task: &FetchTask,
+    file: &mut File,
+    ignore_unsafe_cert: bool,
+) -> Result<(u64, String), String> {
+    use crate::get_info::network::own::counted_agent;
+    use std::io::Read;
+    use ureq::unversioned::resolver::DefaultResolver;
+
+    let config = ureq::Agent::config_builder()
+        .tls_config(
+            ureq::tls::TlsConfig::builder()
+                .disable_verification(ignore_unsafe_cert)
+                .build(),
+        )
+        .timeout_connect(Some(timeouts().connect))
+        .timeout_global(Some(MAX_DURATION))
+        .build();
+    let agent = counted_agent(config, DefaultResolver::default());
+    let mut response = agent
+        .get(&task.url)
+        .header("User-Agent", "curl/11.45.14-rs")
+        .call()
+        .map_err(|e| format!("Download failed: {e}"))?;
+
+    let pacer = Pacer {
+        rate_limit: task.rate_limit,
+        started: Instant::now(),
+    };
+    let mut reader = response.body_mut().with_config().limit(MAX_SIZE).reader();
+    let mut digest = Context::new(&SHA256);
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut bytes = 0;
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .map_err(|e| format!("Download failed: {e}"))?;
+        if n == 0 {
+            break;
+        }
+        digest.update(&buf[..n]);
+        file.write_all(&buf[..n])
+            .map_err(|e| format!("Failed to write: {e}"))?;
+        bytes += n as u64;
+        pacer.pace(bytes);
+    }
+    Ok((bytes, hex(digest.finish().as_ref())))
+}
+
+/// nyquest reads the whole body at once, the rate limit then only paces how fast it is
+/// written, not the transfer itself
+#[cfg(feature = "nyquest-support")]
+fn download(
+    task: &FetchTask,
+    file: &mut File,
+    ignore_unsafe_cert: bool,
+) -> Result<(u64, String), String> {
+    use nyquest::Request;
+
+    let mut client = nyquest::ClientBuilder::default()
+        .request_timeout(MAX_DURATION + timeouts().total())
+        .user_agent("curl/8.7.1");
+    if ignore_unsafe_cert {
+        client = client.dangerously_ignore_certificate_errors();
+    }
+    let body = client
+        .build_blocking()
+        .map_err(|e| e.to_string())?
+        .request(Request::get(task.url.clone()))
+        .and_then(nyquest::blocking::Response::bytes)
+        .map_err(|e| format!("Download failed: {e}"))?;
+    if body.len() as u64 > MAX_SIZE {
+        return Err(format!("Download exceeds {MAX_SIZE} bytes"));
+    }
+
+    let pacer = Pacer {
+        rate_limit: task.rate_limit,
+        started: Instant::now(),
+    };
+    let mut written = 0;
+    for chunk in body.chunks(64 * 1024) {
+        file.write_all(chunk)
+            .map_err(|e| format!("Failed to write: {e}"))?;
+        written += chunk.len() as u64;
+        pacer.pace(written);
+    }
+    let mut digest = Context::new(&SHA256);
+    digest.update(&body);
+    Ok((written, hex(digest.finish().as_ref())))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    use std::fmt::Write as _;
+
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+/// The permission bits of `mode`, checked before anything is downloaded
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    let bits = u32::from_str_radix(mode, 8).map_err(|_| format!("Invalid file mode '{mode}'"))?;
+    if bits & !0o777 != 0 {
+        return Err(format!(
+            "File mode '{mode}' sets setuid, setgid or sticky bits, refused"
+        ));
+    }
+    if bits & 0o002 != 0 {
+        return Err(format!(
+            "File mode '{mode}' makes the file writable by others, refused"
+        ));
+    }
+    Ok(bits)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))
+        .map_err(|e| format!("Failed to set the mode of {}: {e}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<(), String> {
+    Ok(())
+}
+
+fn fetch(
+    task: &FetchTask,
+    fetch_dirs: &[String],
+    ignore_unsafe_cert: bool,
+) -> Result<(u64, String), String> {
+    let path = allowed_path(&task.path, fetch_dirs)?;
+    let mode = task.mode.as_deref().map(parse_mode).transpose()?;
+    let temp = path.with_file_name(format!(
+        ".{}.komari-fetch",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+
+    // A leftover of an interrupted fetch, or anything else planted under that name
+    let _ = std::fs::remove_file(&temp);
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .map_err(|e| format!("Failed to create {}: {e}", temp.display()))
+        .and_then(|mut file| {
+            let downloaded = download(task, &mut file, ignore_unsafe_cert)?;
+            file.sync_all()
+                .map_err(|e| format!("Failed to write {}: {e}", temp.display()))?;
+            Ok(downloaded)
+        })
+        .and_then(|(bytes, sha256)| match &task.sha256 {
+            Some(expected) if !expected.trim().eq_ignore_ascii_case(&sha256) => Err(format!(
+                "Checksum mismatch: expected {expected}, got {sha256}"
+            )),
+            _ => Ok((bytes, sha256)),
+        })
+        .and_then(|downloaded| {
+            if let Some(mode) = mode {
+                set_mode(&temp, mode)?;
+            }
+            std::fs::rename(&temp, &path)
+                .map_err(|e| format!("Failed to replace {}: {e}", path.display()))?;
+            Ok(downloaded)
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+pub async fn fetch_url(
+    utf8_str: &str,
+    fetch_dirs: Vec<String>,
+    ignore_unsafe_cert: bool,
+) -> Result<FetchCallback, String> {
+    let task: FetchTask = parse_task(utf8_str, "FetchTask")?;
+
+    info!("Fetching {} to {}", task.url, task.path);
+    tokio::task::spawn_blocking(move || {
+        let result = fetch(&task, &fetch_dirs, ignore_unsafe_cert);
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        let (bytes, sha256, error) = match result {
+            Ok((bytes, sha256)) => (bytes, Some(sha256), None),
+            Err(e) => (0, None, Some(e)),
+        };
+        FetchCallback {
+            type_str: String::from("fetch_result"),
+            task_id: task.task_id,
+            path: task.path,
+            bytes,
+            sha256,
+            error,
+            finished_at: now.format(&Rfc3339).unwrap_or_default(),
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
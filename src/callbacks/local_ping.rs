@@ -0,0 +1,169 @@
+//! Locally configured ping targets (`--ping-target`).
+//!
+//! Unlike the ping tasks the server hands out, these targets are probed from the start on
+//! and need nothing from the server. Each target is probed every interval with the same
+//! icmp / tcp / http probes, every Real-Time Info carries RTT and loss per target over the
+//! last `WINDOW` probes. ICMP needs root outside Windows, like the server-issued tasks.
+
+use crate::callbacks::ping::probe;
+use crate::data_struct::LocalPing;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Probes per target the reported RTT and loss are computed over
+const WINDOW: usize = 60;
+const DEFAULT_INTERVAL: u64 = 10;
+
+#[derive(Debug, Clone)]
+pub struct PingTarget {
+    pub name: String,
+    /// `icmp` / `tcp` / `http`
+    pub kind: String,
+    pub target: String,
+    pub interval: Duration,
+}
+
+struct TargetState {
+    target: PingTarget,
+    /// RTT in ms of the latest probes, `None` for the lost ones
+    samples: VecDeque<Option<f64>>,
+}
+
+static TARGETS: Mutex<Vec<TargetState>> = Mutex::new(Vec::new());
+
+/// Parse `[NAME=][TYPE:]TARGET[@SECONDS]` specs, the type defaults to icmp
+pub fn parse_ping_targets(specs: &[String]) -> Result<Vec<PingTarget>, String> {
+    specs
+        .iter()
+        .map(|spec| {
+            let is_name = |name: &str| {
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+            };
+            let (name, rest) = match spec.split_once('=') {
+                Some((name, rest)) if is_name(name.trim()) => (Some(name.trim()), rest),
+                _ => (None, spec.as_str()),
+            };
+            let (kind, rest) = match rest.split_once(':') {
+                Some((kind @ ("icmp" | "tcp" | "http"), rest)) => (kind, rest),
+                _ => ("icmp", rest),
+            };
+            let (target, interval) = match rest.rsplit_once('@') {
+                Some((target, seconds)) if seconds.chars().all(|c| c.is_ascii_digit()) => {
+                    let seconds = seconds
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|seconds| *seconds > 0)
+                        .ok_or_else(|| format!("Invalid ping target interval in '{spec}'"))?;
+                    (target, seconds)
+                }
+                _ => (rest, DEFAULT_INTERVAL),
+            };
+            let target = target.trim();
+            if target.is_empty() {
+                return Err(format!(
+                    "Invalid ping target '{spec}', expected [NAME=][TYPE:]TARGET[@SECONDS]"
+                ));
+            }
+            if kind == "http" && !target.starts_with("http://") && !target.starts_with("https://") {
+                return Err(format!("Ping target '{target}' is not an http(s) URL"));
+            }
+            Ok(PingTarget {
+                name: name.unwrap_or(target).to_string(),
+                kind: kind.to_string(),
+                target: target.to_string(),
+                interval: Duration::from_secs(interval),
+            })
+        })
+        .collect()
+}
+
+/// Probe every target on its own interval until the process stops
+pub fn spawn_local_pings(targets: Vec<PingTarget>) {
+    if let Ok(mut states) = TARGETS.lock() {
+        *states = targets
+            .iter()
+            .map(|target| TargetState {
+                target: target.clone(),
+                samples: VecDeque::with_capacity(WINDOW),
+            })
+            .collect();
+    }
+
+    for (index, target) in targets.into_iter().enumerate() {
+        info!(
+            "Pinging {} ({} {}) every {} s",
+            target.name,
+            target.kind,
+            target.target,
+            target.interval.as_secs()
+        );
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(target.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut warned = false;
+            loop {
+                ticker.tick().await;
+                let rtt = match probe(&target.kind, &target.target, 0).await {
+                    Ok(result) => result.value.filter(|rtt| *rtt >= 0).map(|rtt| rtt as f64),
+                    Err(e) => {
+                        // Usually lasting (no root for icmp), once is enough
+                        if !warned {
+                            warn!("Failed to ping {}: {e}", target.name);
+                            warned = true;
+                        }
+                        None
+                    }
+                };
+                if let Ok(mut states) = TARGETS.lock()
+                    && let Some(state) = states.get_mut(index)
+                {
+                    if state.samples.len() == WINDOW {
+                        state.samples.pop_front();
+                    }
+                    state.samples.push_back(rtt);
+                }
+            }
+        });
+    }
+}
+
+/// RTT and loss per target, `None` without targets
+pub fn realtime_local_ping() -> Option<Vec<LocalPing>> {
+    let states = TARGETS.lock().ok()?;
+    if states.is_empty() {
+        return None;
+    }
+
+    Some(
+        states
+            .iter()
+            .map(|state| {
+                let rtts = state.samples.iter().flatten().copied().collect::<Vec<_>>();
+                let sent = state.samples.len() as u64;
+                let received = rtts.len() as u64;
+                LocalPing {
+                    name: state.target.name.clone(),
+                    kind: state.target.kind.clone(),
+                    target: state.target.target.clone(),
+                    sent,
+                    received,
+                    loss: if sent == 0 {
+                        0.0
+                    } else {
+                        (sent - received) as f64 * 100.0 / sent as f64
+                    },
+                    last: state.samples.back().copied().flatten(),
+                    min: rtts.iter().copied().reduce(f64::min),
+                    avg: (!rtts.is_empty()).then(|| rtts.iter().sum::<f64>() / rtts.len() as f64),
+                    max: rtts.iter().copied().reduce(f64::max),
+                }
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,283 @@
+//! Agent-to-agent latency matrix (`--mesh-listen`).
+//!
+//! Every agent answers UDP probes on `--mesh-listen`. The server distributes the other
+//! nodes with a `mesh_peers` message, a new list replaces the previous one and an empty
+//! list stops probing. Each peer then gets one probe per `interval` seconds, a probe
+//! without an answer by the next round is lost. Every Real-Time Info carries this node's
+//! row of the matrix: RTT and loss per peer over the last `WINDOW` probes.
+
+use crate::callbacks::message::parse_task;
+use crate::data_struct::MeshPeer;
+use log::{error, info, warn};
+use miniserde::Deserialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{UdpSocket, lookup_host};
+use tokio::task::AbortHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+const MAGIC: &[u8; 4] = b"KMSH";
+const REQUEST: u8 = b'?';
+const REPLY: u8 = b'!';
+/// Probes per peer the reported RTT and loss are computed over
+const WINDOW: usize = 60;
+const MAX_PEERS: usize = 256;
+
+#[derive(Deserialize)]
+struct PeerList {
+    /// Seconds between two probes of the same peer
+    interval: Option<u64>,
+    peers: Vec<PeerAddress>,
+}
+
+#[derive(Deserialize)]
+struct PeerAddress {
+    name: String,
+    /// `host:port` of the peer's `--mesh-listen`
+    address: String,
+}
+
+struct Peer {
+    name: String,
+    address: String,
+    /// Resolved on every round, replies from other addresses are ignored
+    resolved: Option<SocketAddr>,
+    /// Sequence number and send time of the probe waiting for its reply
+    pending: Option<(u64, Instant)>,
+    /// RTT of the latest probes, `None` for the lost ones
+    samples: VecDeque<Option<Duration>>,
+}
+
+impl Peer {
+    fn record(&mut self, sample: Option<Duration>) {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+}
+
+struct Mesh {
+    socket: Arc<UdpSocket>,
+    peers: Vec<Peer>,
+    next_seq: u64,
+    prober: Option<AbortHandle>,
+}
+
+static MESH: Mutex<Option<Mesh>> = Mutex::new(None);
+
+fn packet(kind: u8, seq: u64) -> [u8; 13] {
+    let mut packet = [0u8; 13];
+    packet[..4].copy_from_slice(MAGIC);
+    packet[4] = kind;
+    packet[5..].copy_from_slice(&seq.to_be_bytes());
+    packet
+}
+
+fn parse_packet(datagram: &[u8]) -> Option<(u8, u64)> {
+    let rest = datagram.strip_prefix(MAGIC)?;
+    let (&kind, seq) = rest.split_first()?;
+    Some((kind, u64::from_be_bytes(seq.try_into().ok()?)))
+}
+
+/// Answer probes on `listen` and match the replies to the probes of this node
+pub async fn mesh_responder(listen: String) {
+    let socket = match UdpSocket::bind(&listen).await {
+        Ok(socket) => Arc::new(socket),
+        Err(e) => {
+            error!("Failed to bind mesh probe socket on {listen}: {e}");
+            return;
+        }
+    };
+    match socket.local_addr() {
+        Ok(addr) => info!("Mesh probes answered on {addr}"),
+        Err(_) => info!("Mesh probes answered on {listen}"),
+    }
+    if let Ok(mut mesh) = MESH.lock() {
+        *mesh = Some(Mesh {
+            socket: socket.clone(),
+            peers: Vec::new(),
+            next_seq: 0,
+            prober: None,
+        });
+    }
+
+    let mut buf = [0u8; 64];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Mesh probe socket error: {e}");
+                continue;
+            }
+        };
+        match parse_packet(&buf[..len]) {
+            Some((REQUEST, seq)) => {
+                let _ = socket.send_to(&packet(REPLY, seq), from).await;
+            }
+            Some((REPLY, seq)) => {
+                received_reply(seq, SocketAddr::new(from.ip().to_canonical(), from.port()));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn received_reply(seq: u64, from: SocketAddr) {
+    let Ok(mut mesh) = MESH.lock() else {
+        return;
+    };
+    let Some(peer) = mesh.as_mut().and_then(|mesh| {
+        mesh.peers.iter_mut().find(|peer| {
+            peer.resolved == Some(from) && peer.pending.is_some_and(|(pending, _)| pending == seq)
+        })
+    }) else {
+        return;
+    };
+    if let Some((_, sent)) = peer.pending.take() {
+        peer.record(Some(sent.elapsed()));
+    }
+}
+
+/// Handle a `mesh_peers` message
+pub fn set_mesh_peers(utf8_str: &str) -> Result<(), String> {
+    let list: PeerList = parse_task(utf8_str, "mesh peer list")?;
+    let mut guard = MESH.lock().map_err(|e| e.to_string())?;
+    let Some(mesh) = guard.as_mut() else {
+        return Err("Mesh probing is disabled, no --mesh-listen configured".to_string());
+    };
+
+    if let Some(prober) = mesh.prober.take() {
+        prober.abort();
+    }
+    mesh.peers = list
+        .peers
+        .into_iter()
+        .take(MAX_PEERS)
+        .map(|peer| Peer {
+            name: peer.name,
+            address: peer.address,
+            resolved: None,
+            pending: None,
+            samples: VecDeque::with_capacity(WINDOW),
+        })
+        .collect();
+    if mesh.peers.is_empty() {
+        info!("Mesh probing stopped");
+        return Ok(());
+    }
+
+    let interval = Duration::from_secs(list.interval.unwrap_or(10).max(1));
+    info!(
+        "Mesh probing {} peers every {} s",
+        mesh.peers.len(),
+        interval.as_secs()
+    );
+    let socket = mesh.socket.clone();
+    let handle = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            probe_round(&socket).await;
+        }
+    });
+    mesh.prober = Some(handle.abort_handle());
+    Ok(())
+}
+
+/// Count the unanswered probes as lost and send the next one to every peer
+async fn probe_round(socket: &UdpSocket) {
+    let addresses = match MESH.lock() {
+        Ok(mesh) => mesh.as_ref().map_or_else(Vec::new, |mesh| {
+            mesh.peers.iter().map(|peer| peer.address.clone()).collect()
+        }),
+        Err(_) => return,
+    };
+
+    // An IPv4 socket cannot reach IPv6 addresses, a dual-stack one reaches both
+    let ipv4_only = socket.local_addr().is_ok_and(|addr| addr.is_ipv4());
+    let mut resolved = Vec::with_capacity(addresses.len());
+    for address in &addresses {
+        resolved.push(
+            lookup_host(address.as_str())
+                .await
+                .ok()
+                .and_then(|mut addrs| addrs.find(|addr| addr.is_ipv4() || !ipv4_only)),
+        );
+    }
+
+    let mut probes = Vec::new();
+    if let Ok(mut mesh) = MESH.lock()
+        && let Some(mesh) = mesh.as_mut()
+    {
+        let now = Instant::now();
+        for (peer, resolved) in mesh.peers.iter_mut().zip(resolved) {
+            if peer.pending.take().is_some() {
+                peer.record(None);
+            }
+            peer.resolved = resolved;
+            let Some(addr) = resolved else {
+                peer.record(None);
+                continue;
+            };
+            mesh.next_seq += 1;
+            peer.pending = Some((mesh.next_seq, now));
+            probes.push((packet(REQUEST, mesh.next_seq), addr));
+        }
+    }
+
+    for (packet, addr) in probes {
+        // A dual-stack socket takes IPv4 peers as mapped addresses
+        let to = match addr {
+            SocketAddr::V4(v4) if !ipv4_only => {
+                SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port())
+            }
+            addr => addr,
+        };
+        if let Err(e) = socket.send_to(&packet, to).await {
+            warn!("Failed to send mesh probe to {addr}: {e}");
+        }
+    }
+}
+
+/// This node's row of the latency matrix, `None` without peers
+pub fn realtime_mesh() -> Option<Vec<MeshPeer>> {
+    let mesh = MESH.lock().ok()?;
+    let mesh = mesh.as_ref()?;
+    if mesh.peers.is_empty() {
+        return None;
+    }
+
+    Some(
+        mesh.peers
+            .iter()
+            .map(|peer| {
+                let rtts = peer
+                    .samples
+                    .iter()
+                    .flatten()
+                    .map(|rtt| rtt.as_secs_f64() * 1000.0)
+                    .collect::<Vec<_>>();
+                let sent = peer.samples.len() as u64;
+                let received = rtts.len() as u64;
+                MeshPeer {
+                    name: peer.name.clone(),
+                    address: peer.address.clone(),
+                    sent,
+                    received,
+                    loss: if sent == 0 {
+                        0.0
+                    } else {
+                        (sent - received) as f64 * 100.0 / sent as f64
+                    },
+                    min: rtts.iter().copied().reduce(f64::min),
+                    avg: (!rtts.is_empty()).then(|| rtts.iter().sum::<f64>() / rtts.len() as f64),
+                    max: rtts.iter().copied().reduce(f64::max),
+                }
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,126 @@
+//! Tolerant parsing of the tasks the server sends, and the version of their format.
+//!
+//! A task is parsed into the struct of its handler as it comes first, unknown fields and
+//! nested objects are ignored by then already. When that fails, the top-level fields whose
+//! JSON type is easily mixed up are converted, one combination after another: numbers sent
+//! as strings (`"42"`), whole floats (`42.0`) for integers, numbers for strings and
+//! `"true"` / `"false"` for booleans. A task still not fitting gets a task error saying so,
+//! as does a message that is no JSON object with a `message` at all.
+//!
+//! Basic Info advertises `PROTOCOL_VERSION` in `protocol`. A task may carry the version it
+//! was written for in `protocol`, one newer than the agent's is refused as unsupported
+//! rather than half understood.
+
+use miniserde::Deserialize;
+use miniserde::json::{self, Number, Object, Value};
+
+/// Version of the task format this agent understands
+pub const PROTOCOL_VERSION: u64 = 1;
+/// Parse attempts with converted fields before giving up
+const MAX_ATTEMPTS: usize = 256;
+
+/// Check the `protocol` a task was written for, if it says
+pub fn check_protocol(payload: &str) -> Result<(), String> {
+    let Ok(Value::Object(object)) = json::from_str::<Value>(payload) else {
+        return Ok(());
+    };
+    let protocol = match object.get("protocol") {
+        Some(Value::Number(Number::U64(protocol))) => *protocol,
+        Some(Value::String(protocol)) => protocol.trim().parse().unwrap_or(0),
+        _ => return Ok(()),
+    };
+    if protocol > PROTOCOL_VERSION {
+        return Err(format!(
+            "unsupported protocol version {protocol}, this agent speaks up to {PROTOCOL_VERSION}"
+        ));
+    }
+    Ok(())
+}
+
+/// What else a field may have been meant as
+fn alternatives(value: &Value) -> Vec<Value> {
+    match value {
+        Value::String(text) => {
+            let text = text.trim();
+            if let Ok(number) = text.parse::<u64>() {
+                vec![Value::Number(Number::U64(number))]
+            } else if let Ok(number) = text.parse::<i64>() {
+                vec![Value::Number(Number::I64(number))]
+            } else if let Ok(number) = text.parse::<f64>() {
+                vec![Value::Number(Number::F64(number))]
+            } else if let Ok(flag) = text.parse::<bool>() {
+                vec![Value::Bool(flag)]
+            } else {
+                Vec::new()
+            }
+        }
+        Value::Number(number) => {
+            let mut alternatives = Vec::new();
+            if let Number::F64(float) = number
+                && float.fract() == 0.0
+                && float.abs() < 2f64.powi(53)
+            {
+                alternatives.push(Value::Number(if *float >= 0.0 {
+                    Number::U64(*float as u64)
+                } else {
+                    Number::I64(*float as i64)
+                }));
+            }
+            alternatives.push(Value::String(match number {
+                Number::U64(number) => number.to_string(),
+                Number::I64(number) => number.to_string(),
+                Number::F64(number) => number.to_string(),
+            }));
+            alternatives
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Parse a task into `T`, converting the fields that do not fit when needed. `what` names
+/// the task in the error
+pub fn parse_task<T: Deserialize>(payload: &str, what: &str) -> Result<T, String> {
+    if let Ok(task) = json::from_str::<T>(payload) {
+        return Ok(task);
+    }
+    let Ok(Value::Object(object)) = json::from_str::<Value>(payload) else {
+        return Err(format!("Failed to parse {what}: not a JSON object"));
+    };
+
+    let fields = object
+        .iter()
+        .map(|(key, value)| (key.clone(), alternatives(value)))
+        .filter(|(_, alternatives)| !alternatives.is_empty())
+        .collect::<Vec<_>>();
+    // Every field at its original value or one of its alternatives, counted like digits
+    let mut choice = vec![0; fields.len()];
+    for _ in 1..MAX_ATTEMPTS {
+        let Some(position) = choice
+            .iter()
+            .zip(&fields)
+            .position(|(chosen, (_, alternatives))| *chosen < alternatives.len())
+        else {
+            break;
+        };
+        choice[position] += 1;
+        choice[..position].fill(0);
+
+        let mut converted = Object::new();
+        for (key, value) in &object {
+            let value = fields
+                .iter()
+                .zip(&choice)
+                .find(|((name, _), chosen)| name == key && **chosen > 0)
+                .map_or(value, |((_, alternatives), chosen)| {
+                    &alternatives[chosen - 1]
+                });
+            converted.insert(key.clone(), value.clone());
+        }
+        if let Ok(task) = json::from_str::<T>(&json::to_string(&Value::Object(converted))) {
+            return Ok(task);
+        }
+    }
+    Err(format!(
+        "Failed to parse {what}: missing fields or fields of the wrong type"
+    ))
+}
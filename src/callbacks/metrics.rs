@@ -0,0 +1,109 @@
+//! Per-callback run counts, durations and failures, shown by `ctl status`.
+//!
+//! With `--callback-stats-daily` the counts of the last 24 hours are also queued as a
+//! `[callback-stats]` event once a day, a node whose pings fail 30 % of the time stands
+//! out on the server.
+
+use crate::events::push_event;
+use miniserde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Clone)]
+struct Totals {
+    runs: u64,
+    failures: u64,
+    total: Duration,
+    max: Duration,
+}
+
+/// Since the start, by callback type
+static TOTALS: Mutex<BTreeMap<&'static str, Totals>> = Mutex::new(BTreeMap::new());
+/// Since the last daily event
+static DAILY: Mutex<BTreeMap<&'static str, Totals>> = Mutex::new(BTreeMap::new());
+
+#[derive(Serialize)]
+pub struct CallbackStats {
+    pub kind: String,
+    pub runs: u64,
+    pub failures: u64,
+    /// Share of the runs that failed, 0 to 1
+    pub failure_rate: f64,
+    pub avg_ms: u64,
+    pub max_ms: u64,
+}
+
+/// Count one run of a `kind` callback started at `started`
+pub fn record_callback(kind: &'static str, started: Instant, ok: bool) {
+    let elapsed = started.elapsed();
+    for totals in [&TOTALS, &DAILY] {
+        if let Ok(mut totals) = totals.lock() {
+            let totals = totals.entry(kind).or_default();
+            totals.runs += 1;
+            if !ok {
+                totals.failures += 1;
+            }
+            totals.total += elapsed;
+            totals.max = totals.max.max(elapsed);
+        }
+    }
+}
+
+fn stats(kind: &str, totals: &Totals) -> CallbackStats {
+    let runs = totals.runs.max(1);
+    CallbackStats {
+        kind: kind.to_string(),
+        runs: totals.runs,
+        failures: totals.failures,
+        failure_rate: totals.failures as f64 / runs as f64,
+        avg_ms: u64::try_from(totals.total.as_millis() / u128::from(runs)).unwrap_or(u64::MAX),
+        max_ms: u64::try_from(totals.max.as_millis()).unwrap_or(u64::MAX),
+    }
+}
+
+/// Every callback type run since the start
+pub fn callback_stats() -> Vec<CallbackStats> {
+    TOTALS.lock().map_or_else(
+        |_| Vec::new(),
+        |totals| {
+            totals
+                .iter()
+                .map(|(kind, totals)| stats(kind, totals))
+                .collect()
+        },
+    )
+}
+
+/// Queue the counts of the last day as an event every 24 hours, days without any
+/// callback are skipped
+pub fn spawn_daily_callback_stats() {
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(Duration::from_hours(24)).await;
+            let daily = DAILY.lock().map(|mut daily| std::mem::take(&mut *daily));
+            let Ok(daily) = daily else {
+                continue;
+            };
+            if daily.is_empty() {
+                continue;
+            }
+            let summary = daily
+                .iter()
+                .map(|(kind, totals)| {
+                    let stats = stats(kind, totals);
+                    format!(
+                        "{} {} runs, {} failed ({:.0}%), avg {} ms",
+                        stats.kind,
+                        stats.runs,
+                        stats.failures,
+                        stats.failure_rate * 100.0,
+                        stats.avg_ms
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("; ");
+            push_event(format!("[callback-stats] last 24 h: {summary}"));
+        }
+    });
+}
@@ -1,136 +1,339 @@
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::approval::approve_session;
+#[cfg(not(feature = "no-exec"))]
+use crate::callbacks::cron::schedule_cron;
+use crate::callbacks::dispatch::{Task, TaskKind, reply_error, task_header};
+#[cfg(not(feature = "no-exec"))]
 use crate::callbacks::exec::exec_command;
+#[cfg(not(feature = "metrics-only"))]
+use crate::callbacks::fetch::fetch_url;
+use crate::callbacks::mesh::set_mesh_peers;
+use crate::callbacks::message::{check_protocol, parse_task};
+use crate::callbacks::metrics::record_callback;
 use crate::callbacks::ping::ping_target;
-use crate::callbacks::pty::{get_pty_ws_link, handle_pty_session};
+use crate::callbacks::ping_schedule::schedule_ping;
+use crate::callbacks::policy::{parse_public_key, verify_command};
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::pty::{get_pty_ws_link, handle_pty_session, open_session, resume_session};
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::recording::SessionRecorder;
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::restricted_shell::allow_list;
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::session_stats::session_started;
+use crate::callbacks::speedtest::speed_test;
+use crate::capabilities::stripped;
 use crate::command_parser::Args;
-use crate::utils::{ConnectionUrls, connect_ws};
-use futures::stream::{SplitSink, SplitStream};
-use futures::{SinkExt, StreamExt};
-use log::{error, info};
-use miniserde::{Deserialize, Serialize, json};
+#[cfg(not(feature = "no-exec"))]
+use crate::command_parser::TerminalApproval;
+use crate::heartbeat::Heartbeat;
+use crate::reload::{Reload, request_reload};
+use crate::signing::sign_message;
+#[cfg(not(feature = "no-terminal"))]
+use crate::utils::connect_ws;
+use crate::utils::{ConnectionUrls, WsStream};
+use futures::{Sink, SinkExt, Stream, StreamExt, future};
+use log::info;
+use miniserde::json;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::net::TcpStream;
+#[cfg(not(feature = "no-terminal"))]
+use std::time::Duration;
+use std::time::Instant;
 use tokio::sync::Mutex;
-use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
 
+#[cfg(not(feature = "no-terminal"))]
+pub mod approval;
+#[cfg(not(feature = "no-exec"))]
+pub mod cron;
+pub mod dispatch;
+#[cfg(not(feature = "no-exec"))]
 pub mod exec;
+#[cfg(not(feature = "metrics-only"))]
+pub mod fetch;
+pub mod local_ping;
+pub mod mesh;
+pub mod message;
+pub mod metrics;
 pub mod ping;
+pub mod ping_schedule;
+pub mod policy;
+#[cfg(not(feature = "no-terminal"))]
 pub mod pty;
+#[cfg(not(feature = "no-terminal"))]
+pub mod recording;
+#[cfg(not(feature = "no-terminal"))]
+pub mod restricted_shell;
+#[cfg(not(feature = "no-terminal"))]
+pub mod session_stats;
+pub mod speedtest;
+#[cfg(not(feature = "no-terminal"))]
+pub mod terminal_user;
 
-#[derive(Serialize, Deserialize)]
-struct Msg {
-    message: String,
-}
+/// Messages from the server, over the Real-Time Info WebSocket or the HTTP fallback
+pub type Reader = Pin<Box<dyn Stream<Item = Result<Message, String>> + Send>>;
+/// Frames to the server, over the Real-Time Info WebSocket or the HTTP fallback
+pub type Writer = Pin<Box<dyn Sink<Message, Error = String> + Send>>;
+type LockedWriter = Arc<Mutex<Writer>>;
 
-type Reader = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
-type LockedWriter = Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>;
+/// Writer and reader of the Real-Time Info WebSocket
+pub fn split_ws(ws_stream: WebSocketStream<WsStream>) -> (Writer, Reader) {
+    let (write, read) = ws_stream.split();
+    (
+        Box::pin(
+            write
+                .sink_map_err(|e| e.to_string())
+                .with(|msg| future::ready(Ok(sign_message(msg)))),
+        ),
+        Box::pin(read.map(|msg| msg.map_err(|e| e.to_string()))),
+    )
+}
 
 pub async fn handle_callbacks(
     args: &Args,
     connection_urls: &ConnectionUrls,
     reader: &mut Reader,
     locked_writer: &LockedWriter,
+    heartbeat: &Heartbeat,
 ) -> () {
     while let Some(msg) = reader.next().await {
         let Ok(msg) = msg else {
             continue;
         };
+        heartbeat.seen();
 
+        // Pongs and the like carry no task
+        if !msg.is_text() && !msg.is_binary() {
+            continue;
+        }
         let Ok(utf8) = msg.into_text() else {
             continue;
         };
 
         info!("Received message from main server: {}", utf8.as_str());
 
-        let json: Msg = if let Ok(value) = json::from_str(utf8.as_str()) {
-            value
-        } else {
+        let Some((message, id)) = task_header(utf8.as_str()) else {
+            let error = "malformed message: not a JSON object with a `message`";
+            reply_error(locked_writer, "", None, error.to_string()).await;
+            continue;
+        };
+        let Some(kind) = TaskKind::parse(&message) else {
+            let error = if stripped(&message) {
+                "not built into this agent"
+            } else {
+                "unknown task type"
+            };
+            reply_error(locked_writer, &message, id, error.to_string()).await;
             continue;
         };
+        let task = Task::new(kind, id, utf8);
+
+        if let Err(e) = check_protocol(task.payload.as_str()) {
+            task.fail(locked_writer, e).await;
+            continue;
+        }
+
+        if let Some(public_key) = &args.command_public_key
+            && kind.signed()
+            && let Err(e) = parse_public_key(public_key)
+                .and_then(|key| verify_command(&key, kind.name(), task.payload.as_str()))
+        {
+            task.fail(locked_writer, format!("rejected: {e}")).await;
+            continue;
+        }
+
+        if let Err(e) = dispatch(task.clone(), args, connection_urls, locked_writer) {
+            task.fail(locked_writer, e).await;
+        }
+    }
+}
+
+/// Whether the options let the server run commands outside a terminal session
+#[cfg(not(feature = "no-exec"))]
+pub fn remote_commands(args: &Args) -> Result<(), String> {
+    if args.restricted_terminal() {
+        // Remote commands would go around the allow-list
+        return Err("the terminal is restricted".to_string());
+    }
+    if args.terminal_user.is_some() {
+        // Remote commands would run with the agent's privileges, not the terminal user's
+        return Err("the terminal runs as another user".to_string());
+    }
+    if !matches!(args.terminal_approval, TerminalApproval::None) {
+        // Commands run without a session that could be approved
+        return Err("terminal sessions need approval".to_string());
+    }
+    if !args.terminal {
+        return Err("the terminal feature is disabled".to_string());
+    }
+    Ok(())
+}
 
-        let utf8_cloned = utf8.clone();
-
-        match json.message.as_str() {
-            "exec" => {
-                if args.terminal {
-                    tokio::spawn({
-                        let utf8_cloned_for_exec = utf8_cloned.clone();
-                        let exec_callback_url = connection_urls.exec_callback.clone();
-                        let ignore_unsafe_cert = args.ignore_unsafe_cert;
-
-                        async move {
-                            if let Err(e) = exec_command(
-                                &utf8_cloned_for_exec,
-                                exec_callback_url,
-                                &ignore_unsafe_cert,
-                            )
-                            .await
-                            {
-                                error!("Exec Error: {e}");
-                            }
-                        }
-                    });
-                } else {
-                    error!("Terminal feature is disabled");
-                }
+/// Handle the task or start it in the background, `Err` when it is refused or fails right away
+fn dispatch(
+    task: Task,
+    args: &Args,
+    connection_urls: &ConnectionUrls,
+    locked_writer: &LockedWriter,
+) -> Result<(), String> {
+    #[cfg(feature = "metrics-only")]
+    let _ = connection_urls;
+    let payload = task.payload.clone();
+    match task.kind {
+        #[cfg(not(feature = "no-exec"))]
+        TaskKind::Exec => {
+            remote_commands(args)?;
+            let exec_callback_url = connection_urls.exec_callback.clone();
+            let ignore_unsafe_cert = args.ignore_unsafe_cert;
+            task.spawn(locked_writer, async move {
+                let started = Instant::now();
+                let result = exec_command(&payload, exec_callback_url, &ignore_unsafe_cert).await;
+                record_callback("exec", started, result.is_ok());
+                result.map(|()| None)
+            })
+        }
+
+        #[cfg(not(feature = "no-exec"))]
+        TaskKind::CronSchedule => {
+            remote_commands(args)?;
+            schedule_cron(payload.as_str())
+        }
+
+        #[cfg(not(feature = "no-exec"))]
+        TaskKind::CronCancel => schedule_cron(payload.as_str()),
+
+        TaskKind::Ping => task.spawn(locked_writer, async move {
+            let json_res = json::to_string(&ping_target(&payload).await?);
+            info!("Ping successful: {json_res}");
+            Ok(Some(json_res))
+        }),
+
+        TaskKind::Reload => {
+            let reload = parse_task::<Reload>(payload.as_str(), "reload message")?;
+            reload
+                .validate()
+                .map_err(|e| format!("Configuration not reloaded: {e}"))?;
+            request_reload(reload);
+            Ok(())
+        }
+
+        TaskKind::PingSchedule | TaskKind::PingCancel => {
+            schedule_ping(payload.as_str(), locked_writer)
+        }
+
+        TaskKind::MeshPeers => set_mesh_peers(payload.as_str()),
+
+        TaskKind::Speedtest => {
+            if !args.speedtest {
+                return Err("the speed test feature is disabled".to_string());
             }
+            let ignore_unsafe_cert = args.ignore_unsafe_cert;
+            task.spawn(locked_writer, async move {
+                let started = Instant::now();
+                let result = speed_test(&payload, ignore_unsafe_cert).await;
+                record_callback("speedtest", started, result.is_ok());
+                let json_res = json::to_string(&result?);
+                info!("Speed test finished: {json_res}");
+                Ok(Some(json_res))
+            })
+        }
 
-            "ping" => {
-                let locked_write_for_ping = locked_writer.clone();
-                tokio::spawn(async move {
-                    match ping_target(&utf8_cloned).await {
-                        Ok(json_res) => {
-                            let mut write = locked_write_for_ping.lock().await;
-                            info!("Ping successful: {}", json::to_string(&json_res));
-                            if let Err(e) = write
-                                .send(Message::Text(Utf8Bytes::from(json::to_string(&json_res))))
-                                .await
-                            {
-                                error!(
-                                    "Error occurred while pushing ping result, attempting to reconnect: {e}"
-                                );
-                            }
-                        }
-                        Err(err) => {
-                            error!("Ping Error: {err}");
-                        }
-                    }
-                });
+        #[cfg(not(feature = "metrics-only"))]
+        TaskKind::FetchUrl => {
+            if args.fetch_dir.is_empty() {
+                return Err("file fetching is disabled, no --fetch-dir configured".to_string());
             }
+            let fetch_dirs = args.fetch_dir.clone();
+            let ignore_unsafe_cert = args.ignore_unsafe_cert;
+            task.spawn(locked_writer, async move {
+                let started = Instant::now();
+                let result = fetch_url(&payload, fetch_dirs, ignore_unsafe_cert).await;
+                record_callback("fetch_url", started, result.is_ok());
+                let json_res = json::to_string(&result?);
+                info!("Fetch finished: {json_res}");
+                Ok(Some(json_res))
+            })
+        }
 
-            "terminal" => {
-                if args.terminal {
-                    let ws_terminal_url = connection_urls.clone().ws_terminal.clone();
-                    let args = args.clone();
-                    let utf8_cloned = utf8_cloned.clone();
-
-                    tokio::spawn(async move {
-                        let ws_url = match get_pty_ws_link(&utf8_cloned, &ws_terminal_url) {
-                            Ok(ws_url) => ws_url,
-                            Err(e) => {
-                                error!("Failed to get PTY WebSocket URL: {e}");
-                                return;
-                            }
-                        };
-
-                        let ws_stream =
-                            match connect_ws(&ws_url, args.tls, args.ignore_unsafe_cert).await {
-                                Ok(ws_stream) => ws_stream,
-                                Err(e) => {
-                                    error!("Failed to connect to PTY WebSocket: {e}");
-                                    return;
-                                }
-                            };
-
-                        if let Err(e) = handle_pty_session(ws_stream, &args.terminal_entry).await {
-                            error!("PTY WebSocket handling error: {e}");
-                        }
-                    });
-                } else {
-                    error!("Terminal feature is disabled");
-                }
+        #[cfg(not(feature = "no-terminal"))]
+        TaskKind::Terminal => {
+            if !args.terminal {
+                return Err("the terminal feature is disabled".to_string());
             }
-            _ => {}
+            let ws_terminal_url = connection_urls.ws_terminal.clone();
+            let args = args.clone();
+            task.spawn(locked_writer, async move {
+                let started = Instant::now();
+                let result = terminal_session(&payload, &ws_terminal_url, &args).await;
+                record_callback("terminal", started, result.is_ok());
+                result.map(|()| None)
+            })
         }
     }
 }
+
+#[cfg(not(feature = "no-terminal"))]
+async fn terminal_session(payload: &str, ws_terminal_url: &str, args: &Args) -> Result<(), String> {
+    let (ws_url, request_id) = get_pty_ws_link(payload, ws_terminal_url)
+        .map_err(|e| format!("Failed to get PTY WebSocket URL: {e}"))?;
+
+    approve_session(
+        payload,
+        &args.terminal_approval,
+        Duration::from_secs(args.terminal_approval_timeout),
+    )
+    .await
+    .map_err(|e| format!("Refused terminal session: {e}"))?;
+
+    let ws_stream = connect_ws(
+        &ws_url,
+        args.tls,
+        args.ignore_unsafe_cert,
+        args.ws_compression,
+    )
+    .await
+    .map_err(|e| format!("Failed to connect to PTY WebSocket: {e}"))?;
+
+    let session = if let Some(session) = resume_session(&request_id).await {
+        info!("Resuming terminal session {request_id}");
+        session
+    } else {
+        // Without its audit trail the session is not opened at all
+        let recorder = args
+            .terminal_record_dir
+            .as_deref()
+            .map(|dir| {
+                SessionRecorder::create(
+                    dir,
+                    args.terminal_record_max_files,
+                    &args.terminal_entry,
+                    args.terminal_record_input,
+                )
+            })
+            .transpose()
+            .map_err(|e| {
+                format!("Failed to start terminal recording, refusing the session: {e}")
+            })?;
+        let restricted = args
+            .restricted_terminal()
+            .then(|| allow_list(&args.terminal_allow));
+        open_session(
+            &args.terminal_entry,
+            args.terminal_user.as_deref(),
+            restricted.as_deref(),
+            recorder,
+        )?
+    };
+
+    let _session = session_started();
+    handle_pty_session(
+        ws_stream,
+        session,
+        request_id,
+        Duration::from_secs(args.terminal_resume_grace),
+    )
+    .await
+    .map_err(|e| format!("PTY WebSocket handling error: {e}"))
+}
@@ -1,3 +1,5 @@
+use crate::callbacks::message::parse_task;
+use crate::callbacks::metrics::record_callback;
 use icmp_socket::packet::WithEchoRequest;
 use icmp_socket::{
     IcmpSocket, IcmpSocket4, IcmpSocket6, Icmpv4Message, Icmpv4Packet, Icmpv6Message, Icmpv6Packet,
@@ -58,10 +60,38 @@ fn split_address(addr: &str) -> (String, u16) {
 }
 
 pub async fn ping_target(utf8_str: &str) -> Result<PingEventCallback, String> {
-    let ping_event: PingEvent =
-        miniserde::json::from_str(utf8_str).map_err(|_| "Failed to parse PingEvent".to_string())?;
+    let ping_event: PingEvent = parse_task(utf8_str, "PingEvent")?;
 
-    match ping_event.ping_type.as_str() {
+    ping_once(
+        &ping_event.ping_type,
+        &ping_event.ping_target,
+        ping_event.ping_task_id,
+    )
+    .await
+}
+
+/// Run a single probe, `value` is the RTT in ms or -1 when the target did not answer
+pub async fn ping_once(
+    ping_type: &str,
+    target: &str,
+    task_id: u64,
+) -> Result<PingEventCallback, String> {
+    let started = Instant::now();
+    let result = probe(ping_type, target, task_id).await;
+    let answered = result
+        .as_ref()
+        .is_ok_and(|result| result.value.is_some_and(|rtt| rtt >= 0));
+    record_callback("ping", started.into_std(), answered);
+    result
+}
+
+/// [`ping_once`] without counting it as a server callback
+pub async fn probe(
+    ping_type: &str,
+    target: &str,
+    task_id: u64,
+) -> Result<PingEventCallback, String> {
+    match ping_type {
         "icmp" => {
             #[cfg(not(target_os = "windows"))]
             if std::env::var("USER").unwrap_or_default() != "root" {
@@ -70,16 +100,25 @@ pub async fn ping_target(utf8_str: &str) -> Result<PingEventCallback, String> {
                 ));
             }
 
-            match get_ip_from_string(&ping_event.ping_target).await {
+            match get_ip_from_string(target).await {
                 Ok(ip) => {
-                    debug!("DNS resolution: {}: {}", ping_event.ping_target, ip);
+                    debug!("DNS resolution: {target}: {ip}");
                     match ip {
-                        IpAddr::V4(ip) => icmp_ipv4(ip, ping_event.ping_task_id),
-                        IpAddr::V6(ip) => icmp_ipv6(ip, ping_event.ping_task_id),
+                        // The raw socket blocks until the reply or the timeout
+                        IpAddr::V4(ip) => {
+                            tokio::task::spawn_blocking(move || icmp_ipv4(ip, task_id))
+                                .await
+                                .map_err(|e| e.to_string())?
+                        }
+                        IpAddr::V6(ip) => {
+                            tokio::task::spawn_blocking(move || icmp_ipv6(ip, task_id))
+                                .await
+                                .map_err(|e| e.to_string())?
+                        }
                     }
                 }
                 Err(e) => {
-                    warn!("DNS resolution failed: {}: {}", ping_event.ping_target, e);
+                    warn!("DNS resolution failed: {target}: {e}");
                     Err(String::from("Failed to resolve IP address"))
                 }
             }
@@ -87,7 +126,7 @@ pub async fn ping_target(utf8_str: &str) -> Result<PingEventCallback, String> {
         "tcp" => {
             let start_time = Instant::now();
 
-            let (ip, port) = split_address(&ping_event.ping_target);
+            let (ip, port) = split_address(target);
 
             let ping = match tokio::time::timeout(
                 Duration::from_secs(10),
@@ -108,7 +147,7 @@ pub async fn ping_target(utf8_str: &str) -> Result<PingEventCallback, String> {
             if let Ok(()) = ping {
                 Ok(PingEventCallback {
                     type_str: String::from("ping_result"),
-                    task_id: ping_event.ping_task_id,
+                    task_id,
                     ping_type: String::from("tcp"),
                     value: i64::try_from(rtt.as_millis()).ok(),
                     finished_at,
@@ -116,7 +155,7 @@ pub async fn ping_target(utf8_str: &str) -> Result<PingEventCallback, String> {
             } else {
                 Ok(PingEventCallback {
                     type_str: String::from("ping_result"),
-                    task_id: ping_event.ping_task_id,
+                    task_id,
                     ping_type: String::from("tcp"),
                     value: Some(-1),
                     finished_at,
@@ -127,7 +166,7 @@ pub async fn ping_target(utf8_str: &str) -> Result<PingEventCallback, String> {
             let start_time = Instant::now();
 
             #[cfg(feature = "ureq-support")]
-            let result = ureq::get(&ping_event.ping_target) // Avoid cloning
+            let result = ureq::get(target) // Avoid cloning
                 .header("User-Agent", "curl/11.45.14")
                 .call()
                 .is_ok();
@@ -136,7 +175,7 @@ pub async fn ping_target(utf8_str: &str) -> Result<PingEventCallback, String> {
             let result = {
                 use nyquest::Request;
                 let client = crate::utils::create_nyquest_client(false);
-                let request = Request::get(ping_event.ping_target);
+                let request = Request::get(target.to_string());
                 client.request(request).is_ok()
             };
 
@@ -146,7 +185,7 @@ pub async fn ping_target(utf8_str: &str) -> Result<PingEventCallback, String> {
             if result {
                 Ok(PingEventCallback {
                     type_str: String::from("ping_result"),
-                    task_id: ping_event.ping_task_id,
+                    task_id,
                     ping_type: String::from("http"),
                     value: i64::try_from(start_time.elapsed().as_millis()).ok(),
                     finished_at,
@@ -154,14 +193,14 @@ pub async fn ping_target(utf8_str: &str) -> Result<PingEventCallback, String> {
             } else {
                 Ok(PingEventCallback {
                     type_str: String::from("ping_result"),
-                    task_id: ping_event.ping_task_id,
+                    task_id,
                     ping_type: String::from("http"),
                     value: Some(-1),
                     finished_at,
                 })
             }
         }
-        _ => Err(format!("Ping Error: Not Support: {}", ping_event.ping_type)),
+        _ => Err(format!("Ping Error: Not Support: {ping_type}")),
     }
 }
 
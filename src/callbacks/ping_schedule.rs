@@ -0,0 +1,167 @@
+//! Recurring ping tasks registered by the server.
+//!
+//! A `ping_schedule` message starts a task that sends `count` probes every `interval`
+//! seconds and reports latency, jitter and packet loss for each round. A task with the same
+//! id replaces the previous one, `ping_cancel` stops it. A task ends when its result can no
+//! longer be pushed, the server registers it again after a reconnect.
+
+use crate::callbacks::LockedWriter;
+use crate::callbacks::message::parse_task;
+use crate::callbacks::ping::ping_once;
+use futures::SinkExt;
+use log::{error, info, warn};
+use miniserde::{Deserialize, Serialize, json};
+use std::sync::Mutex;
+use std::time::Duration;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tokio::task::AbortHandle;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+
+/// Upper bound on probes per round, keeps a misconfigured task from flooding the target
+const MAX_COUNT: u64 = 100;
+/// Delay between two probes of the same round
+const PROBE_SPACING: Duration = Duration::from_secs(1);
+
+static TASKS: Mutex<Vec<(u64, AbortHandle)>> = Mutex::new(Vec::new());
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PingSchedule {
+    message: String,
+    ping_task_id: u64,
+    ping_type: Option<String>,
+    ping_target: Option<String>,
+    /// Seconds between two rounds
+    interval: Option<u64>,
+    /// Probes per round
+    count: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PingStatsCallback {
+    #[serde(rename = "type")]
+    pub type_str: String,
+    pub task_id: u64,
+    pub ping_type: String,
+    pub sent: u64,
+    pub received: u64,
+    /// Percentage of probes without an answer
+    pub loss: f64,
+    pub min: Option<i64>,
+    pub avg: Option<f64>,
+    pub max: Option<i64>,
+    /// Mean absolute difference between consecutive RTTs, in ms
+    pub jitter: Option<f64>,
+    pub finished_at: String,
+}
+
+impl PingStatsCallback {
+    fn from_rtts(task_id: u64, ping_type: &str, sent: u64, rtts: &[i64]) -> Self {
+        let received = rtts.len() as u64;
+        let avg = (!rtts.is_empty()).then(|| rtts.iter().sum::<i64>() as f64 / received as f64);
+        let jitter = (rtts.len() > 1).then(|| {
+            let diffs: i64 = rtts.windows(2).map(|w| (w[1] - w[0]).abs()).sum();
+            diffs as f64 / (rtts.len() - 1) as f64
+        });
+
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        Self {
+            type_str: String::from("ping_stats"),
+            task_id,
+            ping_type: ping_type.to_string(),
+            sent,
+            received,
+            loss: if sent == 0 {
+                0.0
+            } else {
+                (sent - received) as f64 * 100.0 / sent as f64
+            },
+            min: rtts.iter().min().copied(),
+            avg,
+            max: rtts.iter().max().copied(),
+            jitter,
+            finished_at: now.format(&Rfc3339).unwrap_or_default(),
+        }
+    }
+}
+
+fn stop(task_id: u64) -> bool {
+    let mut tasks = TASKS.lock().unwrap();
+    let before = tasks.len();
+    tasks.retain(|(id, handle)| {
+        let keep = *id != task_id;
+        if !keep {
+            handle.abort();
+        }
+        keep
+    });
+    tasks.len() != before
+}
+
+/// Handle `ping_schedule` and `ping_cancel` messages
+pub fn schedule_ping(utf8_str: &str, locked_writer: &LockedWriter) -> Result<(), String> {
+    let schedule: PingSchedule = parse_task(utf8_str, "PingSchedule")?;
+    let task_id = schedule.ping_task_id;
+
+    if schedule.message == "ping_cancel" {
+        if stop(task_id) {
+            info!("Recurring ping task {task_id} cancelled");
+        }
+        return Ok(());
+    }
+
+    let ping_type = schedule
+        .ping_type
+        .ok_or_else(|| "Recurring ping task without `ping_type`".to_string())?;
+    let target = schedule
+        .ping_target
+        .ok_or_else(|| "Recurring ping task without `ping_target`".to_string())?;
+    let count = schedule.count.unwrap_or(1).clamp(1, MAX_COUNT);
+    // A round has to fit in its interval
+    let interval = Duration::from_secs(schedule.interval.unwrap_or(60).max(2 * count));
+
+    stop(task_id);
+    info!(
+        "Recurring ping task {task_id}: {ping_type} {target}, {count} probes every {} s",
+        interval.as_secs()
+    );
+
+    let locked_writer = locked_writer.clone();
+    let handle = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+
+            let mut rtts = Vec::new();
+            for probe in 0..count {
+                if probe > 0 {
+                    tokio::time::sleep(PROBE_SPACING).await;
+                }
+                match ping_once(&ping_type, &target, task_id).await {
+                    Ok(result) => rtts.extend(result.value.filter(|rtt| *rtt >= 0)),
+                    Err(e) => {
+                        warn!("Recurring ping task {task_id} stopped: {e}");
+                        return;
+                    }
+                }
+            }
+
+            let stats = PingStatsCallback::from_rtts(task_id, &ping_type, count, &rtts);
+            let mut write = locked_writer.lock().await;
+            if let Err(e) = write
+                .send(Message::Text(Utf8Bytes::from(json::to_string(&stats))))
+                .await
+            {
+                error!("Recurring ping task {task_id} stopped, failed to push the result: {e}");
+                return;
+            }
+        }
+    });
+    let mut tasks = TASKS.lock().unwrap();
+    tasks.retain(|(_, handle)| !handle.is_finished());
+    tasks.push((task_id, handle.abort_handle()));
+
+    Ok(())
+}
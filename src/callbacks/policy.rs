@@ -0,0 +1,246 @@
+use crate::callbacks::message::parse_task;
+use crate::reload::Reload;
+use miniserde::{Deserialize, Serialize};
+use ring::signature::{ED25519, UnparsedPublicKey};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Signed commands issued longer ago are refused, their ids are remembered as long
+const MAX_AGE: Duration = Duration::from_mins(5);
+/// Clock difference tolerated for an `issued_at` in the future
+const MAX_SKEW: Duration = Duration::from_mins(1);
+/// Ids of the signed commands accepted within `MAX_AGE`
+const SEEN_LIMIT: usize = 4096;
+
+/// Accepted commands as `(issued_at, "<message> <id>")`
+static SEEN: Mutex<Vec<(u64, String)>> = Mutex::new(Vec::new());
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SignedExec {
+    task_id: String,
+    issued_at: Option<u64>,
+    command: String,
+    signature: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SignedCron {
+    task_id: String,
+    issued_at: Option<u64>,
+    command: String,
+    schedule: String,
+    signature: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SignedFetch {
+    task_id: String,
+    issued_at: Option<u64>,
+    url: String,
+    path: String,
+    sha256: Option<String>,
+    rate_limit: Option<u64>,
+    mode: Option<String>,
+    signature: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SignedReload {
+    task_id: String,
+    issued_at: Option<u64>,
+    signature: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SignedTerminal {
+    request_id: String,
+    issued_at: Option<u64>,
+    signature: Option<String>,
+}
+
+/// Parse a hex encoded Ed25519 public key, used at startup to reject malformed keys early
+pub fn parse_public_key(hex: &str) -> Result<[u8; 32], String> {
+    let bytes = decode_hex(hex)?;
+    <[u8; 32]>::try_from(bytes.as_slice())
+        .map_err(|_| format!("Ed25519 public key must be 32 bytes, got {}", bytes.len()))
+}
+
+/// The fields of a signed payload, each as its length in bytes, `:` and the field itself
+pub fn payload(fields: &[&str]) -> String {
+    fields.iter().fold(String::new(), |mut payload, field| {
+        let _ = write!(payload, "{}:{field}", field.len());
+        payload
+    })
+}
+
+fn single_line(name: &str, value: &str) -> Result<(), String> {
+    if value.contains('\n') {
+        return Err(format!("`{name}` must not contain a line break"));
+    }
+    Ok(())
+}
+
+/// Verify the `signature` field of a server-pushed command against the pinned public key.
+///
+/// The signed payload is `payload` of the message type and the identifying fields, with
+/// `issued_at` the Unix time in seconds the command was signed at:
+/// - exec: `exec`, `task_id`, `issued_at`, `command`
+/// - `cron_schedule`: `cron_schedule`, `task_id`, `issued_at`, `schedule`, `command`
+/// - terminal: `terminal`, `request_id`, `issued_at`
+/// - `fetch_url`: `fetch_url`, `task_id`, `issued_at`, `url`, `path`, `sha256`,
+///   `rate_limit`, `mode`, the optional ones empty when unset
+/// - reload: `reload`, `task_id`, `issued_at`, the settings given as `key=value` lines in
+///   this order: `realtime_info_interval`, `sample_interval`, `fake`, `log_level`,
+///   `net_filter_keywords`, `net_allow_interfaces`, `disk_allowed_fs`, `disk_exclude_paths`,
+///   numbers as JavaScript prints them
+///
+/// e.g. `4:exec2:t110:17000000002:id` for `exec` task `t1` running `id`. Commands issued
+/// more than 5 minutes ago and task or request ids already accepted within that time are
+/// refused, so a captured command cannot be replayed.
+pub fn verify_command(public_key: &[u8; 32], message: &str, utf8_str: &str) -> Result<(), String> {
+    let (id, issued_at, fields, signature) = match message {
+        "exec" => {
+            let exec: SignedExec = parse_task(utf8_str, "signed exec command")?;
+            (
+                exec.task_id,
+                exec.issued_at,
+                vec![exec.command],
+                exec.signature,
+            )
+        }
+        "cron_schedule" => {
+            let cron: SignedCron = parse_task(utf8_str, "signed cron_schedule command")?;
+            single_line("schedule", &cron.schedule)?;
+            (
+                cron.task_id,
+                cron.issued_at,
+                vec![cron.schedule, cron.command],
+                cron.signature,
+            )
+        }
+        "terminal" => {
+            let terminal: SignedTerminal = parse_task(utf8_str, "signed terminal request")?;
+            (
+                terminal.request_id,
+                terminal.issued_at,
+                Vec::new(),
+                terminal.signature,
+            )
+        }
+        "fetch_url" => {
+            let fetch: SignedFetch = parse_task(utf8_str, "signed fetch_url command")?;
+            (
+                fetch.task_id,
+                fetch.issued_at,
+                vec![
+                    fetch.url,
+                    fetch.path,
+                    fetch.sha256.unwrap_or_default(),
+                    fetch
+                        .rate_limit
+                        .map(|rate| rate.to_string())
+                        .unwrap_or_default(),
+                    fetch.mode.unwrap_or_default(),
+                ],
+                fetch.signature,
+            )
+        }
+        "reload" => {
+            let reload: Reload = parse_task(utf8_str, "signed reload message")?;
+            let signed: SignedReload = parse_task(utf8_str, "signed reload message")?;
+            (
+                signed.task_id,
+                signed.issued_at,
+                vec![reload.signed_settings()],
+                signed.signature,
+            )
+        }
+        other => return Err(format!("Message type '{other}' cannot be signed")),
+    };
+    let id_field = if message == "terminal" {
+        "request_id"
+    } else {
+        "task_id"
+    };
+    single_line(id_field, &id)?;
+    let issued_at =
+        issued_at.ok_or_else(|| format!("Signed {message} command without `issued_at`"))?;
+
+    let issued = issued_at.to_string();
+    let mut signed = vec![message, id.as_str(), issued.as_str()];
+    signed.extend(fields.iter().map(String::as_str));
+    verify_signature(public_key, message, &payload(&signed), signature.as_deref())?;
+    check_fresh(message, &id, issued_at)
+}
+
+/// Refuse commands outside the time window and the ones accepted before
+fn check_fresh(message: &str, id: &str, issued_at: u64) -> Result<(), String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    if issued_at > now + MAX_SKEW.as_secs() {
+        return Err(format!("{message} command issued in the future"));
+    }
+    if issued_at + MAX_AGE.as_secs() < now {
+        return Err(format!("{message} command expired"));
+    }
+
+    let key = format!("{message} {id}");
+    let mut seen = SEEN.lock().unwrap();
+    seen.retain(|(issued_at, _)| issued_at + MAX_AGE.as_secs() + MAX_SKEW.as_secs() >= now);
+    if seen.iter().any(|(_, seen)| *seen == key) {
+        return Err(format!("{message} command {id} already received"));
+    }
+    if seen.len() >= SEEN_LIMIT {
+        return Err(format!(
+            "Too many signed commands, {message} command refused"
+        ));
+    }
+    seen.push((issued_at, key));
+    Ok(())
+}
+
+/// The signed payload of a `cron_schedule` job, checked again whenever a saved job is loaded
+pub fn cron_payload(task_id: &str, issued_at: u64, schedule: &str, command: &str) -> String {
+    payload(&[
+        "cron_schedule",
+        task_id,
+        &issued_at.to_string(),
+        schedule,
+        command,
+    ])
+}
+
+/// Verify `signature` of the `message` command over `payload`
+pub fn verify_signature(
+    public_key: &[u8; 32],
+    message: &str,
+    payload: &str,
+    signature: Option<&str>,
+) -> Result<(), String> {
+    let Some(signature) = signature else {
+        return Err(format!("Unsigned {message} command rejected"));
+    };
+    let signature = decode_hex(signature)?;
+
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(payload.as_bytes(), &signature)
+        .map_err(|_| format!("Invalid signature on {message} command"))
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let hex = hex.trim();
+    if !hex.len().is_multiple_of(2) {
+        return Err("Hex string has an odd length".to_string());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("Invalid hex at position {i}"))
+        })
+        .collect()
+}
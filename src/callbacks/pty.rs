@@ -1,10 +1,16 @@
+use crate::callbacks::message::parse_task;
+use crate::callbacks::recording::SessionRecorder;
+use crate::callbacks::terminal_user::terminal_command;
 use futures::{SinkExt, StreamExt};
 use log::{error, info};
 use miniserde::{Deserialize, Serialize};
-use portable_pty::{CommandBuilder, NativePtySystem, PtySize, PtySystem};
+use portable_pty::{Child, MasterPty, NativePtySystem, PtySize, PtySystem};
+use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Write};
 use std::sync::{Arc, Mutex};
-use tokio::{sync::mpsc, task};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
 use tokio_tungstenite::tungstenite::Bytes;
 use tokio_tungstenite::{WebSocketStream, tungstenite::protocol::Message};
 
@@ -14,20 +20,51 @@ pub struct TerminalEvent {
     request_id: String,
 }
 
-pub fn get_pty_ws_link(utf8_str: &str, ws_terminal_url: &str) -> Result<String, String> {
-    let ping_event: TerminalEvent = miniserde::json::from_str(utf8_str)
-        .map_err(|_| "Failed to parse TerminalEvent".to_string())?;
+/// PTY WebSocket URL and `request_id` of a `terminal` message
+pub fn get_pty_ws_link(utf8_str: &str, ws_terminal_url: &str) -> Result<(String, String), String> {
+    let ping_event: TerminalEvent = parse_task(utf8_str, "TerminalEvent")?;
 
-    Ok(format!(
-        "{ws_terminal_url}&id={request_id}",
-        request_id = ping_event.request_id
+    Ok((
+        format!(
+            "{ws_terminal_url}&id={request_id}",
+            request_id = ping_event.request_id
+        ),
+        ping_event.request_id,
     ))
 }
 
-pub async fn handle_pty_session<S>(ws_stream: WebSocketStream<S>, cmd: &str) -> Result<(), String>
-where
-    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
-{
+/// Output kept for a detached session, the newest bytes
+const REPLAY_MAX: usize = 64 * 1024;
+
+/// A shell in its PTY, outliving the WebSocket it was opened for with
+/// `--terminal-resume-grace`
+pub struct PtySession {
+    master: Box<dyn MasterPty + Send>,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    child: Box<dyn Child + Send + Sync>,
+    output: mpsc::UnboundedReceiver<Vec<u8>>,
+    recorder: Option<Arc<Mutex<SessionRecorder>>>,
+    /// Output while detached, sent first when resumed
+    replay: VecDeque<u8>,
+}
+
+/// Detached sessions by `request_id`, a request for the session resumes it
+type Resume = oneshot::Sender<oneshot::Sender<PtySession>>;
+static DETACHED: Mutex<Option<HashMap<String, Resume>>> = Mutex::new(None);
+
+enum Ended {
+    /// The shell exited
+    Pty,
+    /// The WebSocket closed or broke, the shell still runs
+    WebSocket,
+}
+
+pub fn open_session(
+    cmd: &str,
+    terminal_user: Option<&str>,
+    restricted: Option<&[String]>,
+    recorder: Option<SessionRecorder>,
+) -> Result<PtySession, String> {
     let pty_system = NativePtySystem::default();
 
     let pair = pty_system
@@ -39,7 +76,7 @@ where
         })
         .map_err(|e| format!("Failed to create PTY: {e}"))?;
 
-    let mut cmd = CommandBuilder::new(cmd);
+    let mut cmd = terminal_command(cmd, terminal_user, restricted)?;
 
     if !cfg!(windows) {
         cmd.env("TERM", "xterm-256color");
@@ -51,29 +88,41 @@ where
         .master
         .try_clone_reader()
         .map_err(|e| format!("Failed to get PTY Reader: {e}"))?;
-    let pty_writer = Arc::new(Mutex::new(
+    let writer = Arc::new(Mutex::new(
         pair.master
             .take_writer()
             .map_err(|e| format!("Failed to get PTY Writer: {e}"))?,
     ));
 
-    let mut child = pair
+    let child = pair
         .slave
         .spawn_command(cmd)
         .map_err(|e| format!("Failed to spawn process: {e}"))?;
+    // Only the child holds the slave now, so the reader sees the end when it exits
+    drop(pair.slave);
 
     info!("Terminal started in PTY, PID: {:?}", child.process_id());
 
-    let (ws_sender, mut ws_receiver) = ws_stream.split();
-    let (pty_to_ws_tx, mut pty_to_ws_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    if let Some(recorder) = &recorder {
+        info!(
+            "Recording terminal session to {}",
+            recorder.path().display()
+        );
+    }
+    let recorder = recorder.map(|recorder| Arc::new(Mutex::new(recorder)));
+    let output_recorder = recorder.clone();
+    let (output_tx, output) = mpsc::unbounded_channel::<Vec<u8>>();
 
     task::spawn_blocking(move || {
         let mut buffer = [0u8; 8192];
         loop {
             match pty_reader.read(&mut buffer) {
                 Ok(count) if count > 0 => {
-                    if pty_to_ws_tx.send(buffer[..count].to_vec()).is_err() {
-                        info!("PTY reader: WebSocket side closed, stopping read.");
+                    if let Some(recorder) = &output_recorder {
+                        recorder.lock().unwrap().output(&buffer[..count]);
+                    }
+                    if output_tx.send(buffer[..count].to_vec()).is_err() {
+                        info!("PTY reader: session closed, stopping read.");
                         break;
                     }
                 }
@@ -85,63 +134,178 @@ where
         }
     });
 
-    let pty_to_ws_task = tokio::spawn(async move {
-        let mut ws_sender = ws_sender;
-        while let Some(data) = pty_to_ws_rx.recv().await {
-            if ws_sender
-                .send(Message::Binary(Bytes::from(data)))
-                .await
-                .is_err()
-            {
-                error!("Failed to send data to WebSocket");
-                break;
-            }
+    Ok(PtySession {
+        master: pair.master,
+        writer,
+        child,
+        output,
+        recorder,
+        replay: VecDeque::new(),
+    })
+}
+
+impl PtySession {
+    fn buffer(&mut self, data: &[u8]) {
+        self.replay.extend(data);
+        let excess = self.replay.len().saturating_sub(REPLAY_MAX);
+        self.replay.drain(..excess);
+    }
+
+    fn close(mut self) -> Result<(), String> {
+        info!("Closing session, terminating child process...");
+        if let Err(e) = self.child.kill() {
+            error!("Failed to terminate child process: {e}");
         }
-    });
+        self.child
+            .wait()
+            .map_err(|e| format!("Failed to wait for child process: {e}"))?;
+        info!("Session successfully closed.");
+        Ok(())
+    }
+}
 
-    let ws_to_pty_task = tokio::spawn(async move {
-        while let Some(result) = ws_receiver.next().await {
-            match result {
-                Ok(msg) => match handle_ws_message(msg, &pty_writer) {
-                    Err(e) => {
-                        error!("Failed to handle WebSocket message: {e}");
-                        break;
-                    }
-                    Ok(Some(resize)) => {
-                        if let Err(e) = pair.master.resize(PtySize {
-                            rows: resize.rows,
-                            cols: resize.cols,
-                            pixel_width: 0,
-                            pixel_height: 0,
-                        }) {
-                            error!("Failed to resize PTY: {e}");
+/// The detached session of `request_id`, if it is still waiting to be resumed
+pub async fn resume_session(request_id: &str) -> Option<PtySession> {
+    let resume = DETACHED
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|detached| detached.remove(request_id))?;
+    let (reply, session) = oneshot::channel();
+    resume.send(reply).ok()?;
+    session.await.ok()
+}
+
+/// Keep the shell of a dropped WebSocket running for `grace`, buffering its output
+fn detach(mut session: PtySession, request_id: String, grace: Duration) {
+    let (resume, mut requests) = oneshot::channel();
+    DETACHED
+        .lock()
+        .unwrap()
+        .get_or_insert_default()
+        .insert(request_id.clone(), resume);
+    info!(
+        "Terminal session {request_id} detached, resumable for {} s",
+        grace.as_secs()
+    );
+
+    tokio::spawn(async move {
+        let expired = tokio::time::sleep(grace);
+        tokio::pin!(expired);
+        loop {
+            tokio::select! {
+                request = &mut requests => {
+                    if let Ok(reply) = request {
+                        match reply.send(session) {
+                            Ok(()) => return,
+                            Err(unsent) => session = unsent,
                         }
                     }
-                    _ => {}
-                },
-                Err(e) => {
-                    error!("Error receiving message from WebSocket: {e}");
+                    break;
+                }
+                data = session.output.recv() => {
+                    let Some(data) = data else {
+                        info!("Terminal session {request_id} ended while detached");
+                        break;
+                    };
+                    session.buffer(&data);
+                }
+                () = &mut expired => {
+                    info!("Terminal session {request_id} not resumed in time");
                     break;
                 }
             }
         }
+        // Ours is the only closed one, another session may have been detached as well
+        requests.close();
+        if let Some(detached) = DETACHED.lock().unwrap().as_mut() {
+            detached.retain(|_, resume| !resume.is_closed());
+        }
+        if let Err(e) = session.close() {
+            error!("{e}");
+        }
     });
+}
 
-    tokio::select! {
-        _ = pty_to_ws_task => info!("PTY -> WebSocket task finished."),
-        _ = ws_to_pty_task => info!("WebSocket -> PTY task finished."),
-    }
+/// Run `session` over `ws_stream`. When the WebSocket goes away first the session is
+/// detached for `grace`, closed right away without one
+pub async fn handle_pty_session<S>(
+    ws_stream: WebSocketStream<S>,
+    mut session: PtySession,
+    request_id: String,
+    grace: Duration,
+) -> Result<(), String>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
-    info!("Closing session, terminating child process...");
-    if let Err(e) = child.kill() {
-        error!("Failed to terminate child process: {e}");
-    }
-    child
-        .wait()
-        .map_err(|e| format!("Failed to wait for child process: {e}"))?;
-    info!("Session successfully closed.");
+    let replay = Vec::from(std::mem::take(&mut session.replay));
+    let ended = if !replay.is_empty()
+        && ws_sender
+            .send(Message::Binary(Bytes::from(replay)))
+            .await
+            .is_err()
+    {
+        Ended::WebSocket
+    } else {
+        loop {
+            tokio::select! {
+                data = session.output.recv() => {
+                    let Some(data) = data else {
+                        info!("PTY -> WebSocket task finished.");
+                        break Ended::Pty;
+                    };
+                    if ws_sender
+                        .send(Message::Binary(Bytes::from(data)))
+                        .await
+                        .is_err()
+                    {
+                        error!("Failed to send data to WebSocket");
+                        break Ended::WebSocket;
+                    }
+                }
+                msg = ws_receiver.next() => {
+                    let msg = match msg {
+                        Some(Ok(msg)) => msg,
+                        Some(Err(e)) => {
+                            error!("Error receiving message from WebSocket: {e}");
+                            break Ended::WebSocket;
+                        }
+                        None => break Ended::WebSocket,
+                    };
+                    match handle_ws_message(msg, &session.writer, session.recorder.as_deref()) {
+                        Err(e) => {
+                            error!("Failed to handle WebSocket message: {e}");
+                            break Ended::WebSocket;
+                        }
+                        Ok(Some(resize)) => {
+                            if let Some(recorder) = &session.recorder {
+                                recorder.lock().unwrap().resize(resize.cols, resize.rows);
+                            }
+                            if let Err(e) = session.master.resize(PtySize {
+                                rows: resize.rows,
+                                cols: resize.cols,
+                                pixel_width: 0,
+                                pixel_height: 0,
+                            }) {
+                                error!("Failed to resize PTY: {e}");
+                            }
+                        }
+                        Ok(None) => {}
+                    }
+                }
+            }
+        }
+    };
 
-    Ok(())
+    match ended {
+        Ended::WebSocket if !grace.is_zero() => {
+            detach(session, request_id, grace);
+            Ok(())
+        }
+        _ => session.close(),
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -155,6 +319,7 @@ struct NeedResize {
 fn handle_ws_message(
     msg: Message,
     pty_writer: &Arc<Mutex<Box<dyn Write + Send>>>,
+    recorder: Option<&Mutex<SessionRecorder>>,
 ) -> Result<Option<NeedResize>, String> {
     #[derive(Serialize, Deserialize, Debug, Clone)]
     struct HeartBeat {
@@ -171,6 +336,9 @@ fn handle_ws_message(
             if let Ok(resize) = miniserde::json::from_str::<NeedResize>(text.as_ref()) {
                 return Ok(Some(resize));
             }
+            if let Some(recorder) = recorder {
+                recorder.lock().unwrap().input(text.as_bytes());
+            }
             pty_writer
                 .lock()
                 .unwrap()
@@ -178,6 +346,9 @@ fn handle_ws_message(
                 .map_err(|e| format!("Failed to write to PTY: {e}"))?;
         }
         Message::Binary(data) => {
+            if let Some(recorder) = recorder {
+                recorder.lock().unwrap().input(&data);
+            }
             pty_writer
                 .lock()
                 .unwrap()
@@ -0,0 +1,184 @@
+//! Terminal session recording (`--terminal-record-dir`) in asciicast v2 format, playable
+//! with `asciinema play`.
+//!
+//! Every session gets its own `komari-<unix ms>.cast` file, the oldest recordings are
+//! deleted once more than `--terminal-record-max-files` exist. Keystrokes are only kept
+//! with `--terminal-record-input` since they include passwords typed at prompts.
+
+use log::warn;
+use miniserde::{Serialize, json};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize)]
+struct CastEnv {
+    #[serde(rename = "TERM")]
+    term: String,
+    #[serde(rename = "SHELL")]
+    shell: String,
+}
+
+#[derive(Serialize)]
+struct CastHeader {
+    version: u8,
+    width: u16,
+    height: u16,
+    timestamp: u64,
+    env: CastEnv,
+}
+
+/// Bytes of an incomplete UTF-8 sequence at the end of a chunk, kept for the next one
+#[derive(Default)]
+struct Utf8Carry(Vec<u8>);
+
+impl Utf8Carry {
+    fn decode(&mut self, data: &[u8]) -> String {
+        self.0.extend_from_slice(data);
+        let valid = match std::str::from_utf8(&self.0) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            // Invalid bytes are replaced by `from_utf8_lossy`
+            _ => self.0.len(),
+        };
+        let text = String::from_utf8_lossy(&self.0[..valid]).to_string();
+        self.0.drain(..valid);
+        text
+    }
+}
+
+pub struct SessionRecorder {
+    file: File,
+    path: PathBuf,
+    started: Instant,
+    record_input: bool,
+    output: Utf8Carry,
+    input: Utf8Carry,
+}
+
+fn unix_now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis())
+}
+
+/// Remove the oldest recordings so that `keep` remain, before a new one is added
+fn rotate(dir: &Path, keep: usize) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut recordings = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("komari-"))
+                && path.extension().is_some_and(|ext| ext == "cast")
+        })
+        .collect::<Vec<_>>();
+    if recordings.len() <= keep {
+        return;
+    }
+    // Names hold the start time, sorting them sorts by age
+    recordings.sort();
+    for path in &recordings[..recordings.len() - keep] {
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!(
+                "Failed to remove old terminal recording {}: {e}",
+                path.display()
+            );
+        }
+    }
+}
+
+impl SessionRecorder {
+    pub fn create(
+        dir: &str,
+        max_files: usize,
+        shell: &str,
+        record_input: bool,
+    ) -> Result<Self, String> {
+        // Recordings may hold passwords typed in the session, only the agent's user reads them
+        let dir = Path::new(dir);
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(dir)
+            .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+        rotate(dir, max_files.max(1) - 1);
+
+        let path = dir.join(format!("komari-{}.cast", unix_now_ms()));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&path)
+            .map_err(|e| format!("Failed to create {}: {e}", path.display()))?;
+
+        let header = CastHeader {
+            version: 2,
+            width: 80,
+            height: 24,
+            timestamp: u64::try_from(unix_now_ms() / 1000).unwrap_or(0),
+            env: CastEnv {
+                term: String::from("xterm-256color"),
+                shell: shell.to_string(),
+            },
+        };
+        writeln!(file, "{}", json::to_string(&header))
+            .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+
+        Ok(Self {
+            file,
+            path,
+            started: Instant::now(),
+            record_input,
+            output: Utf8Carry::default(),
+            input: Utf8Carry::default(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn event(&mut self, code: &str, data: &str) {
+        let line = format!(
+            "[{:.6}, \"{code}\", {}]",
+            self.started.elapsed().as_secs_f64(),
+            json::to_string(data)
+        );
+        // Written unbuffered, the file stays complete even if the agent is killed
+        if let Err(e) = writeln!(self.file, "{line}") {
+            warn!(
+                "Failed to write terminal recording {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+
+    pub fn output(&mut self, data: &[u8]) {
+        let text = self.output.decode(data);
+        if !text.is_empty() {
+            self.event("o", &text);
+        }
+    }
+
+    pub fn input(&mut self, data: &[u8]) {
+        if !self.record_input {
+            return;
+        }
+        let text = self.input.decode(data);
+        if !text.is_empty() {
+            self.event("i", &text);
+        }
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.event("r", &format!("{cols}x{rows}"));
+    }
+}
@@ -0,0 +1,168 @@
+//! `--terminal-restricted`: the PTY runs a command filter instead of a shell.
+//!
+//! Like `--terminal-user`, the agent spawns itself inside the PTY, this time with the
+//! allow-list in its environment. Lines are split into words (quotes are honoured, nothing
+//! else), the command runs without a shell only if its words match an allow-list pattern,
+//! so pipes, redirections and `;` are plain arguments. In a pattern `*` matches anything,
+//! e.g. `systemctl status *`; the arguments it lets through are the administrator's call.
+
+use std::io::{BufRead, Write};
+
+pub const RESTRICTED_ENV: &str = "KOMARI_TERMINAL_RESTRICTED";
+
+/// Read-only diagnostics, used when no `--terminal-allow` is given
+const DEFAULT_ALLOW: &[&str] = &[
+    "uptime",
+    "w",
+    "who",
+    "free",
+    "free -h",
+    "df",
+    "df -h",
+    "top -b -n 1",
+    "ps aux",
+    "ip addr",
+    "ip link",
+    "ip route",
+    "ping *",
+    "systemctl status *",
+];
+
+pub fn allow_list(allow: &[String]) -> Vec<String> {
+    if allow.is_empty() {
+        DEFAULT_ALLOW.iter().map(ToString::to_string).collect()
+    } else {
+        allow.to_vec()
+    }
+}
+
+/// Split a line into words, `'` and `"` group words, no escapes or expansions
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            _ => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(String::from("unterminated quote"));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Glob match where `*` is the only wildcard
+fn matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(first) = parts.next() else {
+        return text.is_empty();
+    };
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+fn allowed(allow: &[String], words: &[String]) -> bool {
+    let line = words.join(" ");
+    allow.iter().any(|pattern| matches(pattern.trim(), &line))
+}
+
+fn run(words: &[String]) {
+    let mut command = std::process::Command::new(&words[0]);
+    // No pager: `!` in less would open a shell outside the allow-list
+    command
+        .args(&words[1..])
+        .env_remove(RESTRICTED_ENV)
+        .env("PAGER", "cat")
+        .env("SYSTEMD_PAGER", "cat")
+        .env("SYSTEMD_LESS", "")
+        .env("LESSSECURE", "1");
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // The filter ignores Ctrl-C, the command it starts must not
+        unsafe {
+            command.pre_exec(|| {
+                libc::signal(libc::SIGINT, libc::SIG_DFL);
+                Ok(())
+            });
+        }
+    }
+    match command.status() {
+        Ok(status) if !status.success() => println!("({status})"),
+        Ok(_) => {}
+        Err(e) => println!("{}: {e}", words[0]),
+    }
+}
+
+fn shell(allow: &[String]) {
+    let stdin = std::io::stdin();
+    println!("Restricted terminal, `help` lists the allowed commands, `exit` leaves");
+    loop {
+        print!("restricted> ");
+        let _ = std::io::stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let words = match split_words(&line) {
+            Ok(words) => words,
+            Err(e) => {
+                println!("{e}");
+                continue;
+            }
+        };
+
+        match words.first().map(String::as_str) {
+            None => {}
+            Some("exit" | "logout") => return,
+            Some("help") => {
+                for pattern in allow {
+                    println!("  {pattern}");
+                }
+            }
+            Some(_) if allowed(allow, &words) => run(&words),
+            Some(_) => println!("Not allowed: {}", words.join(" ")),
+        }
+    }
+}
+
+/// Called first in `main` after `exec_if_terminal_wrapper`, runs the filter and exits when
+/// this process was spawned by `terminal_command` in restricted mode, returns otherwise
+pub fn run_if_restricted_shell() {
+    if let Ok(allow) = std::env::var(RESTRICTED_ENV) {
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_IGN);
+        }
+        let allow = allow
+            .lines()
+            .filter(|pattern| !pattern.trim().is_empty())
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        shell(&allow);
+        std::process::exit(0);
+    }
+}
@@ -0,0 +1,72 @@
+//! Terminal session counters reported in the `terminal` field of Real-Time Info, so
+//! remote-shell activity shows up next to the other metrics.
+
+use crate::data_struct::TerminalStats;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Debug, Default)]
+struct SessionTotals {
+    sessions: u64,
+    /// Start of every session still open
+    active: Vec<Instant>,
+    /// Duration of the sessions that already ended
+    finished: Duration,
+    last_started: Option<OffsetDateTime>,
+}
+
+/// `None` until `--terminal` enables the counters
+static TOTALS: Mutex<Option<SessionTotals>> = Mutex::new(None);
+
+pub fn init_session_stats() {
+    if let Ok(mut totals) = TOTALS.lock() {
+        *totals = Some(SessionTotals::default());
+    }
+}
+
+/// Counts the session as active until dropped
+pub struct SessionGuard {
+    started: Instant,
+}
+
+pub fn session_started() -> SessionGuard {
+    let started = Instant::now();
+    if let Ok(mut guard) = TOTALS.lock()
+        && let Some(totals) = guard.as_mut()
+    {
+        totals.sessions += 1;
+        totals.active.push(started);
+        totals.last_started =
+            Some(OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc()));
+    }
+    SessionGuard { started }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        if let Ok(mut guard) = TOTALS.lock()
+            && let Some(totals) = guard.as_mut()
+            && let Some(index) = totals.active.iter().position(|s| *s == self.started)
+        {
+            totals.active.swap_remove(index);
+            totals.finished += self.started.elapsed();
+        }
+    }
+}
+
+pub fn realtime_session_stats() -> Option<TerminalStats> {
+    let guard = TOTALS.lock().ok()?;
+    let totals = guard.as_ref()?;
+
+    let open: Duration = totals.active.iter().map(Instant::elapsed).sum();
+    Some(TerminalStats {
+        sessions: totals.sessions,
+        active: totals.active.len() as u64,
+        total_duration: (totals.finished + open).as_secs(),
+        last_session: totals
+            .last_started
+            .and_then(|started| started.format(&Rfc3339).ok()),
+    })
+}
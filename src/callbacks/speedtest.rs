@@ -0,0 +1,320 @@
+//! On-demand throughput test triggered by the server (`speedtest` message, `--speedtest`).
+//!
+//! Against an HTTP URL the download streams the response body and the upload posts
+//! zeroes, each stopped once `duration` seconds have passed. With `iperf3` set the
+//! `iperf3` client is run instead, in reverse mode for the download.
+
+use crate::callbacks::message::parse_task;
+use crate::utils::timeouts;
+use log::info;
+use miniserde::{Deserialize, Serialize, json};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+const DEFAULT_DURATION: u64 = 10;
+/// Hard cap on each direction, whatever the server asks for
+const MAX_DURATION: u64 = 60;
+#[cfg(feature = "ureq-support")]
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpeedTestTask {
+    task_id: String,
+    download_url: Option<String>,
+    upload_url: Option<String>,
+    /// `host[:port]` of an iperf3 server, used instead of the URLs
+    iperf3: Option<String>,
+    /// Seconds per direction
+    duration: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpeedTestCallback {
+    #[serde(rename = "type")]
+    pub type_str: String,
+    pub task_id: String,
+    pub download_mbps: Option<f64>,
+    pub upload_mbps: Option<f64>,
+    pub bytes_down: u64,
+    pub bytes_up: u64,
+    pub error: Option<String>,
+    pub finished_at: String,
+}
+
+/// Bytes moved and the time it took
+#[derive(Debug, Clone, Copy, Default)]
+struct Transfer {
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl Transfer {
+    fn mbps(self) -> f64 {
+        self.bytes as f64 * 8.0 / self.elapsed.as_secs_f64().max(0.001) / 1_000_000.0
+    }
+}
+
+#[cfg(feature = "ureq-support")]
+fn agent(duration: Duration, ignore_unsafe_cert: bool) -> ureq::Agent {
+    use crate::get_info::network::own::counted_agent;
+    use ureq::unversioned::resolver::DefaultResolver;
+
+    let config = ureq::Agent::config_builder()
+        .tls_config(
+            ureq::tls::TlsConfig::builder()
+                .disable_verification(ignore_unsafe_cert)
+                .build(),
+        )
+        .timeout_connect(Some(timeouts().connect))
+        .timeout_global(Some(duration + timeouts().total()))
+        .build();
+    counted_agent(config, DefaultResolver::default())
+}
+
+#[cfg(feature = "ureq-support")]
+fn http_download(
+    url: &str,
+    duration: Duration,
+    ignore_unsafe_cert: bool,
+) -> Result<Transfer, String> {
+    use std::io::Read;
+
+    let mut response = agent(duration, ignore_unsafe_cert)
+        .get(url)
+        .header("User-Agent", "curl/11.45.14-rs")
+        .call()
+        .map_err(|e| format!("Download failed: {e}"))?;
+
+    let start = Instant::now();
+    let mut reader = response.body_mut().as_reader();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut bytes = 0;
+    while start.elapsed() < duration {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => bytes += n as u64,
+            // The global timeout cut a slow transfer, what arrived so far still counts
+            Err(_) if bytes > 0 => break,
+            Err(e) => return Err(format!("Download failed: {e}")),
+        }
+    }
+    Ok(Transfer {
+        bytes,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Endless zeroes until the deadline, counting what was read
+#[cfg(feature = "ureq-support")]
+struct ZeroReader {
+    deadline: Instant,
+    bytes: u64,
+}
+
+#[cfg(feature = "ureq-support")]
+impl std::io::Read for ZeroReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if Instant::now() >= self.deadline {
+            return Ok(0);
+        }
+        let n = buf.len().min(CHUNK_SIZE);
+        buf[..n].fill(0);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "ureq-support")]
+fn http_upload(
+    url: &str,
+    duration: Duration,
+    ignore_unsafe_cert: bool,
+) -> Result<Transfer, String> {
+    let start = Instant::now();
+    let mut reader = ZeroReader {
+        deadline: start + duration,
+        bytes: 0,
+    };
+    agent(duration, ignore_unsafe_cert)
+        .post(url)
+        .header("User-Agent", "curl/11.45.14-rs")
+        .header("Content-Type", "application/octet-stream")
+        .send(ureq::SendBody::from_reader(&mut reader))
+        .map_err(|e| format!("Upload failed: {e}"))?;
+    Ok(Transfer {
+        bytes: reader.bytes,
+        elapsed: start.elapsed(),
+    })
+}
+
+#[cfg(feature = "nyquest-support")]
+fn client(duration: Duration, ignore_unsafe_cert: bool) -> Result<nyquest::BlockingClient, String> {
+    let mut client = nyquest::ClientBuilder::default()
+        .request_timeout(duration + timeouts().total())
+        .user_agent("curl/8.7.1");
+    if ignore_unsafe_cert {
+        client = client.dangerously_ignore_certificate_errors();
+    }
+    client.build_blocking().map_err(|e| e.to_string())
+}
+
+/// nyquest has no streaming body without `blocking-stream`, the whole response is read and
+/// the request timeout enforces the duration cap
+#[cfg(feature = "nyquest-support")]
+fn http_download(
+    url: &str,
+    duration: Duration,
+    ignore_unsafe_cert: bool,
+) -> Result<Transfer, String> {
+    use nyquest::Request;
+
+    let start = Instant::now();
+    let bytes = client(duration, ignore_unsafe_cert)?
+        .request(Request::get(url.to_string()))
+        .and_then(nyquest::blocking::Response::bytes)
+        .map_err(|e| format!("Download failed: {e}"))?;
+    Ok(Transfer {
+        bytes: bytes.len() as u64,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Posts a fixed 16 MiB body, the rate only holds if it takes most of `duration`
+#[cfg(feature = "nyquest-support")]
+fn http_upload(
+    url: &str,
+    duration: Duration,
+    ignore_unsafe_cert: bool,
+) -> Result<Transfer, String> {
+    use nyquest::{Body, Request};
+
+    let payload = vec![0u8; 16 * 1024 * 1024];
+    let bytes = payload.len() as u64;
+    let start = Instant::now();
+    client(duration, ignore_unsafe_cert)?
+        .request(
+            Request::post(url.to_string())
+                .with_body(Body::bytes(payload, "application/octet-stream")),
+        )
+        .map_err(|e| format!("Upload failed: {e}"))?;
+    Ok(Transfer {
+        bytes,
+        elapsed: start.elapsed(),
+    })
+}
+
+#[derive(Deserialize)]
+struct IperfReport {
+    end: IperfEnd,
+}
+
+#[derive(Deserialize)]
+struct IperfEnd {
+    sum_received: Option<IperfSum>,
+}
+
+#[derive(Deserialize)]
+struct IperfSum {
+    bytes: u64,
+    seconds: f64,
+}
+
+fn iperf3(endpoint: &str, duration: Duration, reverse: bool) -> Result<Transfer, String> {
+    let (host, port) = match endpoint.rsplit_once(':') {
+        _ if endpoint.parse::<std::net::IpAddr>().is_ok() => (endpoint, "5201"),
+        Some((host, port)) if port.parse::<u16>().is_ok() => (host, port),
+        _ => (endpoint, "5201"),
+    };
+    let mut command = std::process::Command::new("iperf3");
+    command.args([
+        "-c",
+        host.trim_matches(['[', ']']),
+        "-p",
+        port,
+        "-t",
+        &duration.as_secs().to_string(),
+        "-J",
+    ]);
+    if reverse {
+        command.arg("-R");
+    }
+
+    let output = command
+        .output()
+        .map_err(|e| format!("Failed to run iperf3: {e}"))?;
+    let report: IperfReport = json::from_str(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|_| "Failed to parse the iperf3 report".to_string())?;
+    let sum = report
+        .end
+        .sum_received
+        .ok_or_else(|| "iperf3 did not report a result".to_string())?;
+    Ok(Transfer {
+        bytes: sum.bytes,
+        elapsed: Duration::from_secs_f64(sum.seconds.max(0.0)),
+    })
+}
+
+fn run(task: &SpeedTestTask, ignore_unsafe_cert: bool) -> SpeedTestCallback {
+    let duration = Duration::from_secs(
+        task.duration
+            .unwrap_or(DEFAULT_DURATION)
+            .clamp(1, MAX_DURATION),
+    );
+
+    let mut errors = Vec::new();
+    let mut measure = |result: Option<Result<Transfer, String>>| match result? {
+        Ok(transfer) => Some(transfer),
+        Err(e) => {
+            errors.push(e);
+            None
+        }
+    };
+
+    let (download, upload) = if let Some(endpoint) = &task.iperf3 {
+        (
+            measure(Some(iperf3(endpoint, duration, true))),
+            measure(Some(iperf3(endpoint, duration, false))),
+        )
+    } else {
+        (
+            measure(
+                task.download_url
+                    .as_deref()
+                    .map(|url| http_download(url, duration, ignore_unsafe_cert)),
+            ),
+            measure(
+                task.upload_url
+                    .as_deref()
+                    .map(|url| http_upload(url, duration, ignore_unsafe_cert)),
+            ),
+        )
+    };
+
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    SpeedTestCallback {
+        type_str: String::from("speedtest_result"),
+        task_id: task.task_id.clone(),
+        download_mbps: download.map(Transfer::mbps),
+        upload_mbps: upload.map(Transfer::mbps),
+        bytes_down: download.map_or(0, |t| t.bytes),
+        bytes_up: upload.map_or(0, |t| t.bytes),
+        error: (!errors.is_empty()).then(|| errors.join("; ")),
+        finished_at: now.format(&Rfc3339).unwrap_or_default(),
+    }
+}
+
+pub async fn speed_test(
+    utf8_str: &str,
+    ignore_unsafe_cert: bool,
+) -> Result<SpeedTestCallback, String> {
+    let task: SpeedTestTask = parse_task(utf8_str, "SpeedTestTask")?;
+    if task.iperf3.is_none() && task.download_url.is_none() && task.upload_url.is_none() {
+        return Err("Speed test without a URL or iperf3 endpoint".to_string());
+    }
+
+    info!("Running speed test {}", task.task_id);
+    tokio::task::spawn_blocking(move || run(&task, ignore_unsafe_cert))
+        .await
+        .map_err(|e| e.to_string())
+}
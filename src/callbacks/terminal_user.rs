@@ -0,0 +1,163 @@
+//! `--terminal-user`: PTY shells run as an unprivileged user instead of the agent's own.
+//!
+//! portable-pty gives no hook between fork and exec, so on Unix the agent spawns itself
+//! inside the PTY with the user and the entry in its environment. That child drops its
+//! privileges in `exec_if_terminal_wrapper` and replaces itself with the shell.
+
+use crate::callbacks::restricted_shell::RESTRICTED_ENV;
+use portable_pty::CommandBuilder;
+
+#[cfg(unix)]
+const USER_ENV: &str = "KOMARI_TERMINAL_USER";
+#[cfg(unix)]
+const ENTRY_ENV: &str = "KOMARI_TERMINAL_ENTRY";
+
+#[cfg(unix)]
+struct Account {
+    name: std::ffi::CString,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    home: String,
+}
+
+#[cfg(unix)]
+fn lookup(user: &str) -> Result<Account, String> {
+    use std::ffi::{CStr, CString};
+
+    let name = CString::new(user).map_err(|_| format!("Invalid terminal user '{user}'"))?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let ret = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &raw mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &raw mut result,
+        )
+    };
+    if ret != 0 || result.is_null() {
+        return Err(format!("Terminal user '{user}' does not exist"));
+    }
+
+    let home = if passwd.pw_dir.is_null() {
+        String::from("/")
+    } else {
+        unsafe { CStr::from_ptr(passwd.pw_dir) }
+            .to_string_lossy()
+            .to_string()
+    };
+    Ok(Account {
+        name,
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+        home,
+    })
+}
+
+/// Fail at startup rather than on the first terminal request
+pub fn check_terminal_user(user: &str) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        lookup(user).map(|_| ())
+    }
+    #[cfg(not(unix))]
+    {
+        Err(format!(
+            "`--terminal-user {user}` is not supported on this platform"
+        ))
+    }
+}
+
+/// Command spawned in the PTY for `entry`, run as `user` when set. With an allow-list the
+/// agent itself is spawned as the restricted shell in place of `entry`.
+pub fn terminal_command(
+    entry: &str,
+    user: Option<&str>,
+    restricted: Option<&[String]>,
+) -> Result<CommandBuilder, String> {
+    let restricted_entry = match restricted {
+        Some(_) => Some(
+            std::env::current_exe()
+                .map_err(|e| format!("Failed to locate the agent executable: {e}"))?
+                .to_string_lossy()
+                .to_string(),
+        ),
+        None => None,
+    };
+    let entry = restricted_entry.as_deref().unwrap_or(entry);
+
+    let mut cmd = match user {
+        None => CommandBuilder::new(entry),
+        #[cfg(unix)]
+        Some(user) => {
+            let exe = std::env::current_exe()
+                .map_err(|e| format!("Failed to locate the agent executable: {e}"))?;
+            let mut cmd = CommandBuilder::new(exe);
+            cmd.env(USER_ENV, user);
+            cmd.env(ENTRY_ENV, entry);
+            cmd
+        }
+        // Starting a process as another user needs its password on Windows, a terminal with
+        // the agent's privileges is never opened in its place
+        #[cfg(not(unix))]
+        Some(user) => {
+            return Err(format!(
+                "`--terminal-user {user}` is not supported on this platform, refusing to start the terminal"
+            ));
+        }
+    };
+    if let Some(allow) = restricted {
+        cmd.env(RESTRICTED_ENV, allow.join("\n"));
+    }
+    Ok(cmd)
+}
+
+#[cfg(unix)]
+fn drop_and_exec(user: &str, entry: &str) -> String {
+    use std::os::unix::process::CommandExt;
+
+    let account = match lookup(user) {
+        Ok(account) => account,
+        Err(e) => return e,
+    };
+
+    unsafe {
+        if libc::initgroups(account.name.as_ptr(), account.gid as _) != 0 {
+            return format!("initgroups failed: {}", std::io::Error::last_os_error());
+        }
+        if libc::setgid(account.gid) != 0 {
+            return format!("setgid failed: {}", std::io::Error::last_os_error());
+        }
+        if libc::setuid(account.uid) != 0 {
+            return format!("setuid failed: {}", std::io::Error::last_os_error());
+        }
+        // Regaining root must be impossible now
+        if account.uid != 0 && libc::setuid(0) == 0 {
+            return String::from("Privileges could not be dropped");
+        }
+    }
+
+    let _ = std::env::set_current_dir(&account.home);
+    let error = std::process::Command::new(entry)
+        .env("HOME", &account.home)
+        .env("USER", user)
+        .env("LOGNAME", user)
+        .env_remove(USER_ENV)
+        .env_remove(ENTRY_ENV)
+        .exec();
+    format!("Failed to start {entry}: {error}")
+}
+
+/// Called first in `main`, turns this process into the shell when it was spawned by
+/// `terminal_command`, returns otherwise
+pub fn exec_if_terminal_wrapper() {
+    #[cfg(unix)]
+    if let Ok(user) = std::env::var(USER_ENV) {
+        let entry = std::env::var(ENTRY_ENV).unwrap_or_else(|_| String::from("sh"));
+        let error = drop_and_exec(&user, &entry);
+        eprintln!("Terminal: {error}");
+        std::process::exit(1);
+    }
+}
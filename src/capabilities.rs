@@ -0,0 +1,47 @@
+//! Remote control stripped from the binary at compile time, for compliance-sensitive
+//! deployments.
+//!
+//! `no-terminal` removes the PTY sessions with everything around them (restricted shell,
+//! terminal user, approval, recording), `no-exec` the one-shot remote commands and
+//! `metrics-only` both along with file fetching. The flags of a stripped capability are
+//! ignored with a warning and its tasks are answered with a `task_error`. Every Basic Info
+//! lists the task types the build handles in `capabilities`.
+
+use crate::callbacks::dispatch::KINDS;
+use crate::command_parser::Args;
+use log::warn;
+
+/// Task types removed from the build
+const STRIPPED: &[&str] = &[
+    #[cfg(feature = "no-exec")]
+    "exec",
+    #[cfg(feature = "no-exec")]
+    "cron_schedule",
+    #[cfg(feature = "no-exec")]
+    "cron_cancel",
+    #[cfg(feature = "metrics-only")]
+    "fetch_url",
+    #[cfg(feature = "no-terminal")]
+    "terminal",
+];
+
+/// Task types the build handles
+pub fn capabilities() -> Vec<String> {
+    KINDS.iter().map(|kind| kind.name().to_string()).collect()
+}
+
+pub fn stripped(task: &str) -> bool {
+    STRIPPED.contains(&task)
+}
+
+/// Turn off what the command line enables but the build lacks
+pub fn strip_args(args: &mut Args) {
+    if cfg!(feature = "no-terminal") && (args.terminal || args.terminal_totp_secret.is_some()) {
+        warn!("Built without the terminal (`no-terminal`), ignoring the terminal options");
+        args.terminal = false;
+    }
+    if cfg!(feature = "metrics-only") && !args.fetch_dir.is_empty() {
+        warn!("Built without file fetching (`metrics-only`), ignoring `--fetch-dir`");
+        args.fetch_dir.clear();
+    }
+}
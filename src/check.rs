@@ -0,0 +1,134 @@
+//! Enrollment diagnosis (`check` subcommand).
+//!
+//! Walks through what the agent does on start, one step after another: resolving the
+//! server addresses, the Real-Time Info WebSocket handshake and a Basic Info push with the
+//! configured token. Each step prints `[ OK ]` or `[FAIL]` with a hint at the likely cause,
+//! the first failure ends the check.
+
+use crate::auth::panel_headers;
+use crate::command_parser::IpProvider;
+use crate::data_struct::BasicInfo;
+use crate::get_info::collector::MetricsCollector;
+use crate::get_info::network::TrafficOffset;
+use crate::utils::{build_urls, connect_ws};
+use std::sync::Arc;
+use tokio::net::lookup_host;
+use url::Url;
+
+pub struct Check {
+    pub http_server: String,
+    pub ws_server: Option<String>,
+    pub token: String,
+    pub tls: bool,
+    pub ignore_unsafe_cert: bool,
+    pub ws_compression: bool,
+    pub fake: f64,
+    pub ip_provider: IpProvider,
+}
+
+fn ok(step: &str, detail: &str) {
+    println!("[ OK ] {step}: {detail}");
+}
+
+fn fail(step: &str, error: &str) -> bool {
+    println!("[FAIL] {step}: {error}");
+    if let Some(hint) = hint(error) {
+        println!("       {hint}");
+    }
+    false
+}
+
+/// The likely cause behind a connection or push error
+fn hint(error: &str) -> Option<&'static str> {
+    let error = error.to_lowercase();
+    let has = |needles: &[&str]| needles.iter().any(|needle| error.contains(needle));
+    if has(&["401", "403", "unauthorized", "forbidden"]) {
+        Some("The panel rejected the token, check `--token` against the node in the panel")
+    } else if has(&["404", "not found"]) {
+        Some("Endpoint not found, check that `--http-server` points at a Komari panel")
+    } else if has(&["certificate", "unknownissuer", "notvalidforname"]) {
+        Some("The server certificate is not trusted, pass its CA with `--ca-cert` or fix it")
+    } else if has(&["corrupt message", "invalidcontenttype"]) {
+        Some("The server does not speak TLS on this port, use `http://` or the HTTPS port")
+    } else if has(&["refused"]) {
+        Some("Nothing listens on this port, check the port and that the panel is running")
+    } else if has(&["timed out", "timeout"]) {
+        Some("No answer in time, check firewalls between this host and the panel")
+    } else if has(&["lookup", "resolve", "name or service", "dns"]) {
+        Some("The host name does not resolve, check its spelling and `/etc/resolv.conf`")
+    } else if has(&["unreachable"]) {
+        Some("No route to the panel, check the network configuration of this host")
+    } else {
+        None
+    }
+}
+
+/// Resolve the host of `url`, with the port the connection will use
+async fn resolve(url: &str) -> Result<String, String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL {url}: {e}"))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("URL {url} has no host"))?
+        .trim_matches(['[', ']']);
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs = lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Failed to resolve {host}: {e} (DNS)"))?
+        .map(|addr| addr.ip().to_string())
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(format!("Failed to resolve {host}: no addresses (DNS)"));
+    }
+    Ok(format!("{host} resolves to {}", addrs.join(", ")))
+}
+
+/// Run the checks, `true` when the agent can enroll with this configuration
+pub async fn check(check: Check) -> bool {
+    let urls = match build_urls(&check.http_server, check.ws_server.as_ref(), &check.token) {
+        Ok(urls) => urls,
+        Err(e) => return fail("Server address", &format!("Failed to parse: {e}")),
+    };
+    ok("Server address", &urls.basic_info);
+
+    for (step, url) in [
+        ("DNS (HTTP)", &urls.basic_info),
+        ("DNS (WebSocket)", &urls.ws_real_time),
+    ] {
+        match resolve(url).await {
+            Ok(detail) => ok(step, &detail),
+            Err(e) => return fail(step, &e),
+        }
+    }
+
+    match connect_ws(
+        &urls.ws_real_time,
+        check.tls,
+        check.ignore_unsafe_cert,
+        check.ws_compression,
+    )
+    .await
+    {
+        Ok(mut ws_stream) => {
+            let _ = ws_stream.close(None).await;
+            ok("WebSocket", "handshake accepted");
+        }
+        Err(e) => return fail("WebSocket", &e),
+    }
+
+    let collector = MetricsCollector::new(Arc::new(TrafficOffset::default()));
+    let basic_info = BasicInfo::build(&collector.sysinfo_sys, check.fake, &check.ip_provider).await;
+    let url = urls.basic_info.clone();
+    let ignore_unsafe_cert = check.ignore_unsafe_cert;
+    let pushed = tokio::task::spawn_blocking(move || {
+        basic_info.push(&url, &panel_headers(), true, ignore_unsafe_cert)
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Failed to push Basic Info: {e}")));
+    match pushed {
+        Ok(()) => ok("Basic Info", "accepted"),
+        Err(e) => return fail("Basic Info", &e),
+    }
+
+    println!("The agent can enroll with this configuration");
+    true
+}
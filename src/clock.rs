@@ -0,0 +1,298 @@
+//! Clock offset check (`--clock-check`).
+//!
+//! Every `--clock-check-interval` the local clock is compared with an NTP server (SNTP,
+//! RFC 4330) or, with `server`, with the `Date` header of the Komari server, good to about
+//! a second only. Real-Time Info carries the latest offset, drifting past
+//! `--clock-max-offset` and coming back is also queued as an event: a skewed clock breaks
+//! TLS certificates, JWT and TOTP codes long before anything else shows it.
+
+use crate::data_struct::ClockOffset;
+use crate::events::push_event;
+use crate::utils::timeouts;
+use log::{info, trace, warn};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::{Date, Month};
+use tokio::time::MissedTickBehavior;
+
+const NTP_PORT: u16 = 123;
+const NTP_PACKET_LEN: usize = 48;
+/// Seconds from 1900, the NTP epoch, to 1970
+const NTP_UNIX_OFFSET: f64 = 2_208_988_800.0;
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Debug, Clone)]
+pub enum ClockSource {
+    /// URL of the Komari server
+    Server(String),
+    /// `HOST[:PORT]` of an NTP server
+    Ntp(String),
+}
+
+impl ClockSource {
+    pub fn new(source: &str, http_server: &str) -> Self {
+        if source == "server" {
+            Self::Server(http_server.to_string())
+        } else {
+            Self::Ntp(source.to_string())
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::Server(_) => "server",
+            Self::Ntp(server) => server,
+        }
+    }
+}
+
+/// Seconds the local clock is ahead of the source and seconds the query took
+struct Measurement {
+    offset: f64,
+    delay: f64,
+}
+
+struct ClockState {
+    latest: Option<ClockOffset>,
+    /// Whether the offset was past the limit at the latest check
+    skewed: bool,
+}
+
+static CLOCK: Mutex<Option<ClockState>> = Mutex::new(None);
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since| since.as_secs_f64())
+}
+
+fn ntp_timestamp(unix: f64) -> [u8; 8] {
+    let ntp = unix + NTP_UNIX_OFFSET;
+    // The seconds wrap in 2036, the era is implied
+    let seconds = (ntp.trunc() as u64 & 0xffff_ffff) << 32;
+    let fraction = (ntp.fract() * 4_294_967_296.0) as u64;
+    (seconds | fraction).to_be_bytes()
+}
+
+fn from_ntp_timestamp(bytes: &[u8]) -> f64 {
+    let seconds = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let fraction = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    // RFC 4330: a clear top bit means the era starting in 2036
+    let seconds = if seconds & 0x8000_0000 == 0 {
+        f64::from(seconds) + 4_294_967_296.0
+    } else {
+        f64::from(seconds)
+    };
+    seconds - NTP_UNIX_OFFSET + f64::from(fraction) / 4_294_967_296.0
+}
+
+fn query_ntp(server: &str) -> Result<Measurement, String> {
+    let address = server
+        .to_socket_addrs()
+        .or_else(|_| (server, NTP_PORT).to_socket_addrs())
+        .map_err(|e| format!("Failed to resolve {server}: {e}"))?
+        .next()
+        .ok_or_else(|| format!("{server} resolves to no address"))?;
+    let socket = UdpSocket::bind(if address.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })
+    .map_err(|e| e.to_string())?;
+    socket
+        .set_read_timeout(Some(timeouts().request))
+        .map_err(|e| e.to_string())?;
+
+    let mut request = [0u8; NTP_PACKET_LEN];
+    // No leap indicator, version 4, client mode
+    request[0] = 0x23;
+    let sent_at = unix_now();
+    let transmit = ntp_timestamp(sent_at);
+    request[40..48].copy_from_slice(&transmit);
+    socket
+        .send_to(&request, address)
+        .map_err(|e| format!("Failed to query {server}: {e}"))?;
+
+    let mut response = [0u8; NTP_PACKET_LEN];
+    let (read, _) = socket
+        .recv_from(&mut response)
+        .map_err(|e| format!("No answer from {server}: {e}"))?;
+    let received_at = unix_now();
+    if read < NTP_PACKET_LEN || response[0] & 0x07 != 4 || response[24..32] != transmit {
+        return Err(format!("Unexpected answer from {server}"));
+    }
+    if response[1] == 0 {
+        return Err(format!("{server} refused the query (kiss-o'-death)"));
+    }
+
+    let server_received = from_ntp_timestamp(&response[32..40]);
+    let server_sent = from_ntp_timestamp(&response[40..48]);
+    Ok(Measurement {
+        offset: f64::midpoint(sent_at - server_received, received_at - server_sent),
+        delay: (received_at - sent_at) - (server_sent - server_received),
+    })
+}
+
+/// Unix time of an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+fn parse_http_date(date: &str) -> Option<i64> {
+    let mut parts = date.split_whitespace().skip(1);
+    let day = parts.next()?.parse::<u8>().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|name| *name == month)?;
+    let year = parts.next()?.parse::<i32>().ok()?;
+    let mut time = parts.next()?.split(':').map(|part| part.parse::<u8>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    let date = Date::from_calendar_date(year, Month::try_from(month as u8 + 1).ok()?, day).ok()?;
+    Some(
+        date.with_hms(hour, minute, second)
+            .ok()?
+            .assume_utc()
+            .unix_timestamp(),
+    )
+}
+
+#[cfg(feature = "ureq-support")]
+fn server_date(url: &str, ignore_unsafe_cert: bool) -> Result<String, String> {
+    let resp = crate::utils::create_ureq_agent(ignore_unsafe_cert)
+        .get(url)
+        .header("User-Agent", "curl/11.45.14-rs")
+        .config()
+        .http_status_as_error(false)
+        .build()
+        .call()
+        .map_err(|e| e.to_string())?;
+    resp.headers()
+        .get("date")
+        .and_then(|date| date.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| "The server sent no Date header".to_string())
+}
+
+#[cfg(feature = "nyquest-support")]
+fn server_date(url: &str, ignore_unsafe_cert: bool) -> Result<String, String> {
+    use nyquest::Request;
+    let client = crate::utils::create_nyquest_client(ignore_unsafe_cert);
+    let resp = client
+        .request(Request::get(url.to_string()))
+        .map_err(|e| e.to_string())?;
+    resp.get_header("date")
+        .ok()
+        .and_then(|dates| dates.into_iter().next())
+        .ok_or_else(|| "The server sent no Date header".to_string())
+}
+
+fn query_server(url: &str, ignore_unsafe_cert: bool) -> Result<Measurement, String> {
+    let sent_at = unix_now();
+    let date = server_date(url, ignore_unsafe_cert)?;
+    let received_at = unix_now();
+    let date = parse_http_date(&date).ok_or_else(|| format!("Invalid Date header `{date}`"))?;
+    // The header is cut to the second, the middle of that second is the best guess
+    Ok(Measurement {
+        offset: f64::midpoint(sent_at, received_at) - (date as f64 + 0.5),
+        delay: received_at - sent_at,
+    })
+}
+
+fn measure(source: &ClockSource, ignore_unsafe_cert: bool) -> Result<Measurement, String> {
+    match source {
+        ClockSource::Server(url) => query_server(url, ignore_unsafe_cert),
+        ClockSource::Ntp(server) => query_ntp(server),
+    }
+}
+
+/// Check the clock every `interval` until the process stops
+pub fn spawn_clock_check(
+    source: ClockSource,
+    interval: Duration,
+    max_offset_ms: u64,
+    ignore_unsafe_cert: bool,
+) {
+    if let Ok(mut state) = CLOCK.lock() {
+        *state = Some(ClockState {
+            latest: None,
+            skewed: false,
+        });
+    }
+    info!(
+        "Checking the clock against {} every {} s",
+        source.name(),
+        interval.as_secs()
+    );
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let query = source.clone();
+            let Ok(result) =
+                tokio::task::spawn_blocking(move || measure(&query, ignore_unsafe_cert)).await
+            else {
+                continue;
+            };
+            record(source.name(), result, max_offset_ms);
+        }
+    });
+}
+
+fn record(source: &str, result: Result<Measurement, String>, max_offset_ms: u64) {
+    let Ok(mut guard) = CLOCK.lock() else {
+        return;
+    };
+    let Some(state) = guard.as_mut() else {
+        return;
+    };
+
+    let measurement = match result {
+        Ok(measurement) => measurement,
+        Err(e) => {
+            warn!("Clock check against {source} failed: {e}");
+            state.latest = Some(ClockOffset {
+                source: source.to_string(),
+                offset: None,
+                delay: None,
+                error: Some(e),
+            });
+            return;
+        }
+    };
+    trace!(
+        "Clock offset to {source}: {:.3} s, delay {:.3} s",
+        measurement.offset, measurement.delay
+    );
+
+    let skewed = measurement.offset.abs() * 1000.0 > max_offset_ms as f64;
+    if skewed && !state.skewed {
+        let direction = if measurement.offset > 0.0 {
+            "ahead of"
+        } else {
+            "behind"
+        };
+        warn!(
+            "The local clock is {:.1} s {direction} {source}",
+            measurement.offset.abs()
+        );
+        push_event(format!(
+            "[clock] {:.1} s {direction} {source}",
+            measurement.offset.abs()
+        ));
+    } else if !skewed && state.skewed {
+        info!("The local clock is in sync with {source} again");
+        push_event(format!("[clock] in sync with {source} again"));
+    }
+    state.skewed = skewed;
+    state.latest = Some(ClockOffset {
+        source: source.to_string(),
+        offset: Some(measurement.offset * 1000.0),
+        delay: Some(measurement.delay * 1000.0),
+        error: None,
+    });
+}
+
+/// Result of the latest check, `None` without `--clock-check` or before the first one
+pub fn realtime_clock_offset() -> Option<ClockOffset> {
+    CLOCK.lock().ok()?.as_ref()?.latest.clone()
+}
@@ -1,6 +1,10 @@
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::restricted_shell::allow_list;
+use crate::update::RELEASE_URL;
 use log::{error, info};
-use palc::{Parser, ValueEnum};
+use palc::{Parser, Subcommand, ValueEnum};
 use miniserde::{Deserialize, Serialize};
+use std::ffi::OsString;
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::{env, fs};
@@ -25,10 +29,34 @@ pub struct Args {
     #[arg(short, long, allow_hyphen_values = true)]
     pub token: Option<String>,
 
+    /// Also Report To This Panel With Its Own Token, as TOKEN@URL (e.g. abc@https://panel.example.com), can be specified multiple times
+    #[arg(long, allow_hyphen_values = true)]
+    pub tenant: Vec<String>,
+
+    /// How The Token Reaches The Panel: query (?token=), bearer (Authorization Header) Or jwt (Signed, Short-Lived)
+    #[arg(long, value_enum, default_value_t = AuthMode::Query)]
+    pub auth_mode: AuthMode,
+
+    /// File Holding The HMAC Key Signing JWTs (auth-mode jwt)
+    #[arg(long)]
+    pub auth_jwt_key: Option<String>,
+
+    /// JWT Lifetime (s)
+    #[arg(long, default_value_t = 300)]
+    pub auth_jwt_ttl: u64,
+
+    /// Sign Every Frame And Result With HMAC-SHA256 Of The Token, Timestamp And Nonce, For Verifiers Behind TLS Termination
+    #[arg(long, default_value_t = false)]
+    pub sign_payloads: bool,
+
     /// Set Fake Multiplier
     #[arg(short, long, default_value_t = 1.0)]
     pub fake: f64,
 
+    /// Shape The Fake Data With Per-Metric Multipliers, Baselines, Jitter And Daily Patterns From This File
+    #[arg(long)]
+    pub fake_profile: Option<String>,
+
     /// Enable TLS (default disabled)
     #[arg(long, default_value_t = false)]
     pub tls: bool,
@@ -37,19 +65,83 @@ pub struct Args {
     #[arg(long, default_value_t = false)]
     pub ignore_unsafe_cert: bool,
 
-    /// Dry Run
-    #[arg(short, long, default_value_t = false)]
+    /// Extra Header Sent To The Panel As "Name: value", e.g. For An Access Proxy, can be specified multiple times
+    #[arg(long)]
+    pub header: Vec<String>,
+
+    /// PEM CA Bundle Trusted For The Server, e.g. A Private PKI (HTTP Requests Then Trust Only This Bundle)
+    #[arg(long)]
+    pub ca_cert: Option<String>,
+
+    /// Enable WebSocket permessage-deflate Compression (default disabled)
+    #[arg(long, default_value_t = false)]
+    pub ws_compression: bool,
+
+    /// Seconds Between WebSocket Pings
+    #[arg(long, default_value_t = 20)]
+    pub ws_ping_interval: u64,
+
+    /// Reconnect When Nothing is Received From the Server for this Many Seconds (0 = disabled)
+    #[arg(long, default_value_t = 60)]
+    pub ws_timeout: u64,
+
+    /// Fall Back to HTTP POST and Long-Polling After this Many Failed WebSocket Connections in a Row (0 = never)
+    #[arg(long, default_value_t = 5)]
+    pub http_fallback_after: u32,
+
+    /// Seconds Between WebSocket Attempts While on the HTTP Fallback
+    #[arg(long, default_value_t = 300)]
+    pub http_fallback_retry: u64,
+
+    /// Same as the `dry-run` subcommand, kept for existing command lines
+    #[arg(short, long, default_value_t = false, hide = true)]
     pub dry_run: bool,
 
     /// Set Log Level (Enable Debug or Trace for issue reporting)
     #[arg(long, default_value_t = log_level())]
     pub log_level: LogLevel,
 
+    /// Log Line Format, 'json' Writes One Object Per Line (timestamp, level, target, message)
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
     // Other
     /// Public IP Provider
     #[arg(long, default_value_t=ip_provider())]
     pub ip_provider: IpProvider,
 
+    /// Custom Public IPv4 Lookup URL Answering The Address As Plain Text Or JSON
+    #[arg(long)]
+    pub ip_provider_url_v4: Option<String>,
+
+    /// Custom Public IPv6 Lookup URL Answering The Address As Plain Text Or JSON
+    #[arg(long)]
+    pub ip_provider_url_v6: Option<String>,
+
+    /// Dotted Path Of The Address In A JSON Answer Of The Custom URLs, e.g. `ip`
+    #[arg(long)]
+    pub ip_provider_json_field: Option<String>,
+
+    /// Re-check The Public IP Every N Minutes And Re-push Basic Info On Change (0 = Disabled)
+    #[arg(long, default_value_t = 0)]
+    pub ip_recheck_interval: u64,
+
+    /// Report Country, Region and ASN of the Public IP in Basic Info (default disabled)
+    #[arg(long, default_value_t = false)]
+    pub geoip: bool,
+
+    /// `MaxMind` DB File (City, Country or ASN Edition) to Look the Public IP Up In, implies --geoip, can be specified multiple times
+    #[arg(long)]
+    pub geoip_mmdb: Vec<String>,
+
+    /// Hash Public IPs and Syslog Sender Addresses and Drop Syslog Hostnames in Everything Sent to the Server
+    #[arg(long, default_value_t = false)]
+    pub privacy_mode: bool,
+
+    /// Tag Sent With the Basic Info as KEY=VALUE (e.g. env=prod), can be specified multiple times
+    #[arg(long)]
+    pub label: Vec<String>,
+
     /// Enable Terminal (default disabled)
     #[arg(long, default_value_t = false)]
     pub terminal: bool,
@@ -58,14 +150,208 @@ pub struct Args {
     #[arg(long, default_value_t = terminal_entry())]
     pub terminal_entry: String,
 
+    /// Run Terminal Sessions As This Unprivileged User (Unix only), Remote Exec is Refused
+    #[arg(long)]
+    pub terminal_user: Option<String>,
+
+    /// Restricted Terminal: Only Commands Matching the Allow-List Run, Remote Exec is Refused
+    #[arg(long, default_value_t = false)]
+    pub terminal_restricted: bool,
+
+    #[doc = "Command Allowed in the Restricted Terminal, implies --terminal-restricted.
+    \t  `*` matches anything, e.g. \"systemctl status *\", can be specified multiple times.
+    \t  Without any, a read-only set of diagnostics (uptime, df, free, ps, ip, ping...) is allowed"]
+    #[arg(long)]
+    pub terminal_allow: Vec<String>,
+
+    /// Approve Each Terminal Session: local (over the control socket) / totp (code in the request), Remote Exec is Refused
+    #[arg(long, value_enum, default_value_t = TerminalApproval::None)]
+    pub terminal_approval: TerminalApproval,
+
+    /// Seconds a Terminal Session Waits for Local Approval
+    #[arg(long, default_value_t = 60)]
+    pub terminal_approval_timeout: u64,
+
+    /// Base32 TOTP Secret for --terminal-approval totp
+    #[arg(long)]
+    pub terminal_totp_secret: Option<String>,
+
+    /// Record Terminal Sessions (asciicast v2) Into This Directory
+    #[arg(long)]
+    pub terminal_record_dir: Option<String>,
+
+    /// Number of Terminal Recordings Kept, the Oldest Are Deleted
+    #[arg(long, default_value_t = 100)]
+    pub terminal_record_max_files: usize,
+
+    /// Also Record Keystrokes, Including Passwords Typed at Prompts
+    #[arg(long, default_value_t = false)]
+    pub terminal_record_input: bool,
+
+    /// Seconds a Terminal Keeps Running After Its WebSocket Drops, Resumed by a Request With the Same ID (0 = closed right away)
+    #[arg(long, default_value_t = 0)]
+    pub terminal_resume_grace: u64,
+
+    /// Allow Server-Triggered Speed Tests (HTTP or iperf3, default disabled)
+    #[arg(long, default_value_t = false)]
+    pub speedtest: bool,
+
+    /// Directory the Server May Download Files Into (`fetch_url`), can be specified multiple times
+    #[arg(long)]
+    pub fetch_dir: Vec<String>,
+
+    /// Ed25519 Public Key (hex) Required To Sign Exec / Terminal Commands
+    #[arg(long)]
+    pub command_public_key: Option<String>,
+
+    /// Wait For A Default Route And DNS Before The First Connection
+    #[arg(long, default_value_t = false)]
+    pub wait_for_network: bool,
+
+    /// Give Up Waiting For The Network After N Seconds
+    #[arg(long, default_value_t = 120)]
+    pub wait_for_network_timeout: u64,
+
+    /// Timeout For DNS, TCP, TLS And The WebSocket Handshake (s)
+    #[arg(long, default_value_t = 10)]
+    pub connect_timeout: u64,
+
+    /// Timeout For An HTTP Request Once Connected, e.g. Basic Info And IP Lookups (s)
+    #[arg(long, default_value_t = 5)]
+    pub request_timeout: u64,
+
+    /// Address Family Of The Connections To The Server: auto (resolver order) / ipv4 / ipv6
+    #[arg(long, value_enum, default_value_t = ConnectIpFamily::Auto)]
+    pub connect_ip_family: ConnectIpFamily,
+
+    /// Resolve Names Through This DNS Server Instead Of The System Resolver: IP[:PORT] Or An https:// DNS-over-HTTPS URL
+    #[arg(long)]
+    pub dns_server: Option<String>,
+
+    /// Pin A Host To An Address Without Any Lookup: HOST:IP, Can Be Specified Multiple Times
+    #[arg(long)]
+    pub resolve: Vec<String>,
+
+    /// Check The Server's Health Endpoint Every N Seconds, Degraded Mode While It Fails (0 = Disabled)
+    #[arg(long, default_value_t = 0)]
+    pub health_check_interval: u64,
+
+    /// Path Of The Server's Health Endpoint
+    #[arg(long, default_value_t = String::from("/ping"))]
+    pub health_path: String,
+
+    /// Set Real-Time Info Upload Interval In Degraded Mode (ms)
+    #[arg(long, default_value_t = 60000)]
+    pub degraded_interval: u64,
+
     /// Set Real-Time Info Upload Interval (ms)
     #[arg(long, default_value_t = 1000)]
     pub realtime_info_interval: u64,
 
+    /// Rebuild and Re-push Basic Info Every this Many Seconds (0 = Only on Connect and Changes)
+    #[arg(long, default_value_t = 3600)]
+    pub basic_info_interval: u64,
+
+    /// Sample Every N ms And Upload Min / Avg / Max Per Interval (0 = Disabled)
+    #[arg(long, default_value_t = 0)]
+    pub sample_interval: u64,
+
+    /// Re-read Intervals, Filters, Log Level And Fake Multiplier From This File On SIGHUP
+    #[arg(long)]
+    pub reload_file: Option<String>,
+
+    /// Quiet Hours With A Longer Upload Interval (HH:MM-HH:MM, local time)
+    #[arg(long)]
+    pub quiet_hours: Option<String>,
+
+    /// Set Real-Time Info Upload Interval During Quiet Hours (ms)
+    #[arg(long, default_value_t = 60000)]
+    pub quiet_hours_interval: u64,
+
+    /// CPU / RAM Usage (%) That Ends Quiet Hours Throttling Immediately
+    #[arg(long, default_value_t = 90.0)]
+    pub quiet_hours_wake_threshold: f64,
+
+    /// Upload Interval After A CPU / Load Spike Or A Firing Alert (ms, 0 = Disabled)
+    #[arg(long, default_value_t = 0)]
+    pub burst_interval: u64,
+
+    /// How Long Burst Mode Lasts After The Last Spike (s)
+    #[arg(long, default_value_t = 60)]
+    pub burst_duration: u64,
+
+    /// CPU Usage (%) That Starts Burst Mode
+    #[arg(long, default_value_t = 95.0)]
+    pub burst_cpu_threshold: f64,
+
+    /// Load1 / Load5 Ratio That Starts Burst Mode
+    #[arg(long, default_value_t = 2.0)]
+    pub burst_load_jump: f64,
+
+    /// Only Upload Fields That Changed Since The Last Frame (default disabled)
+    #[arg(long, default_value_t = false)]
+    pub delta_mode: bool,
+
+    /// Relative Change A Field Needs To Be Sent Again In Delta Mode
+    #[arg(long, default_value_t = 0.01)]
+    pub delta_epsilon: f64,
+
+    /// Upload A Full Frame Every N Seconds In Delta Mode
+    #[arg(long, default_value_t = 60)]
+    pub delta_keyframe_interval: u64,
+
+    /// Also POST A Real-Time Info Summary Over HTTP Every N Seconds, Even While The WebSocket Is Up (0 = Disabled)
+    #[arg(long, default_value_t = 0)]
+    pub http_summary_interval: u64,
+
+    /// Comma-Separated Fields Of The HTTP Summary (cpu, ram, swap, disk, load, network, connections, uptime, process)
+    #[arg(long, default_value_t = String::from("cpu,ram,disk,network,uptime"))]
+    pub http_summary_fields: String,
+
     /// Disable Windows Toast Notification (Only Windows)
     #[arg(long, default_value_t = false)]
     pub disable_toast_notify: bool,
 
+    /// Also Write Warnings And Errors To The Windows Event Log (Application) (Only Windows)
+    #[arg(long, default_value_t = false)]
+    pub event_log: bool,
+
+    /// Same as the `install` subcommand, kept for existing command lines
+    #[arg(long, default_value_t = false, hide = true)]
+    pub install_service: bool,
+
+    /// Same as the `uninstall` subcommand, kept for existing command lines
+    #[arg(long, default_value_t = false, hide = true)]
+    pub uninstall_service: bool,
+
+    /// Run Under Windows Service Control Manager (Set By `install`)
+    #[arg(long, default_value_t = false, hide = true)]
+    pub run_as_service: bool,
+
+    /// Detach And Run In The Background, For Init Systems Without systemd (Only Unix)
+    #[arg(long, default_value_t = false)]
+    pub daemon: bool,
+
+    /// Write The PID To This File, A Stale One Is Replaced (Only Unix)
+    #[arg(long)]
+    pub pid_file: Option<String>,
+
+    /// Append The Log To This File When Running As A Daemon (default /dev/null)
+    #[arg(long)]
+    pub log_file: Option<String>,
+
+    /// Nice Value Applied At Startup, Higher Is Lower Priority (use --nice=N)
+    #[arg(long)]
+    pub nice: Option<i32>,
+
+    /// CPUs The Agent May Run On, e.g. 0,2-3 (Only Linux)
+    #[arg(long)]
+    pub cpu_affinity: Option<String>,
+
+    /// Use The Idle IO Scheduling Class (Only Linux)
+    #[arg(long, default_value_t = false)]
+    pub idle_io: bool,
+
     // Network
     /// Disable Network Statistics
     #[arg(long, default_value_t = false)]
@@ -81,6 +367,42 @@ pub struct Args {
     #[arg(long)]
     pub network_save_path: Option<String>,
 
+    /// Network Statistics Storage, 'sqlite' Also Keeps Every Cycle And All Daily Interface Totals (Requires The sqlite-backend Feature)
+    #[arg(long, value_enum, default_value_t = NetworkSaveBackend::File)]
+    pub network_save_backend: NetworkSaveBackend,
+
+    /// Writable Directory For State Files When Their Usual Location Is Read-Only (default: `$STATE_DIRECTORY`, else /var/lib/komari-monitor)
+    #[arg(long)]
+    pub state_dir: Option<String>,
+
+    /// Listen On A Local Control Socket (status, reload, reset-traffic, push-basicinfo, history), Also Enabled By A systemd Socket Named "control"
+    #[arg(long, default_value_t = false)]
+    pub control_socket: bool,
+
+    /// Control Socket Path (Named Pipe On Windows), Also Used By `ctl`
+    #[arg(long, default_value_t = control_socket_path())]
+    pub control_socket_path: String,
+
+    /// File Remembering The Agent Version To Announce Upgrades (default: komari-version beside the Network Statistics file)
+    #[arg(long)]
+    pub version_file: Option<String>,
+
+    /// Start In Safe Mode After This Many Starts Within The Crash-Loop Window (0 = Disabled)
+    #[arg(long, default_value_t = 5)]
+    pub crash_loop_starts: u32,
+
+    /// Crash-Loop Window (s)
+    #[arg(long, default_value_t = 300)]
+    pub crash_loop_window: u64,
+
+    /// Restart The Agent When The Collection Loop Made No Progress For This Many Real-Time Info Intervals, At Least 3 Minutes (0 = Disabled)
+    #[arg(long, default_value_t = 60)]
+    pub stall_intervals: u32,
+
+    /// Queue A Daily Event With The Run Counts And Failure Rates Of Each Callback Type
+    #[arg(long, default_value_t = false)]
+    pub callback_stats_daily: bool,
+
     /// Network Statistics Save Interval (s)
     #[arg(long, default_value_t = 10)]
     pub network_interval: u32,
@@ -99,11 +421,407 @@ pub struct Args {
     pub traffic_period: TrafficPeriod,
 
     #[doc = "Network statistics reset day, for 'natural' mode only.
+    \t    For 'day', ignored, the cycle resets at midnight.
     \t    For 'week', accepts 1-7 (Mon-Sun) or names like 'mon', 'tue'.
     \t    For 'month', accepts a day number like 1-31.
     \t    For 'year', accepts a date in 'MM/DD' format, e.g., '12/31'."]
     #[arg(long, default_value_t = String::from("1"))]
     pub traffic_reset_day: String,
+
+    #[doc = "Additional traffic cycle with its own reset schedule, e.g. a daily cap.
+    \t  Format: NAME=PERIOD[:RESET], PERIOD is day / week / month / year,
+    \t  can be specified multiple times"]
+    #[arg(long)]
+    pub traffic_cycle: Vec<String>,
+
+    #[doc = "Interface group counted separately over the statistics cycle, e.g. wan=eth0,eth1:500G.
+    \t  Format: NAME=IFACE[,IFACE...][:QUOTA], the quota counts up and down together,
+    \t  can be specified multiple times"]
+    #[arg(long)]
+    pub traffic_group: Vec<String>,
+
+    /// Traffic Cap Of The Statistics Cycle, Up And Down Together, e.g. 500G (binary suffixes K / M / G / T)
+    #[arg(long)]
+    pub traffic_limit: Option<String>,
+
+    /// Action Once The Traffic Cap Is Reached
+    #[arg(long, value_enum, default_value_t = TrafficLimitAction::Notify)]
+    pub traffic_limit_action: TrafficLimitAction,
+
+    /// Command Run By The `command` Action, Usage Passed As `KOMARI_TRAFFIC_USED` / `KOMARI_TRAFFIC_LIMIT`
+    #[arg(long)]
+    pub traffic_limit_command: Option<String>,
+
+    /// Keep Per-Interface Daily Traffic For The Last N Days, Shown By `ctl history` (0 = Disabled)
+    #[arg(long, default_value_t = 0)]
+    pub interface_history: u32,
+
+    /// Take The Agent's Own Traffic (Panel Connections, IP Lookups, Speed Tests) Off The Statistics Cycle
+    #[arg(long, default_value_t = false)]
+    pub exclude_own_traffic: bool,
+
+    #[doc = "Comma separated filesystems counted in disk usage, replacing the defaults.
+    \t  Prefix with '+' to extend the defaults instead, e.g. +bcachefs,ceph"]
+    #[arg(long)]
+    pub disk_allowed_fs: Option<String>,
+
+    #[doc = "Comma separated mount point keywords excluded from disk usage, replacing the defaults.
+    \t  Prefix with '+' to extend the defaults instead, e.g. +/mnt/backup"]
+    #[arg(long)]
+    pub disk_exclude_paths: Option<String>,
+
+    /// How Used Memory Is Counted: 'available' Total - Available, 'used' Like `free` (Cache And Buffers Excluded), 'classical' Total - Free
+    #[arg(long, value_enum, default_value_t = MemMode::Available)]
+    pub mem_mode: MemMode,
+
+    /// Also Report Buffers And Cache Sizes With The Memory (Linux Only)
+    #[arg(long, default_value_t = false)]
+    pub mem_report_cache: bool,
+
+    /// Report tmpfs / ramfs Mounts Apart From The Disk Totals, e.g. To Watch /tmp Filling Up
+    #[arg(long, default_value_t = false)]
+    pub disk_report_tmpfs: bool,
+
+    /// Leave zram Devices Out Of The Swap Totals, Their Pages Are Compressed RAM (Linux Only)
+    #[arg(long, default_value_t = false)]
+    pub swap_exclude_zram: bool,
+
+    /// Report 0 Processes Instead Of Listing Them, For Hosts With A Huge Number Of Processes
+    #[arg(long, default_value_t = false)]
+    pub disable_process_count: bool,
+
+    /// Report 0 TCP / UDP Connections Instead Of Enumerating The Sockets Every Interval
+    #[arg(long, default_value_t = false)]
+    pub disable_connections: bool,
+
+    /// Report 0 Disk Usage And Stop Refreshing The Disks, e.g. With Slow Network Mounts
+    #[arg(long, default_value_t = false)]
+    pub disable_disk: bool,
+
+    /// Report 0 Load Average
+    #[arg(long, default_value_t = false)]
+    pub disable_load: bool,
+
+    #[doc = "Comma separated interface name keywords to exclude from network statistics.
+    \t  Default: br,cni,docker,podman,flannel,lo,veth,virbr,vmbr,tap,tun,fwln,fwpr"]
+    #[arg(long)]
+    pub net_filter_keywords: Option<String>,
+
+    /// Comma Separated Interface Names that are Always Monitored, overriding the filter
+    #[arg(long)]
+    pub net_allow_interfaces: Option<String>,
+
+    #[doc = "Account traffic to / from remote CIDRs separately (Linux nftables only).
+    \t  Format: NAME=CIDR[,CIDR...], can be specified multiple times"]
+    #[arg(long)]
+    pub traffic_account: Vec<String>,
+
+    /// Attribute Network Traffic to Processes with eBPF (Linux only, requires root)
+    #[arg(long, default_value_t = false)]
+    pub ebpf_process_network: bool,
+
+    /// Count TCP Connections with an eBPF Tracepoint Instead of a Netlink Dump per Sample (Linux only, requires root, counts every network namespace)
+    #[arg(long, default_value_t = false)]
+    pub ebpf_connections: bool,
+
+    /// Number of Processes Reported in the Top-N Section
+    #[arg(long, default_value_t = 5)]
+    pub top_processes: usize,
+
+    /// Report VPN Tunnel Health (wireguard via `wg show`, openvpn via its status file)
+    #[arg(long, default_value_t = false)]
+    pub vpn_stats: bool,
+
+    /// Path of the openvpn Status File (the `status` directive), implies --vpn-stats
+    #[arg(long)]
+    pub openvpn_status: Option<String>,
+
+    /// Report Upload / Download Split By IP Family (IPv4 / IPv6, Linux only)
+    #[arg(long, default_value_t = false)]
+    pub ip_family_stats: bool,
+
+    /// Report Hardware Temperatures (`LibreHardwareMonitor` / `OpenHardwareMonitor` Sensors Preferred on Windows)
+    #[arg(long, default_value_t = false)]
+    pub temperatures: bool,
+
+    /// Count CPU Thermal Throttle Events per Sample (Linux sysfs counters, Raspberry Pi firmware flags, Windows performance limit)
+    #[arg(long, default_value_t = false)]
+    pub thermal_throttle: bool,
+
+    /// Report Raspberry Pi-Class Board Readings: Chip Temperature, Clock, Core Voltage, Under-Voltage and Throttle Flags (Linux only)
+    #[arg(long, default_value_t = false)]
+    pub sbc_stats: bool,
+
+    /// Report SSID, Signal (dBm), Link Quality and Bitrate of the Wireless Interfaces (Linux via `iw`, Windows via `netsh`)
+    #[arg(long, default_value_t = false)]
+    pub wifi_stats: bool,
+
+    /// Report Battery And UPS Status (sysfs / Windows Batteries, NUT via `upsc`, apcupsd via `apcaccess`)
+    #[arg(long, default_value_t = false)]
+    pub power_stats: bool,
+
+    /// NUT UPS To Query As name[@host[:port]] Instead Of Every Local One, implies --power-stats, can be specified multiple times
+    #[arg(long)]
+    pub nut_ups: Vec<String>,
+
+    /// Report Whether Processes With This Name Are Running, can be specified multiple times
+    #[arg(long)]
+    pub watch_process: Vec<String>,
+
+    /// Report Whether This systemd / Windows Service Is Running, can be specified multiple times
+    #[arg(long)]
+    pub watch_service: Vec<String>,
+
+    /// Report CPU and Memory of this cgroup, as [NAME=]PATH below /sys/fs/cgroup (e.g. system.slice/nginx.service), can be specified multiple times (Linux only)
+    #[arg(long)]
+    pub cgroup: Vec<String>,
+
+    /// Skip Top Processes, VPN, Accounting and Temperatures While CPU or Memory Pressure (PSI avg10, %) is at Least This (0 = Disabled, Linux only)
+    #[arg(long, default_value_t = 0.0)]
+    pub shed_pressure: f64,
+
+    /// Answer Agent Mesh Probes On This UDP Address (e.g. 0.0.0.0:29999) And Probe The Peers The Server Distributes
+    #[arg(long)]
+    pub mesh_listen: Option<String>,
+
+    /// Ping this Target Continuously and Report RTT / Loss, as [NAME=][TYPE:]TARGET[@SECONDS] (TYPE: icmp / tcp / http, default icmp every 10 s), can be specified multiple times
+    #[arg(long)]
+    pub ping_target: Vec<String>,
+
+    /// Check this URL with an HTTP GET and Report Status Code / Response Time, as [NAME=]URL[@SECONDS] (default every 60 s), can be specified multiple times
+    #[arg(long)]
+    pub http_check: Vec<String>,
+
+    /// Compare the Clock With this NTP Server (HOST[:PORT]) or, With `server`, the Date Header of the Komari Server and Report the Offset
+    #[arg(long)]
+    pub clock_check: Option<String>,
+
+    /// Seconds Between Clock Checks
+    #[arg(long, default_value_t = 600)]
+    pub clock_check_interval: u64,
+
+    /// Queue an Event When the Clock Is Off By More Than this Many Milliseconds
+    #[arg(long, default_value_t = 2000)]
+    pub clock_max_offset: u64,
+
+    /// Listen for Syslog Messages on this UDP Address (e.g. 0.0.0.0:514) and Forward them as Events, Also Enabled By A systemd Socket Named "syslog"
+    #[arg(long)]
+    pub syslog_listen: Option<String>,
+
+    /// Lowest Syslog Severity to Forward (0 = emerg ... 7 = debug)
+    #[arg(long, default_value_t = 4)]
+    pub syslog_severity: u8,
+
+    /// Maximum Syslog Messages Forwarded per Second
+    #[arg(long, default_value_t = 10)]
+    pub syslog_rate_limit: u32,
+
+    /// Forward New Lines of this File as Events (e.g. /var/log/auth.log), can be specified multiple times
+    #[arg(long)]
+    pub tail_file: Vec<String>,
+
+    /// Only Forward Lines of --tail-file Containing this Text (e.g. "Failed password"), can be specified multiple times
+    #[arg(long)]
+    pub tail_match: Vec<String>,
+
+    /// Maximum Log Lines Forwarded per Second
+    #[arg(long, default_value_t = 10)]
+    pub tail_rate_limit: u32,
+
+    #[doc = "Local alert rule in METRIC>THRESHOLD[:DURATION] format, can be specified multiple times.
+    \t  METRIC: cpu / ram / swap / disk (%), up / down (bytes/s), traffic (cycle bytes)
+    \t  DURATION: seconds the threshold must be exceeded before firing, e.g. cpu>90:60"]
+    #[arg(long)]
+    pub alert: Vec<String>,
+
+    /// Alert Sampling Interval (ms)
+    #[arg(long, default_value_t = 5000)]
+    pub alert_interval: u64,
+
+    /// Webhook URL to POST Alert Events to
+    #[arg(long)]
+    pub alert_webhook: Option<String>,
+
+    /// Local Command to Run on Alert Events, details are passed as environment variables
+    #[arg(long)]
+    pub alert_command: Option<String>,
+
+    /// Record Samples Locally to this File (JSON lines), for the `export` Subcommand
+    #[arg(long)]
+    pub record_path: Option<String>,
+
+    /// Sample Record Interval (s)
+    #[arg(long, default_value_t = 60)]
+    pub record_interval: u64,
+
+    /// Rotate the Record File after this Size (MB)
+    #[arg(long, default_value_t = 64)]
+    pub record_max_size: u64,
+
+    /// Append Every Sample in the Influx Line Protocol to this File, or Send It to udp://HOST:PORT
+    #[arg(long)]
+    pub influx_export: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run the agent, the same as giving no subcommand
+    Run,
+
+    /// Print the devices that would be monitored, then exit
+    DryRun,
+
+    /// Check the connection to the panel and the token, then exit
+    Check,
+
+    /// Install a service running the agent with the preceding arguments (systemd or Windows)
+    Install {
+        /// Unit File Path (Only systemd)
+        #[arg(long, default_value_t = default_unit_path())]
+        unit_path: String,
+
+        /// Watchdog Timeout In The Unit (s), 0 to disable (Only systemd)
+        #[arg(long, default_value_t = 30)]
+        watchdog_sec: u64,
+    },
+
+    /// Remove the service set up by `install`
+    Uninstall {
+        /// Unit File Path (Only systemd)
+        #[arg(long, default_value_t = default_unit_path())]
+        unit_path: String,
+    },
+
+    /// Replace this executable by the latest release
+    Update {
+        /// Release Download URL, the Asset Name is Appended
+        #[arg(long, default_value_t = String::from(RELEASE_URL))]
+        url: String,
+
+        /// Release Asset, Detected For Linux `x86_64` / `aarch64` / `i686` And macOS `arm64`
+        #[arg(long)]
+        asset: Option<String>,
+
+        /// Expected SHA-256 Of The Asset (Hex), As Published With The Release
+        #[arg(long)]
+        sha256: String,
+    },
+
+    /// Send a command to the running agent over `--control-socket-path`
+    Ctl {
+        /// Command
+        #[arg(value_enum)]
+        command: CtlCommand,
+
+        /// Terminal Session ID, for approve / deny
+        id: Option<String>,
+    },
+
+    /// Dump locally recorded samples (see `--record-path`) to stdout
+    Export {
+        /// Start Time, Unix Timestamp or Local YYYY-MM-DD[THH:MM[:SS]]
+        #[arg(long)]
+        from: Option<String>,
+
+        /// End Time, Unix Timestamp or Local YYYY-MM-DD[THH:MM[:SS]]
+        #[arg(long)]
+        to: Option<String>,
+
+        /// Output Format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+    },
+
+    /// Time every collector, optional ones included, count its allocations and print a table
+    /// with a suggested Real-Time Info interval to stdout
+    Bench {
+        /// Calls Timed per Collector
+        #[arg(long, default_value_t = 100)]
+        iterations: u32,
+    },
+
+    /// Same as `bench`, kept for existing scripts
+    BenchCollectors {
+        /// Calls Timed per Collector
+        #[arg(long, default_value_t = 100)]
+        iterations: u32,
+    },
+
+    /// Load-test the panel with virtual agents using the tokens `<token>-1` to `<token>-N`
+    Simulate {
+        /// Number of Virtual Agents
+        #[arg(long, default_value_t = 10)]
+        agents: u32,
+
+        /// New Connections per Second
+        #[arg(long, default_value_t = 10)]
+        ramp_up: u32,
+    },
+
+    /// Stand in for the panel: accept an agent's reports and issue tasks to it, for
+    /// integration tests and protocol debugging
+    MockServer {
+        /// Listen Address
+        #[arg(long, default_value_t = String::from("127.0.0.1:8080"))]
+        listen: String,
+
+        /// Command Issued as an `exec` Task, can be specified multiple times
+        #[arg(long)]
+        exec: Vec<String>,
+
+        /// Ping Issued as a `ping` Task, e.g. tcp:example.com:443 (icmp / tcp / http), can be specified multiple times
+        #[arg(long)]
+        ping: Vec<String>,
+
+        /// Open a Terminal Session and Type this Input Into It
+        #[arg(long)]
+        terminal: Option<String>,
+
+        /// Stop after this Many Seconds, Exiting with 1 Unless Every Report and Task Result Came Without Error (0 = Run Until Stopped)
+        #[arg(long, default_value_t = 0)]
+        duration: u64,
+    },
+}
+
+fn default_unit_path() -> String {
+    String::from("/etc/systemd/system/komari-monitor-rs.service")
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum CtlCommand {
+    Status,
+    Reload,
+    ResetTraffic,
+    PushBasicinfo,
+    History,
+    Pending,
+    Approve,
+    Deny,
+}
+
+impl CtlCommand {
+    /// The line sent over the control socket
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CtlCommand::Status => "status",
+            CtlCommand::Reload => "reload",
+            CtlCommand::ResetTraffic => "reset-traffic",
+            CtlCommand::PushBasicinfo => "push-basicinfo",
+            CtlCommand::History => "history",
+            CtlCommand::Pending => "pending",
+            CtlCommand::Approve => "approve",
+            CtlCommand::Deny => "deny",
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -121,6 +839,9 @@ pub struct NetworkConfig {
 impl Args {
     pub fn par() -> Self {
         let mut args = Self::parse();
+        if args.command.is_none() {
+            args.command = args.legacy_command();
+        }
         if args.terminal_entry == "default" {
             args.terminal_entry = {
                 if cfg!(windows) {
@@ -134,11 +855,57 @@ impl Args {
         }
         args
     }
+
+    /// The arguments before the `install` subcommand, the ones a service runs the agent with.
+    /// The subcommand is told apart from an option value `install` by parsing up to it
+    pub fn service_arguments() -> Vec<OsString> {
+        let argv: Vec<OsString> = env::args_os().collect();
+        let end = (1..argv.len())
+            .find(|&i| {
+                argv[i] == "install"
+                    && Self::try_parse_from(&argv[..=i])
+                        .is_ok_and(|args| matches!(args.command, Some(Command::Install { .. })))
+            })
+            .unwrap_or(argv.len());
+        argv[1..end]
+            .iter()
+            .filter(|arg| *arg != "--install-service")
+            .cloned()
+            .collect()
+    }
+
+    /// The subcommand replacing `--dry-run`, `--install-service` or `--uninstall-service`
+    fn legacy_command(&self) -> Option<Command> {
+        if self.dry_run {
+            Some(Command::DryRun)
+        } else if self.install_service {
+            Some(Command::Install {
+                unit_path: default_unit_path(),
+                watchdog_sec: 30,
+            })
+        } else if self.uninstall_service {
+            Some(Command::Uninstall {
+                unit_path: default_unit_path(),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn restricted_terminal(&self) -> bool {
+        self.terminal_restricted || !self.terminal_allow.is_empty()
+    }
+
     pub fn network_config(&self) -> NetworkConfig {
+        let file_name = match self.network_save_backend {
+            NetworkSaveBackend::File => "komari-network.conf",
+            NetworkSaveBackend::Sqlite => "komari-network.db",
+        };
         let path = {
             if self.network_save_path.is_none() {
                 if cfg!(windows) {
-                    PathBuf::from(r"C:\komari-network.conf")
+                    PathBuf::from(r"C:\")
+                        .join(file_name)
                         .to_string_lossy()
                         .to_string()
                 } else {
@@ -153,7 +920,8 @@ impl Args {
                             .unwrap_or(999)
                             == 0;
                     let path = if is_root {
-                        PathBuf::from("/etc/komari-network.conf")
+                        PathBuf::from("/etc")
+                            .join(file_name)
                             .to_string_lossy()
                             .to_string()
                     } else {
@@ -165,7 +933,8 @@ impl Args {
                         });
 
                         PathBuf::from(home)
-                            .join(".config/komari-network.conf")
+                            .join(".config")
+                            .join(file_name)
                             .to_string_lossy()
                             .to_string()
                     };
@@ -220,10 +989,27 @@ impl Display for Args {
         if let Some(token) = &self.token {
             writeln!(f, "  Token: {}", token)?;
         }
+        match self.auth_mode {
+            AuthMode::Query => {}
+            AuthMode::Bearer => writeln!(f, "  Auth Mode: bearer")?,
+            AuthMode::Jwt => writeln!(f, "  Auth Mode: jwt, lifetime {} s", self.auth_jwt_ttl)?,
+        }
+        if self.sign_payloads {
+            writeln!(f, "  Signed Payloads: HMAC-SHA256")?;
+        }
+        for tenant in &self.tenant {
+            let server = tenant
+                .split_once('@')
+                .map_or(tenant.as_str(), |(_, server)| server);
+            writeln!(f, "  Tenant: {server}")?;
+        }
 
         if self.fake != 1.0 {
             writeln!(f, "  Fake Multiplier: {}", self.fake)?;
         }
+        if let Some(fake_profile) = &self.fake_profile {
+            writeln!(f, "  Fake Profile: {fake_profile}")?;
+        }
 
         if self.tls {
             writeln!(f, "  TLS Enabled: true")?;
@@ -233,16 +1019,160 @@ impl Display for Args {
             writeln!(f, "  Ignore Unsafe Certificates: true")?;
         }
 
-        if self.dry_run {
-            writeln!(f, "  Dry Run Mode: enabled")?;
+        if !self.header.is_empty() {
+            let names = self
+                .header
+                .iter()
+                .map(|header| header.split(':').next().unwrap_or_default().trim())
+                .collect::<Vec<_>>();
+            writeln!(f, "  Extra Headers: {}", names.join(", "))?;
+        }
+
+        if let Some(ca_cert) = &self.ca_cert {
+            writeln!(f, "  CA Certificate: {ca_cert}")?;
+        }
+
+        if self.ws_compression {
+            writeln!(f, "  WebSocket Compression: true")?;
+        }
+
+        if self.ws_timeout > 0 {
+            writeln!(
+                f,
+                "  WebSocket Keepalive: ping every {} s, timeout {} s",
+                self.ws_ping_interval, self.ws_timeout
+            )?;
+        }
+
+        if self.http_fallback_after > 0 {
+            writeln!(
+                f,
+                "  HTTP Fallback: after {} failed WebSocket connections, retrying every {} s",
+                self.http_fallback_after, self.http_fallback_retry
+            )?;
+        }
+
+        if let Some(command) = &self.command {
+            writeln!(f, "  Command: {command:?}")?;
         }
 
         writeln!(f, "  Log Level: {:?}", self.log_level)?;
+        writeln!(f, "  Log Format: {:?}", self.log_format)?;
+        if let Some(reload_file) = &self.reload_file {
+            writeln!(f, "  Reload File: {reload_file}")?;
+        }
         writeln!(f, "  IP Provider: {:?}", self.ip_provider)?;
+        if let Some(url) = &self.ip_provider_url_v4 {
+            writeln!(f, "  IPv4 Provider URL: {url}")?;
+        }
+        if let Some(url) = &self.ip_provider_url_v6 {
+            writeln!(f, "  IPv6 Provider URL: {url}")?;
+        }
+        if let Some(field) = &self.ip_provider_json_field {
+            writeln!(f, "  IP Provider JSON Field: {field}")?;
+        }
+        if self.ip_recheck_interval > 0 {
+            writeln!(
+                f,
+                "  IP Re-check Interval: {} min",
+                self.ip_recheck_interval
+            )?;
+        }
+        if self.geoip && self.geoip_mmdb.is_empty() {
+            writeln!(f, "  GeoIP: from the IP provider")?;
+        }
+        for mmdb in &self.geoip_mmdb {
+            writeln!(f, "  GeoIP Database: {mmdb}")?;
+        }
+        if self.privacy_mode {
+            writeln!(f, "  Privacy Mode: enabled")?;
+        }
+        for label in &self.label {
+            writeln!(f, "  Label: {label}")?;
+        }
 
         if self.terminal {
             writeln!(f, "  Terminal Enabled: true")?;
             writeln!(f, "  Terminal Entry: {}", self.terminal_entry)?;
+            if let Some(terminal_user) = &self.terminal_user {
+                writeln!(f, "  Terminal User: {terminal_user}")?;
+            }
+            #[cfg(not(feature = "no-terminal"))]
+            if self.restricted_terminal() {
+                writeln!(
+                    f,
+                    "  Terminal Restricted: {}",
+                    allow_list(&self.terminal_allow).join(", ")
+                )?;
+            }
+            match self.terminal_approval {
+                TerminalApproval::None => {}
+                TerminalApproval::Local => writeln!(
+                    f,
+                    "  Terminal Approval: local, {} s timeout",
+                    self.terminal_approval_timeout
+                )?,
+                TerminalApproval::Totp => writeln!(f, "  Terminal Approval: totp")?,
+            }
+            if let Some(record_dir) = &self.terminal_record_dir {
+                writeln!(
+                    f,
+                    "  Terminal Recording: {record_dir} (keep {}, input: {})",
+                    self.terminal_record_max_files, self.terminal_record_input
+                )?;
+            }
+            if self.terminal_resume_grace > 0 {
+                writeln!(
+                    f,
+                    "  Terminal Resume Grace: {} s",
+                    self.terminal_resume_grace
+                )?;
+            }
+        }
+
+        if self.speedtest {
+            writeln!(f, "  Speed Test Enabled: true")?;
+        }
+
+        for fetch_dir in &self.fetch_dir {
+            writeln!(f, "  Fetch Directory: {fetch_dir}")?;
+        }
+
+        if let Some(command_public_key) = &self.command_public_key {
+            writeln!(f, "  Command Public Key: {command_public_key}")?;
+        }
+
+        if self.wait_for_network {
+            writeln!(
+                f,
+                "  Wait For Network: up to {} s",
+                self.wait_for_network_timeout
+            )?;
+        }
+
+        writeln!(
+            f,
+            "  Timeouts: connect {} s, request {} s",
+            self.connect_timeout, self.request_timeout
+        )?;
+        match self.connect_ip_family {
+            ConnectIpFamily::Auto => {}
+            ConnectIpFamily::Ipv4 => writeln!(f, "  Connect IP Family: IPv4 only")?,
+            ConnectIpFamily::Ipv6 => writeln!(f, "  Connect IP Family: IPv6 only")?,
+        }
+        if let Some(dns_server) = &self.dns_server {
+            writeln!(f, "  DNS Server: {dns_server}")?;
+        }
+        for resolve in &self.resolve {
+            writeln!(f, "  Resolve: {resolve}")?;
+        }
+
+        if self.health_check_interval > 0 {
+            writeln!(
+                f,
+                "  Health Check: {} every {} s, degraded interval {} ms",
+                self.health_path, self.health_check_interval, self.degraded_interval
+            )?;
         }
 
         writeln!(
@@ -251,12 +1181,90 @@ impl Display for Args {
             self.realtime_info_interval
         )?;
 
+        if self.sample_interval > 0 {
+            writeln!(f, "  Sample Interval: {} ms", self.sample_interval)?;
+        }
+
+        if self.basic_info_interval > 0 {
+            writeln!(
+                f,
+                "  Basic Info Refresh: every {} s",
+                self.basic_info_interval
+            )?;
+        }
+
+        if let Some(quiet_hours) = &self.quiet_hours {
+            writeln!(f, "  Quiet Hours: {quiet_hours}")?;
+            writeln!(
+                f,
+                "    Interval: {} ms, Wake Threshold: {}%",
+                self.quiet_hours_interval, self.quiet_hours_wake_threshold
+            )?;
+        }
+
+        if self.burst_interval > 0 {
+            writeln!(
+                f,
+                "  Burst Mode: {} ms for {} s, CPU Threshold: {}%, Load Jump: {}x",
+                self.burst_interval,
+                self.burst_duration,
+                self.burst_cpu_threshold,
+                self.burst_load_jump
+            )?;
+        }
+
+        if self.delta_mode {
+            writeln!(
+                f,
+                "  Delta Mode: epsilon {}, keyframe every {} s",
+                self.delta_epsilon, self.delta_keyframe_interval
+            )?;
+        }
+
+        if self.http_summary_interval > 0 {
+            writeln!(
+                f,
+                "  HTTP Summary: every {} s, fields {}",
+                self.http_summary_interval, self.http_summary_fields
+            )?;
+        }
+
         writeln!(
             f,
             "  Disable Windows Toast Notify: {}",
             self.disable_toast_notify
         )?;
 
+        if self.run_as_service {
+            writeln!(f, "  Running As Windows Service: true")?;
+        }
+
+        if self.event_log {
+            writeln!(f, "  Windows Event Log: true")?;
+        }
+
+        if self.daemon {
+            writeln!(f, "  Daemon: true")?;
+        }
+        if let Some(pid_file) = &self.pid_file {
+            writeln!(f, "  PID File: {pid_file}")?;
+        }
+        if let Some(log_file) = &self.log_file {
+            writeln!(f, "  Log File: {log_file}")?;
+        }
+
+        if let Some(nice) = self.nice {
+            writeln!(f, "  Nice: {nice}")?;
+        }
+
+        if let Some(cpu_affinity) = &self.cpu_affinity {
+            writeln!(f, "  CPU Affinity: {cpu_affinity}")?;
+        }
+
+        if self.idle_io {
+            writeln!(f, "  Idle IO Priority: true")?;
+        }
+
         writeln!(
             f,
             "  Network Statistics: {}",
@@ -276,6 +1284,206 @@ impl Display for Args {
             } else {
                 writeln!(f, "    Save Path: auto-determined")?;
             }
+            writeln!(f, "    Save Backend: {:?}", self.network_save_backend)?;
+            for traffic_cycle in &self.traffic_cycle {
+                writeln!(f, "    Additional Cycle: {traffic_cycle}")?;
+            }
+            for traffic_group in &self.traffic_group {
+                writeln!(f, "    Interface Group: {traffic_group}")?;
+            }
+            if let Some(limit) = &self.traffic_limit {
+                writeln!(
+                    f,
+                    "    Traffic Limit: {limit}, action {:?}",
+                    self.traffic_limit_action
+                )?;
+            }
+            if self.interface_history > 0 {
+                writeln!(f, "    Interface History: {} days", self.interface_history)?;
+            }
+            if self.exclude_own_traffic {
+                writeln!(f, "    Own Traffic: excluded")?;
+            }
+        }
+
+        if let Some(allowed_fs) = &self.disk_allowed_fs {
+            writeln!(f, "  Disk Allowed Filesystems: {allowed_fs}")?;
+        }
+        if let Some(exclude_paths) = &self.disk_exclude_paths {
+            writeln!(f, "  Disk Excluded Paths: {exclude_paths}")?;
+        }
+        if self.disk_report_tmpfs {
+            writeln!(f, "  Disk: tmpfs reported separately")?;
+        }
+        if self.mem_mode != MemMode::Available {
+            writeln!(f, "  Memory Mode: {:?}", self.mem_mode)?;
+        }
+        if self.mem_report_cache {
+            writeln!(f, "  Memory Cache: reported")?;
+        }
+        if self.swap_exclude_zram {
+            writeln!(f, "  Swap: zram excluded")?;
+        }
+        if self.disable_process_count {
+            writeln!(f, "  Process Count: disabled")?;
+        }
+        if self.disable_connections {
+            writeln!(f, "  Connections: disabled")?;
+        }
+        if self.disable_disk {
+            writeln!(f, "  Disk: disabled")?;
+        }
+        if self.disable_load {
+            writeln!(f, "  Load: disabled")?;
+        }
+
+        if let Some(keywords) = &self.net_filter_keywords {
+            writeln!(f, "  Network Filter Keywords: {keywords}")?;
+        }
+        if let Some(allow) = &self.net_allow_interfaces {
+            writeln!(f, "  Network Allowed Interfaces: {allow}")?;
+        }
+
+        for traffic_account in &self.traffic_account {
+            writeln!(f, "  Traffic Account: {traffic_account}")?;
+        }
+
+        if self.vpn_stats || self.openvpn_status.is_some() {
+            writeln!(f, "  VPN Stats: enabled")?;
+            if let Some(status) = &self.openvpn_status {
+                writeln!(f, "    OpenVPN Status: {status}")?;
+            }
+        }
+
+        if self.ip_family_stats {
+            writeln!(f, "  IP Family Stats: enabled")?;
+        }
+
+        if self.temperatures {
+            writeln!(f, "  Temperatures: enabled")?;
+        }
+
+        if self.thermal_throttle {
+            writeln!(f, "  Thermal Throttle: enabled")?;
+        }
+
+        if self.sbc_stats {
+            writeln!(f, "  SBC Stats: enabled")?;
+        }
+
+        if self.wifi_stats {
+            writeln!(f, "  Wi-Fi Stats: enabled")?;
+        }
+
+        if self.power_stats || !self.nut_ups.is_empty() {
+            writeln!(f, "  Power Stats: enabled")?;
+            for ups in &self.nut_ups {
+                writeln!(f, "    NUT UPS: {ups}")?;
+            }
+        }
+
+        for process in &self.watch_process {
+            writeln!(f, "  Watched Process: {process}")?;
+        }
+        for service in &self.watch_service {
+            writeln!(f, "  Watched Service: {service}")?;
+        }
+        for cgroup in &self.cgroup {
+            writeln!(f, "  cgroup: {cgroup}")?;
+        }
+
+        if self.shed_pressure > 0.0 {
+            writeln!(f, "  Load Shedding: at {} % pressure", self.shed_pressure)?;
+        }
+
+        if let Some(listen) = &self.mesh_listen {
+            writeln!(f, "  Mesh Probes: {listen}")?;
+        }
+
+        for target in &self.ping_target {
+            writeln!(f, "  Ping Target: {target}")?;
+        }
+
+        for check in &self.http_check {
+            writeln!(f, "  HTTP Check: {check}")?;
+        }
+
+        if let Some(source) = &self.clock_check {
+            writeln!(
+                f,
+                "  Clock Check: {source} every {} s, event past {} ms",
+                self.clock_check_interval, self.clock_max_offset
+            )?;
+        }
+
+        if let Some(listen) = &self.syslog_listen {
+            writeln!(f, "  Syslog Receiver: {listen}")?;
+            writeln!(f, "    Severity: <= {}", self.syslog_severity)?;
+            writeln!(f, "    Rate Limit: {} /s", self.syslog_rate_limit)?;
+        }
+
+        if !self.tail_file.is_empty() {
+            writeln!(f, "  Log Forwarder: {}", self.tail_file.join(", "))?;
+            for pattern in &self.tail_match {
+                writeln!(f, "    Match: {pattern}")?;
+            }
+            writeln!(f, "    Rate Limit: {} /s", self.tail_rate_limit)?;
+        }
+
+        for alert in &self.alert {
+            writeln!(f, "  Alert: {alert}")?;
+        }
+        if !self.alert.is_empty() {
+            writeln!(f, "    Interval: {} ms", self.alert_interval)?;
+            if let Some(webhook) = &self.alert_webhook {
+                writeln!(f, "    Webhook: {webhook}")?;
+            }
+            if let Some(command) = &self.alert_command {
+                writeln!(f, "    Command: {command}")?;
+            }
+        }
+
+        if self.ebpf_process_network {
+            writeln!(f, "  eBPF Process Network: top {}", self.top_processes)?;
+        }
+
+        if self.ebpf_connections {
+            writeln!(f, "  eBPF Connection Counting: enabled")?;
+        }
+
+        if let Some(state_dir) = &self.state_dir {
+            writeln!(f, "  State Dir: {state_dir}")?;
+        }
+        if let Some(version_file) = &self.version_file {
+            writeln!(f, "  Version File: {version_file}")?;
+        }
+        if self.crash_loop_starts > 0 {
+            writeln!(
+                f,
+                "  Crash Loop: safe mode after {} starts within {} s",
+                self.crash_loop_starts, self.crash_loop_window
+            )?;
+        }
+        if self.stall_intervals > 0 {
+            writeln!(f, "  Stall Watchdog: {} intervals", self.stall_intervals)?;
+        }
+        if self.control_socket {
+            writeln!(f, "  Control Socket: {}", self.control_socket_path)?;
+        }
+        if self.callback_stats_daily {
+            writeln!(f, "  Callback Stats: daily event")?;
+        }
+
+        if let Some(record_path) = &self.record_path {
+            writeln!(f, "  Record Path: {record_path}")?;
+            writeln!(
+                f,
+                "    Interval: {} s, Max Size: {} MB",
+                self.record_interval, self.record_max_size
+            )?;
+        }
+        if let Some(influx_export) = &self.influx_export {
+            writeln!(f, "  InfluxDB Export: {influx_export}")?;
         }
 
         Ok(())
@@ -288,10 +1496,46 @@ fn terminal_entry() -> String {
     "default".to_string()
 }
 
+fn control_socket_path() -> String {
+    if cfg!(windows) {
+        r"\\.\pipe\komari-agent".to_string()
+    } else {
+        "/run/komari-agent.sock".to_string()
+    }
+}
+
 fn ip_provider() -> IpProvider {
     IpProvider::Ipinfo
 }
 
+#[derive(Debug, Clone, ValueEnum)]
+pub enum TrafficLimitAction {
+    Notify,
+    Command,
+    Shutdown,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum TerminalApproval {
+    None,
+    Local,
+    Totp,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum AuthMode {
+    Query,
+    Bearer,
+    Jwt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConnectIpFamily {
+    Auto,
+    Ipv4,
+    Ipv6,
+}
+
 #[derive(Debug, Clone, ValueEnum)]
 pub enum IpProvider {
     Cloudflare,
@@ -300,6 +1544,7 @@ pub enum IpProvider {
 
 #[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, PartialEq)]
 pub enum TrafficPeriod {
+    Day,
     Week,
     Month,
     Year,
@@ -310,6 +1555,19 @@ pub enum NetworkStatisticsMode {
     Natural,
     Fixed,
 }
+#[derive(ValueEnum, Debug, Clone, PartialEq)]
+pub enum MemMode {
+    Available,
+    Used,
+    Classical,
+}
+
+#[derive(ValueEnum, Debug, Clone, PartialEq)]
+pub enum NetworkSaveBackend {
+    File,
+    Sqlite,
+}
+
 fn network_statistics_mode() -> NetworkStatisticsMode {
     NetworkStatisticsMode::Fixed
 }
@@ -327,6 +1585,12 @@ pub enum LogLevel {
     Trace,
 }
 
+#[derive(Debug, Clone, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
 fn log_level() -> LogLevel {
     LogLevel::Info
 }
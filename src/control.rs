@@ -0,0 +1,315 @@
+//! Local control socket (`--control-socket`) and the `ctl` subcommand.
+//!
+//! A client connects to the Unix socket, or the named pipe on Windows, writes one command line and reads one reply line:
+//! `status` (JSON), `reload` (re-read `--reload-file`), `reset-traffic` (start a new
+//! network statistics cycle), `push-basicinfo` (rebuild and push Basic Info) or `history`
+//! (per-interface daily traffic as JSON, see `--interface-history`), `pending` (terminal
+//! sessions waiting for `--terminal-approval local`, JSON), `approve <id>` or `deny <id>`.
+//! Replies start with `ok` or `error`, the JSON replies aside. The socket is only accessible to
+//! the user the agent runs as.
+
+use crate::callbacks::metrics::{CallbackStats, callback_stats};
+use crate::health::ServerHealth;
+use log::warn;
+use miniserde::{Serialize, json};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+static PUSHED: AtomicU64 = AtomicU64::new(0);
+/// Unix timestamp (s) of the last Real-Time Info sent, 0 before the first one
+static LAST_PUSH: AtomicU64 = AtomicU64::new(0);
+static PUSH_BASIC_INFO: AtomicBool = AtomicBool::new(false);
+
+pub fn set_connected(connected: bool) {
+    CONNECTED.store(connected, Ordering::Relaxed);
+}
+
+/// Count a Real-Time Info sent to the server
+pub fn record_push() {
+    PUSHED.fetch_add(1, Ordering::Relaxed);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    LAST_PUSH.store(now, Ordering::Relaxed);
+}
+
+/// Whether `push-basicinfo` was asked for since the last call
+pub fn take_basic_info_request() -> bool {
+    PUSH_BASIC_INFO.swap(false, Ordering::Relaxed)
+}
+
+#[derive(Serialize)]
+struct Status {
+    version: String,
+    /// Agent uptime (s)
+    uptime: u64,
+    connected: bool,
+    degraded: bool,
+    /// Started in safe mode after a crash loop
+    safe_mode: bool,
+    /// Real-Time Info sent since the start
+    pushed: u64,
+    last_push: Option<u64>,
+    /// Run counts, durations and failures by callback type
+    callbacks: Vec<CallbackStats>,
+}
+
+pub struct ControlContext {
+    pub reload_file: Option<String>,
+    pub network_statistics: bool,
+    pub health: Option<Arc<ServerHealth>>,
+    pub started: Instant,
+}
+
+impl ControlContext {
+    fn handle(&self, line: &str) -> String {
+        let (command, argument) = line
+            .split_once(' ')
+            .map_or((line, None), |(command, argument)| {
+                (command, Some(argument.trim()))
+            });
+        match command {
+            "status" => {
+                let last_push = LAST_PUSH.load(Ordering::Relaxed);
+                json::to_string(&Status {
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    uptime: self.started.elapsed().as_secs(),
+                    connected: CONNECTED.load(Ordering::Relaxed),
+                    degraded: self.health.as_ref().is_some_and(|health| health.degraded()),
+                    safe_mode: crate::safe_mode::safe_mode(),
+                    pushed: PUSHED.load(Ordering::Relaxed),
+                    last_push: (last_push > 0).then_some(last_push),
+                    callbacks: callback_stats(),
+                })
+            }
+            "reload" => {
+                let Some(path) = &self.reload_file else {
+                    return "error: no `--reload-file` given".to_string();
+                };
+                match crate::reload::Reload::read(path) {
+                    Ok(reload) => {
+                        crate::reload::request_reload(reload);
+                        "ok".to_string()
+                    }
+                    Err(e) => format!("error: {e}"),
+                }
+            }
+            "reset-traffic" => {
+                if !self.network_statistics {
+                    return "error: network statistics are disabled".to_string();
+                }
+                crate::get_info::network::network_saver::request_traffic_reset();
+                "ok: the cycle resets at the next save interval".to_string()
+            }
+            "push-basicinfo" => {
+                PUSH_BASIC_INFO.store(true, Ordering::Relaxed);
+                "ok".to_string()
+            }
+            "history" => crate::get_info::network::history::history_json()
+                .unwrap_or_else(|| "error: no `--interface-history` given".to_string()),
+            #[cfg(not(feature = "no-terminal"))]
+            "pending" => crate::callbacks::approval::pending_sessions(),
+            #[cfg(not(feature = "no-terminal"))]
+            "approve" | "deny" => {
+                let Some(id) = argument.filter(|id| !id.is_empty()) else {
+                    return format!("error: `{command}` needs a terminal session id");
+                };
+                match crate::callbacks::approval::decide(id, command == "approve") {
+                    Ok(()) => "ok".to_string(),
+                    Err(e) => format!("error: {e}"),
+                }
+            }
+            #[cfg(feature = "no-terminal")]
+            "pending" | "approve" | "deny" => {
+                let _ = argument;
+                "error: this agent is built without the terminal".to_string()
+            }
+            _ => format!("error: unknown command `{command}`"),
+        }
+    }
+}
+
+/// Serve the control socket at `path`, or the one passed by systemd, in the background
+#[cfg(unix)]
+pub fn spawn_control_socket(path: &str, context: ControlContext) {
+    use crate::systemd::take_listen_fd;
+    use log::{error, info};
+    use std::io::{BufRead as _, BufReader, Read as _, Write as _};
+    use std::os::unix::fs::{DirBuilderExt as _, PermissionsExt as _};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::time::Duration;
+
+    /// Time a client gets to send its command and to take the reply
+    const READ_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Longest command line read
+    const MAX_COMMAND: u64 = 4096;
+
+    let listener = if let Some(fd) = take_listen_fd("control") {
+        // systemd owns the socket file and its permissions
+        UnixListener::from(fd)
+    } else {
+        // A socket file nobody answers on is left over from a previous run
+        if std::path::Path::new(path).exists() {
+            if UnixStream::connect(path).is_ok() {
+                error!("Control socket {path} is in use by another agent, not listening");
+                return;
+            }
+            let _ = std::fs::remove_file(path);
+        }
+        // Bound in a private directory and moved into place once restricted, nobody can
+        // connect in between
+        let dir = format!("{path}.{}", std::process::id());
+        let staged = format!("{dir}/socket");
+        let bound = std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .and_then(|()| UnixListener::bind(&staged))
+            .and_then(|listener| {
+                std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+                std::fs::rename(&staged, path)?;
+                Ok(listener)
+            });
+        let _ = std::fs::remove_file(&staged);
+        let _ = std::fs::remove_dir(&dir);
+        let listener = match bound {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to bind control socket {path}: {e}");
+                return;
+            }
+        };
+        info!("Control socket listening on {path}");
+        listener
+    };
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Control socket error: {e}");
+                    continue;
+                }
+            };
+            // A client that never finishes its line would hold up every other one
+            let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+            let _ = stream.set_write_timeout(Some(READ_TIMEOUT));
+            let mut line = String::new();
+            if let Err(e) = BufReader::new((&stream).take(MAX_COMMAND)).read_line(&mut line) {
+                warn!("Failed to read control command: {e}");
+                continue;
+            }
+            let command = line.trim();
+            info!("Control command: {command}");
+            let reply = context.handle(command);
+            let _ = writeln!(stream, "{reply}");
+        }
+    });
+}
+
+/// Serve the named pipe at `path` (`\\.\pipe\komari-agent`) in the background. The
+/// default pipe security lets administrators and the agent's user send commands
+#[cfg(windows)]
+pub fn spawn_control_socket(path: &str, context: ControlContext) {
+    use log::{error, info};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader};
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    // A second agent on the same pipe name fails here instead of sharing it
+    let mut server = match ServerOptions::new().first_pipe_instance(true).create(path) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to create control pipe {path}: {e}");
+            return;
+        }
+    };
+    info!("Control pipe listening on {path}");
+
+    let path = path.to_string();
+    let context = Arc::new(context);
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = server.connect().await {
+                warn!("Control pipe error: {e}");
+                continue;
+            }
+            // The next client connects to a new instance while this one is served
+            let connected = server;
+            server = match ServerOptions::new().create(&path) {
+                Ok(server) => server,
+                Err(e) => {
+                    error!("Failed to create control pipe {path}: {e}");
+                    return;
+                }
+            };
+
+            let context = context.clone();
+            tokio::spawn(async move {
+                let mut pipe = BufReader::new(connected);
+                let mut line = String::new();
+                if let Err(e) = pipe.read_line(&mut line).await {
+                    warn!("Failed to read control command: {e}");
+                    return;
+                }
+                let command = line.trim();
+                info!("Control command: {command}");
+                let reply = context.handle(command);
+                if pipe
+                    .write_all(format!("{reply}\n").as_bytes())
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                // Unread data is dropped with the pipe, wait for the client to hang up
+                let _ = tokio::time::timeout(Duration::from_secs(5), pipe.read(&mut [0; 1])).await;
+            });
+        }
+    });
+}
+
+/// Send `command` to the agent listening at `path` and return its reply
+#[cfg(unix)]
+pub fn send_control_command(path: &str, command: &str) -> Result<String, String> {
+    use std::io::{Read as _, Write as _};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream =
+        UnixStream::connect(path).map_err(|e| format!("Failed to connect to {path}: {e}"))?;
+    writeln!(stream, "{command}").map_err(|e| e.to_string())?;
+    let _ = stream.shutdown(std::net::Shutdown::Write);
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .map_err(|e| e.to_string())?;
+    let reply = reply.trim_end().to_string();
+    if let Some(e) = reply.strip_prefix("error: ") {
+        return Err(e.to_string());
+    }
+    Ok(reply)
+}
+
+/// Send `command` to the agent listening on the named pipe `path` and return its reply
+#[cfg(windows)]
+pub fn send_control_command(path: &str, command: &str) -> Result<String, String> {
+    use std::io::{BufRead as _, BufReader, Write as _};
+
+    let mut pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("Failed to connect to {path}: {e}"))?;
+    writeln!(pipe, "{command}").map_err(|e| e.to_string())?;
+    let mut reply = String::new();
+    BufReader::new(pipe)
+        .read_line(&mut reply)
+        .map_err(|e| e.to_string())?;
+    let reply = reply.trim_end().to_string();
+    if let Some(e) = reply.strip_prefix("error: ") {
+        return Err(e.to_string());
+    }
+    Ok(reply)
+}
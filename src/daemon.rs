@@ -0,0 +1,208 @@
+//! Running in the background without a service manager (`--daemon`, `--pid-file`), e.g.
+//! under `OpenRC` or `procd`.
+//!
+//! `--daemon` forks twice with a `setsid` in between, so the agent is neither a session leader
+//! nor attached to a terminal, and redirects stdin to `/dev/null` and stdout / stderr, the
+//! log included, to `--log-file` (`/dev/null` without one). The working directory is kept
+//! so relative paths of the other options still work. The starting process only exits once
+//! the daemon wrote its PID file, with status 1 when it failed. A PID file naming a running
+//! agent stops the start, one left behind by a crashed agent is replaced. It is removed again
+//! on SIGTERM and SIGINT, like the `--traffic-account` nftables table.
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+mod imp {
+    use crate::get_info::network::accounting::remove_accounting;
+    use log::{info, warn};
+    use std::fs::{File, OpenOptions};
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::process::exit;
+
+    /// PID in the file at `path` if that process still runs
+    fn running_pid(path: &str) -> Option<i32> {
+        let pid = std::fs::read_to_string(path)
+            .ok()?
+            .trim()
+            .parse::<i32>()
+            .ok()
+            .filter(|pid| *pid > 0)?;
+        if pid == std::process::id().cast_signed() {
+            return None;
+        }
+        // EPERM: it runs, as another user
+        let alive = unsafe { libc::kill(pid, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+        if !alive {
+            return None;
+        }
+        // A PID reused by another program is no running agent
+        #[cfg(target_os = "linux")]
+        if let (Ok(exe), Ok(own)) = (
+            std::fs::read_link(format!("/proc/{pid}/exe")),
+            std::env::current_exe(),
+        ) && exe != own
+        {
+            return None;
+        }
+        Some(pid)
+    }
+
+    /// Write the PID of this process to `path`, replacing a stale file
+    pub fn write_pid_file(path: &str) -> Result<(), String> {
+        if let Some(pid) = running_pid(path) {
+            return Err(format!(
+                "The agent is already running as PID {pid} ({path})"
+            ));
+        }
+        if std::fs::exists(path).unwrap_or(false) {
+            info!("Replacing the stale PID file {path}");
+        }
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .map_err(|e| format!("Failed to write PID file {path}: {e}"))
+    }
+
+    pub fn remove_pid_file(path: &str) {
+        // Only our own, a new agent may have taken over
+        let own = std::fs::read_to_string(path)
+            .is_ok_and(|content| content.trim() == std::process::id().to_string());
+        if own && let Err(e) = std::fs::remove_file(path) {
+            warn!("Failed to remove PID file {path}: {e}");
+        }
+    }
+
+    fn fork() -> Result<libc::pid_t, String> {
+        match unsafe { libc::fork() } {
+            -1 => Err(format!("fork failed: {}", std::io::Error::last_os_error())),
+            pid => Ok(pid),
+        }
+    }
+
+    /// Write end of the pipe the starting process waits on
+    pub struct Started(File);
+
+    impl Started {
+        /// Let the starting process exit, once the PID file is written
+        pub fn notify(mut self) {
+            info!("Running in the background as PID {}", std::process::id());
+            if let Err(e) = self.0.write_all(&[1]) {
+                warn!("Failed to signal the start: {e}");
+            }
+        }
+    }
+
+    /// Detach from the terminal, must run before any thread is started and before the
+    /// logger checks whether stderr is a terminal
+    pub fn daemonize(pid_file: Option<&str>, log_file: Option<&str>) -> Result<Started, String> {
+        if let Some(path) = pid_file
+            && let Some(pid) = running_pid(path)
+        {
+            return Err(format!(
+                "The agent is already running as PID {pid} ({path})"
+            ));
+        }
+        let stdin =
+            File::open("/dev/null").map_err(|e| format!("Failed to open /dev/null: {e}"))?;
+        let output = match log_file {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open log file {path}: {e}"))?,
+            None => OpenOptions::new()
+                .write(true)
+                .open("/dev/null")
+                .map_err(|e| format!("Failed to open /dev/null: {e}"))?,
+        };
+
+        // The daemon tells the starting process through the pipe that it is up
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(format!(
+                "Failed to create a pipe: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        let (mut reader, writer) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+        if fork()? > 0 {
+            drop(writer);
+            let mut ready = [0; 1];
+            let started = reader.read(&mut ready).is_ok_and(|read| read == 1);
+            exit(i32::from(!started));
+        }
+        drop(reader);
+        if unsafe { libc::setsid() } == -1 {
+            return Err(format!(
+                "setsid failed: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        // Not a session leader, so opening a terminal never makes it the controlling one
+        if fork()? > 0 {
+            exit(0);
+        }
+        unsafe { libc::umask(0o022) };
+
+        std::io::stdout().flush().ok();
+        for (from, to) in [
+            (stdin.as_raw_fd(), libc::STDIN_FILENO),
+            (output.as_raw_fd(), libc::STDOUT_FILENO),
+            (output.as_raw_fd(), libc::STDERR_FILENO),
+        ] {
+            if unsafe { libc::dup2(from, to) } == -1 {
+                return Err(format!(
+                    "Failed to redirect the standard streams: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+        }
+        Ok(Started(writer))
+    }
+
+    /// Remove the PID file and the accounting table when stopped by SIGTERM or SIGINT, which
+    /// would kill the agent right away otherwise
+    pub fn spawn_exit_cleanup(pid_file: Option<String>) {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        tokio::spawn(async move {
+            let (Ok(mut terminate), Ok(mut interrupt)) = (
+                signal(SignalKind::terminate()),
+                signal(SignalKind::interrupt()),
+            ) else {
+                warn!("Failed to listen for SIGTERM, nothing is cleaned up on exit");
+                return;
+            };
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
+            if let Some(path) = &pid_file {
+                remove_pid_file(path);
+            }
+            remove_accounting();
+            exit(0);
+        });
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+mod imp {
+    pub fn write_pid_file(_path: &str) -> Result<(), String> {
+        Err("`--pid-file` is only supported on Unix".to_string())
+    }
+
+    pub struct Started;
+
+    impl Started {
+        pub fn notify(self) {}
+    }
+
+    pub fn daemonize(_pid_file: Option<&str>, _log_file: Option<&str>) -> Result<Started, String> {
+        Err("`--daemon` is only supported on Unix".to_string())
+    }
+
+    pub fn spawn_exit_cleanup(_pid_file: Option<String>) {}
+}
+
+pub use imp::{daemonize, spawn_exit_cleanup, write_pid_file};
@@ -1,26 +1,61 @@
+use crate::callbacks::local_ping::realtime_local_ping;
+use crate::callbacks::mesh::realtime_mesh;
+use crate::callbacks::message::PROTOCOL_VERSION;
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::session_stats::realtime_session_stats;
+use crate::capabilities::capabilities;
+use crate::clock::realtime_clock_offset;
 use crate::command_parser::IpProvider;
+use crate::fake_profile::apply_fake_profile;
 
+use crate::get_info::cgroup::realtime_cgroups;
+use crate::get_info::collector::MetricsCollector;
 use crate::get_info::cpu::{arch, cpu_info_without_usage, realtime_cpu};
+use crate::get_info::geoip::geoip;
 use crate::get_info::ip::ip;
 use crate::get_info::load::realtime_load;
-use crate::get_info::mem::{mem_info_without_usage, realtime_disk, realtime_mem, realtime_swap};
-use crate::get_info::network::{realtime_connections, realtime_network};
+use crate::get_info::mem::{
+    mem_info_without_usage, realtime_disk, realtime_mem, realtime_swap, realtime_tmpfs,
+};
+use crate::get_info::network::accounting::realtime_accounting;
+use crate::get_info::network::cycles::realtime_cycles;
+use crate::get_info::network::ebpf::realtime_process_network;
+use crate::get_info::network::family::realtime_ip_family;
+use crate::get_info::network::groups::realtime_groups;
+use crate::get_info::network::link::link_info;
+use crate::get_info::network::vpn::realtime_vpn;
+use crate::get_info::network::{filter_network, realtime_connections};
 use crate::get_info::os::os;
+use crate::get_info::power::realtime_power;
+use crate::get_info::pressure::load_shed;
+use crate::get_info::sbc::realtime_sbc;
+use crate::get_info::temperature::realtime_temperatures;
+use crate::get_info::thermal::realtime_thermal_throttle;
+use crate::get_info::watch::realtime_watch;
+use crate::get_info::wifi::realtime_wifi;
 use crate::get_info::{realtime_process, realtime_uptime};
-use log::{debug, error, info};
-use miniserde::{Deserialize, Serialize};
-use sysinfo::{Disks, Networks};
+use crate::labels::{hostname, labels};
+use crate::privacy::public_ip;
+use crate::signing::sign_json;
+use crate::uptime::realtime_http_checks;
+use log::{debug, error, info, warn};
+use miniserde::json::{Number, Object, Value};
+use miniserde::{Deserialize, Serialize, json};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BasicInfo {
     pub arch: String,
     pub cpu_cores: u64,
     pub cpu_name: String,
-    pub gpu_name: String, // Not supported yet
+    pub gpu_name: String, // Windows only (DXGI)
 
     pub disk_total: u64,
     pub swap_total: u64,
     pub mem_total: u64,
+    /// Bytes held by a virtio balloon, already taken off `mem_total`
+    pub mem_balloon: Option<u64>,
 
     pub ipv4: Option<String>,
     pub ipv6: Option<String>,
@@ -29,8 +64,58 @@ pub struct BasicInfo {
     pub version: String,
     pub kernel_version: String,
     pub virtualization: String,
+
+    pub interfaces: Vec<LinkInfo>,
+    pub geo: Option<GeoInfo>,
+    /// Left out in privacy mode
+    pub hostname: Option<String>,
+    /// `--label` tags
+    pub labels: BTreeMap<String, String>,
+    /// Task types this build handles, remote control may be stripped at compile time
+    pub capabilities: Vec<String>,
+    /// Newest task format this agent understands
+    pub protocol: u64,
+}
+
+/// Location of the public address (`--geoip`)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub asn: Option<u64>,
+    pub as_org: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LinkInfo {
+    pub name: String,
+    /// Negotiated speed in Mb/s
+    pub speed: Option<u32>,
+    /// `full` / `half`
+    pub duplex: Option<String>,
+    pub carrier: bool,
 }
 
+/// Hardware adapters as `Name (N GB)`, comma separated
+fn gpu_name() -> String {
+    #[cfg(target_os = "windows")]
+    {
+        crate::get_info::windows_hw::gpus()
+            .into_iter()
+            .map(|(name, vram)| format!("{name} ({:.1} GB)", vram as f64 / 1073741824.0))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        String::new()
+    }
+}
+
+/// Pause before a Basic Info whose push failed is sent again on the same connection
+pub const BASIC_INFO_RETRY: Duration = Duration::from_mins(1);
+
 impl BasicInfo {
     pub async fn build(sysinfo_sys: &sysinfo::System, fake: f64, ip_provider: &IpProvider) -> Self {
         let cpu = cpu_info_without_usage(sysinfo_sys);
@@ -46,16 +131,25 @@ impl BasicInfo {
             arch: arch(),
             cpu_cores: fake_cpu_cores,
             cpu_name: cpu.name,
-            gpu_name: String::new(),
+            gpu_name: gpu_name(),
             disk_total: fake_disk_total,
             swap_total: fake_swap_total,
             mem_total: fake_mem_total,
-            ipv4: ip.ipv4.map(|ip| ip.to_string()),
-            ipv6: ip.ipv6.map(|ip| ip.to_string()),
+            mem_balloon: mem_disk
+                .balloon
+                .map(|balloon| (balloon as f64 * fake) as u64),
+            ipv4: ip.ipv4.map(public_ip),
+            ipv6: ip.ipv6.map(public_ip),
             os: os.os,
             version: format!("komari-monitor-rs {}", env!("CARGO_PKG_VERSION")),
             kernel_version: os.version,
             virtualization: os.virtualization,
+            interfaces: link_info(),
+            geo: geoip(&ip),
+            hostname: hostname(),
+            labels: labels(),
+            capabilities: capabilities(),
+            protocol: PROTOCOL_VERSION,
         };
 
         debug!("Basic Info successfully retrieved: {basic_info:?}");
@@ -63,32 +157,39 @@ impl BasicInfo {
         basic_info
     }
 
-    pub fn push(&self, basic_info_url: String, ignore_unsafe_cert: bool) {
+    /// Push to `basic_info_url` with `headers`, `panel_headers()` and `signed` for the panel
+    pub fn push(
+        &self,
+        basic_info_url: &str,
+        headers: &[(String, String)],
+        signed: bool,
+        ignore_unsafe_cert: bool,
+    ) -> Result<(), String> {
         let json_string = miniserde::json::to_string(self);
+        let json_string = if signed {
+            sign_json(json_string)
+        } else {
+            json_string
+        };
         #[cfg(feature = "ureq-support")]
         {
             use crate::utils::create_ureq_agent;
             let agent = create_ureq_agent(ignore_unsafe_cert);
-            let resp = agent
+            let mut request = agent
                 .post(basic_info_url)
-                .header("User-Agent", "curl/11.45.14-rs")
-                .send(&json_string);
-
-            let resp = match resp {
-                Ok(resp) => resp,
-                Err(e) => {
-                    error!("Failed to push Basic Info: {e}");
-                    return;
-                }
-            };
+                .header("User-Agent", "curl/11.45.14-rs");
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let resp = request
+                .send(&json_string)
+                .map_err(|e| format!("Failed to push Basic Info: {e}"))?;
 
-            if resp.status().is_success() {
-                info!("Successfully pushed Basic Info");
-            } else {
-                error!(
+            if !resp.status().is_success() {
+                return Err(format!(
                     "Failed to push Basic Info, HTTP status code: {}",
                     resp.status()
-                );
+                ));
             }
         }
         #[cfg(feature = "nyquest-support")]
@@ -97,37 +198,81 @@ impl BasicInfo {
             use nyquest::Request;
             let client = crate::utils::create_nyquest_client(ignore_unsafe_cert);
             let body = Body::text(json_string, "application/json");
-            let resp = client.request(Request::post(basic_info_url).with_body(body));
-
-            let resp = match resp {
-                Ok(resp) => resp,
-                Err(e) => {
-                    error!("Failed to push Basic Info: {e}");
-                    return;
-                }
-            };
+            let mut request = Request::post(basic_info_url.to_string()).with_body(body);
+            for (name, value) in headers {
+                request = request.with_header(name.clone(), value.clone());
+            }
+            let resp = client
+                .request(request)
+                .map_err(|e| format!("Failed to push Basic Info: {e}"))?;
 
-            if resp.status().is_successful() {
-                info!("Successfully pushed Basic Info");
-            } else {
-                error!(
+            if !resp.status().is_successful() {
+                return Err(format!(
                     "Failed to push Basic Info, HTTP status code: {}",
                     resp.status()
-                );
+                ));
             }
         }
+        Ok(())
+    }
+
+    /// Push with exponential backoff (1 s, 2 s, 4 s, ...), gives the struct back when every
+    /// attempt failed so it can be re-sent after `BASIC_INFO_RETRY` or on the next connection
+    pub async fn push_with_retry(
+        self,
+        basic_info_url: String,
+        headers: Vec<(String, String)>,
+        signed: bool,
+        ignore_unsafe_cert: bool,
+        attempts: u32,
+    ) -> Result<(), Self> {
+        let mut backoff = Duration::from_secs(1);
+        for attempt in 1..=attempts.max(1) {
+            let info = self.clone();
+            let url = basic_info_url.clone();
+            let headers = headers.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                info.push(&url, &headers, signed, ignore_unsafe_cert)
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("Failed to push Basic Info: {e}")));
+
+            match result {
+                Ok(()) => {
+                    info!("Successfully pushed Basic Info");
+                    return Ok(());
+                }
+                Err(e) if attempt < attempts => {
+                    warn!("{e}, retrying in {} s", backoff.as_secs());
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_mins(1));
+                }
+                Err(e) => error!("{e}, will retry in {} s", BASIC_INFO_RETRY.as_secs()),
+            }
+        }
+        Err(self)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Cpu {
     pub usage: f64,
+    /// Time stolen by the hypervisor (%), Linux only
+    pub steal: Option<f64>,
+    /// System-wide context switches per second, Linux and Windows
+    pub context_switches: Option<f64>,
+    /// System-wide interrupts per second, Linux and Windows
+    pub interrupts: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Ram {
     pub used: u64,
     pub total: u64,
+    /// Reported with `--mem-report-cache` (Linux only)
+    pub buffers: Option<u64>,
+    /// Page cache and reclaimable slab, as in the `buff/cache` column of `free`
+    pub cached: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -142,6 +287,15 @@ pub struct Disk {
     pub total: u64,
 }
 
+/// A tmpfs / ramfs mount, reported apart from the disk totals (`--disk-report-tmpfs`)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MemoryMount {
+    pub mount_point: String,
+    pub file_system: String,
+    pub used: u64,
+    pub total: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Load {
     pub load1: f64,
@@ -160,6 +314,273 @@ pub struct Network {
     pub total_down: u64,
 }
 
+/// Traffic of an interface group in the current cycle (`--traffic-group`)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrafficGroup {
+    pub name: String,
+    pub interfaces: Vec<String>,
+    #[serde(rename = "totalUp")]
+    pub total_up: u64,
+
+    #[serde(rename = "totalDown")]
+    pub total_down: u64,
+    /// Bytes up and down together
+    pub quota: Option<u64>,
+    pub over_quota: bool,
+}
+
+/// An additional traffic cycle (`--traffic-cycle`)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrafficCycle {
+    pub name: String,
+    /// Period and reset day, e.g. `Day:1`
+    pub schedule: String,
+    #[serde(rename = "totalUp")]
+    pub total_up: u64,
+
+    #[serde(rename = "totalDown")]
+    pub total_down: u64,
+    /// RFC 3339
+    pub next_reset: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountedTraffic {
+    pub name: String,
+    #[serde(rename = "totalUp")]
+    pub total_up: u64,
+
+    #[serde(rename = "totalDown")]
+    pub total_down: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcessNetwork {
+    pub pid: u32,
+    pub name: String,
+    pub up: u64,
+    pub down: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopProcesses {
+    pub network: Option<Vec<ProcessNetwork>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VpnTunnel {
+    pub name: String,
+    /// `wireguard` / `openvpn`
+    pub kind: String,
+    pub peers: u64,
+    pub active_peers: u64,
+    /// Seconds since the latest handshake (wireguard) or status file update (openvpn)
+    pub handshake_age: Option<u64>,
+    pub rx: u64,
+    pub tx: u64,
+}
+
+/// Latency to one peer of the agent mesh over the latest probes, RTTs in ms
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MeshPeer {
+    pub name: String,
+    pub address: String,
+    pub sent: u64,
+    pub received: u64,
+    /// Percentage of probes without an answer
+    pub loss: f64,
+    pub min: Option<f64>,
+    pub avg: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// Latency to a `--ping-target` over the latest probes, RTTs in ms
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LocalPing {
+    pub name: String,
+    /// `icmp` / `tcp` / `http`
+    pub kind: String,
+    pub target: String,
+    pub sent: u64,
+    pub received: u64,
+    /// Percentage of probes without an answer
+    pub loss: f64,
+    /// RTT of the latest probe, `None` when it was lost
+    pub last: Option<f64>,
+    pub min: Option<f64>,
+    pub avg: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// Latest result of an `--http-check`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HttpCheck {
+    pub name: String,
+    pub url: String,
+    pub up: bool,
+    /// `None` when no response was received
+    pub status: Option<u16>,
+    /// Milliseconds until the response
+    pub response_time: Option<f64>,
+    pub error: Option<String>,
+    pub checks: u64,
+    /// Percentage of the latest checks that were up
+    pub uptime: f64,
+}
+
+/// Result of the latest `--clock-check`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClockOffset {
+    pub source: String,
+    /// Milliseconds the local clock is ahead of the source, negative when behind
+    pub offset: Option<f64>,
+    /// Milliseconds the query took on the network
+    pub delay: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// Traffic split by IP family, rates in bytes/s and totals since boot
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IpFamilyTraffic {
+    pub up4: u64,
+    pub down4: u64,
+    #[serde(rename = "totalUp4")]
+    pub total_up4: u64,
+    #[serde(rename = "totalDown4")]
+    pub total_down4: u64,
+    pub up6: u64,
+    pub down6: u64,
+    #[serde(rename = "totalUp6")]
+    pub total_up6: u64,
+    #[serde(rename = "totalDown6")]
+    pub total_down6: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Temperature {
+    pub label: String,
+    /// Degrees Celsius
+    pub value: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThermalThrottle {
+    /// Throttle events since the previous sample
+    pub events: u64,
+    /// Since boot with the Linux counters, since the agent started otherwise
+    pub total: u64,
+    /// Performance is limited right now
+    pub throttled: bool,
+    /// Performance allowed in % of nominal, below 100 while limited (Windows only)
+    pub performance_limit: Option<f64>,
+    /// Conditions the Raspberry Pi firmware reports right now, e.g. `under-voltage`
+    pub flags: Option<Vec<String>>,
+}
+
+/// Raspberry Pi-class board readings, `--sbc-stats`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SbcStats {
+    /// From the device tree, e.g. `Raspberry Pi 4 Model B Rev 1.4`
+    pub model: Option<String>,
+    /// °C
+    pub soc_temperature: Option<f64>,
+    /// V, from `vcgencmd` only
+    pub core_voltage: Option<f64>,
+    /// MHz
+    pub arm_clock: Option<u64>,
+    pub under_voltage: Option<bool>,
+    /// Capped, throttled or at the soft temperature limit right now
+    pub throttled: Option<bool>,
+    /// Firmware flags right now and, suffixed `-occurred`, since boot
+    pub flags: Option<Vec<String>>,
+}
+
+/// A wireless interface, `--wifi-stats`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WifiLink {
+    pub interface: String,
+    pub ssid: Option<String>,
+    pub connected: bool,
+    /// dBm, estimated from the quality on Windows
+    pub signal: Option<f64>,
+    /// Link quality in %
+    pub quality: Option<f64>,
+    /// MHz, Linux only
+    pub frequency: Option<u32>,
+    /// Receive bitrate in Mb/s
+    pub bitrate: Option<f64>,
+}
+
+/// A battery or UPS powering the host
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PowerSource {
+    pub name: String,
+    /// `battery` / `nut` / `apcupsd`
+    pub kind: String,
+    /// Charge in %
+    pub charge: Option<f64>,
+    /// As reported, e.g. `discharging` for a battery, `OB LB` for NUT, `ONBATT` for apcupsd
+    pub status: String,
+    /// Mains power is lost and the host runs on this source
+    pub on_battery: bool,
+    /// Estimated seconds left on battery
+    pub runtime: Option<u64>,
+    /// UPS load in %
+    pub load: Option<f64>,
+}
+
+/// A `--watch-process` or `--watch-service`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchedService {
+    pub name: String,
+    /// `process` / `service`
+    pub kind: String,
+    pub running: bool,
+    /// `running` / `stopped` for a process, e.g. `active (running)` for a systemd unit,
+    /// `running` for a Windows service, `not-found` or `unsupported`
+    pub status: String,
+    /// Matching processes, processes only
+    pub instances: Option<u64>,
+    /// Restarts seen since the agent started
+    pub restarts: u64,
+}
+
+/// A `--cgroup`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CgroupUsage {
+    pub name: String,
+    /// Below the cgroup root, e.g. `system.slice/nginx.service`
+    pub path: String,
+    /// Whether the cgroup could be read
+    pub found: bool,
+    /// % of all CPUs since the previous reading, `None` on the first one
+    pub cpu: Option<f64>,
+    /// Bytes in use, without the inactive page cache
+    pub memory: Option<u64>,
+    /// Bytes, `None` when unlimited
+    pub memory_limit: Option<u64>,
+}
+
+/// Remote shell activity since the agent started
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TerminalStats {
+    pub sessions: u64,
+    pub active: u64,
+    /// Seconds spent in sessions, open ones included
+    pub total_duration: u64,
+    /// Start of the latest session (RFC 3339)
+    pub last_session: Option<String>,
+}
+
+/// Host pressure that made this sample skip optional collectors (`--shed-pressure`)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoadShed {
+    /// PSI avg10 in %, `some` for the CPU and `full` for memory
+    pub cpu: f64,
+    pub memory: f64,
+    pub skipped: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Connections {
     pub tcp: u64,
@@ -172,23 +593,68 @@ pub struct RealTimeInfo {
     pub ram: Ram,
     pub swap: Swap,
     pub disk: Disk,
+    pub tmpfs: Option<Vec<MemoryMount>>,
     pub load: Load,
     pub network: Network,
     pub connections: Connections,
     pub uptime: u64,
     pub process: u64,
     pub message: String,
+    pub accounting: Option<Vec<AccountedTraffic>>,
+    pub cycles: Option<Vec<TrafficCycle>>,
+    pub groups: Option<Vec<TrafficGroup>>,
+    pub top: Option<TopProcesses>,
+    pub vpn: Option<Vec<VpnTunnel>>,
+    pub ip_family: Option<IpFamilyTraffic>,
+    pub mesh: Option<Vec<MeshPeer>>,
+    pub ping: Option<Vec<LocalPing>>,
+    pub http_checks: Option<Vec<HttpCheck>>,
+    pub clock: Option<ClockOffset>,
+    pub terminal: Option<TerminalStats>,
+    pub temperatures: Option<Vec<Temperature>>,
+    pub throttle: Option<ThermalThrottle>,
+    pub sbc: Option<SbcStats>,
+    pub wifi: Option<Vec<WifiLink>>,
+    pub power: Option<Vec<PowerSource>>,
+    pub services: Option<Vec<WatchedService>>,
+    pub cgroups: Option<Vec<CgroupUsage>>,
+    /// Set on the first sample after a resume: seconds the machine was suspended
+    pub resumed_after: Option<u64>,
+    pub shed: Option<LoadShed>,
+    pub window: Option<SampleWindow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MinAvgMax {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+/// Spread of the samples taken within one upload interval, see `--sample-interval`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SampleWindow {
+    pub samples: u64,
+    pub cpu: MinAvgMax,
+    pub ram: MinAvgMax,
+    pub swap: MinAvgMax,
+    pub load1: MinAvgMax,
+    pub up: MinAvgMax,
+    pub down: MinAvgMax,
+    pub tcp: MinAvgMax,
+    pub udp: MinAvgMax,
 }
 
 impl RealTimeInfo {
-    pub fn build(
-        sysinfo_sys: &sysinfo::System,
-        network: &Networks,
-        disk: &Disks,
-        fake: f64,
-        interval_ms: u64,
-    ) -> Self {
-        let cpu = realtime_cpu(sysinfo_sys);
+    /// The metrics every Komari server knows, without the optional collectors
+    pub fn build_core(collector: &MetricsCollector, fake: f64) -> Self {
+        let sysinfo_sys = &collector.sysinfo_sys;
+        let mut cpu = realtime_cpu(sysinfo_sys);
+        cpu.steal = collector.steal();
+        if let Some(switches) = collector.switches() {
+            cpu.context_switches = Some(switches.context_switches);
+            cpu.interrupts = Some(switches.interrupts);
+        }
 
         let ram = realtime_mem(sysinfo_sys);
         let fake_ram_used = (ram.used as f64 * fake) as u64;
@@ -198,7 +664,7 @@ impl RealTimeInfo {
         let fake_swap_used = (swap.used as f64 * fake) as u64;
         let fake_swap_total = (swap.total as f64 * fake) as u64;
 
-        let disk_info = realtime_disk(disk);
+        let disk_info = realtime_disk(&collector.disks);
         let fake_disk_used = (disk_info.used as f64 * fake) as u64;
         let fake_disk_total = (disk_info.total as f64 * fake) as u64;
 
@@ -207,7 +673,7 @@ impl RealTimeInfo {
         let fake_load5 = load.load5 * fake;
         let fake_load15 = load.load15 * fake;
 
-        let network_info = realtime_network(network, interval_ms);
+        let network_info = collector.network();
         let fake_network_up = (network_info.up as f64 * fake) as u64;
         let fake_network_down = (network_info.down as f64 * fake) as u64;
         let fake_network_total_up = (network_info.total_up as f64 * fake) as u64;
@@ -220,11 +686,13 @@ impl RealTimeInfo {
         let process = realtime_process();
         let fake_process = (process as f64 * fake) as u64;
 
-        let realtime_info = Self {
+        Self {
             cpu,
             ram: Ram {
                 used: fake_ram_used,
                 total: fake_ram_total,
+                buffers: ram.buffers.map(|buffers| (buffers as f64 * fake) as u64),
+                cached: ram.cached.map(|cached| (cached as f64 * fake) as u64),
             },
             swap: Swap {
                 used: fake_swap_used,
@@ -234,6 +702,7 @@ impl RealTimeInfo {
                 used: fake_disk_used,
                 total: fake_disk_total,
             },
+            tmpfs: None,
             load: Load {
                 load1: fake_load1,
                 load5: fake_load5,
@@ -252,10 +721,239 @@ impl RealTimeInfo {
             uptime: realtime_uptime(),
             process: fake_process,
             message: String::new(),
+            accounting: None,
+            cycles: None,
+            groups: None,
+            top: None,
+            vpn: None,
+            ip_family: None,
+            mesh: None,
+            ping: None,
+            http_checks: None,
+            clock: None,
+            terminal: None,
+            temperatures: None,
+            throttle: None,
+            sbc: None,
+            wifi: None,
+            power: None,
+            services: None,
+            cgroups: None,
+            resumed_after: None,
+            shed: None,
+            window: None,
+        }
+    }
+
+    pub fn build(collector: &MetricsCollector, fake: f64) -> Self {
+        let shed = load_shed();
+        let shedding = shed.is_some();
+
+        let accounting = (!shedding)
+            .then(realtime_accounting)
+            .flatten()
+            .map(|accounts| {
+                accounts
+                    .into_iter()
+                    .map(|account| AccountedTraffic {
+                        name: account.name,
+                        total_up: (account.total_up as f64 * fake) as u64,
+                        total_down: (account.total_down as f64 * fake) as u64,
+                    })
+                    .collect()
+            });
+
+        let (_, _, raw_total_up, raw_total_down) =
+            filter_network(&collector.networks, &collector.counter_wraps);
+        let cycles = realtime_cycles(raw_total_up, raw_total_down).map(|cycles| {
+            cycles
+                .into_iter()
+                .map(|cycle| TrafficCycle {
+                    total_up: (cycle.total_up as f64 * fake) as u64,
+                    total_down: (cycle.total_down as f64 * fake) as u64,
+                    ..cycle
+                })
+                .collect()
+        });
+
+        let groups = realtime_groups(&collector.networks, &collector.counter_wraps).map(|groups| {
+            groups
+                .into_iter()
+                .map(|group| TrafficGroup {
+                    total_up: (group.total_up as f64 * fake) as u64,
+                    total_down: (group.total_down as f64 * fake) as u64,
+                    ..group
+                })
+                .collect()
+        });
+
+        let top_network = (!shedding)
+            .then(realtime_process_network)
+            .flatten()
+            .map(|processes| {
+                processes
+                    .into_iter()
+                    .map(|process| ProcessNetwork {
+                        up: (process.up as f64 * fake) as u64,
+                        down: (process.down as f64 * fake) as u64,
+                        ..process
+                    })
+                    .collect()
+            });
+        let top = top_network.map(|network| TopProcesses {
+            network: Some(network),
+        });
+
+        let vpn = (!shedding).then(realtime_vpn).flatten().map(|tunnels| {
+            tunnels
+                .into_iter()
+                .map(|tunnel| VpnTunnel {
+                    rx: (tunnel.rx as f64 * fake) as u64,
+                    tx: (tunnel.tx as f64 * fake) as u64,
+                    ..tunnel
+                })
+                .collect()
+        });
+
+        let scale = |bytes: u64| (bytes as f64 * fake) as u64;
+        let ip_family = realtime_ip_family().map(|traffic| IpFamilyTraffic {
+            up4: scale(traffic.up4),
+            down4: scale(traffic.down4),
+            total_up4: scale(traffic.total_up4),
+            total_down4: scale(traffic.total_down4),
+            up6: scale(traffic.up6),
+            down6: scale(traffic.down6),
+            total_up6: scale(traffic.total_up6),
+            total_down6: scale(traffic.total_down6),
+        });
+
+        let resumed_after = collector.take_resumed_after();
+        if let Some(slept) = resumed_after {
+            info!("Resumed after {slept} s of suspend, network rates of this sample are dropped");
+        }
+
+        let mut realtime_info = Self {
+            accounting,
+            cycles,
+            groups,
+            top,
+            vpn,
+            tmpfs: realtime_tmpfs(&collector.disks).map(|mounts| {
+                mounts
+                    .into_iter()
+                    .map(|mount| MemoryMount {
+                        used: (mount.used as f64 * fake) as u64,
+                        total: (mount.total as f64 * fake) as u64,
+                        ..mount
+                    })
+                    .collect()
+            }),
+            ip_family,
+            mesh: realtime_mesh(),
+            ping: realtime_local_ping(),
+            http_checks: realtime_http_checks(),
+            clock: realtime_clock_offset(),
+            #[cfg(not(feature = "no-terminal"))]
+            terminal: realtime_session_stats(),
+            temperatures: (!shedding).then(realtime_temperatures).flatten(),
+            throttle: realtime_thermal_throttle(),
+            sbc: realtime_sbc(),
+            wifi: realtime_wifi(),
+            power: realtime_power(),
+            services: realtime_watch(),
+            cgroups: realtime_cgroups(),
+            resumed_after,
+            shed,
+            ..Self::build_core(collector, fake)
         };
 
+        apply_fake_profile(&mut realtime_info);
+
         debug!("Real-Time Info successfully retrieved: {realtime_info:?}");
 
         realtime_info
     }
 }
+
+/// Client-side diff layer for `--delta-mode`: between keyframes only the fields that
+/// moved by more than `epsilon` (relative) since they were last sent are pushed, with
+/// `"delta": true` added to the frame.
+#[derive(Clone)]
+pub struct DeltaEncoder {
+    epsilon: f64,
+    keyframe_interval: Duration,
+    sent: Object,
+    last_keyframe: Option<Instant>,
+}
+
+impl DeltaEncoder {
+    pub fn new(epsilon: f64, keyframe_interval_s: u64) -> Self {
+        Self {
+            epsilon,
+            keyframe_interval: Duration::from_secs(keyframe_interval_s),
+            sent: Object::new(),
+            last_keyframe: None,
+        }
+    }
+
+    /// Serialize the sample as a full keyframe when one is due, as a delta frame otherwise
+    pub fn encode(&mut self, real_time: &RealTimeInfo) -> String {
+        let json_string = json::to_string(real_time);
+        let Ok(Value::Object(current)) = json::from_str::<Value>(&json_string) else {
+            return json_string;
+        };
+
+        if self
+            .last_keyframe
+            .is_none_or(|last| last.elapsed() >= self.keyframe_interval)
+        {
+            self.sent = current;
+            self.last_keyframe = Some(Instant::now());
+            return json_string;
+        }
+
+        let mut delta = diff_object(&mut self.sent, &current, self.epsilon);
+        delta.insert("delta".to_string(), Value::Bool(true));
+        json::to_string(&Value::Object(delta))
+    }
+}
+
+fn number_as_f64(number: &Number) -> f64 {
+    match number {
+        Number::U64(n) => *n as f64,
+        Number::I64(n) => *n as f64,
+        Number::F64(n) => *n,
+    }
+}
+
+fn changed(old: &Value, new: &Value, epsilon: f64) -> bool {
+    match (old, new) {
+        (Value::Number(old), Value::Number(new)) => {
+            let (old, new) = (number_as_f64(old), number_as_f64(new));
+            (old - new).abs() > epsilon * old.abs().max(new.abs())
+        }
+        _ => json::to_string(old) != json::to_string(new),
+    }
+}
+
+/// Fields of `new` that changed compared to `sent`, recursing into nested objects.
+/// `sent` is updated with every field that ends up in the delta.
+fn diff_object(sent: &mut Object, new: &Object, epsilon: f64) -> Object {
+    let mut delta = Object::new();
+    for (key, value) in &**new {
+        match (sent.get_mut(key), value) {
+            (Some(Value::Object(sent_child)), Value::Object(new_child)) => {
+                let child = diff_object(sent_child, new_child, epsilon);
+                if !child.is_empty() {
+                    delta.insert(key.clone(), Value::Object(child));
+                }
+            }
+            (Some(old), _) if !changed(old, value, epsilon) => {}
+            _ => {
+                sent.insert(key.clone(), value.clone());
+                delta.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    delta
+}
@@ -0,0 +1,116 @@
+//! Agent-side downsampling (`--sample-interval`).
+//!
+//! With a long upload interval a short CPU or network spike falls between two samples and
+//! is never reported. Metrics are instead sampled every `--sample-interval` ms and each
+//! upload carries the average of the window, its min / max being reported in `window`.
+
+use crate::data_struct::{MinAvgMax, RealTimeInfo, SampleWindow};
+
+#[derive(Debug, Clone, Copy)]
+struct Accumulator {
+    min: f64,
+    max: f64,
+    sum: f64,
+}
+
+impl Accumulator {
+    const fn new() -> Self {
+        Self {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+    }
+
+    fn finish(&self, count: u64) -> MinAvgMax {
+        MinAvgMax {
+            min: self.min,
+            avg: self.sum / count as f64,
+            max: self.max,
+        }
+    }
+}
+
+pub struct Downsampler {
+    samples_per_upload: u64,
+    count: u64,
+    cpu: Accumulator,
+    ram: Accumulator,
+    swap: Accumulator,
+    load1: Accumulator,
+    up: Accumulator,
+    down: Accumulator,
+    tcp: Accumulator,
+    udp: Accumulator,
+    /// A resume within the window, the sample carrying it may not be the uploaded one
+    resumed_after: Option<u64>,
+}
+
+impl Downsampler {
+    pub fn new(samples_per_upload: u64) -> Self {
+        Self {
+            samples_per_upload: samples_per_upload.max(1),
+            count: 0,
+            cpu: Accumulator::new(),
+            ram: Accumulator::new(),
+            swap: Accumulator::new(),
+            load1: Accumulator::new(),
+            up: Accumulator::new(),
+            down: Accumulator::new(),
+            tcp: Accumulator::new(),
+            udp: Accumulator::new(),
+            resumed_after: None,
+        }
+    }
+
+    /// Add a sample, returns the aggregated one once the window is complete
+    pub fn add(&mut self, mut sample: RealTimeInfo) -> Option<RealTimeInfo> {
+        self.count += 1;
+        self.cpu.add(sample.cpu.usage);
+        self.ram.add(sample.ram.used as f64);
+        self.swap.add(sample.swap.used as f64);
+        self.load1.add(sample.load.load1);
+        self.up.add(sample.network.up as f64);
+        self.down.add(sample.network.down as f64);
+        self.tcp.add(sample.connections.tcp as f64);
+        self.udp.add(sample.connections.udp as f64);
+        self.resumed_after = sample.resumed_after.or(self.resumed_after);
+
+        if self.count < self.samples_per_upload {
+            return None;
+        }
+
+        let window = SampleWindow {
+            samples: self.count,
+            cpu: self.cpu.finish(self.count),
+            ram: self.ram.finish(self.count),
+            swap: self.swap.finish(self.count),
+            load1: self.load1.finish(self.count),
+            up: self.up.finish(self.count),
+            down: self.down.finish(self.count),
+            tcp: self.tcp.finish(self.count),
+            udp: self.udp.finish(self.count),
+        };
+        let resumed_after = self.resumed_after;
+        *self = Self::new(self.samples_per_upload);
+
+        // Totals, disk and the other slow moving values are kept from the latest sample
+        sample.cpu.usage = window.cpu.avg;
+        sample.ram.used = window.ram.avg as u64;
+        sample.swap.used = window.swap.avg as u64;
+        sample.load.load1 = window.load1.avg;
+        sample.network.up = window.up.avg as u64;
+        sample.network.down = window.down.avg as u64;
+        sample.connections.tcp = window.tcp.avg as u64;
+        sample.connections.udp = window.udp.avg as u64;
+        sample.resumed_after = resumed_after;
+        sample.window = Some(window);
+        Some(sample)
+    }
+}
@@ -1,9 +1,11 @@
 use crate::get_info::cpu::cpu_info_without_usage;
+use crate::get_info::filters::{disk_verdicts, interface_verdict};
 use crate::get_info::load::realtime_load;
-use crate::get_info::mem::{filter_disks, mem_info_without_usage, realtime_mem, realtime_swap};
+use crate::get_info::mem::{mem_info_without_usage, realtime_disk, realtime_mem, realtime_swap};
+use crate::get_info::network::link::link_info;
 use crate::get_info::network::realtime_connections;
+use crate::get_info::network::topology::{double_counted, topology};
 use log::info;
-use std::collections::HashSet;
 use sysinfo::{Disks, Networks};
 
 pub async fn dry_run() {
@@ -24,6 +26,9 @@ pub async fn dry_run() {
         mem.used / 1000 / 1000,
         mem_with_out_usage.mem / 1000 / 1000
     );
+    if let Some(balloon) = mem_with_out_usage.balloon {
+        info!("Memory Balloon: {} MB", balloon / 1000 / 1000);
+    }
     info!(
         "Swap: {} MB / {} MB",
         swap.used / 1000 / 1000,
@@ -38,10 +43,10 @@ pub async fn dry_run() {
 
     info!("");
 
-    info!("Hard drives will be monitored:");
-    let disks = filter_disks(&disks);
-    for disk in disks {
-        info!(
+    // The verdicts come from the same filters the live loop uses
+    info!("Hard drives (+ monitored, - skipped):");
+    for (disk, skip) in disk_verdicts(&disks) {
+        let line = format!(
             "{} | {} | {} | {} GB / {} GB",
             disk.name().to_string_lossy(),
             disk.file_system().to_string_lossy(),
@@ -49,34 +54,53 @@ pub async fn dry_run() {
             disk.available_space() / 1000 / 1000 / 1000,
             disk.total_space() / 1000 / 1000 / 1000
         );
+        match skip {
+            None => info!("+ {line}"),
+            Some(reason) => info!("- {line} ({reason})"),
+        }
     }
+    let disk = realtime_disk(&disks);
+    info!(
+        "Disk: {} GB / {} GB",
+        disk.used / 1000 / 1000 / 1000,
+        disk.total / 1000 / 1000 / 1000
+    );
 
     info!("");
-    info!("Network interfaces will be monitored:");
-    let filter_keywords: HashSet<&str> = [
-        "br", "cni", "docker", "podman", "flannel", "lo", "veth", "virbr", "vmbr", "tap", "tun",
-        "fwln", "fwpr",
-    ]
-    .iter()
-    .cloned()
-    .collect();
+    info!("Network interfaces (+ monitored, - skipped):");
+    let double_counted = double_counted();
     for (name, data) in networks.iter() {
-        let should_filter = filter_keywords
-            .iter()
-            .any(|&keyword| name.contains(keyword));
-
-        if should_filter || data.mac_address().0 == [0, 0, 0, 0, 0, 0] {
-            continue;
-        } else {
-            info!(
-                "{} | {} | UP: {} GB / DOWN: {} GB",
-                name,
-                data.mac_address().to_string(),
-                data.total_transmitted() / 1000 / 1000 / 1000,
-                data.total_received() / 1000 / 1000 / 1000
-            )
+        let line = format!(
+            "{} | {} | UP: {} GB / DOWN: {} GB",
+            name,
+            data.mac_address(),
+            data.total_transmitted() / 1000 / 1000 / 1000,
+            data.total_received() / 1000 / 1000 / 1000
+        );
+        match interface_verdict(name, data, &double_counted) {
+            None => info!("+ {line}"),
+            Some(reason) => info!("- {line} ({reason})"),
         }
     }
+    for aggregate in topology() {
+        info!(
+            "{}: {} | members: {}",
+            aggregate.kind.to_uppercase(),
+            aggregate.name,
+            aggregate.members.join(", ")
+        );
+    }
+    for link in link_info() {
+        let speed = link
+            .speed
+            .map_or_else(|| "unknown".to_string(), |speed| format!("{speed} Mb/s"));
+        info!(
+            "LINK: {} | carrier: {} | speed: {speed} | duplex: {}",
+            link.name,
+            if link.carrier { "up" } else { "down" },
+            link.duplex.as_deref().unwrap_or("unknown")
+        );
+    }
     let connections = realtime_connections();
     info!("CONNS: TCP: {} | UDP: {}", connections.tcp, connections.udp);
 
@@ -0,0 +1,110 @@
+//! Warnings and errors copied to the Windows Event Log (`--event-log`).
+//!
+//! An agent running unattended, as a service above all, has no console anyone reads. With
+//! `--event-log` every warning and error also goes to the Application log under the source
+//! `komari-monitor-rs`, next to the console output. The source has no message file, so Event
+//! Viewer prefixes the text with a note that the description of event 0 cannot be found.
+
+use log::{Log, Metadata, Record};
+
+#[cfg(target_os = "windows")]
+mod imp {
+    use log::{Level, Record};
+    use std::ffi::c_void;
+
+    const SOURCE: &str = "komari-monitor-rs";
+    const EVENTLOG_ERROR_TYPE: u16 = 0x0001;
+    const EVENTLOG_WARNING_TYPE: u16 = 0x0002;
+
+    #[link(name = "advapi32")]
+    unsafe extern "system" {
+        fn RegisterEventSourceW(server: *const u16, source: *const u16) -> *mut c_void;
+        fn ReportEventW(
+            event_log: *mut c_void,
+            kind: u16,
+            category: u16,
+            event_id: u32,
+            user_sid: *const c_void,
+            strings: u16,
+            data_size: u32,
+            messages: *const *const u16,
+            data: *const c_void,
+        ) -> i32;
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    /// Handle of the event source, `None` when it could not be registered. An address so
+    /// that the logger stays `Send` and `Sync`
+    pub struct Source(Option<usize>);
+
+    impl Source {
+        pub fn register() -> Self {
+            let name = wide(SOURCE);
+            let handle = unsafe { RegisterEventSourceW(std::ptr::null(), name.as_ptr()) };
+            Self((!handle.is_null()).then(|| handle.expose_provenance()))
+        }
+
+        pub fn report(&self, record: &Record) {
+            let kind = match record.level() {
+                Level::Error => EVENTLOG_ERROR_TYPE,
+                Level::Warn => EVENTLOG_WARNING_TYPE,
+                _ => return,
+            };
+            let Some(handle) = self.0 else {
+                return;
+            };
+            let message = wide(&format!("[{}] {}", record.target(), record.args()));
+            let messages = [message.as_ptr()];
+            unsafe {
+                ReportEventW(
+                    std::ptr::with_exposed_provenance_mut(handle),
+                    kind,
+                    0,
+                    0,
+                    std::ptr::null(),
+                    1,
+                    0,
+                    messages.as_ptr(),
+                    std::ptr::null(),
+                );
+            }
+        }
+    }
+}
+
+/// The console logger with the warnings and errors also written to the Event Log, only the
+/// console elsewhere
+pub struct EventLog {
+    console: Box<dyn Log>,
+    #[cfg(target_os = "windows")]
+    source: imp::Source,
+}
+
+impl EventLog {
+    pub fn new(console: Box<dyn Log>) -> Self {
+        Self {
+            console,
+            #[cfg(target_os = "windows")]
+            source: imp::Source::register(),
+        }
+    }
+}
+
+impl Log for EventLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.console.log(record);
+        #[cfg(target_os = "windows")]
+        self.source.report(record);
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+    }
+}
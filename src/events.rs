@@ -0,0 +1,26 @@
+//! Event queue drained into the `message` field of the next Real-Time Info push
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Oldest events are dropped once the queue is full, e.g. while the server is unreachable
+const MAX_EVENTS: usize = 100;
+
+static EVENTS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+pub fn push_event(event: String) {
+    if let Ok(mut events) = EVENTS.lock() {
+        if events.len() >= MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+}
+
+/// Take all queued events as one newline separated message
+pub fn take_events() -> String {
+    EVENTS.lock().map_or_else(
+        |_| String::new(),
+        |mut events| events.drain(..).collect::<Vec<_>>().join("\n"),
+    )
+}
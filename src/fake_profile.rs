@@ -0,0 +1,173 @@
+//! Realistic demo data (`--fake-profile`).
+//!
+//! The profile file holds `metric.setting=value` lines applied on top of `--fake` to every
+//! Real-Time Info, e.g. `cpu.baseline=20`, `cpu.daily=15` and `cpu.jitter=5`. Each metric
+//! becomes `value * multiplier + baseline + daily * cos(hour - peak) + jitter * noise`,
+//! the daily wave peaks at `peak-hour` (local time, default 20). Metrics: `cpu` (%),
+//! `ram`, `swap` and `disk` (their settings in % of the total), `load`, `up` and `down`
+//! (bytes/s, the multiplier also scales the totals), `tcp`, `udp` and `process`.
+
+use crate::data_struct::RealTimeInfo;
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use std::sync::{Mutex, OnceLock};
+use time::OffsetDateTime;
+
+const METRICS: [&str; 10] = [
+    "cpu", "ram", "swap", "disk", "load", "up", "down", "tcp", "udp", "process",
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Shape {
+    multiplier: f64,
+    baseline: f64,
+    jitter: f64,
+    daily: f64,
+}
+
+impl Default for Shape {
+    fn default() -> Self {
+        Self {
+            multiplier: 1.0,
+            baseline: 0.0,
+            jitter: 0.0,
+            daily: 0.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FakeProfile {
+    shapes: HashMap<String, Shape>,
+    peak_hour: f64,
+}
+
+static PROFILE: OnceLock<FakeProfile> = OnceLock::new();
+/// xorshift state for the jitter, demo data needs no better randomness
+static NOISE: Mutex<u64> = Mutex::new(0);
+
+/// Read the profile at `path`, every later Real-Time Info is shaped by it
+pub fn load_fake_profile(path: &str) -> Result<(), String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read fake profile {path}: {e}"))?;
+
+    let mut profile = FakeProfile {
+        shapes: HashMap::new(),
+        peak_hour: 20.0,
+    };
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("Format error: expected key=value, got '{line}'"))?;
+        let key = key.trim();
+        let value: f64 = value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|value: &f64| value.is_finite())
+            .ok_or_else(|| format!("Invalid number for key '{key}'"))?;
+
+        if key == "peak-hour" {
+            profile.peak_hour = value;
+            continue;
+        }
+        let (metric, setting) = key
+            .split_once('.')
+            .filter(|(metric, _)| METRICS.contains(metric))
+            .ok_or_else(|| {
+                format!(
+                    "Unknown key '{key}', expected <metric>.<setting> with a metric of {}",
+                    METRICS.join(", ")
+                )
+            })?;
+        let shape = profile.shapes.entry(metric.to_string()).or_default();
+        match setting {
+            "multiplier" => shape.multiplier = value,
+            "baseline" => shape.baseline = value,
+            "jitter" => shape.jitter = value,
+            "daily" => shape.daily = value,
+            _ => {
+                return Err(format!(
+                    "Unknown setting '{setting}', expected multiplier, baseline, jitter or daily"
+                ));
+            }
+        }
+    }
+
+    if let Ok(mut noise) = NOISE.lock() {
+        *noise = OffsetDateTime::now_utc().unix_timestamp_nanos() as u64 | 1;
+    }
+    let _ = PROFILE.set(profile);
+    Ok(())
+}
+
+/// Uniform in -1..1
+fn noise() -> f64 {
+    let Ok(mut state) = NOISE.lock() else {
+        return 0.0;
+    };
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    (x >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+}
+
+impl FakeProfile {
+    /// `value` shaped as `metric`, the additive parts scaled by `unit`
+    fn shape(&self, metric: &str, value: f64, unit: f64, daily_phase: f64) -> f64 {
+        let Some(shape) = self.shapes.get(metric) else {
+            return value;
+        };
+        let offset = shape.baseline + shape.daily * daily_phase + shape.jitter * noise();
+        (value * shape.multiplier + offset * unit).max(0.0)
+    }
+
+    fn multiplier(&self, metric: &str) -> f64 {
+        self.shapes
+            .get(metric)
+            .map_or(1.0, |shape| shape.multiplier)
+    }
+}
+
+/// Shape the core metrics of `real_time` with the loaded profile, if any
+pub fn apply_fake_profile(real_time: &mut RealTimeInfo) {
+    let Some(profile) = PROFILE.get() else {
+        return;
+    };
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let hour = f64::from(now.hour()) + f64::from(now.minute()) / 60.0;
+    let phase = (TAU * (hour - profile.peak_hour) / 24.0).cos();
+
+    real_time.cpu.usage = profile
+        .shape("cpu", real_time.cpu.usage, 1.0, phase)
+        .min(100.0);
+
+    let used_share = |metric: &str, used: u64, total: u64| {
+        let total_f = total as f64;
+        (profile.shape(metric, used as f64, total_f / 100.0, phase) as u64).min(total)
+    };
+    real_time.ram.used = used_share("ram", real_time.ram.used, real_time.ram.total);
+    real_time.swap.used = used_share("swap", real_time.swap.used, real_time.swap.total);
+    real_time.disk.used = used_share("disk", real_time.disk.used, real_time.disk.total);
+
+    let load = &mut real_time.load;
+    load.load1 = profile.shape("load", load.load1, 1.0, phase);
+    load.load5 = profile.shape("load", load.load5, 1.0, phase);
+    load.load15 = profile.shape("load", load.load15, 1.0, phase);
+
+    let count = |metric: &str, value: u64| profile.shape(metric, value as f64, 1.0, phase) as u64;
+    let network = &mut real_time.network;
+    network.up = count("up", network.up);
+    network.down = count("down", network.down);
+    network.total_up = (network.total_up as f64 * profile.multiplier("up")) as u64;
+    network.total_down = (network.total_down as f64 * profile.multiplier("down")) as u64;
+    real_time.connections.tcp = count("tcp", real_time.connections.tcp);
+    real_time.connections.udp = count("udp", real_time.connections.udp);
+    real_time.process = count("process", real_time.process);
+}
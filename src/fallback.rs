@@ -0,0 +1,187 @@
+//! HTTP transport for networks that block the WebSocket (`--http-fallback-after`).
+//!
+//! Once that many WebSocket connections failed in a row, every frame the agent would send on
+//! the Real-Time Info WebSocket, Real-Time Info and task results alike, is sent as a POST to
+//! `/api/clients/report` and the messages the server would send on it are long-polled from
+//! `/api/clients/tasks`. That endpoint holds the request up to `wait` seconds and answers
+//! with a JSON array of messages, empty (or `204`) when none came. The WebSocket is tried
+//! again every `--http-fallback-retry` seconds and replaces the fallback once it connects.
+
+use crate::auth::panel_headers;
+use crate::callbacks::{Reader, Writer};
+use crate::http_summary::post;
+use crate::signing::sign_message;
+use crate::utils::{ConnectionUrls, timeouts};
+use log::{info, warn};
+use miniserde::json::{self, Value};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+
+/// How long the server may hold a poll
+const POLL_WAIT: Duration = Duration::from_secs(30);
+/// Pause after a failed poll
+const POLL_RETRY: Duration = Duration::from_secs(5);
+
+/// Counts the WebSocket failures and decides which transport the next connection uses
+#[derive(Debug)]
+pub struct Fallback {
+    /// Failures in a row that engage the fallback, 0 never does
+    after: u32,
+    retry: Duration,
+    failures: u32,
+    /// When the fallback was engaged or the WebSocket last failed while engaged
+    since: Option<Instant>,
+}
+
+impl Fallback {
+    pub fn new(after: u32, retry_s: u64) -> Self {
+        Self {
+            after,
+            retry: Duration::from_secs(retry_s.max(1)),
+            failures: 0,
+            since: None,
+        }
+    }
+
+    /// Whether the next connection goes over HTTP
+    pub fn active(&self) -> bool {
+        self.since.is_some()
+    }
+
+    /// Whether the WebSocket is due for another try while the fallback is engaged
+    pub fn retry_due(&self) -> bool {
+        self.since
+            .is_some_and(|since| since.elapsed() >= self.retry)
+    }
+
+    pub fn ws_failed(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+        if self.after == 0 || self.failures < self.after {
+            return;
+        }
+        if self.since.is_none() {
+            warn!(
+                "{} WebSocket connections failed in a row, falling back to HTTP polling",
+                self.failures
+            );
+        }
+        self.since = Some(Instant::now());
+    }
+
+    pub fn ws_connected(&mut self) {
+        if self.since.take().is_some() {
+            info!("WebSocket connected again, leaving the HTTP fallback");
+        }
+        self.failures = 0;
+    }
+}
+
+/// Writer and reader over HTTP in place of the Real-Time Info WebSocket
+pub fn connect_http(
+    connection_urls: &ConnectionUrls,
+    ignore_unsafe_cert: bool,
+) -> (Writer, Reader) {
+    let report_url = connection_urls.http_real_time.clone();
+    let writer = futures::sink::unfold((), move |(), message: Message| {
+        let url = report_url.clone();
+        async move {
+            // Pings and the like have no HTTP counterpart
+            let Message::Text(text) = sign_message(message) else {
+                return Ok(());
+            };
+            tokio::task::spawn_blocking(move || post(&url, text.as_str(), ignore_unsafe_cert))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+        }
+    });
+
+    let tasks_url = connection_urls.http_tasks.clone();
+    let reader = futures::stream::unfold(VecDeque::new(), move |mut pending| {
+        let url = tasks_url.clone();
+        async move {
+            loop {
+                if let Some(message) = pending.pop_front() {
+                    return Some((Ok(message), pending));
+                }
+                let url = url.clone();
+                match tokio::task::spawn_blocking(move || poll(&url, ignore_unsafe_cert))
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string()))
+                {
+                    Ok(messages) => pending.extend(messages),
+                    Err(e) => {
+                        warn!("Failed to poll the server for tasks: {e}");
+                        tokio::time::sleep(POLL_RETRY).await;
+                    }
+                }
+            }
+        }
+    });
+
+    (Box::pin(writer), Box::pin(reader))
+}
+
+/// The messages of a poll response, an array of them or a single one
+fn parse_messages(body: &str) -> Result<Vec<Message>, String> {
+    if body.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let messages = match json::from_str::<Value>(body) {
+        Ok(Value::Array(messages)) => messages.to_vec(),
+        Ok(message @ Value::Object(_)) => vec![message],
+        _ => return Err("The poll response is no JSON array of messages".to_string()),
+    };
+    Ok(messages
+        .iter()
+        .map(|message| Message::Text(Utf8Bytes::from(json::to_string(message))))
+        .collect())
+}
+
+fn poll_url(url: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}wait={}", POLL_WAIT.as_secs())
+}
+
+#[cfg(feature = "ureq-support")]
+fn poll(url: &str, ignore_unsafe_cert: bool) -> Result<Vec<Message>, String> {
+    let agent = crate::utils::create_ureq_agent(ignore_unsafe_cert);
+    let mut request = agent
+        .get(poll_url(url))
+        .header("User-Agent", "curl/11.45.14-rs");
+    for (name, value) in panel_headers() {
+        request = request.header(name, value);
+    }
+    let mut resp = request
+        .config()
+        .timeout_global(Some(POLL_WAIT + timeouts().total()))
+        .build()
+        .call()
+        .map_err(|e| e.to_string())?;
+    let body = resp
+        .body_mut()
+        .read_to_string()
+        .map_err(|e| e.to_string())?;
+    parse_messages(&body)
+}
+
+#[cfg(feature = "nyquest-support")]
+fn poll(url: &str, ignore_unsafe_cert: bool) -> Result<Vec<Message>, String> {
+    use nyquest::Request;
+    let mut client = nyquest::ClientBuilder::default()
+        .request_timeout(POLL_WAIT + timeouts().total())
+        .user_agent("curl/8.7.1");
+    if ignore_unsafe_cert {
+        client = client.dangerously_ignore_certificate_errors();
+    }
+    let client = client.build_blocking().map_err(|e| e.to_string())?;
+    let mut request = Request::get(poll_url(url));
+    for (name, value) in panel_headers() {
+        request = request.with_header(name, value);
+    }
+    let resp = client.request(request).map_err(|e| e.to_string())?;
+    if !resp.status().is_successful() {
+        return Err(format!("HTTP status code: {}", resp.status()));
+    }
+    parse_messages(&resp.text().map_err(|e| e.to_string())?)
+}
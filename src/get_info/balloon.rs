@@ -0,0 +1,87 @@
+//! virtio memory balloon of KVM guests.
+//!
+//! The host reclaims guest memory by having the `virtio_balloon` driver inflate, the
+//! driver converges on the target of the host within a few seconds. Without the
+//! `DEFLATE_ON_OOM` feature the inflated pages are taken off `MemTotal` already. With it
+//! they stay counted in `MemTotal` and show as used, so the total reported would be the
+//! static boot value. Only then the balloon is taken off the reported memory.
+//!
+//! The inflated size is read from the driver's debugfs file, or from the balloon
+//! counters of `/proc/vmstat` without debugfs. The features are negotiated once when the
+//! driver binds, they are only looked up on the first sample.
+
+/// Bit of `VIRTIO_BALLOON_F_DEFLATE_ON_OOM` in the device features
+#[cfg(target_os = "linux")]
+const DEFLATE_ON_OOM: usize = 2;
+
+#[cfg(target_os = "linux")]
+static DEFLATE: std::sync::OnceLock<Option<bool>> = std::sync::OnceLock::new();
+
+/// Whether a balloon device is bound with `DEFLATE_ON_OOM`, `None` without a balloon
+#[cfg(target_os = "linux")]
+fn deflate_on_oom() -> Option<bool> {
+    *DEFLATE.get_or_init(read_deflate_on_oom)
+}
+
+#[cfg(target_os = "linux")]
+fn read_deflate_on_oom() -> Option<bool> {
+    let devices = std::fs::read_dir("/sys/bus/virtio/drivers/virtio_balloon").ok()?;
+    for entry in devices.flatten() {
+        let is_device = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with("virtio"));
+        if !is_device {
+            continue;
+        }
+        // One character per feature bit, `1` when negotiated
+        let features = std::fs::read_to_string(entry.path().join("features")).ok()?;
+        return Some(features.as_bytes().get(DEFLATE_ON_OOM) == Some(&b'1'));
+    }
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn inflated_pages() -> Option<u64> {
+    use super::with_file;
+
+    let pages = with_file("/sys/kernel/debug/virtio-balloon", |debugfs| {
+        debugfs.lines().find_map(|line| {
+            line.strip_prefix("inflated_pages:")
+                .and_then(|pages| pages.trim().parse::<u64>().ok())
+        })
+    });
+    if pages.is_some() {
+        return pages;
+    }
+
+    with_file("/proc/vmstat", |vmstat| {
+        let counter = |name: &str| {
+            vmstat.lines().find_map(|line| {
+                let (key, value) = line.split_once(' ')?;
+                if key != name {
+                    return None;
+                }
+                value.trim().parse::<u64>().ok()
+            })
+        };
+        Some(counter("balloon_inflate")?.saturating_sub(counter("balloon_deflate")?))
+    })
+}
+
+/// Bytes held by the balloon that are still counted in the total memory, `None` when
+/// there is no balloon or nothing to take off
+#[cfg(target_os = "linux")]
+pub fn balloon_bytes() -> Option<u64> {
+    if !deflate_on_oom()? {
+        return None;
+    }
+    let page_size = u64::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).ok()?;
+    let bytes = inflated_pages()? * page_size;
+    (bytes > 0).then_some(bytes)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn balloon_bytes() -> Option<u64> {
+    None
+}
@@ -0,0 +1,188 @@
+//! CPU and memory of named cgroups (`--cgroup`, Linux only).
+//!
+//! A cgroup is given by its path below the cgroup root, e.g. `system.slice/nginx.service`,
+//! and read from the unified hierarchy (v2) or from the `cpuacct` and `memory` controllers
+//! (v1). CPU is the share of all CPUs used since the previous refresh, memory leaves out
+//! the inactive page cache the kernel can reclaim, as `docker stats` does. Readings are
+//! cached for 10 s.
+
+use crate::data_struct::CgroupUsage;
+use log::{trace, warn};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// v1 reports an unlimited cgroup with a limit near `i64::MAX`, rounded to the page size
+const UNLIMITED_V1: u64 = 1 << 62;
+
+/// A `--cgroup` as `[NAME=]PATH`
+#[derive(Debug, Clone)]
+pub struct CgroupTarget {
+    pub name: String,
+    /// Below the cgroup root, without a leading `/`
+    pub path: String,
+}
+
+struct Cgroup {
+    target: CgroupTarget,
+    /// CPU time in ns at the previous refresh
+    previous: Option<(u64, Instant)>,
+    latest: Option<CgroupUsage>,
+}
+
+struct CgroupState {
+    /// The unified hierarchy, v1 otherwise
+    unified: bool,
+    cpus: f64,
+    cgroups: Vec<Cgroup>,
+    refreshed_at: Option<Instant>,
+}
+
+static CGROUPS: Mutex<Option<CgroupState>> = Mutex::new(None);
+
+/// Parse `[NAME=]PATH` specs
+pub fn parse_cgroups(specs: &[String]) -> Result<Vec<CgroupTarget>, String> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (name, path) = match spec.split_once('=') {
+                Some((name, path)) if !name.trim().is_empty() => (Some(name.trim()), path),
+                _ => (None, spec.as_str()),
+            };
+            let path = path.trim().trim_matches('/');
+            if path.is_empty() || path.split('/').any(|part| part == "..") {
+                return Err(format!(
+                    "Invalid cgroup '{spec}', expected [NAME=]PATH below {CGROUP_ROOT}"
+                ));
+            }
+            Ok(CgroupTarget {
+                name: name.unwrap_or(path).to_string(),
+                path: path.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn init_cgroups(targets: Vec<CgroupTarget>) {
+    if cfg!(not(target_os = "linux")) {
+        warn!("`--cgroup` is only supported on Linux");
+        return;
+    }
+    let unified = std::path::Path::new(CGROUP_ROOT)
+        .join("cgroup.controllers")
+        .exists();
+    for target in &targets {
+        let path = if unified {
+            PathBuf::from(CGROUP_ROOT).join(&target.path)
+        } else {
+            PathBuf::from(CGROUP_ROOT).join("memory").join(&target.path)
+        };
+        if !path.is_dir() {
+            warn!("cgroup {} not found at {}", target.name, path.display());
+        }
+    }
+    let cpus = std::thread::available_parallelism().map_or(1, std::num::NonZero::get) as f64;
+    if let Ok(mut cgroups) = CGROUPS.lock() {
+        *cgroups = Some(CgroupState {
+            unified,
+            cpus,
+            cgroups: targets
+                .into_iter()
+                .map(|target| Cgroup {
+                    target,
+                    previous: None,
+                    latest: None,
+                })
+                .collect(),
+            refreshed_at: None,
+        });
+    }
+}
+
+/// Value of `key` in a flat keyed file such as `cpu.stat` or `memory.stat`
+fn stat_value(content: &str, key: &str) -> Option<u64> {
+    content.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        if name != key {
+            return None;
+        }
+        value.trim().parse().ok()
+    })
+}
+
+fn read_u64(path: PathBuf) -> Option<u64> {
+    super::with_file(path, |value| value.trim().parse().ok())
+}
+
+/// CPU time in ns, memory in use and the memory limit of a cgroup
+fn read_usage(unified: bool, path: &str) -> (Option<u64>, Option<u64>, Option<u64>) {
+    let root = PathBuf::from(CGROUP_ROOT);
+    if unified {
+        let dir = root.join(path);
+        let cpu = super::with_file(dir.join("cpu.stat"), |stat| stat_value(stat, "usage_usec"))
+            .map(|usec| usec * 1000);
+        let inactive = super::with_file(dir.join("memory.stat"), |stat| {
+            stat_value(stat, "inactive_file")
+        });
+        let memory = read_u64(dir.join("memory.current"))
+            .map(|current| current.saturating_sub(inactive.unwrap_or(0)));
+        // `max` when unlimited
+        let limit = read_u64(dir.join("memory.max"));
+        (cpu, memory, limit)
+    } else {
+        let cpu = read_u64(root.join("cpuacct").join(path).join("cpuacct.usage"));
+        let dir = root.join("memory").join(path);
+        let inactive = super::with_file(dir.join("memory.stat"), |stat| {
+            stat_value(stat, "total_inactive_file")
+        });
+        let memory = read_u64(dir.join("memory.usage_in_bytes"))
+            .map(|usage| usage.saturating_sub(inactive.unwrap_or(0)));
+        let limit =
+            read_u64(dir.join("memory.limit_in_bytes")).filter(|limit| *limit < UNLIMITED_V1);
+        (cpu, memory, limit)
+    }
+}
+
+pub fn realtime_cgroups() -> Option<Vec<CgroupUsage>> {
+    let mut guard = CGROUPS.lock().ok()?;
+    let state = guard.as_mut()?;
+
+    if state
+        .refreshed_at
+        .is_none_or(|refreshed_at| refreshed_at.elapsed() >= REFRESH_INTERVAL)
+    {
+        let now = Instant::now();
+        for cgroup in &mut state.cgroups {
+            let (cpu_time, memory, memory_limit) = read_usage(state.unified, &cgroup.target.path);
+            let cpu = match (cgroup.previous, cpu_time) {
+                (Some((previous, at)), Some(cpu_time)) => {
+                    let elapsed = now.duration_since(at).as_nanos() as f64 * state.cpus;
+                    (elapsed > 0.0)
+                        .then(|| cpu_time.saturating_sub(previous) as f64 / elapsed * 100.0)
+                }
+                _ => None,
+            };
+            cgroup.previous = cpu_time.map(|cpu_time| (cpu_time, now));
+            cgroup.latest = Some(CgroupUsage {
+                name: cgroup.target.name.clone(),
+                path: cgroup.target.path.clone(),
+                found: cpu_time.is_some() || memory.is_some(),
+                cpu,
+                memory,
+                memory_limit,
+            });
+        }
+        state.refreshed_at = Some(now);
+        trace!("REALTIME CGROUPS successfully retrieved");
+    }
+
+    Some(
+        state
+            .cgroups
+            .iter()
+            .filter_map(|cgroup| cgroup.latest.clone())
+            .collect(),
+    )
+}
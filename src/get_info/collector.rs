@@ -0,0 +1,263 @@
+//! State carried between two samples.
+//!
+//! sysinfo computes CPU usage and network speed from the difference with the previous
+//! refresh, so the handles have to live as long as the sampling loop does. Everything a
+//! sample depends on is owned here and passed explicitly to `RealTimeInfo::build`.
+
+use crate::data_struct::{LinkInfo, Network};
+use crate::events::push_event;
+use crate::get_info::balloon::balloon_bytes;
+#[cfg(target_os = "windows")]
+use crate::get_info::cpu::PerfCounters;
+#[cfg(not(target_os = "windows"))]
+use crate::get_info::cpu::switch_rates;
+use crate::get_info::cpu::{CpuTimes, SwitchRates, cpu_times, steal_percent};
+use crate::get_info::filters::filter_disks;
+use crate::get_info::mem::disk_disabled;
+use crate::get_info::network::link::{link_changes, link_info};
+use crate::get_info::network::wrap::CounterWraps;
+use crate::get_info::network::{TrafficOffset, realtime_network};
+use crate::get_info::suspend::SuspendDetector;
+use log::info;
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sysinfo::{CpuRefreshKind, DiskRefreshKind, Disks, MemoryRefreshKind, Networks, RefreshKind};
+
+/// Links are read with a netlink dump and an ioctl each, not on every refresh
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct MetricsCollector {
+    pub sysinfo_sys: sysinfo::System,
+    pub networks: Networks,
+    /// Wraps and resets of the interface counters seen by `networks`
+    pub counter_wraps: CounterWraps,
+    pub disks: Disks,
+    /// Shared with `network_saver`, which updates it at the start of every cycle
+    pub traffic_offset: Arc<TrafficOffset>,
+    /// Time covered by the latest refresh, measured so rates stay right when the pace changes
+    pub interval_ms: u64,
+    last_refresh: Instant,
+    suspend: SuspendDetector,
+    /// The latest refresh spans a suspend, its rates are meaningless
+    spans_suspend: bool,
+    /// Seconds suspended not reported yet, kept across refreshes until a sample takes them
+    resumed_after: Cell<Option<u64>>,
+    /// Mount points of the counted disks, `None` before the first refresh
+    mounts: Option<Vec<String>>,
+    /// A volume was attached or detached since the last `take_disks_changed`
+    disks_changed: bool,
+    /// Physical links at the latest check, `None` before the first one
+    links: Option<Vec<LinkInfo>>,
+    links_checked_at: Instant,
+    /// A link changed speed, duplex or carrier since the last `take_links_changed`
+    links_changed: bool,
+    /// Balloon size at the previous refresh
+    balloon: Option<u64>,
+    /// Latest balloon size that held for two refreshes
+    settled_balloon: Option<u64>,
+    /// `/proc/stat` times at the latest refresh
+    cpu_times: Option<CpuTimes>,
+    /// Steal time over the latest refresh (%)
+    steal: Option<f64>,
+    #[cfg(target_os = "windows")]
+    perf_counters: Option<PerfCounters>,
+    /// Context switches and interrupts over the latest refresh
+    switches: Option<SwitchRates>,
+}
+
+impl MetricsCollector {
+    pub fn new(traffic_offset: Arc<TrafficOffset>) -> Self {
+        let mut sysinfo_sys = sysinfo::System::new();
+        sysinfo_sys.refresh_cpu_list(
+            CpuRefreshKind::nothing()
+                .without_cpu_usage()
+                .without_frequency(),
+        );
+        sysinfo_sys.refresh_memory_specifics(MemoryRefreshKind::everything());
+
+        let balloon = balloon_bytes();
+
+        Self {
+            sysinfo_sys,
+            networks: Networks::new_with_refreshed_list(),
+            counter_wraps: CounterWraps::default(),
+            disks: Disks::new(),
+            traffic_offset,
+            interval_ms: 0,
+            last_refresh: Instant::now(),
+            suspend: SuspendDetector::default(),
+            spans_suspend: false,
+            resumed_after: Cell::new(None),
+            mounts: None,
+            disks_changed: false,
+            links: None,
+            links_checked_at: Instant::now(),
+            links_changed: false,
+            balloon,
+            settled_balloon: balloon,
+            cpu_times: cpu_times(),
+            steal: None,
+            #[cfg(target_os = "windows")]
+            perf_counters: PerfCounters::open(),
+            switches: None,
+        }
+    }
+
+    /// Refresh every handle, the next sample covers the time since the previous refresh
+    pub fn refresh(&mut self) {
+        self.sysinfo_sys.refresh_specifics(
+            RefreshKind::nothing()
+                .with_cpu(CpuRefreshKind::everything().without_frequency())
+                .with_memory(MemoryRefreshKind::everything()),
+        );
+        self.networks.refresh(true);
+        self.counter_wraps.update(&self.networks);
+        self.interval_ms = u64::try_from(self.last_refresh.elapsed().as_millis()).unwrap_or(0);
+        self.last_refresh = Instant::now();
+        let slept = self.suspend.check();
+        self.spans_suspend = slept.is_some();
+        if let Some(slept) = slept {
+            let pending = self.resumed_after.get().unwrap_or(0);
+            self.resumed_after.set(Some(pending + slept.as_secs()));
+        }
+        if !disk_disabled() {
+            self.disks
+                .refresh_specifics(true, DiskRefreshKind::nothing().with_storage());
+            self.check_mounts();
+        }
+        self.check_links();
+        self.check_balloon();
+        let cpu_times = cpu_times();
+        self.steal = self
+            .cpu_times
+            .zip(cpu_times)
+            .and_then(|(previous, current)| steal_percent(previous, current));
+        #[cfg(target_os = "windows")]
+        let switches = self.perf_counters.as_ref().and_then(PerfCounters::rates);
+        #[cfg(not(target_os = "windows"))]
+        let switches = self
+            .cpu_times
+            .zip(cpu_times)
+            .and_then(|(previous, current)| {
+                switch_rates(previous, current, Duration::from_millis(self.interval_ms))
+            });
+        self.switches = switches.filter(|_| !self.spans_suspend);
+        self.cpu_times = cpu_times;
+    }
+
+    fn check_mounts(&mut self) {
+        let mut mounts = filter_disks(&self.disks)
+            .iter()
+            .map(|disk| disk.mount_point().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        mounts.sort_unstable();
+
+        if let Some(previous) = &self.mounts {
+            if *previous == mounts {
+                return;
+            }
+            for mount in mounts.iter().filter(|mount| !previous.contains(mount)) {
+                info!("Disk attached: {mount}");
+            }
+            for mount in previous.iter().filter(|mount| !mounts.contains(mount)) {
+                info!("Disk detached: {mount}");
+            }
+            self.disks_changed = true;
+        }
+        self.mounts = Some(mounts);
+    }
+
+    /// A renegotiated link is queued as an event, e.g. a 1000 Mb/s one that came back at 100
+    fn check_links(&mut self) {
+        if self.links.is_some() && self.links_checked_at.elapsed() < LINK_CHECK_INTERVAL {
+            return;
+        }
+        self.links_checked_at = Instant::now();
+        let links = link_info();
+        if let Some(previous) = &self.links {
+            let changes = link_changes(previous, &links);
+            for change in &changes {
+                info!("Link changed: {change}");
+                push_event(format!("[link] {change}"));
+            }
+            self.links_changed |= !changes.is_empty();
+        }
+        self.links = Some(links);
+    }
+
+    /// The driver inflates in steps, a size only counts once it held for two refreshes
+    fn check_balloon(&mut self) {
+        let balloon = balloon_bytes();
+        if balloon == self.balloon && balloon != self.settled_balloon {
+            info!(
+                "Memory balloon settled at {} MB",
+                balloon.unwrap_or(0) / 1000 / 1000
+            );
+            self.settled_balloon = balloon;
+        }
+        self.balloon = balloon;
+    }
+
+    pub fn network(&self) -> Network {
+        let mut network = realtime_network(
+            &self.networks,
+            &self.counter_wraps,
+            self.interval_ms,
+            &self.traffic_offset,
+        );
+        // Counters jump when interfaces come back, divided by an interval that left the
+        // sleep out they would show as a huge spike
+        if self.spans_suspend {
+            network.up = 0;
+            network.down = 0;
+        }
+        network
+    }
+
+    /// Whether the counted disks changed since the previous call, `disk_total` of the
+    /// Basic Info is outdated then
+    pub fn take_disks_changed(&mut self) -> bool {
+        std::mem::take(&mut self.disks_changed)
+    }
+
+    /// Whether a link changed since the previous call, `interfaces` of the Basic Info is
+    /// outdated then
+    pub fn take_links_changed(&mut self) -> bool {
+        std::mem::take(&mut self.links_changed)
+    }
+
+    /// Physical links at the latest check
+    pub fn links(&self) -> Vec<LinkInfo> {
+        self.links.clone().unwrap_or_else(link_info)
+    }
+
+    /// Bytes held by the balloon, `mem_total` of the Basic Info is outdated when it was
+    /// built with another size
+    pub fn settled_balloon(&self) -> Option<u64> {
+        self.settled_balloon
+    }
+
+    /// Total memory with the settled balloon taken off
+    pub fn mem_total(&self) -> u64 {
+        self.sysinfo_sys
+            .total_memory()
+            .saturating_sub(self.settled_balloon.unwrap_or(0))
+    }
+
+    /// CPU steal time over the latest refresh (%), `None` outside Linux
+    pub fn steal(&self) -> Option<f64> {
+        self.steal
+    }
+
+    /// Context switches and interrupts per second over the latest refresh, `None` outside
+    /// Linux and Windows
+    pub fn switches(&self) -> Option<SwitchRates> {
+        self.switches
+    }
+
+    /// Seconds the machine was suspended before this sample, reported once
+    pub fn take_resumed_after(&self) -> Option<u64> {
+        self.resumed_after.take()
+    }
+}
@@ -1,6 +1,7 @@
 use crate::data_struct::Cpu;
 use log::trace;
 use std::collections::HashSet;
+use std::time::Duration;
 use sysinfo::System;
 
 pub fn arch() -> String {
@@ -43,7 +44,205 @@ pub fn realtime_cpu(sysinfo_sys: &System) -> Cpu {
     }
     let avg = f64::from(avg) / cpus.len() as f64;
 
-    let cpu = Cpu { usage: avg };
+    let cpu = Cpu {
+        usage: avg,
+        steal: None,
+        context_switches: None,
+        interrupts: None,
+    };
     trace!("REALTIME CPU successfully retrieved: {cpu:?}");
     cpu
 }
+
+/// Cumulative counters of `/proc/stat`: the time of all CPUs from the `cpu` line in clock
+/// ticks, the context switches from `ctxt` and the interrupts from `intr`
+#[derive(Debug, Clone, Copy)]
+pub struct CpuTimes {
+    pub steal: u64,
+    pub total: u64,
+    pub context_switches: Option<u64>,
+    pub interrupts: Option<u64>,
+}
+
+/// Context switches and interrupts per second over the latest refresh
+#[derive(Debug, Clone, Copy)]
+pub struct SwitchRates {
+    pub context_switches: f64,
+    pub interrupts: f64,
+}
+
+#[cfg(target_os = "linux")]
+pub fn cpu_times() -> Option<CpuTimes> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let mut fields = stat.lines().next()?.split_whitespace();
+    if fields.next()? != "cpu" {
+        return None;
+    }
+    // user nice system idle iowait irq softirq steal, guest time is part of user
+    let ticks = fields
+        .take(8)
+        .map(|field| field.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    // Kernels before 2.6.11 have no steal column
+    let steal = *ticks.get(7)?;
+    // The first number of `intr` is the total, the others are per interrupt line
+    let counter = |name: &str| {
+        stat.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .and_then(|values| values.split_whitespace().next()?.parse().ok())
+    };
+    Some(CpuTimes {
+        steal,
+        total: ticks.iter().sum(),
+        context_switches: counter("ctxt"),
+        interrupts: counter("intr"),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn cpu_times() -> Option<CpuTimes> {
+    None
+}
+
+/// Share of the CPU time (%) the hypervisor gave to other guests between two readings
+pub fn steal_percent(previous: CpuTimes, current: CpuTimes) -> Option<f64> {
+    let total = current.total.checked_sub(previous.total)?;
+    let steal = current.steal.checked_sub(previous.steal)?;
+    (total > 0).then(|| steal as f64 / total as f64 * 100.0)
+}
+
+/// Context switches and interrupts per second between two readings `elapsed` apart
+pub fn switch_rates(
+    previous: CpuTimes,
+    current: CpuTimes,
+    elapsed: Duration,
+) -> Option<SwitchRates> {
+    let seconds = elapsed.as_secs_f64();
+    if seconds <= 0.0 {
+        return None;
+    }
+    let rate = |previous: Option<u64>, current: Option<u64>| {
+        Some(current?.checked_sub(previous?)? as f64 / seconds)
+    };
+    Some(SwitchRates {
+        context_switches: rate(previous.context_switches, current.context_switches)?,
+        interrupts: rate(previous.interrupts, current.interrupts)?,
+    })
+}
+
+#[cfg(target_os = "windows")]
+pub use perf::PerfCounters;
+
+/// The `System\Context Switches/sec` and `Processor(_Total)\Interrupts/sec` performance
+/// counters, read through PDH
+#[cfg(target_os = "windows")]
+mod perf {
+    use super::SwitchRates;
+    use std::ffi::c_void;
+
+    const CONTEXT_SWITCHES: &str = "\\System\\Context Switches/sec";
+    const INTERRUPTS: &str = "\\Processor(_Total)\\Interrupts/sec";
+    const PDH_FMT_DOUBLE: u32 = 0x0000_0200;
+    const ERROR_SUCCESS: u32 = 0;
+
+    /// `PDH_FMT_COUNTERVALUE` with the `double` member of its union
+    #[repr(C)]
+    struct FormattedValue {
+        status: u32,
+        value: f64,
+    }
+
+    #[link(name = "pdh")]
+    unsafe extern "system" {
+        fn PdhOpenQueryW(data_source: *const u16, user_data: usize, query: *mut *mut c_void)
+        -> u32;
+        fn PdhAddEnglishCounterW(
+            query: *mut c_void,
+            path: *const u16,
+            user_data: usize,
+            counter: *mut *mut c_void,
+        ) -> u32;
+        fn PdhCollectQueryData(query: *mut c_void) -> u32;
+        fn PdhGetFormattedCounterValue(
+            counter: *mut c_void,
+            format: u32,
+            kind: *mut u32,
+            value: *mut FormattedValue,
+        ) -> u32;
+        fn PdhCloseQuery(query: *mut c_void) -> u32;
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    /// Query handle and counter handles, addresses so that the collector stays `Send`
+    pub struct PerfCounters {
+        query: usize,
+        context_switches: usize,
+        interrupts: usize,
+    }
+
+    impl PerfCounters {
+        /// The query with its first collection done, rates need two
+        pub fn open() -> Option<Self> {
+            let mut query = std::ptr::null_mut();
+            if unsafe { PdhOpenQueryW(std::ptr::null(), 0, &raw mut query) } != ERROR_SUCCESS {
+                return None;
+            }
+            let add = |path: &str| {
+                let path = wide(path);
+                let mut counter = std::ptr::null_mut();
+                let status =
+                    unsafe { PdhAddEnglishCounterW(query, path.as_ptr(), 0, &raw mut counter) };
+                (status == ERROR_SUCCESS).then(|| counter.expose_provenance())
+            };
+            let (Some(context_switches), Some(interrupts)) =
+                (add(CONTEXT_SWITCHES), add(INTERRUPTS))
+            else {
+                unsafe { PdhCloseQuery(query) };
+                return None;
+            };
+            unsafe { PdhCollectQueryData(query) };
+            Some(Self {
+                query: query.expose_provenance(),
+                context_switches,
+                interrupts,
+            })
+        }
+
+        fn value(counter: usize) -> Option<f64> {
+            let mut value = FormattedValue {
+                status: 0,
+                value: 0.0,
+            };
+            let status = unsafe {
+                PdhGetFormattedCounterValue(
+                    std::ptr::with_exposed_provenance_mut(counter),
+                    PDH_FMT_DOUBLE,
+                    std::ptr::null_mut(),
+                    &raw mut value,
+                )
+            };
+            (status == ERROR_SUCCESS && value.status == ERROR_SUCCESS).then_some(value.value)
+        }
+
+        /// The rates since the previous call, or since `open`
+        pub fn rates(&self) -> Option<SwitchRates> {
+            let query = std::ptr::with_exposed_provenance_mut(self.query);
+            if unsafe { PdhCollectQueryData(query) } != ERROR_SUCCESS {
+                return None;
+            }
+            Some(SwitchRates {
+                context_switches: Self::value(self.context_switches)?,
+                interrupts: Self::value(self.interrupts)?,
+            })
+        }
+    }
+
+    impl Drop for PerfCounters {
+        fn drop(&mut self) {
+            unsafe { PdhCloseQuery(std::ptr::with_exposed_provenance_mut(self.query)) };
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! Which disks and network interfaces are counted.
+//!
+//! The collectors, `network_saver` and `dry_run` all decide through here: the dry run
+//! lists every disk and interface with the verdict the live loop uses, and why the
+//! skipped ones are left out.
+
+use log::trace;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{PoisonError, RwLock};
+use sysinfo::{Disk, Disks, NetworkData};
+
+const DEFAULT_ALLOWED_FS: &[&str] = &[
+    "apfs",
+    "ext4",
+    "ext3",
+    "ext2",
+    "f2fs",
+    "reiserfs",
+    "jfs",
+    "btrfs",
+    "fuseblk",
+    "zfs",
+    "simfs",
+    "ntfs",
+    "fat32",
+    "exfat",
+    "xfs",
+    "fuse.rclone",
+    "ubifs",
+];
+
+const DEFAULT_EXCLUDE_KEYWORDS: &[&str] = &[
+    "/snap",
+    "/var/lib/docker",
+    "/var/lib/lxcfs",
+    "/run/user",
+    "/tmp",
+    "/dev",
+    "/sys",
+    "/proc",
+    "/boot",
+    "/lost+found",
+    "/nix/store",
+    "/var/log.hdd",
+];
+
+#[derive(Debug)]
+struct DiskFilter {
+    allowed_fs: HashSet<String>,
+    exclude_keywords: HashSet<String>,
+}
+
+impl DiskFilter {
+    fn new(allowed_fs: Option<&str>, exclude_paths: Option<&str>) -> Self {
+        Self {
+            allowed_fs: override_list(DEFAULT_ALLOWED_FS, allowed_fs),
+            exclude_keywords: override_list(DEFAULT_EXCLUDE_KEYWORDS, exclude_paths),
+        }
+    }
+}
+
+static DISK_FILTER: RwLock<Option<DiskFilter>> = RwLock::new(None);
+
+/// Apply a comma separated override on top of the defaults:
+/// a leading `+` extends the defaults, otherwise the list replaces them
+fn override_list(defaults: &[&str], list: Option<&str>) -> HashSet<String> {
+    let defaults = defaults.iter().map(|s| (*s).to_string());
+    let Some(list) = list else {
+        return defaults.collect();
+    };
+
+    let (extend, list) = match list.strip_prefix('+') {
+        Some(list) => (true, list),
+        None => (false, list),
+    };
+    let items = list
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string);
+
+    if extend {
+        defaults.chain(items).collect()
+    } else {
+        items.collect()
+    }
+}
+
+/// Set the disk filter from `--disk-allowed-fs` / `--disk-exclude-paths`, again on every
+/// configuration reload
+pub fn set_disk_filter(allowed_fs: Option<&str>, exclude_paths: Option<&str>) {
+    let filter = DiskFilter::new(allowed_fs, exclude_paths);
+    trace!("Disk filter: {filter:?}");
+    if let Ok(mut disk_filter) = DISK_FILTER.write() {
+        *disk_filter = Some(filter);
+    }
+}
+
+/// Every disk with the reason it is skipped, `None` for the counted ones
+pub fn disk_verdicts(disks: &Disks) -> Vec<(&Disk, Option<&'static str>)> {
+    let disk_filter = DISK_FILTER.read().unwrap_or_else(PoisonError::into_inner);
+    let default_filter;
+    let filter = if let Some(filter) = disk_filter.as_ref() {
+        filter
+    } else {
+        default_filter = DiskFilter::new(None, None);
+        &default_filter
+    };
+    let allowed_fs = &filter.allowed_fs;
+    let exclude_keywords = &filter.exclude_keywords;
+
+    let mut seen_devices = HashSet::new();
+    disks
+        .iter()
+        .map(|disk| {
+            let fs = disk.file_system().to_string_lossy();
+            let mount_point = disk.mount_point().to_string_lossy();
+            let skip = if !allowed_fs.contains(fs.as_ref()) {
+                Some("filesystem not counted")
+            } else if exclude_keywords
+                .iter()
+                .any(|keyword| mount_point.contains(keyword.as_str()))
+            {
+                Some("mount point excluded")
+            } else if !seen_devices.insert(dedup_key(disk)) {
+                Some("filesystem already counted")
+            } else {
+                None
+            };
+            (disk, skip)
+        })
+        .collect()
+}
+
+pub fn filter_disks(disks: &Disks) -> Vec<&Disk> {
+    disk_verdicts(disks)
+        .into_iter()
+        .filter_map(|(disk, skip)| skip.is_none().then_some(disk))
+        .collect()
+}
+
+/// Mount points of container runtimes, their tmpfs mounts belong to the containers
+const MEMORY_EXCLUDE_KEYWORDS: &[&str] = &[
+    "/var/lib/docker",
+    "/var/lib/containers",
+    "/var/lib/kubelet",
+    "/snap",
+];
+
+/// Memory-backed mounts (tmpfs, ramfs) with a size limit, never part of the disk totals
+pub fn memory_disks(disks: &Disks) -> Vec<&Disk> {
+    disks
+        .iter()
+        .filter(|disk| matches!(disk.file_system().to_str(), Some("tmpfs" | "ramfs")))
+        .filter(|disk| disk.total_space() > 0)
+        .filter(|disk| {
+            let mount_point = disk.mount_point().to_string_lossy();
+            !MEMORY_EXCLUDE_KEYWORDS
+                .iter()
+                .any(|keyword| mount_point.starts_with(keyword))
+        })
+        .collect()
+}
+
+/// Mounts of one filesystem share a key: every btrfs subvolume reports the size of the
+/// whole filesystem, so they are keyed by its UUID. Other filesystems by device name
+fn dedup_key(disk: &Disk) -> Cow<'_, str> {
+    let name = disk.name().to_string_lossy();
+    if disk.file_system() == "btrfs"
+        && let Some(uuid) = btrfs_uuid(&name)
+    {
+        return Cow::Owned(format!("btrfs:{uuid}"));
+    }
+    name
+}
+
+/// UUID of the btrfs filesystem `device` belongs to, from `/sys/fs/btrfs/<uuid>/devices`
+#[cfg(target_os = "linux")]
+fn btrfs_uuid(device: &str) -> Option<String> {
+    // `/dev/mapper/*` names are links to the `dm-*` nodes listed there
+    let device = std::fs::canonicalize(device).ok()?;
+    let device = device.file_name()?;
+    std::fs::read_dir("/sys/fs/btrfs")
+        .ok()?
+        .flatten()
+        .find(|fs| fs.path().join("devices").join(device).exists())
+        .map(|fs| fs.file_name().to_string_lossy().into_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn btrfs_uuid(_device: &str) -> Option<String> {
+    None
+}
+
+/// Interface name keywords filtered out by default (virtual, container and tunnel devices)
+pub const DEFAULT_FILTER_KEYWORDS: &[&str] = &[
+    "br", "cni", "docker", "podman", "flannel", "lo", "veth", "virbr", "vmbr", "tap", "tun",
+    "fwln", "fwpr",
+];
+
+#[derive(Debug, Clone)]
+struct NetFilter {
+    keywords: Vec<String>,
+    allow: Vec<String>,
+}
+
+static NET_FILTER: RwLock<Option<NetFilter>> = RwLock::new(None);
+
+/// Override the default keyword list and / or always include some interfaces,
+/// both are comma separated lists
+pub fn set_net_filter(keywords: Option<&str>, allow: Option<&str>) {
+    let split = |list: &str| {
+        list.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    let filter = NetFilter {
+        keywords: keywords.map_or_else(
+            || {
+                DEFAULT_FILTER_KEYWORDS
+                    .iter()
+                    .map(|k| (*k).to_string())
+                    .collect()
+            },
+            split,
+        ),
+        allow: allow.map(split).unwrap_or_default(),
+    };
+    trace!("Network interface filter: {filter:?}");
+    if let Ok(mut net_filter) = NET_FILTER.write() {
+        *net_filter = Some(filter);
+    }
+}
+
+/// Why an interface is skipped, `None` if it is counted.
+///
+/// Allowed interfaces are always counted, otherwise keyword matches, interfaces without
+/// a MAC address and double-counted bond members / bridges are skipped.
+pub fn interface_verdict(
+    name: &str,
+    data: &NetworkData,
+    double_counted: &HashSet<String>,
+) -> Option<&'static str> {
+    let Ok(net_filter) = NET_FILTER.read() else {
+        return None;
+    };
+
+    let (is_allowed, is_filtered) = match net_filter.as_ref() {
+        Some(filter) => (
+            filter.allow.iter().any(|allow| allow == name),
+            filter
+                .keywords
+                .iter()
+                .any(|keyword| name.contains(keyword.as_str())),
+        ),
+        None => (
+            false,
+            DEFAULT_FILTER_KEYWORDS
+                .iter()
+                .any(|&keyword| name.contains(keyword)),
+        ),
+    };
+
+    if is_allowed {
+        None
+    } else if is_filtered {
+        Some("name matches a filter keyword")
+    } else if double_counted.contains(name) {
+        Some("double-counted with a bond or bridge")
+    } else if data.mac_address().0 == [0, 0, 0, 0, 0, 0] {
+        Some("no MAC address")
+    } else {
+        None
+    }
+}
+
+/// Whether an interface is counted, see `interface_verdict`
+pub fn should_monitor(name: &str, data: &NetworkData, double_counted: &HashSet<String>) -> bool {
+    interface_verdict(name, data, double_counted).is_none()
+}
@@ -0,0 +1,317 @@
+//! Country, region and ASN of the public address (`--geoip`).
+//!
+//! Without a database the details come from the answer of the IP provider, which ipinfo
+//! and Cloudflare send along with the address anyway. With `--geoip-mmdb` they are looked
+//! up in `MaxMind` DB files instead (`GeoLite2` / DB-IP City, Country and ASN editions), the
+//! fields found in several files are merged. A file is only read while Basic Info is
+//! built, nothing of it is kept in memory.
+
+use crate::data_struct::GeoInfo;
+use crate::get_info::ip::IPInfo;
+use log::{trace, warn};
+use miniserde::json::{Array, Number, Object, Value};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+#[derive(Debug, Default)]
+struct GeoIpConfig {
+    enabled: bool,
+    mmdb: Vec<String>,
+}
+
+static GEOIP: OnceLock<GeoIpConfig> = OnceLock::new();
+
+pub fn set_geoip(enabled: bool, mmdb: Vec<String>) {
+    let _ = GEOIP.set(GeoIpConfig {
+        enabled: enabled || !mmdb.is_empty(),
+        mmdb,
+    });
+}
+
+/// The details of the IPv4 address, of the IPv6 one without IPv4. `None` unless
+/// `--geoip` is given
+pub fn geoip(ip: &IPInfo) -> Option<GeoInfo> {
+    let config = GEOIP.get().filter(|config| config.enabled)?;
+    if config.mmdb.is_empty() {
+        return ip.geo.clone();
+    }
+
+    let addr = ip
+        .ipv4
+        .map(IpAddr::V4)
+        .or_else(|| ip.ipv6.map(IpAddr::V6))?;
+    let mut geo = GeoInfo::default();
+    for path in &config.mmdb {
+        match lookup(path, addr) {
+            Ok(Some(record)) => merge(&mut geo, &record),
+            Ok(None) => trace!("{addr} not found in {path}"),
+            Err(e) => warn!("Failed to look up {addr} in {path}: {e}"),
+        }
+    }
+    trace!("GEOIP successfully retrieved: {geo:?}");
+    Some(geo)
+}
+
+/// `AS13335 Cloudflare, Inc.` as sent by ipinfo in `org`
+pub fn parse_as_org(org: &str) -> (Option<u64>, Option<String>) {
+    let Some((asn, name)) = org.strip_prefix("AS").and_then(|org| org.split_once(' ')) else {
+        return (None, Some(org.to_string()));
+    };
+    match asn.parse() {
+        Ok(asn) => (Some(asn), Some(name.to_string())),
+        Err(_) => (None, Some(org.to_string())),
+    }
+}
+
+fn get<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    let mut value = value;
+    for key in path {
+        value = match value {
+            Value::Object(object) => object.get(*key)?,
+            Value::Array(array) => array.get(key.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+fn get_string(value: &Value, path: &[&str]) -> Option<String> {
+    match get(value, path)? {
+        Value::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+fn merge(geo: &mut GeoInfo, record: &Value) {
+    if geo.country.is_none() {
+        geo.country = get_string(record, &["country", "iso_code"]);
+    }
+    if geo.region.is_none() {
+        geo.region = get_string(record, &["subdivisions", "0", "names", "en"]);
+    }
+    if geo.asn.is_none()
+        && let Some(Value::Number(Number::U64(asn))) = get(record, &["autonomous_system_number"])
+    {
+        geo.asn = Some(*asn);
+    }
+    if geo.as_org.is_none() {
+        geo.as_org = get_string(record, &["autonomous_system_organization"]);
+    }
+}
+
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+/// Zero bytes between the search tree and the data section
+const DATA_SEPARATOR: usize = 16;
+
+fn lookup(path: &str, addr: IpAddr) -> Result<Option<Value>, String> {
+    let db = std::fs::read(path).map_err(|e| e.to_string())?;
+    let reader = Reader::open(&db)?;
+    reader.lookup(addr)
+}
+
+struct Reader<'a> {
+    db: &'a [u8],
+    node_count: usize,
+    record_size: usize,
+    ip_version: u64,
+    /// Start of the data section, pointers are relative to it
+    data: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn open(db: &'a [u8]) -> Result<Self, String> {
+        let marker = db
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .ok_or("not a MaxMind DB file")?;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let (metadata, _) = Decoder {
+            section: &db[metadata_start..],
+        }
+        .decode(0)?;
+
+        let number = |key: &str| match get(&metadata, &[key]) {
+            Some(Value::Number(Number::U64(n))) => Ok(*n),
+            _ => Err(format!("metadata without `{key}`")),
+        };
+        let node_count = usize::try_from(number("node_count")?).map_err(|e| e.to_string())?;
+        let record_size = usize::try_from(number("record_size")?).map_err(|e| e.to_string())?;
+        if ![24, 28, 32].contains(&record_size) {
+            return Err(format!("unsupported record size {record_size}"));
+        }
+        let data = node_count * record_size / 4 + DATA_SEPARATOR;
+        if data > marker {
+            return Err("truncated search tree".to_string());
+        }
+
+        Ok(Self {
+            db,
+            node_count,
+            record_size,
+            ip_version: number("ip_version")?,
+            data,
+        })
+    }
+
+    /// Left (`bit == 0`) or right record of a node
+    fn record(&self, node: usize, bit: u8) -> Result<usize, String> {
+        let node_bytes = self.record_size / 4;
+        let offset = node * node_bytes;
+        let bytes = self
+            .db
+            .get(offset..offset + node_bytes)
+            .ok_or("node out of bounds")?;
+        let be = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .fold(0usize, |acc, byte| (acc << 8) | usize::from(*byte))
+        };
+        Ok(match (self.record_size, bit) {
+            (24, 0) => be(&bytes[..3]),
+            (24, _) => be(&bytes[3..]),
+            // The middle byte holds the high nibble of both records
+            (28, 0) => (usize::from(bytes[3] >> 4) << 24) | be(&bytes[..3]),
+            (28, _) => (usize::from(bytes[3] & 0x0F) << 24) | be(&bytes[4..]),
+            (_, 0) => be(&bytes[..4]),
+            (_, _) => be(&bytes[4..]),
+        })
+    }
+
+    fn lookup(&self, addr: IpAddr) -> Result<Option<Value>, String> {
+        let bits: Vec<u8> = match addr {
+            IpAddr::V4(v4) if self.ip_version == 6 => v4.to_ipv6_compatible().octets().to_vec(),
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(_) if self.ip_version == 4 => return Ok(None),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+
+        let mut node = 0;
+        for i in 0..bits.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (bits[i / 8] >> (7 - i % 8)) & 1;
+            node = self.record(node, bit)?;
+        }
+
+        if node == self.node_count {
+            return Ok(None);
+        }
+        if node < self.node_count {
+            return Err("search tree deeper than the address".to_string());
+        }
+        let offset = (node - self.node_count)
+            .checked_sub(DATA_SEPARATOR)
+            .ok_or("data pointer into the separator")?;
+        let (value, _) = Decoder {
+            section: &self.db[self.data..],
+        }
+        .decode(offset)?;
+        Ok(Some(value))
+    }
+}
+
+/// Decoder of the `MaxMind` DB data section format
+struct Decoder<'a> {
+    section: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8], String> {
+        self.section
+            .get(offset..offset + len)
+            .ok_or_else(|| "data out of bounds".to_string())
+    }
+
+    fn uint(&self, offset: usize, len: usize) -> Result<u64, String> {
+        Ok(self
+            .bytes(offset, len)?
+            .iter()
+            .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte)))
+    }
+
+    /// The value at `offset` and the offset right after it
+    fn decode(&self, offset: usize) -> Result<(Value, usize), String> {
+        let control = *self.bytes(offset, 1)?.first().unwrap_or(&0);
+        let mut offset = offset + 1;
+        let mut kind = control >> 5;
+
+        if kind == 1 {
+            return self.pointer(control, offset);
+        }
+        if kind == 0 {
+            kind = 7 + self.bytes(offset, 1)?[0];
+            offset += 1;
+        }
+
+        let mut size = usize::from(control & 0x1F);
+        if size >= 29 {
+            let extra = size - 28;
+            let value = usize::try_from(self.uint(offset, extra)?).map_err(|e| e.to_string())?;
+            size = match extra {
+                1 => 29 + value,
+                2 => 285 + value,
+                _ => 65_821 + value,
+            };
+            offset += extra;
+        }
+
+        let value = match kind {
+            2 => Value::String(String::from_utf8_lossy(self.bytes(offset, size)?).into_owned()),
+            3 => {
+                let bits = self.uint(offset, 8)?;
+                Value::Number(Number::F64(f64::from_bits(bits)))
+            }
+            4 | 10 => Value::Null,
+            5 | 6 | 9 => Value::Number(Number::U64(self.uint(offset, size)?)),
+            8 => {
+                let n = self.uint(offset, size)? as u32;
+                Value::Number(Number::I64(i64::from(n.cast_signed())))
+            }
+            14 => return Ok((Value::Bool(size != 0), offset)),
+            15 => {
+                let bits = self.uint(offset, 4)? as u32;
+                Value::Number(Number::F64(f64::from(f32::from_bits(bits))))
+            }
+            7 => {
+                let mut object = Object::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode(offset)?;
+                    let (value, next) = self.decode(next)?;
+                    offset = next;
+                    if let Value::String(key) = key {
+                        object.insert(key, value);
+                    }
+                }
+                return Ok((Value::Object(object), offset));
+            }
+            11 => {
+                let mut array = Array::new();
+                for _ in 0..size {
+                    let (value, next) = self.decode(offset)?;
+                    offset = next;
+                    array.push(value);
+                }
+                return Ok((Value::Array(array), offset));
+            }
+            kind => return Err(format!("unsupported data type {kind}")),
+        };
+        Ok((value, offset + size))
+    }
+
+    /// A pointer is followed to its value, decoding goes on right after the pointer
+    fn pointer(&self, control: u8, offset: usize) -> Result<(Value, usize), String> {
+        let len = usize::from((control >> 3) & 0x3) + 1;
+        let low = u64::from(control & 0x7);
+        let raw = self.uint(offset, len)?;
+        let target = match len {
+            1 => (low << 8) | raw,
+            2 => ((low << 16) | raw) + 2048,
+            3 => ((low << 24) | raw) + 526_336,
+            _ => raw,
+        };
+        let target = usize::try_from(target).map_err(|e| e.to_string())?;
+        let (value, _) = self.decode(target)?;
+        Ok((value, offset + len))
+    }
+}
@@ -1,17 +1,88 @@
 use crate::command_parser::IpProvider;
+use crate::data_struct::GeoInfo;
+use crate::get_info::geoip::parse_as_org;
 use log::trace;
 use miniserde::{Deserialize, Serialize, json};
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 use tokio::task::JoinHandle;
 
 #[cfg(feature = "ureq-support")]
-use std::time::Duration;
+use crate::utils::timeouts;
+
+/// A lookup counted as the agent's own traffic
+#[cfg(feature = "ureq-support")]
+fn get(url: &str) -> ureq::RequestBuilder<ureq::typestate::WithoutBody> {
+    use crate::get_info::network::own::counted_agent;
+    use ureq::unversioned::resolver::DefaultResolver;
+
+    counted_agent(ureq::config::Config::default(), DefaultResolver::default()).get(url)
+}
+
+/// Self-hosted echo-IP services from `--ip-provider-url-v4` / `--ip-provider-url-v6`
+#[derive(Debug, Clone, Default)]
+struct CustomProvider {
+    url_v4: Option<String>,
+    url_v6: Option<String>,
+    /// Dotted path of the address in a JSON answer, the answer is plain text otherwise
+    json_field: Option<String>,
+}
+
+static CUSTOM_PROVIDER: OnceLock<CustomProvider> = OnceLock::new();
+
+pub fn set_custom_ip_provider(
+    url_v4: Option<String>,
+    url_v6: Option<String>,
+    json_field: Option<String>,
+) {
+    let _ = CUSTOM_PROVIDER.set(CustomProvider {
+        url_v4,
+        url_v6,
+        json_field,
+    });
+}
 
+/// A family without a custom URL is still looked up with `provider`
 pub async fn ip(provider: &IpProvider) -> IPInfo {
-    match provider {
-        IpProvider::Cloudflare => ip_cloudflare().await,
-        IpProvider::Ipinfo => ip_ipinfo().await,
+    let custom = CUSTOM_PROVIDER.get().cloned().unwrap_or_default();
+    let builtin = async {
+        if custom.url_v4.is_some() && custom.url_v6.is_some() {
+            return IPInfo {
+                ipv4: None,
+                ipv6: None,
+                geo: None,
+            };
+        }
+        match provider {
+            IpProvider::Cloudflare => ip_cloudflare().await,
+            IpProvider::Ipinfo => ip_ipinfo().await,
+        }
+    };
+    let field = custom.json_field.as_deref();
+    let custom_v4 = async {
+        let body = fetch_custom(custom.url_v4.as_deref()?, false)?;
+        Ipv4Addr::from_str(&extract_custom_ip(&body, field)?).ok()
+    };
+    let custom_v6 = async {
+        let body = fetch_custom(custom.url_v6.as_deref()?, true)?;
+        Ipv6Addr::from_str(&extract_custom_ip(&body, field)?).ok()
+    };
+    let (builtin, ipv4, ipv6) = tokio::join!(builtin, custom_v4, custom_v6);
+
+    IPInfo {
+        ipv4: if custom.url_v4.is_some() {
+            ipv4
+        } else {
+            builtin.ipv4
+        },
+        ipv6: if custom.url_v6.is_some() {
+            ipv6
+        } else {
+            builtin.ipv6
+        },
+        geo: builtin.geo,
     }
 }
 
@@ -19,21 +90,51 @@ pub async fn ip(provider: &IpProvider) -> IPInfo {
 pub struct IPInfo {
     pub ipv4: Option<Ipv4Addr>,
     pub ipv6: Option<Ipv6Addr>,
+    /// Location sent along by the provider, of the IPv4 address when there is one
+    pub geo: Option<GeoInfo>,
+}
+
+/// Latest lookup of `spawn_ip_recheck`, taken by the upload loop
+pub type IpUpdate = Arc<Mutex<Option<IPInfo>>>;
+
+/// Re-resolve the public addresses every `interval` for `--ip-recheck-interval`, a
+/// lookup without any answer (e.g. offline) is dropped
+pub fn spawn_ip_recheck(interval: Duration, provider: IpProvider) -> IpUpdate {
+    let update = IpUpdate::default();
+    let latest = update.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let ip = ip(&provider).await;
+            trace!("Public IP re-checked: {ip:?}");
+            if (ip.ipv4.is_some() || ip.ipv6.is_some())
+                && let Ok(mut latest) = latest.lock()
+            {
+                *latest = Some(ip);
+            }
+        }
+    });
+    update
 }
 
 #[derive(Serialize, Deserialize)]
 struct IpJson {
     ip: String,
+    country: Option<String>,
+    region: Option<String>,
+    /// `AS13335 Cloudflare, Inc.`
+    org: Option<String>,
 }
 
 // 提取公共的请求函数以减少重复代码
 async fn fetch_ipinfo_v4() -> Option<String> {
     #[cfg(feature = "ureq-support")]
     {
-        let resp = ureq::get("https://ipinfo.io")
+        let resp = get("https://ipinfo.io")
             .header("User-Agent", "curl/8.7.1")
             .config()
-            .timeout_global(Some(Duration::from_secs(5)))
+            .timeout_connect(Some(timeouts().connect))
+            .timeout_global(Some(timeouts().total()))
             .ip_family(ureq::config::IpFamily::Ipv4Only)
             .build()
             .call();
@@ -60,10 +161,11 @@ async fn fetch_ipinfo_v4() -> Option<String> {
 async fn fetch_ipinfo_v6() -> Option<String> {
     #[cfg(feature = "ureq-support")]
     {
-        let resp = ureq::get("https://6.ipinfo.io")
+        let resp = get("https://6.ipinfo.io")
             .header("User-Agent", "curl/8.7.1")
             .config()
-            .timeout_global(Some(Duration::from_secs(5)))
+            .timeout_connect(Some(timeouts().connect))
+            .timeout_global(Some(timeouts().total()))
             .ip_family(ureq::config::IpFamily::Ipv6Only)
             .build()
             .call();
@@ -90,10 +192,11 @@ async fn fetch_ipinfo_v6() -> Option<String> {
 async fn fetch_cloudflare_v4() -> Option<String> {
     #[cfg(feature = "ureq-support")]
     {
-        let resp = ureq::get("https://www.cloudflare.com/cdn-cgi/trace")
+        let resp = get("https://www.cloudflare.com/cdn-cgi/trace")
             .header("User-Agent", "curl/8.7.1")
             .config()
-            .timeout_global(Some(Duration::from_secs(5)))
+            .timeout_connect(Some(timeouts().connect))
+            .timeout_global(Some(timeouts().total()))
             .ip_family(ureq::config::IpFamily::Ipv4Only)
             .build()
             .call();
@@ -120,10 +223,11 @@ async fn fetch_cloudflare_v4() -> Option<String> {
 async fn fetch_cloudflare_v6() -> Option<String> {
     #[cfg(feature = "ureq-support")]
     {
-        let resp = ureq::get("https://www.cloudflare.com/cdn-cgi/trace")
+        let resp = get("https://www.cloudflare.com/cdn-cgi/trace")
             .header("User-Agent", "curl/8.7.1")
             .config()
-            .timeout_global(Some(Duration::from_secs(5)))
+            .timeout_connect(Some(timeouts().connect))
+            .timeout_global(Some(timeouts().total()))
             .ip_family(ureq::config::IpFamily::Ipv6Only)
             .build()
             .call();
@@ -147,34 +251,111 @@ async fn fetch_cloudflare_v6() -> Option<String> {
     None
 }
 
-fn parse_ipinfo_response(body: &str) -> Option<String> {
+/// nyquest cannot pin the address family, the answer of the other family is not an
+/// address of the expected one and is dropped
+fn fetch_custom(url: &str, ipv6: bool) -> Option<String> {
+    #[cfg(feature = "ureq-support")]
+    {
+        let family = if ipv6 {
+            ureq::config::IpFamily::Ipv6Only
+        } else {
+            ureq::config::IpFamily::Ipv4Only
+        };
+        let resp = get(url)
+            .header("User-Agent", "curl/8.7.1")
+            .config()
+            .timeout_connect(Some(timeouts().connect))
+            .timeout_global(Some(timeouts().total()))
+            .ip_family(family)
+            .build()
+            .call();
+
+        if let Ok(mut response) = resp {
+            return response.body_mut().read_to_string().ok();
+        }
+    }
+
+    #[cfg(feature = "nyquest-support")]
+    {
+        use nyquest::Request;
+        let _ = ipv6;
+        let client = crate::utils::create_nyquest_client(false);
+        let request = Request::get(url.to_string());
+
+        if let Ok(res) = client.request(request) {
+            return res.text().ok();
+        }
+    }
+
+    None
+}
+
+/// The address at `field` (e.g. `data.ip`) of a JSON answer, or the trimmed plain text
+fn extract_custom_ip(body: &str, field: Option<&str>) -> Option<String> {
+    use miniserde::json::Value;
+
+    let Some(field) = field else {
+        return Some(body.trim().to_string());
+    };
+    let mut value = &json::from_str::<Value>(body).ok()?;
+    for key in field.split('.') {
+        let Value::Object(object) = value else {
+            return None;
+        };
+        value = object.get(key)?;
+    }
+    match value {
+        Value::String(ip) => Some(ip.trim().to_string()),
+        _ => None,
+    }
+}
+
+fn parse_ipinfo_response(body: &str) -> Option<(String, GeoInfo)> {
     let json: IpJson = json::from_str(body).ok()?;
-    Some(json.ip)
+    let (asn, as_org) = json.org.as_deref().map_or((None, None), parse_as_org);
+    let geo = GeoInfo {
+        country: json.country,
+        region: json.region,
+        asn,
+        as_org,
+    };
+    Some((json.ip, geo))
 }
 
-fn extract_cloudflare_ip(body: &str) -> Option<String> {
+/// Only the country (`loc=`) is in the trace
+fn extract_cloudflare_ip(body: &str) -> Option<(String, GeoInfo)> {
+    let mut ip = None;
+    let mut country = None;
     for line in body.lines() {
-        if line.starts_with("ip=") {
-            return Some(line.replace("ip=", ""));
+        if let Some(addr) = line.strip_prefix("ip=") {
+            ip = Some(addr.to_string());
+        } else if let Some(loc) = line.strip_prefix("loc=") {
+            country = Some(loc.to_string());
         }
     }
-    None
+    let geo = GeoInfo {
+        country,
+        region: None,
+        asn: None,
+        as_org: None,
+    };
+    Some((ip?, geo))
 }
 
 pub async fn ip_ipinfo() -> IPInfo {
-    let ipv4: JoinHandle<Option<Ipv4Addr>> = tokio::spawn(async move {
+    let ipv4: JoinHandle<Option<(Ipv4Addr, GeoInfo)>> = tokio::spawn(async move {
         if let Some(body) = fetch_ipinfo_v4().await {
-            if let Some(ip_str) = parse_ipinfo_response(&body) {
-                return Ipv4Addr::from_str(&ip_str).ok();
+            if let Some((ip_str, geo)) = parse_ipinfo_response(&body) {
+                return Ipv4Addr::from_str(&ip_str).ok().map(|ip| (ip, geo));
             }
         }
         None
     });
 
-    let ipv6: JoinHandle<Option<Ipv6Addr>> = tokio::spawn(async move {
+    let ipv6: JoinHandle<Option<(Ipv6Addr, GeoInfo)>> = tokio::spawn(async move {
         if let Some(body) = fetch_ipinfo_v6().await {
-            if let Some(ip_str) = parse_ipinfo_response(&body) {
-                return Ipv6Addr::from_str(&ip_str).ok();
+            if let Some((ip_str, geo)) = parse_ipinfo_response(&body) {
+                return Ipv6Addr::from_str(&ip_str).ok().map(|ip| (ip, geo));
             }
         }
         None
@@ -184,8 +365,13 @@ pub async fn ip_ipinfo() -> IPInfo {
     let ipv6_result = ipv6.await.unwrap_or(None);
 
     let ip_info = IPInfo {
-        ipv4: ipv4_result,
-        ipv6: ipv6_result,
+        geo: ipv4_result
+            .as_ref()
+            .map(|(_, geo)| geo)
+            .or(ipv6_result.as_ref().map(|(_, geo)| geo))
+            .cloned(),
+        ipv4: ipv4_result.map(|(ip, _)| ip),
+        ipv6: ipv6_result.map(|(ip, _)| ip),
     };
 
     trace!("IP INFO (ipinfo) successfully retrieved: {:?}", ip_info);
@@ -194,19 +380,19 @@ pub async fn ip_ipinfo() -> IPInfo {
 }
 
 pub async fn ip_cloudflare() -> IPInfo {
-    let ipv4: JoinHandle<Option<Ipv4Addr>> = tokio::spawn(async move {
+    let ipv4: JoinHandle<Option<(Ipv4Addr, GeoInfo)>> = tokio::spawn(async move {
         if let Some(body) = fetch_cloudflare_v4().await {
-            if let Some(ip_str) = extract_cloudflare_ip(&body) {
-                return Ipv4Addr::from_str(&ip_str).ok();
+            if let Some((ip_str, geo)) = extract_cloudflare_ip(&body) {
+                return Ipv4Addr::from_str(&ip_str).ok().map(|ip| (ip, geo));
             }
         }
         None
     });
 
-    let ipv6: JoinHandle<Option<Ipv6Addr>> = tokio::spawn(async move {
+    let ipv6: JoinHandle<Option<(Ipv6Addr, GeoInfo)>> = tokio::spawn(async move {
         if let Some(body) = fetch_cloudflare_v6().await {
-            if let Some(ip_str) = extract_cloudflare_ip(&body) {
-                return Ipv6Addr::from_str(&ip_str).ok();
+            if let Some((ip_str, geo)) = extract_cloudflare_ip(&body) {
+                return Ipv6Addr::from_str(&ip_str).ok().map(|ip| (ip, geo));
             }
         }
         None
@@ -216,8 +402,13 @@ pub async fn ip_cloudflare() -> IPInfo {
     let ipv6_result = ipv6.await.unwrap_or(None);
 
     let ip_info = IPInfo {
-        ipv4: ipv4_result,
-        ipv6: ipv6_result,
+        geo: ipv4_result
+            .as_ref()
+            .map(|(_, geo)| geo)
+            .or(ipv6_result.as_ref().map(|(_, geo)| geo))
+            .cloned(),
+        ipv4: ipv4_result.map(|(ip, _)| ip),
+        ipv6: ipv6_result.map(|(ip, _)| ip),
     };
 
     trace!("IP INFO (cloudflare) successfully retrieved: {:?}", ip_info);
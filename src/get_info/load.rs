@@ -1,8 +1,27 @@
 use crate::data_struct::Load;
 use log::trace;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static LOAD_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// `--disable-load`
+pub fn disable_load() {
+    LOAD_DISABLED.store(true, Ordering::Relaxed);
+}
 
-#[cfg(not(target_os = "windows"))]
 pub fn realtime_load() -> Load {
+    if LOAD_DISABLED.load(Ordering::Relaxed) {
+        return Load {
+            load1: 0.0,
+            load5: 0.0,
+            load15: 0.0,
+        };
+    }
+    load_average()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn load_average() -> Load {
     use sysinfo::System;
     let load = System::load_average();
     let load_info = Load {
@@ -15,7 +34,7 @@ pub fn realtime_load() -> Load {
 }
 
 #[cfg(target_os = "windows")]
-pub fn realtime_load() -> Load {
+fn load_average() -> Load {
     let load_info = Load {
         load1: 0.0,
         load5: 0.0,
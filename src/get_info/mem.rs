@@ -1,29 +1,35 @@
-use crate::data_struct::{Disk, Ram, Swap};
+use crate::command_parser::MemMode;
+use crate::data_struct::{Disk, MemoryMount, Ram, Swap};
+use crate::get_info::balloon::balloon_bytes;
+use crate::get_info::filters::{filter_disks, memory_disks};
 use log::trace;
-use std::collections::HashSet;
-use std::sync::OnceLock;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
 use sysinfo::{Disks, System};
 
 #[derive(Debug)]
 pub struct MemDiskTotalInfoWithOutUsage {
     pub mem: u64,
+    /// Bytes of a virtio balloon taken off `mem`
+    pub balloon: Option<u64>,
     pub swap: u64,
     pub disk: u64,
 }
 
 pub fn mem_info_without_usage(sysinfo_sys: &System) -> MemDiskTotalInfoWithOutUsage {
-    let mem_total = sysinfo_sys.total_memory();
-    let swap_total = sysinfo_sys.total_swap();
+    let balloon = balloon_bytes();
+    let mem_total = sysinfo_sys
+        .total_memory()
+        .saturating_sub(balloon.unwrap_or(0));
+    let swap_total = sysinfo_sys.total_swap().saturating_sub(excluded_swap().0);
 
     let disks = Disks::new_with_refreshed_list();
-    let disk_list = filter_disks(&disks);
-    let mut all_disk_space: u64 = 0;
-    for disk in &disk_list {
-        all_disk_space += disk.total_space();
-    }
+    let (_, all_disk_space) = disk_space(&filter_disks(&disks));
 
     let info = MemDiskTotalInfoWithOutUsage {
         mem: mem_total,
+        balloon,
         swap: swap_total,
         disk: all_disk_space,
     };
@@ -33,33 +39,149 @@ pub fn mem_info_without_usage(sysinfo_sys: &System) -> MemDiskTotalInfoWithOutUs
     info
 }
 
+static MEM_MODE: RwLock<MemMode> = RwLock::new(MemMode::Available);
+static MEM_REPORT_CACHE: AtomicBool = AtomicBool::new(false);
+
+/// Set from `--mem-mode` / `--mem-report-cache`
+pub fn set_mem_mode(mode: MemMode, report_cache: bool) {
+    if let Ok(mut mem_mode) = MEM_MODE.write() {
+        *mem_mode = mode;
+    }
+    MEM_REPORT_CACHE.store(report_cache, Ordering::Relaxed);
+}
+
+/// Used memory as `--mem-mode` counts it:
+/// `available` total - available, `used` total - free - buffers - cache as `free` does,
+/// `classical` total - free with the cache counted as used.
+/// Pages inflated into a balloon count as used in `MemTotal`, they are taken off both
 pub fn realtime_mem(sysinfo_sys: &System) -> Ram {
+    let balloon = balloon_bytes().unwrap_or(0);
+    let total = sysinfo_sys.total_memory();
+    let mode = MEM_MODE
+        .read()
+        .map_or(MemMode::Available, |mode| mode.clone());
+    let cache = page_cache();
+    let used = match mode {
+        MemMode::Available => total - sysinfo_sys.available_memory(),
+        MemMode::Used => cache.map_or_else(
+            || sysinfo_sys.used_memory(),
+            |(buffers, cached)| {
+                total
+                    .saturating_sub(sysinfo_sys.free_memory())
+                    .saturating_sub(buffers + cached)
+            },
+        ),
+        MemMode::Classical => total.saturating_sub(sysinfo_sys.free_memory()),
+    };
+    let report_cache = MEM_REPORT_CACHE.load(Ordering::Relaxed);
     let ram = Ram {
-        used: sysinfo_sys.total_memory() - sysinfo_sys.available_memory(),
-        total: sysinfo_sys.total_memory(),
+        used: used.saturating_sub(balloon),
+        total: total.saturating_sub(balloon),
+        buffers: cache.filter(|_| report_cache).map(|(buffers, _)| buffers),
+        cached: cache.filter(|_| report_cache).map(|(_, cached)| cached),
     };
     trace!("REALTIME MEM successfully retrieved: {ram:?}");
     ram
 }
 
+/// Buffers and cache (page cache + reclaimable slab) in bytes, from `/proc/meminfo`
+#[cfg(target_os = "linux")]
+fn page_cache() -> Option<(u64, u64)> {
+    super::with_file("/proc/meminfo", |meminfo| {
+        let field = |name: &str| {
+            meminfo.lines().find_map(|line| {
+                let value = line.strip_prefix(name)?.strip_prefix(':')?;
+                let kib = value.trim().trim_end_matches("kB").trim();
+                kib.parse::<u64>().ok().map(|kib| kib * 1024)
+            })
+        };
+        Some((
+            field("Buffers")?,
+            field("Cached")? + field("SReclaimable").unwrap_or(0),
+        ))
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn page_cache() -> Option<(u64, u64)> {
+    None
+}
+
 pub fn realtime_swap(sysinfo_sys: &System) -> Swap {
+    let (excluded_total, excluded_used) = excluded_swap();
     let swap = Swap {
-        used: sysinfo_sys.used_swap(),
-        total: sysinfo_sys.total_swap(),
+        used: sysinfo_sys.used_swap().saturating_sub(excluded_used),
+        total: sysinfo_sys.total_swap().saturating_sub(excluded_total),
     };
     trace!("REALTIME SWAP successfully retrieved: {swap:?}");
     swap
 }
 
+static SWAP_EXCLUDE_ZRAM: AtomicBool = AtomicBool::new(false);
+
+/// Set from `--swap-exclude-zram`
+pub fn set_swap_exclude_zram(exclude: bool) {
+    SWAP_EXCLUDE_ZRAM.store(exclude, Ordering::Relaxed);
+}
+
+/// Size and used bytes of the swap left out of the totals
+fn excluded_swap() -> (u64, u64) {
+    if SWAP_EXCLUDE_ZRAM.load(Ordering::Relaxed) {
+        zram_swap()
+    } else {
+        (0, 0)
+    }
+}
+
+/// Size and used bytes of the zram swap devices in `/proc/swaps` (KiB there)
+#[cfg(target_os = "linux")]
+fn zram_swap() -> (u64, u64) {
+    let Ok(swaps) = std::fs::read_to_string("/proc/swaps") else {
+        return (0, 0);
+    };
+    // Filename Type Size Used Priority
+    swaps
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            let size: u64 = fields.nth(1)?.parse().ok()?;
+            let used: u64 = fields.next()?.parse().ok()?;
+            name.starts_with("/dev/zram")
+                .then_some((size * 1024, used * 1024))
+        })
+        .fold((0, 0), |(total, used), (size, size_used)| {
+            (total + size, used + size_used)
+        })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn zram_swap() -> (u64, u64) {
+    (0, 0)
+}
+
+static DISK_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// `--disable-disk`, the disks are then no longer refreshed either, e.g. with slow network
+/// mounts
+pub fn disable_disk() {
+    DISK_DISABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn disk_disabled() -> bool {
+    DISK_DISABLED.load(Ordering::Relaxed)
+}
+
 pub fn realtime_disk(disk: &Disks) -> Disk {
-    let mut used_disk: u64 = 0;
-    let mut total_disk: u64 = 0;
+    if disk_disabled() {
+        return Disk { used: 0, total: 0 };
+    }
     let disk_list = filter_disks(disk);
-    for disk in disk_list {
+    for disk in &disk_list {
         trace!("FILTERED DISK: {disk:?}");
-        used_disk += disk.total_space() - disk.available_space();
-        total_disk += disk.total_space();
     }
+    let (used_disk, total_disk) = disk_space(&disk_list);
 
     let disk_info = Disk { 
         used: used_disk,
@@ -69,86 +191,55 @@ pub fn realtime_disk(disk: &Disks) -> Disk {
     disk_info
 }
 
-fn get_allowed_filesystems() -> &'static HashSet<&'static str> {
-    static ALLOWED_FS: OnceLock<HashSet<&str>> = OnceLock::new();
-    ALLOWED_FS.get_or_init(|| {
-        [
-            "apfs",
-            "ext4",
-            "ext3",
-            "ext2",
-            "f2fs",
-            "reiserfs",
-            "jfs",
-            "btrfs",
-            "fuseblk",
-            "zfs",
-            "simfs",
-            "ntfs",
-            "fat32",
-            "exfat",
-            "xfs",
-            "fuse.rclone",
-            "ubifs",
-        ]
-        .iter()
-        .cloned()
-        .collect()
-    })
-}
+static REPORT_TMPFS: AtomicBool = AtomicBool::new(false);
 
-fn get_exclude_keywords() -> &'static HashSet<&'static str> {
-    static EXCLUDE_KEYWORDS: OnceLock<HashSet<&str>> = OnceLock::new();
-    EXCLUDE_KEYWORDS.get_or_init(|| {
-        [
-            "/snap",
-            "/var/lib/docker",
-            "/var/lib/lxcfs",
-            "/run/user",
-            "/tmp",
-            "/dev",
-            "/sys",
-            "/proc",
-            "/boot",
-            "/lost+found",
-            "/nix/store",
-            "/var/log.hdd",
-        ]
-        .iter()
-        .cloned()
-        .collect()
-    })
+/// Set from `--disk-report-tmpfs`
+pub fn set_report_tmpfs(report: bool) {
+    REPORT_TMPFS.store(report, Ordering::Relaxed);
 }
 
-pub fn filter_disks(disks: &Disks) -> Vec<&sysinfo::Disk> {
-    let allowed_fs = get_allowed_filesystems();
-    let exclude_keywords = get_exclude_keywords();
-
-    let mut unique_disks = Vec::new();
-    let mut seen_devices = HashSet::new();
-
-    for disk in disks.iter() {
-        // Filter by filesystem type
-        let fs = disk.file_system().to_string_lossy();
-        if !allowed_fs.contains(fs.as_ref()) {
-            continue;
-        }
-
-        // Filter by mount point
-        let mount_point = disk.mount_point().to_string_lossy();
-        if exclude_keywords
-            .iter()
-            .any(|keyword| mount_point.contains(keyword))
-        {
-            continue;
-        }
+/// Usage of every tmpfs / ramfs mount, `None` unless `--disk-report-tmpfs` is set
+pub fn realtime_tmpfs(disks: &Disks) -> Option<Vec<MemoryMount>> {
+    if !REPORT_TMPFS.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mounts = memory_disks(disks)
+        .into_iter()
+        .map(|disk| MemoryMount {
+            mount_point: disk.mount_point().to_string_lossy().into_owned(),
+            file_system: disk.file_system().to_string_lossy().into_owned(),
+            used: disk.total_space().saturating_sub(disk.available_space()),
+            total: disk.total_space(),
+        })
+        .collect();
+    trace!("REALTIME TMPFS successfully retrieved: {mounts:?}");
+    Some(mounts)
+}
 
-        // Deduplicate by device name
-        let name = disk.name().to_string_lossy().into_owned();
-        if seen_devices.insert(name) {
-            unique_disks.push(disk);
+/// Used and total space of the filtered disks. ZFS datasets all report the free space of
+/// their pool, it is counted once per pool next to what each dataset uses
+fn disk_space(disks: &[&sysinfo::Disk]) -> (u64, u64) {
+    let mut used = 0u64;
+    let mut total = 0u64;
+    // Pool name -> (used by its datasets, available)
+    let mut pools: HashMap<String, (u64, u64)> = HashMap::new();
+    for disk in disks {
+        let disk_used = disk.total_space().saturating_sub(disk.available_space());
+        if disk.file_system() == "zfs" {
+            let name = disk.name().to_string_lossy();
+            let pool = name.split('/').next().unwrap_or_default().to_string();
+            let (pool_used, available) = pools.entry(pool).or_default();
+            *pool_used += disk_used;
+            // A dataset with a quota sees less than the pool
+            *available = (*available).max(disk.available_space());
+        } else {
+            used += disk_used;
+            total += disk.total_space();
         }
     }
-
-    unique_disks
+    for (pool_used, available) in pools.values() {
+        used += pool_used;
+        total += pool_used + available;
+    }
+    (used, total)
 }
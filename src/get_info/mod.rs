@@ -1,13 +1,44 @@
 use log::trace;
-use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use sysinfo::System;
 
+pub mod balloon;
+pub mod cgroup;
+pub mod collector;
 pub mod cpu;
+pub mod filters;
+pub mod geoip;
 pub mod ip;
 pub mod load;
 pub mod mem;
 pub mod network;
 pub mod os;
+pub mod power;
+pub mod pressure;
+pub mod sbc;
+pub mod suspend;
+pub mod temperature;
+pub mod thermal;
+pub mod watch;
+pub mod wifi;
+#[cfg(target_os = "windows")]
+pub mod windows_hw;
+
+/// Buffer the per-sample file reads go through, it keeps its capacity between samples
+static READ_BUFFER: Mutex<String> = Mutex::new(String::new());
+
+/// Read `path` into the shared buffer and parse it, without allocating once the buffer has
+/// grown to the largest file. `parse` must not read a file this way itself
+pub fn with_file<T>(path: impl AsRef<Path>, parse: impl FnOnce(&str) -> Option<T>) -> Option<T> {
+    let mut buffer = READ_BUFFER.lock().ok()?;
+    buffer.clear();
+    File::open(path).ok()?.read_to_string(&mut buffer).ok()?;
+    parse(&buffer)
+}
 
 pub fn realtime_uptime() -> u64 {
     let uptime = System::uptime();
@@ -15,24 +46,80 @@ pub fn realtime_uptime() -> u64 {
     uptime
 }
 
+static PROCESS_COUNT_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// `--disable-process-count`, listing every process is costly on hosts with a huge number
+pub fn disable_process_count() {
+    PROCESS_COUNT_DISABLED.store(true, Ordering::Relaxed);
+}
+
 pub fn realtime_process() -> u64 {
+    if PROCESS_COUNT_DISABLED.load(Ordering::Relaxed) {
+        return 0;
+    }
+    let process_count = count_processes();
+    trace!("REALTIME PROCESS successfully retrieved: {process_count}");
+    process_count
+}
+
+/// `readdir` directly, `fs::read_dir` allocates a name for every entry
+#[cfg(target_os = "linux")]
+fn count_processes() -> u64 {
     let mut process_count = 0;
 
-    let Ok(entries) = fs::read_dir("/proc") else {
+    let dir = unsafe { libc::opendir(c"/proc".as_ptr()) };
+    if dir.is_null() {
         trace!("REALTIME PROCESS failed: Cannot read /proc directory");
         return 0;
-    };
+    }
 
-    for entry in entries.flatten() {
-        let file_name = entry.file_name();
-        if let Some(name_str) = file_name.to_str()
-            && name_str.parse::<u32>().is_ok()
-        {
+    loop {
+        let entry = unsafe { libc::readdir(dir) };
+        if entry.is_null() {
+            break;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+        if !name.is_empty() && name.iter().all(u8::is_ascii_digit) {
             process_count += 1;
         }
     }
+    unsafe { libc::closedir(dir) };
 
-    let process_count = process_count as u64;
-    trace!("REALTIME PROCESS successfully retrieved: {process_count}");
     process_count
 }
+
+#[cfg(target_os = "windows")]
+fn count_processes() -> u64 {
+    use windows::Win32::System::ProcessStatus::EnumProcesses;
+
+    let mut pids = vec![0u32; 1024];
+    loop {
+        let size = u32::try_from(std::mem::size_of_val(pids.as_slice())).unwrap_or(u32::MAX);
+        let mut needed = 0u32;
+        // SAFETY: `pids` holds `size` bytes
+        if unsafe { EnumProcesses(pids.as_mut_ptr(), size, &raw mut needed) }.is_err() {
+            trace!("REALTIME PROCESS failed: EnumProcesses");
+            return 0;
+        }
+        // A full buffer may have been too small for every ID
+        if needed < size {
+            return u64::from(needed) / 4;
+        }
+        pids.resize(pids.len() * 2, 0);
+    }
+}
+
+/// sysinfo lists the processes without reading any of their details
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn count_processes() -> u64 {
+    use sysinfo::{ProcessRefreshKind, ProcessesToUpdate};
+
+    static SYSTEM: Mutex<Option<System>> = Mutex::new(None);
+
+    let Ok(mut system) = SYSTEM.lock() else {
+        return 0;
+    };
+    let system = system.get_or_insert_with(System::new);
+    system.refresh_processes_specifics(ProcessesToUpdate::All, true, ProcessRefreshKind::nothing());
+    system.processes().len() as u64
+}
@@ -0,0 +1,344 @@
+use crate::data_struct::AccountedTraffic;
+use log::{trace, warn};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const NFT_TABLE: &str = "komari_accounting";
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A named group of remote CIDRs whose traffic is accounted separately
+#[derive(Debug, Clone)]
+pub struct AccountRule {
+    pub name: String,
+    pub cidrs: Vec<(IpAddr, u8)>,
+}
+
+#[derive(Debug)]
+struct AccountState {
+    name: String,
+    /// Cycle totals restored from the state file
+    base_tx: u64,
+    base_rx: u64,
+    /// Kernel counter values since the last reset
+    counter_tx: u64,
+    counter_rx: u64,
+}
+
+static ACCOUNTS: Mutex<Vec<AccountState>> = Mutex::new(Vec::new());
+/// Held while reading or resetting the kernel counters, `ACCOUNTS` only while applying them
+static NFT: Mutex<()> = Mutex::new(());
+
+/// Parse `--traffic-account` values in `NAME=CIDR[,CIDR...]` format
+pub fn parse_account_rules(specs: &[String]) -> Result<Vec<AccountRule>, String> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (name, cidrs) = spec
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid traffic account '{spec}', expected NAME=CIDR"))?;
+            // Used unquoted in the nft counter names
+            let name = name.trim();
+            if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(format!("Invalid traffic account name '{name}'"));
+            }
+
+            let cidrs = cidrs
+                .split(',')
+                .map(parse_cidr)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(AccountRule {
+                name: name.to_string(),
+                cidrs,
+            })
+        })
+        .collect()
+}
+
+fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8), String> {
+    let cidr = cidr.trim();
+    let (ip, prefix) = cidr.split_once('/').unwrap_or((cidr, ""));
+    let ip = ip
+        .parse::<IpAddr>()
+        .map_err(|_| format!("Invalid IP address in '{cidr}'"))?;
+    let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = if prefix.is_empty() {
+        max_prefix
+    } else {
+        prefix
+            .parse::<u8>()
+            .ok()
+            .filter(|p| *p <= max_prefix)
+            .ok_or_else(|| format!("Invalid prefix length in '{cidr}'"))?
+    };
+    Ok((ip, prefix))
+}
+
+/// Build the nftables ruleset: traffic to a CIDR counts as tx, traffic from it as rx
+#[cfg(target_os = "linux")]
+fn build_ruleset(rules: &[AccountRule]) -> String {
+    use std::fmt::Write;
+
+    let mut counters = String::new();
+    let mut output = String::new();
+    let mut input = String::new();
+    let mut forward = String::new();
+
+    for rule in rules {
+        let _ = write!(
+            counters,
+            "\tcounter {name}_tx {{ packets 0 bytes 0 }}\n\tcounter {name}_rx {{ packets 0 bytes 0 }}\n",
+            name = rule.name
+        );
+
+        for (family, is_v4) in [("ip", true), ("ip6", false)] {
+            let set = rule
+                .cidrs
+                .iter()
+                .filter(|(ip, _)| ip.is_ipv4() == is_v4)
+                .map(|(ip, prefix)| format!("{ip}/{prefix}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if set.is_empty() {
+                continue;
+            }
+
+            let tx = format!(
+                "\t\t{family} daddr {{ {set} }} counter name \"{}_tx\"\n",
+                rule.name
+            );
+            let rx = format!(
+                "\t\t{family} saddr {{ {set} }} counter name \"{}_rx\"\n",
+                rule.name
+            );
+            output.push_str(&tx);
+            input.push_str(&rx);
+            forward.push_str(&tx);
+            forward.push_str(&rx);
+        }
+    }
+
+    format!(
+        "table inet {NFT_TABLE}\n\
+         delete table inet {NFT_TABLE}\n\
+         table inet {NFT_TABLE} {{\n\
+         {counters}\
+         \tchain output {{\n\t\ttype filter hook output priority 0; policy accept;\n{output}\t}}\n\
+         \tchain input {{\n\t\ttype filter hook input priority 0; policy accept;\n{input}\t}}\n\
+         \tchain forward {{\n\t\ttype filter hook forward priority 0; policy accept;\n{forward}\t}}\n\
+         }}\n"
+    )
+}
+
+/// Install the accounting table and start tracking the given rules
+#[cfg(target_os = "linux")]
+pub fn init_accounting(rules: &[AccountRule]) -> Result<(), String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let ruleset = build_ruleset(rules);
+    trace!("nftables accounting ruleset:\n{ruleset}");
+
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run nft: {e}"))?;
+    child
+        .stdin
+        .take()
+        .ok_or("Failed to open nft stdin")?
+        .write_all(ruleset.as_bytes())
+        .map_err(|e| format!("Failed to write nftables ruleset: {e}"))?;
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to wait for nft: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "nft rejected the accounting ruleset: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let mut accounts = ACCOUNTS.lock().map_err(|_| "Accounting state poisoned")?;
+    *accounts = rules
+        .iter()
+        .map(|rule| AccountState {
+            name: rule.name.clone(),
+            base_tx: 0,
+            base_rx: 0,
+            counter_tx: 0,
+            counter_rx: 0,
+        })
+        .collect();
+
+    // `nft` can stall, the counters are read in the background and Real-Time Info takes the
+    // last values
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _ = tokio::task::spawn_blocking(refresh_counters).await;
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn init_accounting(_rules: &[AccountRule]) -> Result<(), String> {
+    Err("Traffic accounting requires nftables and is only supported on Linux".to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn run_nft(args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("nft").args(args).output().ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        warn!(
+            "nft {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_nft(_args: &[&str]) -> Option<String> {
+    None
+}
+
+/// Parse `nft list counters` output into (counter name, bytes) pairs
+fn parse_counters(output: &str) -> Vec<(String, u64)> {
+    let mut counters = Vec::new();
+    let mut current = None;
+
+    for line in output.lines() {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("counter"), Some(name)) => current = Some(name.to_string()),
+            (Some("packets"), Some(_)) => {
+                if let (Some(name), Some("bytes"), Some(bytes)) =
+                    (current.take(), words.next(), words.next())
+                {
+                    counters.push((name, bytes.parse().unwrap_or(0)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    counters
+}
+
+fn refresh_counters() {
+    let Ok(_nft) = NFT.lock() else {
+        return;
+    };
+    if ACCOUNTS.lock().map_or(true, |accounts| accounts.is_empty()) {
+        return;
+    }
+    let Some(output) = run_nft(&["list", "counters", "table", "inet", NFT_TABLE]) else {
+        return;
+    };
+
+    let Ok(mut accounts) = ACCOUNTS.lock() else {
+        return;
+    };
+    for (name, bytes) in parse_counters(&output) {
+        for account in accounts.iter_mut() {
+            if name.strip_suffix("_tx") == Some(account.name.as_str()) {
+                account.counter_tx = bytes;
+            } else if name.strip_suffix("_rx") == Some(account.name.as_str()) {
+                account.counter_rx = bytes;
+            }
+        }
+    }
+}
+
+/// Current cycle totals per account as (name, tx, rx), refreshing the kernel counters first
+pub fn cycle_totals() -> Vec<(String, u64, u64)> {
+    refresh_counters();
+    ACCOUNTS
+        .lock()
+        .map_or_else(|_| Vec::new(), |accounts| totals(&accounts))
+}
+
+fn totals(accounts: &[AccountState]) -> Vec<(String, u64, u64)> {
+    accounts
+        .iter()
+        .map(|a| {
+            (
+                a.name.clone(),
+                a.base_tx + a.counter_tx,
+                a.base_rx + a.counter_rx,
+            )
+        })
+        .collect()
+}
+
+/// Reset the kernel counters and continue the cycle from the given totals
+pub fn restore_cycle(totals: &[(String, u64, u64)]) {
+    let Ok(_nft) = NFT.lock() else {
+        return;
+    };
+    if ACCOUNTS.lock().map_or(true, |accounts| accounts.is_empty()) {
+        return;
+    }
+    if run_nft(&["reset", "counters", "table", "inet", NFT_TABLE]).is_none() {
+        warn!("Failed to reset traffic accounting counters");
+    }
+
+    let Ok(mut accounts) = ACCOUNTS.lock() else {
+        return;
+    };
+
+    for account in accounts.iter_mut() {
+        let (base_tx, base_rx) = totals
+            .iter()
+            .find(|(name, _, _)| *name == account.name)
+            .map_or((0, 0), |(_, tx, rx)| (*tx, *rx));
+        account.base_tx = base_tx;
+        account.base_rx = base_rx;
+        account.counter_tx = 0;
+        account.counter_rx = 0;
+    }
+}
+
+/// Delete the accounting table, on exit
+pub fn remove_accounting() {
+    let Ok(_nft) = NFT.lock() else {
+        return;
+    };
+    let Ok(mut accounts) = ACCOUNTS.lock() else {
+        return;
+    };
+    if !accounts.is_empty() && run_nft(&["delete", "table", "inet", NFT_TABLE]).is_some() {
+        accounts.clear();
+    }
+}
+
+/// Cycle totals as of the last background refresh
+pub fn realtime_accounting() -> Option<Vec<AccountedTraffic>> {
+    let totals = totals(&ACCOUNTS.lock().ok()?);
+    if totals.is_empty() {
+        return None;
+    }
+
+    let accounting = totals
+        .into_iter()
+        .map(|(name, tx, rx)| AccountedTraffic {
+            name,
+            total_up: tx,
+            total_down: rx,
+        })
+        .collect();
+    trace!("REALTIME ACCOUNTING successfully retrieved: {accounting:?}");
+    Some(accounting)
+}
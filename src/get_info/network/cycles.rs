@@ -0,0 +1,255 @@
+//! Additional traffic cycles (`--traffic-cycle`), e.g. a daily cap next to the monthly
+//! quota tracked by the main cycle.
+//!
+//! Each cycle keeps its own offset to the interface counters and resets on its own
+//! calendar schedule. `network_saver` persists them in the same state file as the main
+//! cycle, under `cycle.<name>.*` keys.
+
+use crate::command_parser::{NetworkConfig, NetworkStatisticsMode, TrafficPeriod};
+use crate::data_struct::TrafficCycle;
+use crate::get_info::network::network_saver::calculate_next_reset_timestamp;
+use log::info;
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleSpec {
+    pub name: String,
+    pub period: TrafficPeriod,
+    pub reset_day: String,
+}
+
+impl CycleSpec {
+    /// Stored next to the totals, a cycle whose schedule changed starts over
+    fn schedule(&self) -> String {
+        format!("{:?}:{}", self.period, self.reset_day)
+    }
+
+    fn next_reset(&self, now: OffsetDateTime) -> Result<i64, String> {
+        let config = NetworkConfig {
+            disable_network_statistics: false,
+            network_interval: 0,
+            network_save_path: String::new(),
+            traffic_period: self.period.clone(),
+            traffic_reset_day: self.reset_day.clone(),
+            network_statistics_mode: NetworkStatisticsMode::Natural,
+            network_duration: 0,
+            network_interval_number: 0,
+        };
+        calculate_next_reset_timestamp(&config, now)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CycleState {
+    pub name: String,
+    pub schedule: String,
+    pub tx: u64,
+    pub rx: u64,
+    pub next_reset: i64,
+    pub offset_tx: i64,
+    pub offset_rx: i64,
+}
+
+static CYCLES: Mutex<Vec<CycleState>> = Mutex::new(Vec::new());
+
+fn signed(bytes: u64) -> i64 {
+    i64::try_from(bytes).unwrap_or(i64::MAX)
+}
+
+/// Parse `NAME=PERIOD[:RESET]`, the reset day defaults to the start of the period
+pub fn parse_cycles(specs: &[String]) -> Result<Vec<CycleSpec>, String> {
+    let cycles = specs
+        .iter()
+        .map(|spec| {
+            let (name, schedule) = spec.split_once('=').ok_or_else(|| {
+                format!("Invalid traffic cycle '{spec}', expected NAME=PERIOD[:RESET]")
+            })?;
+            let name = name.trim();
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(format!("Invalid traffic cycle name '{name}'"));
+            }
+
+            let (period, reset_day) = schedule
+                .split_once(':')
+                .map_or((schedule, None), |(period, day)| (period, Some(day)));
+            let (period, default_day) = match period.trim().to_lowercase().as_str() {
+                "day" => (TrafficPeriod::Day, "1"),
+                "week" => (TrafficPeriod::Week, "1"),
+                "month" => (TrafficPeriod::Month, "1"),
+                "year" => (TrafficPeriod::Year, "01/01"),
+                other => {
+                    return Err(format!(
+                        "Invalid traffic cycle period '{other}', expected day / week / month / year"
+                    ));
+                }
+            };
+            let cycle = CycleSpec {
+                name: name.to_string(),
+                period,
+                reset_day: reset_day.unwrap_or(default_day).trim().to_string(),
+            };
+            cycle.next_reset(OffsetDateTime::now_utc())?;
+            Ok(cycle)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    for (index, cycle) in cycles.iter().enumerate() {
+        if cycles[..index].iter().any(|other| other.name == cycle.name) {
+            return Err(format!("Duplicate traffic cycle '{}'", cycle.name));
+        }
+    }
+    Ok(cycles)
+}
+
+/// Cycles for this session from the saved ones. After a reboot the interface counters
+/// start over, the saved totals become the offsets.
+pub fn start_cycles(
+    specs: &[CycleSpec],
+    saved: &[CycleState],
+    now: OffsetDateTime,
+    is_reboot: bool,
+    current_tx: u64,
+    current_rx: u64,
+) -> Result<Vec<CycleState>, String> {
+    specs
+        .iter()
+        .map(|spec| {
+            let schedule = spec.schedule();
+            let saved = saved
+                .iter()
+                .find(|cycle| cycle.name == spec.name && cycle.schedule == schedule);
+            let cycle = match saved {
+                Some(cycle) if now.unix_timestamp() < cycle.next_reset => {
+                    let mut cycle = cycle.clone();
+                    if is_reboot {
+                        cycle.offset_tx = signed(cycle.tx);
+                        cycle.offset_rx = signed(cycle.rx);
+                    }
+                    cycle
+                }
+                _ => CycleState {
+                    name: spec.name.clone(),
+                    schedule,
+                    tx: 0,
+                    rx: 0,
+                    next_reset: spec.next_reset(now)?,
+                    offset_tx: -signed(current_tx),
+                    offset_rx: -signed(current_rx),
+                },
+            };
+            Ok(cycle)
+        })
+        .collect()
+}
+
+/// Update the totals and reset the cycles whose period ended, `true` if any did
+pub fn tick_cycles(
+    cycles: &mut [CycleState],
+    specs: &[CycleSpec],
+    now: OffsetDateTime,
+    current_tx: u64,
+    current_rx: u64,
+) -> bool {
+    let mut reset = false;
+    for (cycle, spec) in cycles.iter_mut().zip(specs) {
+        if now.unix_timestamp() >= cycle.next_reset {
+            info!("Traffic cycle '{}' ended. Resetting...", cycle.name);
+            cycle.offset_tx = -signed(current_tx);
+            cycle.offset_rx = -signed(current_rx);
+            cycle.next_reset = spec.next_reset(now).unwrap_or(now.unix_timestamp() + 86400);
+            reset = true;
+        }
+        cycle.tx = current_tx.saturating_add_signed(cycle.offset_tx);
+        cycle.rx = current_rx.saturating_add_signed(cycle.offset_rx);
+    }
+    publish(cycles);
+    reset
+}
+
+pub fn publish(cycles: &[CycleState]) {
+    if let Ok(mut published) = CYCLES.lock() {
+        *published = cycles.to_vec();
+    }
+}
+
+/// `key=value` lines appended to the state file
+pub fn encode_cycles(cycles: &[CycleState]) -> String {
+    use std::fmt::Write as _;
+
+    let mut output = String::new();
+    for cycle in cycles {
+        let name = &cycle.name;
+        let _ = writeln!(output, "cycle.{name}.schedule={}", cycle.schedule);
+        let _ = writeln!(output, "cycle.{name}.tx={}", cycle.tx);
+        let _ = writeln!(output, "cycle.{name}.rx={}", cycle.rx);
+        let _ = writeln!(output, "cycle.{name}.next_reset={}", cycle.next_reset);
+        let _ = writeln!(output, "cycle.{name}.offset_tx={}", cycle.offset_tx);
+        let _ = writeln!(output, "cycle.{name}.offset_rx={}", cycle.offset_rx);
+    }
+    output
+}
+
+/// Apply one `cycle.<name>.<field>` line, `false` if the key is not a cycle key
+pub fn decode_cycle_line(
+    cycles: &mut Vec<CycleState>,
+    key: &str,
+    value: &str,
+) -> Result<bool, String> {
+    let Some((name, field)) = key
+        .strip_prefix("cycle.")
+        .and_then(|rest| rest.rsplit_once('.'))
+    else {
+        return Ok(false);
+    };
+    let index = if let Some(index) = cycles.iter().position(|cycle| cycle.name == name) {
+        index
+    } else {
+        cycles.push(CycleState {
+            name: name.to_string(),
+            ..CycleState::default()
+        });
+        cycles.len() - 1
+    };
+
+    let cycle = &mut cycles[index];
+    let parse_err = |type_name: &str| format!("Invalid {type_name} for key '{key}'");
+    match field {
+        "schedule" => cycle.schedule = value.to_string(),
+        "tx" => cycle.tx = value.parse().map_err(|_| parse_err("u64"))?,
+        "rx" => cycle.rx = value.parse().map_err(|_| parse_err("u64"))?,
+        "next_reset" => cycle.next_reset = value.parse().map_err(|_| parse_err("i64"))?,
+        "offset_tx" => cycle.offset_tx = value.parse().map_err(|_| parse_err("i64"))?,
+        "offset_rx" => cycle.offset_rx = value.parse().map_err(|_| parse_err("i64"))?,
+        _ => {}
+    }
+    Ok(true)
+}
+
+/// Live totals of every cycle from the current interface totals
+pub fn realtime_cycles(current_tx: u64, current_rx: u64) -> Option<Vec<TrafficCycle>> {
+    let cycles = CYCLES.lock().ok()?;
+    if cycles.is_empty() {
+        return None;
+    }
+    Some(
+        cycles
+            .iter()
+            .map(|cycle| TrafficCycle {
+                name: cycle.name.clone(),
+                schedule: cycle.schedule.clone(),
+                total_up: current_tx.saturating_add_signed(cycle.offset_tx),
+                total_down: current_rx.saturating_add_signed(cycle.offset_rx),
+                next_reset: OffsetDateTime::from_unix_timestamp(cycle.next_reset)
+                    .ok()
+                    .and_then(|at| at.format(&Rfc3339).ok())
+                    .unwrap_or_default(),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,648 @@
+//! Per-process network accounting and TCP connection counting with eBPF.
+//!
+//! A tiny hand-assembled BPF program is attached to the socket send / receive paths
+//! (the same probe points as bcc's `tcptop`) and adds the byte count into a hash map
+//! keyed by TGID. The map is read from user space on every Real-Time Info sample.
+//!
+//! Another one follows the `sock:inet_sock_set_state` tracepoint and keeps the number of
+//! TCP connections entering minus leaving `ESTABLISHED` in a single counter. Added to a
+//! netlink dump taken at start (and again every `RESYNC_INTERVAL`, in case an event was
+//! missed), it gives the same count as the dump without walking every socket per sample.
+
+use crate::data_struct::ProcessNetwork;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use crate::data_struct::ProcessNetwork;
+    use log::{debug, trace, warn};
+    use std::collections::HashMap;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    const BPF_MAP_CREATE: libc::c_long = 0;
+    const BPF_MAP_LOOKUP_ELEM: libc::c_long = 1;
+    const BPF_MAP_DELETE_ELEM: libc::c_long = 3;
+    const BPF_MAP_GET_NEXT_KEY: libc::c_long = 4;
+    const BPF_PROG_LOAD: libc::c_long = 5;
+
+    const BPF_MAP_TYPE_ARRAY: u32 = 2;
+    const BPF_MAP_TYPE_LRU_HASH: u32 = 9;
+    const BPF_PROG_TYPE_KPROBE: u32 = 2;
+    const BPF_PROG_TYPE_TRACEPOINT: u32 = 5;
+    const BPF_PSEUDO_MAP_FD: u8 = 1;
+    const BPF_NOEXIST: i32 = 1;
+
+    const BPF_FUNC_MAP_LOOKUP_ELEM: i32 = 1;
+    const BPF_FUNC_MAP_UPDATE_ELEM: i32 = 2;
+    const BPF_FUNC_GET_CURRENT_PID_TGID: i32 = 14;
+
+    const PERF_TYPE_TRACEPOINT: u32 = 2;
+    const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+    const PERF_EVENT_IOC_SET_BPF: libc::c_ulong = 0x4004_2408;
+    const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+    const MAX_PROCESSES: u32 = 4096;
+
+    const TCP_ESTABLISHED: i32 = 1;
+    const TRACEPOINT: &str = "sock/inet_sock_set_state";
+    /// The count is taken from a netlink dump again this often
+    const RESYNC_INTERVAL: Duration = Duration::from_mins(10);
+
+    /// Offset of the Nth function argument in `struct pt_regs`
+    #[cfg(target_arch = "x86_64")]
+    const ARG_OFFSETS: [i16; 3] = [112, 104, 96]; // di, si, dx
+    #[cfg(target_arch = "aarch64")]
+    const ARG_OFFSETS: [i16; 3] = [0, 8, 16]; // regs[0..3]
+
+    /// Value slot in the map, `[tx, rx]`
+    #[derive(Clone, Copy)]
+    enum Direction {
+        Tx = 0,
+        Rx = 8,
+    }
+
+    /// Kernel function, 1-based index of its byte count argument, and direction
+    const PROBES: [(&str, usize, Direction); 5] = [
+        ("tcp_sendmsg", 3, Direction::Tx),
+        ("udp_sendmsg", 3, Direction::Tx),
+        ("udpv6_sendmsg", 3, Direction::Tx),
+        ("tcp_cleanup_rbuf", 2, Direction::Rx),
+        ("skb_consume_udp", 3, Direction::Rx),
+    ];
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Insn {
+        code: u8,
+        regs: u8,
+        off: i16,
+        imm: i32,
+    }
+
+    const fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Insn {
+        Insn {
+            code,
+            regs: dst | (src << 4),
+            off,
+            imm,
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct MapCreateAttr {
+        map_type: u32,
+        key_size: u32,
+        value_size: u32,
+        max_entries: u32,
+        map_flags: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct MapElemAttr {
+        map_fd: u32,
+        _pad: u32,
+        key: u64,
+        value: u64,
+        flags: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct ProgLoadAttr {
+        prog_type: u32,
+        insn_cnt: u32,
+        insns: u64,
+        license: u64,
+        log_level: u32,
+        log_size: u32,
+        log_buf: u64,
+        kern_version: u32,
+    }
+
+    /// `PERF_ATTR_SIZE_VER1` layout of `struct perf_event_attr`
+    #[repr(C)]
+    #[derive(Default)]
+    struct PerfEventAttr {
+        type_: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+        config2: u64,
+    }
+
+    struct Collector {
+        map: OwnedFd,
+        _prog_fds: Vec<OwnedFd>,
+        _perf_fds: Vec<OwnedFd>,
+        top: usize,
+        previous: HashMap<u32, (u64, u64)>,
+        last_read: Instant,
+    }
+
+    static COLLECTOR: Mutex<Option<Collector>> = Mutex::new(None);
+
+    struct ConnectionCounter {
+        map: OwnedFd,
+        _prog_fd: OwnedFd,
+        _perf_fd: OwnedFd,
+        /// Dump count minus the counter at the time of the dump
+        baseline: i64,
+        synced_at: Option<Instant>,
+    }
+
+    static CONNECTIONS: Mutex<Option<ConnectionCounter>> = Mutex::new(None);
+
+    fn bpf<T>(cmd: libc::c_long, attr: &mut T) -> io::Result<RawFd> {
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_bpf,
+                cmd,
+                std::ptr::from_mut(attr),
+                size_of::<T>() as libc::c_uint,
+            )
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret as RawFd)
+        }
+    }
+
+    /// `LINUX_VERSION_CODE` of the running kernel, checked for kprobe programs before 5.0
+    fn kernel_version() -> u32 {
+        let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+        let mut parts = release.trim().split(['.', '-']).map(|p| {
+            p.chars()
+                .take_while(char::is_ascii_digit)
+                .collect::<String>()
+                .parse::<u32>()
+                .unwrap_or(0)
+        });
+        let major = parts.next().unwrap_or(0);
+        let minor = parts.next().unwrap_or(0);
+        let patch = parts.next().unwrap_or(0).min(255);
+        (major << 16) | (minor << 8) | patch
+    }
+
+    /// `map[tgid][direction] += <argument>`
+    fn program(map_fd: RawFd, arg: usize, direction: Direction) -> Vec<Insn> {
+        let arg_offset = ARG_OFFSETS[arg - 1];
+        let slot = direction as i16;
+
+        vec![
+            insn(0xbf, 6, 1, 0, 0),          // r6 = r1 (ctx)
+            insn(0x61, 7, 6, arg_offset, 0), // r7 = *(u32 *)(r6 + arg)
+            insn(0x15, 7, 0, 24, 0),         // if r7 == 0 goto exit
+            insn(0x25, 7, 0, 23, i32::MAX),  // if r7 > INT_MAX goto exit (negative int)
+            insn(0x85, 0, 0, 0, BPF_FUNC_GET_CURRENT_PID_TGID),
+            insn(0x77, 0, 0, 0, 32),  // r0 >>= 32 (tgid)
+            insn(0x63, 10, 0, -4, 0), // *(u32 *)(r10 - 4) = r0
+            insn(0x18, 1, BPF_PSEUDO_MAP_FD, 0, map_fd),
+            insn(0, 0, 0, 0, 0),
+            insn(0xbf, 2, 10, 0, 0), // r2 = r10
+            insn(0x07, 2, 0, 0, -4), // r2 -= 4
+            insn(0x85, 0, 0, 0, BPF_FUNC_MAP_LOOKUP_ELEM),
+            insn(0x15, 0, 0, 3, 0),           // if r0 == 0 goto insert
+            insn(0xdb, 0, 7, slot, 0),        // lock *(u64 *)(r0 + slot) += r7
+            insn(0xb7, 0, 0, 0, 0),           // r0 = 0
+            insn(0x95, 0, 0, 0, 0),           // exit
+            insn(0x7a, 10, 0, -24, 0),        // insert: *(u64 *)(r10 - 24) = 0
+            insn(0x7a, 10, 0, -16, 0),        // *(u64 *)(r10 - 16) = 0
+            insn(0x7b, 10, 7, -24 + slot, 0), // *(u64 *)(r10 - 24 + slot) = r7
+            insn(0x18, 1, BPF_PSEUDO_MAP_FD, 0, map_fd),
+            insn(0, 0, 0, 0, 0),
+            insn(0xbf, 2, 10, 0, 0),  // r2 = r10
+            insn(0x07, 2, 0, 0, -4),  // r2 -= 4
+            insn(0xbf, 3, 10, 0, 0),  // r3 = r10
+            insn(0x07, 3, 0, 0, -24), // r3 -= 24
+            insn(0xb7, 4, 0, 0, BPF_NOEXIST),
+            insn(0x85, 0, 0, 0, BPF_FUNC_MAP_UPDATE_ELEM),
+            insn(0xb7, 0, 0, 0, 0), // exit: r0 = 0
+            insn(0x95, 0, 0, 0, 0),
+        ]
+    }
+
+    /// Offsets of `oldstate`, `newstate` and `protocol` in the tracepoint record
+    #[derive(Clone, Copy)]
+    struct StateOffsets {
+        oldstate: i16,
+        newstate: i16,
+        protocol: i16,
+    }
+
+    /// `counter += (newstate == ESTABLISHED) - (oldstate == ESTABLISHED)` for TCP sockets
+    fn connection_program(map_fd: RawFd, offsets: StateOffsets) -> Vec<Insn> {
+        vec![
+            insn(0xbf, 6, 1, 0, 0),                  // r6 = r1 (ctx)
+            insn(0x69, 2, 6, offsets.protocol, 0),   // r2 = *(u16 *)(r6 + protocol)
+            insn(0x55, 2, 0, 16, libc::IPPROTO_TCP), // if r2 != TCP goto exit
+            insn(0x61, 7, 6, offsets.oldstate, 0),   // r7 = *(u32 *)(r6 + oldstate)
+            insn(0x61, 8, 6, offsets.newstate, 0),   // r8 = *(u32 *)(r6 + newstate)
+            insn(0xb7, 9, 0, 0, 0),                  // r9 = 0
+            insn(0x55, 8, 0, 1, TCP_ESTABLISHED),    // if r8 != ESTABLISHED skip
+            insn(0x07, 9, 0, 0, 1),                  // r9 += 1
+            insn(0x55, 7, 0, 1, TCP_ESTABLISHED),    // if r7 != ESTABLISHED skip
+            insn(0x07, 9, 0, 0, -1),                 // r9 -= 1
+            insn(0x15, 9, 0, 8, 0),                  // if r9 == 0 goto exit
+            insn(0x62, 10, 0, -4, 0),                // *(u32 *)(r10 - 4) = 0
+            insn(0x18, 1, BPF_PSEUDO_MAP_FD, 0, map_fd),
+            insn(0, 0, 0, 0, 0),
+            insn(0xbf, 2, 10, 0, 0), // r2 = r10
+            insn(0x07, 2, 0, 0, -4), // r2 -= 4
+            insn(0x85, 0, 0, 0, BPF_FUNC_MAP_LOOKUP_ELEM),
+            insn(0x15, 0, 0, 1, 0), // if r0 == 0 goto exit
+            insn(0xdb, 0, 9, 0, 0), // lock *(u64 *)(r0 + 0) += r9
+            insn(0xb7, 0, 0, 0, 0), // exit: r0 = 0
+            insn(0x95, 0, 0, 0, 0),
+        ]
+    }
+
+    fn load_program(map_fd: RawFd, arg: usize, direction: Direction) -> io::Result<OwnedFd> {
+        load(&program(map_fd, arg, direction), BPF_PROG_TYPE_KPROBE)
+    }
+
+    fn load(insns: &[Insn], prog_type: u32) -> io::Result<OwnedFd> {
+        let license = c"GPL";
+        let mut attr = ProgLoadAttr {
+            prog_type,
+            insn_cnt: insns.len() as u32,
+            insns: insns.as_ptr() as u64,
+            license: license.as_ptr() as u64,
+            kern_version: kernel_version(),
+            ..Default::default()
+        };
+        let fd = bpf(BPF_PROG_LOAD, &mut attr)?;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    fn attach_kprobe(pmu_type: u32, function: &str, prog_fd: RawFd) -> io::Result<OwnedFd> {
+        let name =
+            CString::new(function).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+        attach(
+            &PerfEventAttr {
+                type_: pmu_type,
+                size: size_of::<PerfEventAttr>() as u32,
+                config1: name.as_ptr() as u64,
+                ..Default::default()
+            },
+            prog_fd,
+        )
+    }
+
+    fn attach_tracepoint(id: u64, prog_fd: RawFd) -> io::Result<OwnedFd> {
+        attach(
+            &PerfEventAttr {
+                type_: PERF_TYPE_TRACEPOINT,
+                size: size_of::<PerfEventAttr>() as u32,
+                config: id,
+                sample_period: 1,
+                ..Default::default()
+            },
+            prog_fd,
+        )
+    }
+
+    fn attach(attr: &PerfEventAttr, prog_fd: RawFd) -> io::Result<OwnedFd> {
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                std::ptr::from_ref(attr),
+                -1 as libc::pid_t,
+                0 as libc::c_int,
+                -1 as libc::c_int,
+                PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+        if unsafe { libc::ioctl(fd.as_raw_fd(), PERF_EVENT_IOC_SET_BPF, prog_fd) } < 0
+            || unsafe { libc::ioctl(fd.as_raw_fd(), PERF_EVENT_IOC_ENABLE, 0) } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fd)
+    }
+
+    /// Kernels before 5.11 charge BPF maps against `RLIMIT_MEMLOCK`
+    fn raise_memlock() {
+        let unlimited = libc::rlimit {
+            rlim_cur: libc::RLIM_INFINITY,
+            rlim_max: libc::RLIM_INFINITY,
+        };
+        unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &raw const unlimited) };
+    }
+
+    pub fn init_process_network(top: usize) -> Result<(), String> {
+        if !cfg!(any(target_arch = "x86_64", target_arch = "aarch64")) {
+            return Err("eBPF collector only supports x86_64 and aarch64".to_string());
+        }
+
+        raise_memlock();
+
+        let pmu_type = std::fs::read_to_string("/sys/bus/event_source/devices/kprobe/type")
+            .map_err(|e| format!("kprobe PMU is not available: {e}"))?
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| format!("Invalid kprobe PMU type: {e}"))?;
+
+        let mut attr = MapCreateAttr {
+            map_type: BPF_MAP_TYPE_LRU_HASH,
+            key_size: 4,
+            value_size: 16,
+            max_entries: MAX_PROCESSES,
+            map_flags: 0,
+        };
+        let map =
+            bpf(BPF_MAP_CREATE, &mut attr).map_err(|e| format!("Failed to create BPF map: {e}"))?;
+        let map = unsafe { OwnedFd::from_raw_fd(map) };
+
+        let mut prog_fds = Vec::new();
+        let mut perf_fds = Vec::new();
+        for (function, arg, direction) in PROBES {
+            let prog = load_program(map.as_raw_fd(), arg, direction)
+                .map_err(|e| format!("Failed to load BPF program: {e}"))?;
+            match attach_kprobe(pmu_type, function, prog.as_raw_fd()) {
+                Ok(perf) => {
+                    debug!("eBPF kprobe attached to {function}");
+                    perf_fds.push(perf);
+                    prog_fds.push(prog);
+                }
+                Err(e) => warn!("Failed to attach eBPF kprobe to {function}: {e}"),
+            }
+        }
+        if perf_fds.is_empty() {
+            return Err("No eBPF kprobe could be attached".to_string());
+        }
+
+        let mut collector = COLLECTOR.lock().map_err(|_| "eBPF collector poisoned")?;
+        *collector = Some(Collector {
+            map,
+            _prog_fds: prog_fds,
+            _perf_fds: perf_fds,
+            top,
+            previous: HashMap::new(),
+            last_read: Instant::now(),
+        });
+        Ok(())
+    }
+
+    fn read_map(map_fd: RawFd) -> Vec<(u32, u64, u64)> {
+        let mut entries = Vec::new();
+        let mut key: Option<u32> = None;
+
+        loop {
+            let mut next_key = 0u32;
+            let mut attr = MapElemAttr {
+                map_fd: map_fd as u32,
+                key: key.as_ref().map_or(0, |k| std::ptr::from_ref(k) as u64),
+                value: std::ptr::from_mut(&mut next_key) as u64,
+                ..Default::default()
+            };
+            if bpf(BPF_MAP_GET_NEXT_KEY, &mut attr).is_err() {
+                break;
+            }
+
+            let mut value = [0u64; 2];
+            let mut attr = MapElemAttr {
+                map_fd: map_fd as u32,
+                key: std::ptr::from_ref(&next_key) as u64,
+                value: value.as_mut_ptr() as u64,
+                ..Default::default()
+            };
+            if bpf(BPF_MAP_LOOKUP_ELEM, &mut attr).is_ok() {
+                entries.push((next_key, value[0], value[1]));
+            }
+            key = Some(next_key);
+        }
+
+        entries
+    }
+
+    fn delete_key(map_fd: RawFd, pid: u32) {
+        let mut attr = MapElemAttr {
+            map_fd: map_fd as u32,
+            key: std::ptr::from_ref(&pid) as u64,
+            ..Default::default()
+        };
+        let _ = bpf(BPF_MAP_DELETE_ELEM, &mut attr);
+    }
+
+    pub fn realtime_process_network() -> Option<Vec<ProcessNetwork>> {
+        let mut guard = COLLECTOR.lock().ok()?;
+        let collector = guard.as_mut()?;
+
+        let elapsed = collector.last_read.elapsed().as_secs_f64().max(0.001);
+        collector.last_read = Instant::now();
+
+        let mut previous = HashMap::new();
+        let mut rates = Vec::new();
+        for (pid, tx, rx) in read_map(collector.map.as_raw_fd()) {
+            let Ok(name) = std::fs::read_to_string(format!("/proc/{pid}/comm")) else {
+                delete_key(collector.map.as_raw_fd(), pid);
+                continue;
+            };
+
+            // A counter going backwards means the PID was reused
+            let (last_tx, last_rx) = collector
+                .previous
+                .get(&pid)
+                .copied()
+                .filter(|(last_tx, last_rx)| *last_tx <= tx && *last_rx <= rx)
+                .unwrap_or((0, 0));
+            previous.insert(pid, (tx, rx));
+
+            let up = ((tx - last_tx) as f64 / elapsed) as u64;
+            let down = ((rx - last_rx) as f64 / elapsed) as u64;
+            if up == 0 && down == 0 {
+                continue;
+            }
+            rates.push(ProcessNetwork {
+                pid,
+                name: name.trim().to_string(),
+                up,
+                down,
+            });
+        }
+        collector.previous = previous;
+
+        rates.sort_unstable_by_key(|p| std::cmp::Reverse(p.up + p.down));
+        rates.truncate(collector.top);
+
+        trace!("REALTIME PROCESS NETWORK successfully retrieved: {rates:?}");
+        Some(rates)
+    }
+
+    /// The tracepoint's `format` file, tracefs is mounted in one of two places
+    fn tracepoint_format() -> Result<(u64, String), String> {
+        let dir = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"]
+            .iter()
+            .map(|root| format!("{root}/events/{TRACEPOINT}"))
+            .find(|dir| std::path::Path::new(dir).exists())
+            .ok_or_else(|| format!("Tracepoint {TRACEPOINT} not found, is tracefs mounted?"))?;
+        let id = std::fs::read_to_string(format!("{dir}/id"))
+            .map_err(|e| format!("Failed to read tracepoint id: {e}"))?
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid tracepoint id: {e}"))?;
+        let format = std::fs::read_to_string(format!("{dir}/format"))
+            .map_err(|e| format!("Failed to read tracepoint format: {e}"))?;
+        Ok((id, format))
+    }
+
+    /// Offset of `field` in a `field:int oldstate;\toffset:16;\tsize:4;` line
+    fn field_offset(format: &str, field: &str) -> Result<i16, String> {
+        format
+            .lines()
+            .find(|line| {
+                line.split(';')
+                    .next()
+                    .is_some_and(|declaration| declaration.ends_with(&format!(" {field}")))
+            })
+            .and_then(|line| {
+                line.split(';')
+                    .find_map(|part| part.trim().strip_prefix("offset:"))
+                    .and_then(|offset| offset.parse::<i16>().ok())
+            })
+            .ok_or_else(|| format!("Tracepoint {TRACEPOINT} has no field {field}"))
+    }
+
+    fn read_counter(map_fd: RawFd) -> Option<i64> {
+        let key = 0u32;
+        let mut value = 0i64;
+        let mut attr = MapElemAttr {
+            map_fd: map_fd as u32,
+            key: std::ptr::from_ref(&key) as u64,
+            value: std::ptr::from_mut(&mut value) as u64,
+            ..Default::default()
+        };
+        bpf(BPF_MAP_LOOKUP_ELEM, &mut attr).ok()?;
+        Some(value)
+    }
+
+    /// Established TCP connections from a netlink dump
+    fn dump_established() -> Option<i64> {
+        use super::super::netlink::connections_counts;
+        let tcp = libc::IPPROTO_TCP as u8;
+        let mut total = 0;
+        for count in connections_counts([(libc::AF_INET as u8, tcp), (libc::AF_INET6 as u8, tcp)]) {
+            total += count.ok()?;
+        }
+        i64::try_from(total).ok()
+    }
+
+    pub fn init_ebpf_connections() -> Result<(), String> {
+        raise_memlock();
+
+        let (id, format) = tracepoint_format()?;
+        let offsets = StateOffsets {
+            oldstate: field_offset(&format, "oldstate")?,
+            newstate: field_offset(&format, "newstate")?,
+            protocol: field_offset(&format, "protocol")?,
+        };
+
+        let mut attr = MapCreateAttr {
+            map_type: BPF_MAP_TYPE_ARRAY,
+            key_size: 4,
+            value_size: 8,
+            max_entries: 1,
+            map_flags: 0,
+        };
+        let map =
+            bpf(BPF_MAP_CREATE, &mut attr).map_err(|e| format!("Failed to create BPF map: {e}"))?;
+        let map = unsafe { OwnedFd::from_raw_fd(map) };
+
+        let prog = load(
+            &connection_program(map.as_raw_fd(), offsets),
+            BPF_PROG_TYPE_TRACEPOINT,
+        )
+        .map_err(|e| format!("Failed to load BPF program: {e}"))?;
+        let perf = attach_tracepoint(id, prog.as_raw_fd())
+            .map_err(|e| format!("Failed to attach to {TRACEPOINT}: {e}"))?;
+        debug!("eBPF program attached to {TRACEPOINT}");
+
+        let mut connections = CONNECTIONS.lock().map_err(|_| "eBPF counter poisoned")?;
+        *connections = Some(ConnectionCounter {
+            map,
+            _prog_fd: prog,
+            _perf_fd: perf,
+            baseline: 0,
+            synced_at: None,
+        });
+        Ok(())
+    }
+
+    pub fn ebpf_tcp_connections() -> Option<u64> {
+        let mut guard = CONNECTIONS.lock().ok()?;
+        let connections = guard.as_mut()?;
+
+        let counter = read_counter(connections.map.as_raw_fd())?;
+        if connections
+            .synced_at
+            .is_none_or(|synced_at| synced_at.elapsed() >= RESYNC_INTERVAL)
+            && let Some(dumped) = dump_established()
+        {
+            // Connections changing state during the dump may be off by a few until the next one
+            connections.baseline = dumped - read_counter(connections.map.as_raw_fd())?;
+            connections.synced_at = Some(Instant::now());
+        }
+        let count = u64::try_from(connections.baseline + counter).unwrap_or(0);
+        trace!("REALTIME EBPF TCP CONNECTIONS successfully retrieved: {count}");
+        Some(count)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use crate::data_struct::ProcessNetwork;
+
+    pub fn init_process_network(_top: usize) -> Result<(), String> {
+        Err("eBPF process network accounting is only supported on Linux".to_string())
+    }
+
+    pub fn realtime_process_network() -> Option<Vec<ProcessNetwork>> {
+        None
+    }
+
+    pub fn init_ebpf_connections() -> Result<(), String> {
+        Err("eBPF connection counting is only supported on Linux".to_string())
+    }
+
+    pub fn ebpf_tcp_connections() -> Option<u64> {
+        None
+    }
+}
+
+/// Load and attach the kprobes, needs root or `CAP_BPF` + `CAP_PERFMON`
+pub fn init_process_network(top: usize) -> Result<(), String> {
+    imp::init_process_network(top)
+}
+
+/// Top processes by network throughput (bytes/s) since the previous call,
+/// `None` if the collector is not running
+pub fn realtime_process_network() -> Option<Vec<ProcessNetwork>> {
+    imp::realtime_process_network()
+}
+
+/// Attach the TCP connection counter, needs root or `CAP_BPF` + `CAP_PERFMON`
+pub fn init_ebpf_connections() -> Result<(), String> {
+    imp::init_ebpf_connections()
+}
+
+/// Established TCP connections, `None` if the counter is not running
+pub fn ebpf_tcp_connections() -> Option<u64> {
+    imp::ebpf_tcp_connections()
+}
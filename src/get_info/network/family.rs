@@ -0,0 +1,148 @@
+//! Upload / download split by IP family (`--ip-family-stats`).
+//!
+//! IPv6 is read per interface from `/proc/net/dev_snmp6`, so loopback is left out exactly.
+//! The kernel only keeps IPv4 octets globally (`IpExt` in `/proc/net/netstat`), the
+//! loopback share is estimated from `/proc/net/dev` and subtracted. Totals are counted
+//! since boot and Linux only.
+
+use crate::data_struct::IpFamilyTraffic;
+use crate::get_info::with_file;
+use log::{trace, warn};
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counters {
+    in4: u64,
+    out4: u64,
+    in6: u64,
+    out6: u64,
+}
+
+struct FamilyState {
+    previous: Option<(Instant, Counters)>,
+}
+
+static FAMILY: Mutex<Option<FamilyState>> = Mutex::new(None);
+
+pub fn init_ip_family() {
+    if cfg!(not(target_os = "linux")) {
+        warn!("`--ip-family-stats` is only supported on Linux");
+        return;
+    }
+    if let Ok(mut family) = FAMILY.lock() {
+        *family = Some(FamilyState { previous: None });
+    }
+}
+
+/// `Ip6InOctets` / `Ip6OutOctets` of one `/proc/net/dev_snmp6` file
+fn parse_snmp6(content: &str) -> (u64, u64) {
+    let mut octets = (0, 0);
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next().and_then(|v| v.parse().ok())) {
+            (Some("Ip6InOctets"), Some(value)) => octets.0 = value,
+            (Some("Ip6OutOctets"), Some(value)) => octets.1 = value,
+            _ => {}
+        }
+    }
+    octets
+}
+
+/// `InOctets` / `OutOctets` of the `IpExt` section, its first line names the columns
+fn parse_netstat(content: &str) -> Option<(u64, u64)> {
+    let mut lines = content.lines().filter(|line| line.starts_with("IpExt:"));
+    let names = lines.next()?;
+    let values = lines.next()?;
+    let value = |name: &str| {
+        let index = names.split_whitespace().position(|n| n == name)?;
+        values.split_whitespace().nth(index)?.parse::<u64>().ok()
+    };
+    Some((value("InOctets")?, value("OutOctets")?))
+}
+
+/// Loopback `(rx_bytes, rx_packets, tx_bytes, tx_packets)` from `/proc/net/dev`
+fn parse_loopback(content: &str) -> Option<(u64, u64, u64, u64)> {
+    let line = content
+        .lines()
+        .find(|line| line.trim_start().starts_with("lo:"))?;
+    let mut fields = line
+        .split_once(':')?
+        .1
+        .split_whitespace()
+        .map(|v| v.parse::<u64>().unwrap_or(0));
+    let (rx_bytes, rx_packets) = (fields.next()?, fields.next()?);
+    Some((rx_bytes, rx_packets, fields.nth(6)?, fields.next()?))
+}
+
+fn read_counters() -> Option<Counters> {
+    let (in4, out4) = with_file("/proc/net/netstat", parse_netstat)?;
+
+    let mut counters = Counters {
+        in4,
+        out4,
+        ..Counters::default()
+    };
+    let mut lo6 = (0, 0);
+    for entry in std::fs::read_dir("/proc/net/dev_snmp6").ok()?.flatten() {
+        let Some((in6, out6)) = with_file(entry.path(), |content| Some(parse_snmp6(content)))
+        else {
+            continue;
+        };
+        if entry.file_name() == "lo" {
+            lo6 = (in6, out6);
+        } else {
+            counters.in6 += in6;
+            counters.out6 += out6;
+        }
+    }
+
+    // Loopback frames carry a 14 byte Ethernet header that the IP counters do not see
+    if let Some((rx_bytes, rx_packets, tx_bytes, tx_packets)) =
+        with_file("/proc/net/dev", parse_loopback)
+    {
+        let lo_in4 = rx_bytes.saturating_sub(rx_packets * 14 + lo6.0);
+        let lo_out4 = tx_bytes.saturating_sub(tx_packets * 14 + lo6.1);
+        counters.in4 = counters.in4.saturating_sub(lo_in4);
+        counters.out4 = counters.out4.saturating_sub(lo_out4);
+    }
+
+    Some(counters)
+}
+
+pub fn realtime_ip_family() -> Option<IpFamilyTraffic> {
+    let mut guard = FAMILY.lock().ok()?;
+    let state = guard.as_mut()?;
+
+    let counters = read_counters()?;
+    let now = Instant::now();
+    let rate = |current: u64, previous: u64, elapsed: f64| {
+        (current.saturating_sub(previous) as f64 / elapsed) as u64
+    };
+    let (up4, down4, up6, down6) = match state.previous {
+        Some((at, previous)) => {
+            let elapsed = now.duration_since(at).as_secs_f64().max(0.001);
+            (
+                rate(counters.out4, previous.out4, elapsed),
+                rate(counters.in4, previous.in4, elapsed),
+                rate(counters.out6, previous.out6, elapsed),
+                rate(counters.in6, previous.in6, elapsed),
+            )
+        }
+        None => (0, 0, 0, 0),
+    };
+    state.previous = Some((now, counters));
+
+    let traffic = IpFamilyTraffic {
+        up4,
+        down4,
+        total_up4: counters.out4,
+        total_down4: counters.in4,
+        up6,
+        down6,
+        total_up6: counters.out6,
+        total_down6: counters.in6,
+    };
+    trace!("REALTIME IP FAMILY successfully retrieved: {traffic:?}");
+    Some(traffic)
+}
@@ -0,0 +1,280 @@
+//! Interface groups (`--traffic-group`), e.g. the billable uplinks of a router next to
+//! its free peering links.
+//!
+//! A group counts the traffic of its member interfaces, whether or not they pass the
+//! network filter, over the main statistics cycle. `network_saver` persists the totals
+//! with the main cycle under `group.<name>.*` keys and checks them against the quota.
+
+use crate::data_struct::TrafficGroup;
+use crate::get_info::network::wrap::CounterWraps;
+use log::{info, warn};
+use std::sync::Mutex;
+use sysinfo::Networks;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupSpec {
+    pub name: String,
+    pub interfaces: Vec<String>,
+    /// Bytes up and down together per cycle
+    pub quota: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GroupState {
+    pub name: String,
+    /// Comma separated members, a group whose members changed starts over
+    pub interfaces: String,
+    pub quota: Option<u64>,
+    pub tx: u64,
+    pub rx: u64,
+    pub offset_tx: i64,
+    pub offset_rx: i64,
+    /// The quota warning was logged in this cycle, not persisted
+    pub warned: bool,
+}
+
+static GROUPS: Mutex<Vec<GroupState>> = Mutex::new(Vec::new());
+
+fn signed(bytes: u64) -> i64 {
+    i64::try_from(bytes).unwrap_or(i64::MAX)
+}
+
+/// `500G`, `1.5T`, `800000`: bytes, the suffixes are binary
+pub fn parse_quota(quota: &str) -> Result<u64, String> {
+    let quota = quota.trim();
+    let (number, unit) = quota
+        .find(|c: char| c.is_ascii_alphabetic())
+        .map_or((quota, ""), |index| quota.split_at(index));
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "Invalid unit in quota '{quota}', expected K / M / G / T"
+            ));
+        }
+    };
+    let number = number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number > 0.0)
+        .ok_or_else(|| format!("Invalid quota '{quota}'"))?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parse `NAME=IFACE[,IFACE...][:QUOTA]`
+pub fn parse_groups(specs: &[String]) -> Result<Vec<GroupSpec>, String> {
+    let groups = specs
+        .iter()
+        .map(|spec| {
+            let (name, members) = spec.split_once('=').ok_or_else(|| {
+                format!("Invalid traffic group '{spec}', expected NAME=IFACE[,IFACE...][:QUOTA]")
+            })?;
+            let name = name.trim();
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(format!("Invalid traffic group name '{name}'"));
+            }
+
+            let (members, quota) = members
+                .split_once(':')
+                .map_or((members, None), |(members, quota)| (members, Some(quota)));
+            let interfaces = members
+                .split(',')
+                .map(str::trim)
+                .filter(|interface| !interface.is_empty())
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            if interfaces.is_empty() {
+                return Err(format!("Traffic group '{name}' has no interfaces"));
+            }
+
+            Ok(GroupSpec {
+                name: name.to_string(),
+                interfaces,
+                quota: quota.map(parse_quota).transpose()?,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    for (index, group) in groups.iter().enumerate() {
+        if groups[..index].iter().any(|other| other.name == group.name) {
+            return Err(format!("Duplicate traffic group '{}'", group.name));
+        }
+    }
+    Ok(groups)
+}
+
+/// Interface totals of the members present on the machine
+fn member_totals(networks: &Networks, wraps: &CounterWraps, interfaces: &str) -> (u64, u64) {
+    let members = interfaces.split(',').collect::<Vec<_>>();
+    networks
+        .iter()
+        .filter(|(name, _)| members.contains(&name.as_str()))
+        .fold((0, 0), |(tx, rx), (name, data)| {
+            let (total_tx, total_rx) = wraps.totals(name, data);
+            (tx + total_tx, rx + total_rx)
+        })
+}
+
+/// Groups for this session from the saved ones, the saved list is empty after the main
+/// cycle reset. After a reboot the interface counters start over, the saved totals
+/// become the offsets.
+pub fn start_groups(
+    specs: &[GroupSpec],
+    saved: &[GroupState],
+    networks: &Networks,
+    wraps: &CounterWraps,
+    is_reboot: bool,
+) -> Vec<GroupState> {
+    specs
+        .iter()
+        .map(|spec| {
+            let interfaces = spec.interfaces.join(",");
+            let saved = saved
+                .iter()
+                .find(|group| group.name == spec.name && group.interfaces == interfaces);
+            let mut group = if let Some(group) = saved {
+                let mut group = group.clone();
+                if is_reboot {
+                    group.offset_tx = signed(group.tx);
+                    group.offset_rx = signed(group.rx);
+                }
+                group
+            } else {
+                let (tx, rx) = member_totals(networks, wraps, &interfaces);
+                GroupState {
+                    name: spec.name.clone(),
+                    interfaces,
+                    offset_tx: -signed(tx),
+                    offset_rx: -signed(rx),
+                    ..GroupState::default()
+                }
+            };
+            group.quota = spec.quota;
+            group
+        })
+        .collect()
+}
+
+/// Update the totals and warn once per cycle about every group over its quota
+pub fn tick_groups(groups: &mut [GroupState], networks: &Networks, wraps: &CounterWraps) {
+    for group in groups.iter_mut() {
+        let (tx, rx) = member_totals(networks, wraps, &group.interfaces);
+        group.tx = tx.saturating_add_signed(group.offset_tx);
+        group.rx = rx.saturating_add_signed(group.offset_rx);
+
+        let used = group.tx.saturating_add(group.rx);
+        match group.quota {
+            Some(quota) if used >= quota && !group.warned => {
+                warn!(
+                    "Traffic group '{}' exceeded its quota: {used} of {quota} bytes",
+                    group.name
+                );
+                group.warned = true;
+            }
+            Some(quota) if used < quota && group.warned => {
+                info!("Traffic group '{}' is back within its quota", group.name);
+                group.warned = false;
+            }
+            _ => {}
+        }
+    }
+    publish(groups);
+}
+
+pub fn publish(groups: &[GroupState]) {
+    if let Ok(mut published) = GROUPS.lock() {
+        *published = groups.to_vec();
+    }
+}
+
+/// `key=value` lines appended to the state file
+pub fn encode_groups(groups: &[GroupState]) -> String {
+    use std::fmt::Write as _;
+
+    let mut output = String::new();
+    for group in groups {
+        let name = &group.name;
+        let _ = writeln!(output, "group.{name}.interfaces={}", group.interfaces);
+        let _ = writeln!(output, "group.{name}.tx={}", group.tx);
+        let _ = writeln!(output, "group.{name}.rx={}", group.rx);
+        let _ = writeln!(output, "group.{name}.offset_tx={}", group.offset_tx);
+        let _ = writeln!(output, "group.{name}.offset_rx={}", group.offset_rx);
+    }
+    output
+}
+
+/// Apply one `group.<name>.<field>` line, `false` if the key is not a group key
+pub fn decode_group_line(
+    groups: &mut Vec<GroupState>,
+    key: &str,
+    value: &str,
+) -> Result<bool, String> {
+    let Some((name, field)) = key
+        .strip_prefix("group.")
+        .and_then(|rest| rest.rsplit_once('.'))
+    else {
+        return Ok(false);
+    };
+    let index = if let Some(index) = groups.iter().position(|group| group.name == name) {
+        index
+    } else {
+        groups.push(GroupState {
+            name: name.to_string(),
+            ..GroupState::default()
+        });
+        groups.len() - 1
+    };
+
+    let group = &mut groups[index];
+    let parse_err = |type_name: &str| format!("Invalid {type_name} for key '{key}'");
+    match field {
+        "interfaces" => group.interfaces = value.to_string(),
+        "tx" => group.tx = value.parse().map_err(|_| parse_err("u64"))?,
+        "rx" => group.rx = value.parse().map_err(|_| parse_err("u64"))?,
+        "offset_tx" => group.offset_tx = value.parse().map_err(|_| parse_err("i64"))?,
+        "offset_rx" => group.offset_rx = value.parse().map_err(|_| parse_err("i64"))?,
+        _ => {}
+    }
+    Ok(true)
+}
+
+/// Live totals of every group from the current interface counters
+pub fn realtime_groups(networks: &Networks, wraps: &CounterWraps) -> Option<Vec<TrafficGroup>> {
+    let groups = GROUPS.lock().ok()?;
+    if groups.is_empty() {
+        return None;
+    }
+    Some(
+        groups
+            .iter()
+            .map(|group| {
+                let (tx, rx) = member_totals(networks, wraps, &group.interfaces);
+                let total_up = tx.saturating_add_signed(group.offset_tx);
+                let total_down = rx.saturating_add_signed(group.offset_rx);
+                TrafficGroup {
+                    name: group.name.clone(),
+                    interfaces: group
+                        .interfaces
+                        .split(',')
+                        .map(ToString::to_string)
+                        .collect(),
+                    total_up,
+                    total_down,
+                    quota: group.quota,
+                    over_quota: group
+                        .quota
+                        .is_some_and(|quota| total_up.saturating_add(total_down) >= quota),
+                }
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,202 @@
+//! Per-interface daily traffic (`--interface-history`), like the day view of vnstat.
+//!
+//! Every interface counted in the network totals gets one entry per local day, the N most
+//! recent days are kept. `network_saver` persists them in its state file under
+//! `history.<YYYY-MM-DD>.<iface>` keys, they are not cleared when the statistics cycle
+//! resets. The counters seen last are saved too, traffic while the agent was stopped
+//! lands on the day it starts again. `ctl history` prints them.
+
+use crate::get_info::filters::should_monitor;
+use crate::get_info::network::topology;
+use crate::get_info::network::wrap::CounterWraps;
+use miniserde::{Serialize, json};
+use std::sync::Mutex;
+use sysinfo::Networks;
+use time::{Date, OffsetDateTime};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InterfaceTotals {
+    pub name: String,
+    pub tx: u64,
+    pub rx: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DayTotals {
+    /// Local date, `YYYY-MM-DD`
+    pub date: String,
+    pub interfaces: Vec<InterfaceTotals>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HistoryState {
+    /// Oldest first
+    pub days: Vec<DayTotals>,
+    /// Interface counters at the last tick
+    pub last: Vec<InterfaceTotals>,
+}
+
+static HISTORY: Mutex<Option<Vec<DayTotals>>> = Mutex::new(None);
+
+fn date_string(date: Date) -> String {
+    format!(
+        "{:04}-{:02}-{:02}",
+        date.year(),
+        u8::from(date.month()),
+        date.day()
+    )
+}
+
+/// History for this session from the saved one. After a reboot the interface counters
+/// start over from zero.
+pub fn start_history(saved: &HistoryState, is_reboot: bool) -> HistoryState {
+    let mut history = saved.clone();
+    if is_reboot {
+        for last in &mut history.last {
+            last.tx = 0;
+            last.rx = 0;
+        }
+    }
+    history
+}
+
+/// Add the traffic since the last tick to today and keep the `days` most recent days
+pub fn tick_history(
+    history: &mut HistoryState,
+    networks: &Networks,
+    wraps: &CounterWraps,
+    days: u32,
+) {
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let today = date_string(now.date());
+    if history.days.last().is_none_or(|day| day.date != today) {
+        history.days.push(DayTotals {
+            date: today,
+            interfaces: Vec::new(),
+        });
+    }
+    let day = history.days.last_mut().expect("today was just added");
+
+    let double_counted = topology::double_counted();
+    for (name, data) in networks {
+        if !should_monitor(name, data, &double_counted) {
+            continue;
+        }
+        let (tx, rx) = wraps.totals(name, data);
+        // An interface seen for the first time starts counting now
+        let Some(last) = history.last.iter_mut().find(|last| last.name == *name) else {
+            history.last.push(InterfaceTotals {
+                name: name.clone(),
+                tx,
+                rx,
+            });
+            continue;
+        };
+        // A counter going backwards was reset, e.g. the interface was recreated
+        let delta_tx = tx.checked_sub(last.tx).unwrap_or(tx);
+        let delta_rx = rx.checked_sub(last.rx).unwrap_or(rx);
+        last.tx = tx;
+        last.rx = rx;
+
+        if let Some(totals) = day
+            .interfaces
+            .iter_mut()
+            .find(|totals| totals.name == *name)
+        {
+            totals.tx = totals.tx.saturating_add(delta_tx);
+            totals.rx = totals.rx.saturating_add(delta_rx);
+        } else {
+            day.interfaces.push(InterfaceTotals {
+                name: name.clone(),
+                tx: delta_tx,
+                rx: delta_rx,
+            });
+        }
+    }
+
+    let keep = usize::try_from(days.max(1)).unwrap_or(usize::MAX);
+    let excess = history.days.len().saturating_sub(keep);
+    history.days.drain(..excess);
+    publish(history);
+}
+
+fn publish(history: &HistoryState) {
+    if let Ok(mut published) = HISTORY.lock() {
+        *published = Some(history.days.clone());
+    }
+}
+
+/// The recorded days as JSON, `None` unless `--interface-history` is given
+pub fn history_json() -> Option<String> {
+    let history = HISTORY.lock().ok()?;
+    history.as_ref().map(json::to_string)
+}
+
+/// `key=value` lines appended to the state file
+pub fn encode_history(history: &HistoryState) -> String {
+    use std::fmt::Write as _;
+
+    let mut output = String::new();
+    for day in &history.days {
+        for totals in &day.interfaces {
+            let _ = writeln!(
+                output,
+                "history.{}.{}={},{}",
+                day.date, totals.name, totals.tx, totals.rx
+            );
+        }
+    }
+    for last in &history.last {
+        let _ = writeln!(output, "history_last.{}={},{}", last.name, last.tx, last.rx);
+    }
+    output
+}
+
+/// Apply one `history.<date>.<iface>` or `history_last.<iface>` line, `false` if the key
+/// is not a history key
+pub fn decode_history_line(
+    history: &mut HistoryState,
+    key: &str,
+    value: &str,
+) -> Result<bool, String> {
+    let parse = || {
+        value
+            .split_once(',')
+            .and_then(|(tx, rx)| Some((tx.parse().ok()?, rx.parse().ok()?)))
+            .ok_or_else(|| format!("Invalid tx,rx for key '{key}'"))
+    };
+
+    if let Some(name) = key.strip_prefix("history_last.") {
+        let (tx, rx) = parse()?;
+        history.last.push(InterfaceTotals {
+            name: name.to_string(),
+            tx,
+            rx,
+        });
+        return Ok(true);
+    }
+    // Interface names may contain dots (VLANs), dates do not
+    let Some((date, name)) = key
+        .strip_prefix("history.")
+        .and_then(|rest| rest.split_once('.'))
+    else {
+        return Ok(false);
+    };
+    let (tx, rx) = parse()?;
+    let totals = InterfaceTotals {
+        name: name.to_string(),
+        tx,
+        rx,
+    };
+    if let Some(day) = history.days.iter_mut().find(|day| day.date == date) {
+        day.interfaces.push(totals);
+    } else {
+        history.days.push(DayTotals {
+            date: date.to_string(),
+            interfaces: vec![totals],
+        });
+        // The file is written in order, this only matters for edited files
+        history.days.sort_by(|a, b| a.date.cmp(&b.date));
+    }
+    Ok(true)
+}
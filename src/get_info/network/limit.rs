@@ -0,0 +1,120 @@
+//! Traffic cap of the main statistics cycle (`--traffic-limit`), for VPS billed per GB.
+//!
+//! Once up and down together reach the limit, `--traffic-limit-action` is taken once per
+//! cycle: `notify` logs a warning and queues a `[traffic-limit]` event, `command` also
+//! runs `--traffic-limit-command`, `shutdown` also halts the machine. `network_saver`
+//! persists that the action was taken, a machine halted over its cap is not halted again
+//! right after it boots in the same cycle.
+
+use crate::command_parser::TrafficLimitAction;
+use crate::events::push_event;
+use crate::get_info::network::groups::parse_quota;
+use log::{error, warn};
+
+#[derive(Debug, Clone)]
+pub struct TrafficLimit {
+    /// Bytes up and down together per cycle
+    pub bytes: u64,
+    pub action: TrafficLimitAction,
+    pub command: Option<String>,
+}
+
+impl TrafficLimit {
+    /// `500G`, `1.5T` or bytes, the action `command` needs a command
+    pub fn parse(
+        limit: &str,
+        action: TrafficLimitAction,
+        command: Option<String>,
+    ) -> Result<Self, String> {
+        if matches!(action, TrafficLimitAction::Command) && command.is_none() {
+            return Err(
+                "`--traffic-limit-action command` requires `--traffic-limit-command`".to_string(),
+            );
+        }
+        Ok(Self {
+            bytes: parse_quota(limit)?,
+            action,
+            command,
+        })
+    }
+
+    /// Take the action when `used` reached the limit and it was not taken in this cycle yet,
+    /// `true` if it was taken now
+    pub fn check(&self, used: u64, triggered: &mut bool) -> bool {
+        if used < self.bytes || *triggered {
+            return false;
+        }
+        *triggered = true;
+        true
+    }
+
+    pub fn enforce(&self, used: u64) {
+        warn!(
+            "Traffic limit reached: {used} of {} bytes in this cycle, action: {:?}",
+            self.bytes, self.action
+        );
+        push_event(format!(
+            "[traffic-limit] {used} of {} bytes used, action: {:?}",
+            self.bytes, self.action
+        ));
+        match self.action {
+            TrafficLimitAction::Notify => {}
+            TrafficLimitAction::Command => {
+                if let Some(command) = &self.command {
+                    self.run_command(command, used);
+                }
+            }
+            TrafficLimitAction::Shutdown => shutdown(),
+        }
+    }
+
+    fn run_command(&self, command: &str, used: u64) {
+        #[cfg(target_os = "windows")]
+        let mut cmd = {
+            let mut cmd = tokio::process::Command::new("cmd");
+            cmd.arg("/C").arg(command);
+            cmd
+        };
+        #[cfg(not(target_os = "windows"))]
+        let mut cmd = {
+            let mut cmd = tokio::process::Command::new("sh");
+            cmd.arg("-c").arg(command);
+            cmd
+        };
+
+        cmd.env("KOMARI_TRAFFIC_USED", used.to_string())
+            .env("KOMARI_TRAFFIC_LIMIT", self.bytes.to_string());
+
+        match cmd.spawn() {
+            Ok(mut child) => {
+                tokio::spawn(async move {
+                    if let Ok(status) = child.wait().await
+                        && !status.success()
+                    {
+                        warn!("Traffic limit command exited with {status}");
+                    }
+                });
+            }
+            Err(e) => error!("Failed to run traffic limit command: {e}"),
+        }
+    }
+}
+
+fn shutdown() {
+    #[cfg(target_os = "windows")]
+    let result = std::process::Command::new("shutdown")
+        .args(["/s", "/t", "0"])
+        .status();
+    #[cfg(not(target_os = "windows"))]
+    let result = std::process::Command::new("shutdown")
+        .args(["-h", "now"])
+        .status();
+
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            error!("Failed to shut down over the traffic limit: shutdown exited with {status}");
+        }
+        Err(e) => error!("Failed to shut down over the traffic limit: {e}"),
+    }
+}
@@ -0,0 +1,166 @@
+use crate::data_struct::LinkInfo;
+use log::trace;
+use std::fmt::Write as _;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::super::netlink::{ARPHRD_ETHER, links};
+    use crate::data_struct::LinkInfo;
+    use std::io;
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    const SIOCETHTOOL: libc::c_ulong = 0x8946;
+    const ETHTOOL_GSET: u32 = 0x0000_0001;
+    const SPEED_UNKNOWN: u32 = u32::MAX;
+    const DUPLEX_HALF: u8 = 0;
+    const DUPLEX_FULL: u8 = 1;
+
+    // from linux/ethtool.h
+    #[repr(C)]
+    #[derive(Default)]
+    struct EthtoolCmd {
+        cmd: u32,
+        supported: u32,
+        advertising: u32,
+        speed: u16,
+        duplex: u8,
+        port: u8,
+        phy_address: u8,
+        transceiver: u8,
+        autoneg: u8,
+        mdio_support: u8,
+        maxtxpkt: u32,
+        maxrxpkt: u32,
+        speed_hi: u16,
+        eth_tp_mdix: u8,
+        eth_tp_mdix_ctrl: u8,
+        lp_advertising: u32,
+        reserved: [u32; 2],
+    }
+
+    #[repr(C)]
+    struct IfReq {
+        ifr_name: [libc::c_char; libc::IFNAMSIZ],
+        ifr_data: *mut libc::c_void,
+        _pad: [u8; 16],
+    }
+
+    /// Query speed (Mb/s) and duplex with the legacy `ETHTOOL_GSET` ioctl
+    fn ethtool_settings(socket: &OwnedFd, name: &str) -> io::Result<(Option<u32>, Option<String>)> {
+        use std::os::fd::AsRawFd;
+
+        let mut cmd = EthtoolCmd {
+            cmd: ETHTOOL_GSET,
+            ..Default::default()
+        };
+        let mut req = IfReq {
+            ifr_name: [0; libc::IFNAMSIZ],
+            ifr_data: std::ptr::from_mut(&mut cmd).cast(),
+            _pad: [0; 16],
+        };
+        for (dst, src) in req
+            .ifr_name
+            .iter_mut()
+            .zip(name.bytes().take(libc::IFNAMSIZ - 1))
+        {
+            *dst = libc::c_char::from_ne_bytes([src]);
+        }
+
+        if unsafe { libc::ioctl(socket.as_raw_fd(), SIOCETHTOOL as _, &raw mut req) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let speed = u32::from(cmd.speed) | (u32::from(cmd.speed_hi) << 16);
+        let speed =
+            (speed != 0 && speed != SPEED_UNKNOWN && speed != u32::from(u16::MAX)).then_some(speed);
+        let duplex = match cmd.duplex {
+            DUPLEX_HALF => Some("half".to_string()),
+            DUPLEX_FULL => Some("full".to_string()),
+            _ => None,
+        };
+        Ok((speed, duplex))
+    }
+
+    pub fn link_info() -> Vec<LinkInfo> {
+        let Ok(links) = links() else {
+            return Vec::new();
+        };
+
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        let socket = (fd >= 0).then(|| unsafe { OwnedFd::from_raw_fd(fd) });
+
+        links
+            .into_iter()
+            .filter(|link| link.arp_type == ARPHRD_ETHER && link.kind.is_none())
+            .map(|link| {
+                // Speed and duplex are meaningless (and often -1) without carrier
+                let (speed, duplex) = match &socket {
+                    Some(socket) if link.carrier => {
+                        ethtool_settings(socket, &link.name).unwrap_or((None, None))
+                    }
+                    _ => (None, None),
+                };
+                LinkInfo {
+                    name: link.name,
+                    speed,
+                    duplex,
+                    carrier: link.carrier,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use crate::data_struct::LinkInfo;
+
+    pub fn link_info() -> Vec<LinkInfo> {
+        Vec::new()
+    }
+}
+
+/// Speed, duplex and carrier of every physical Ethernet interface (Linux only)
+pub fn link_info() -> Vec<LinkInfo> {
+    let links = imp::link_info();
+    trace!("LINK INFO successfully retrieved: {links:?}");
+    links
+}
+
+/// e.g. `up, 1000 Mb/s full duplex`
+fn describe(link: &LinkInfo) -> String {
+    if !link.carrier {
+        return "down".to_string();
+    }
+    let mut state = "up".to_string();
+    if let Some(speed) = link.speed {
+        let _ = write!(state, ", {speed} Mb/s");
+    }
+    if let Some(duplex) = &link.duplex {
+        let _ = write!(state, " {duplex} duplex");
+    }
+    state
+}
+
+/// What changed between two readings of the links, one line per link
+pub fn link_changes(previous: &[LinkInfo], current: &[LinkInfo]) -> Vec<String> {
+    let mut changes = Vec::new();
+    for link in current {
+        match previous.iter().find(|previous| previous.name == link.name) {
+            Some(previous) if previous == link => {}
+            Some(previous) => changes.push(format!(
+                "{} {} (was {})",
+                link.name,
+                describe(link),
+                describe(previous)
+            )),
+            None => changes.push(format!("{} appeared, {}", link.name, describe(link))),
+        }
+    }
+    for link in previous {
+        if !current.iter().any(|current| current.name == link.name) {
+            changes.push(format!("{} disappeared", link.name));
+        }
+    }
+    changes
+}
@@ -1,44 +1,77 @@
-use crate::data_struct::Connections;
+use crate::data_struct::{Connections, Network};
+use crate::get_info::filters::should_monitor;
 use log::trace;
+use std::sync::atomic::{AtomicBool, Ordering};
 use sysinfo::Networks;
-
+use wrap::CounterWraps;
+
+pub mod accounting;
+pub mod cycles;
+pub mod ebpf;
+pub mod family;
+pub mod groups;
+pub mod history;
+pub mod limit;
+pub mod link;
 #[cfg(target_os = "linux")]
 mod netlink;
 pub mod network_saver;
+pub mod own;
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+mod pcblist;
+#[cfg(feature = "sqlite-backend")]
+mod sqlite;
+pub mod topology;
+pub mod vpn;
+pub mod wrap;
+
+/// Offset added to the interface totals to get the traffic of the current cycle, kept up
+/// to date by `network_saver` and read by every `MetricsCollector`
+pub use imp::TrafficOffset;
+
+pub fn realtime_network(
+    network: &Networks,
+    wraps: &CounterWraps,
+    interval_ms: u64,
+    offset: &TrafficOffset,
+) -> Network {
+    let (up, down, total_up, total_down) = filter_network(network, wraps);
+
+    let (offset_tx, offset_rx) = offset.get();
+
+    let cycle_total_up = total_up.saturating_add_signed(offset_tx);
+    let cycle_total_down = total_down.saturating_add_signed(offset_rx);
+
+    let interval_s = interval_ms as f64 / 1000.0;
+    Network {
+        up: if interval_s > 0.0 { (up as f64 / interval_s) as u64 } else { 0 },
+        down: if interval_s > 0.0 { (down as f64 / interval_s) as u64 } else { 0 },
+        total_up: cycle_total_up,
+        total_down: cycle_total_down,
+    }
+}
 
 // Use lock-free atomics on platforms that support them for best performance.
 #[cfg(target_has_atomic = "64")]
 mod imp {
-    use super::filter_network;
-    use crate::data_struct::Network;
     use log::trace;
     use std::sync::atomic::{AtomicI64, Ordering};
-    use sysinfo::Networks;
-
-    static TRAFFIC_OFFSET_TX: AtomicI64 = AtomicI64::new(0);
-    static TRAFFIC_OFFSET_RX: AtomicI64 = AtomicI64::new(0);
 
-    pub fn update_traffic_offset(offset_tx: i64, offset_rx: i64) {
-        TRAFFIC_OFFSET_TX.store(offset_tx, Ordering::Relaxed);
-        TRAFFIC_OFFSET_RX.store(offset_rx, Ordering::Relaxed);
-        trace!("Traffic offset updated to: tx={}, rx={}", offset_tx, offset_rx);
+    #[derive(Debug, Default)]
+    pub struct TrafficOffset {
+        tx: AtomicI64,
+        rx: AtomicI64,
     }
 
-    pub fn realtime_network(network: &Networks, interval_ms: u64) -> Network {
-        let (up, down, total_up, total_down) = filter_network(network);
-
-        let offset_tx = TRAFFIC_OFFSET_TX.load(Ordering::Relaxed);
-        let offset_rx = TRAFFIC_OFFSET_RX.load(Ordering::Relaxed);
-
-        let cycle_total_up = (total_up as i64 + offset_tx).max(0) as u64;
-        let cycle_total_down = (total_down as i64 + offset_rx).max(0) as u64;
+    impl TrafficOffset {
+        pub fn update(&self, offset_tx: i64, offset_rx: i64) {
+            self.tx.store(offset_tx, Ordering::Relaxed);
+            self.rx.store(offset_rx, Ordering::Relaxed);
+            trace!("Traffic offset updated to: tx={offset_tx}, rx={offset_rx}");
+        }
 
-        let interval_s = interval_ms as f64 / 1000.0;
-        Network {
-            up: if interval_s > 0.0 { (up as f64 / interval_s) as u64 } else { 0 },
-            down: if interval_s > 0.0 { (down as f64 / interval_s) as u64 } else { 0 },
-            total_up: cycle_total_up,
-            total_down: cycle_total_down,
+        pub fn get(&self) -> (i64, i64) {
+            (self.tx.load(Ordering::Relaxed), self.rx.load(Ordering::Relaxed))
         }
     }
 }
@@ -46,62 +79,65 @@ mod imp {
 // Use a RwLock as a fallback for older 32-bit platforms without 64-bit atomic support.
 #[cfg(not(target_has_atomic = "64"))]
 mod imp {
-    use super::filter_network;
-    use crate::data_struct::Network;
     use log::{trace, warn};
     use std::sync::RwLock;
-    use sysinfo::Networks;
 
-    static TRAFFIC_OFFSET: RwLock<(i64, i64)> = RwLock::new((0, 0));
+    #[derive(Debug, Default)]
+    pub struct TrafficOffset {
+        offset: RwLock<(i64, i64)>,
+    }
 
-    pub fn update_traffic_offset(offset_tx: i64, offset_rx: i64) {
-        if let Ok(mut offset) = TRAFFIC_OFFSET.write() {
-            *offset = (offset_tx, offset_rx);
-            trace!("Traffic offset updated to: tx={}, rx={}", offset_tx, offset_rx);
-        } else {
-            warn!("Failed to acquire write lock on traffic offset, it may be poisoned.");
+    impl TrafficOffset {
+        pub fn update(&self, offset_tx: i64, offset_rx: i64) {
+            if let Ok(mut offset) = self.offset.write() {
+                *offset = (offset_tx, offset_rx);
+                trace!("Traffic offset updated to: tx={offset_tx}, rx={offset_rx}");
+            } else {
+                warn!("Failed to acquire write lock on traffic offset, it may be poisoned.");
+            }
         }
-    }
 
-    pub fn realtime_network(network: &Networks, interval_ms: u64) -> Network {
-        let (up, down, total_up, total_down) = filter_network(network);
-
-        let (offset_tx, offset_rx) = if let Ok(offset) = TRAFFIC_OFFSET.read() {
-            *offset
-        } else {
-            warn!("Failed to acquire read lock on traffic offset, it may be poisoned. Using (0,0).");
-            (0, 0)
-        };
-
-        let cycle_total_up = (total_up as i64 + offset_tx).max(0) as u64;
-        let cycle_total_down = (total_down as i64 + offset_rx).max(0) as u64;
-
-        let interval_s = interval_ms as f64 / 1000.0;
-        Network {
-            up: if interval_s > 0.0 { (up as f64 / interval_s) as u64 } else { 0 },
-            down: if interval_s > 0.0 { (down as f64 / interval_s) as u64 } else { 0 },
-            total_up: cycle_total_up,
-            total_down: cycle_total_down,
+        pub fn get(&self) -> (i64, i64) {
+            if let Ok(offset) = self.offset.read() {
+                *offset
+            } else {
+                warn!("Failed to acquire read lock on traffic offset, it may be poisoned. Using (0,0).");
+                (0, 0)
+            }
         }
     }
 }
 
-pub use imp::{realtime_network, update_traffic_offset};
+static CONNECTIONS_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// `--disable-connections`, enumerating the sockets is costly on hosts with a lot of them
+pub fn disable_connections() {
+    CONNECTIONS_DISABLED.store(true, Ordering::Relaxed);
+}
 
-#[cfg(target_os = "linux")]
 pub fn realtime_connections() -> Connections {
-    use netlink::connections_count_with_protocol;
-    let tcp4 =
-        connections_count_with_protocol(libc::AF_INET as u8, libc::IPPROTO_TCP as u8).unwrap_or(0);
-    let tcp6 =
-        connections_count_with_protocol(libc::AF_INET6 as u8, libc::IPPROTO_TCP as u8).unwrap_or(0);
-    let udp4 =
-        connections_count_with_protocol(libc::AF_INET as u8, libc::IPPROTO_UDP as u8).unwrap_or(0);
-    let udp6 =
-        connections_count_with_protocol(libc::AF_INET6 as u8, libc::IPPROTO_UDP as u8).unwrap_or(0);
+    if CONNECTIONS_DISABLED.load(Ordering::Relaxed) {
+        return Connections { tcp: 0, udp: 0 };
+    }
+    count_connections()
+}
+
+#[cfg(target_os = "linux")]
+fn count_connections() -> Connections {
+    let (inet, inet6) = (libc::AF_INET as u8, libc::AF_INET6 as u8);
+    let (tcp, udp) = (libc::IPPROTO_TCP as u8, libc::IPPROTO_UDP as u8);
+
+    let count = |dumps: [(u8, u8); 2]| -> u64 {
+        netlink::connections_counts(dumps)
+            .into_iter()
+            .map(|count| count.unwrap_or(0))
+            .sum()
+    };
+
+    // The dumps run on the shared socket, TCP is not needed with eBPF
     let connections = Connections {
-        tcp: tcp4 + tcp6,
-        udp: udp4 + udp6,
+        tcp: ebpf::ebpf_tcp_connections().unwrap_or_else(|| count([(inet, tcp), (inet6, tcp)])),
+        udp: count([(inet, udp), (inet6, udp)]),
     };
     trace!(
         "REALTIME CONNECTIONS successfully retrieved: {:?}",
@@ -111,7 +147,7 @@ pub fn realtime_connections() -> Connections {
 }
 
 #[cfg(target_os = "windows")]
-pub fn realtime_connections() -> Connections {
+fn count_connections() -> Connections {
     use netstat2::{ProtocolFlags, ProtocolSocketInfo, iterate_sockets_info_without_pids};
     let proto_flags = ProtocolFlags::TCP | ProtocolFlags::UDP;
 
@@ -138,8 +174,24 @@ pub fn realtime_connections() -> Connections {
     connections
 }
 
-#[cfg(not(any(target_os = "linux", target_os = "windows")))]
-pub fn realtime_connections() -> Connections {
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+fn count_connections() -> Connections {
+    use pcblist::pcb_count;
+    let connections = Connections {
+        tcp: pcb_count(c"net.inet.tcp.pcblist").unwrap_or(0),
+        udp: pcb_count(c"net.inet.udp.pcblist").unwrap_or(0),
+    };
+    trace!("REALTIME CONNECTIONS successfully retrieved: {connections:?}");
+    connections
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "macos",
+    target_os = "freebsd"
+)))]
+fn count_connections() -> Connections {
     let connections = Connections { tcp: 0, udp: 0 };
     trace!(
         "REALTIME CONNECTIONS successfully retrieved: {:?}",
@@ -148,30 +200,25 @@ pub fn realtime_connections() -> Connections {
     connections
 }
 
-pub fn filter_network(network: &Networks) -> (u64, u64, u64, u64) {
+pub fn filter_network(network: &Networks, wraps: &CounterWraps) -> (u64, u64, u64, u64) {
     let mut total_up = 0;
     let mut total_down = 0;
     let mut up = 0;
     let mut down = 0;
 
-    static FILTER_KEYWORDS: &[&str] = &[
-        "br", "cni", "docker", "podman", "flannel", "lo", "veth", "virbr", "vmbr", "tap", "tun",
-        "fwln", "fwpr",
-    ];
+    let double_counted = topology::double_counted();
 
     for (name, data) in network {
-        let should_filter = FILTER_KEYWORDS
-            .iter()
-            .any(|&keyword| name.contains(keyword));
-
-        if should_filter || data.mac_address().0 == [0, 0, 0, 0, 0, 0] {
+        if !should_monitor(name, data, &double_counted) {
             continue;
         }
 
-        total_up += data.total_transmitted();
-        total_down += data.total_received();
-        up += data.transmitted();
-        down += data.received();
+        let (tx, rx) = wraps.totals(name, data);
+        let (delta_tx, delta_rx) = wraps.deltas(name, data);
+        total_up += tx;
+        total_down += rx;
+        up += delta_tx;
+        down += delta_rx;
     }
 
     (up, down, total_up, total_down)
@@ -3,11 +3,13 @@ use std::io;
 use std::mem::{size_of, zeroed};
 use std::os::fd::RawFd;
 use std::ptr;
+use std::sync::Mutex;
 
 const SOCK_DIAG_BY_FAMILY: u16 = 20;
 const ALL_TCP_STATES: u32 = 0xffffffff;
 const TCP_ESTABLISHED: u32 = 1;
 const NLMSG_HDRLEN: usize = size_of::<libc::nlmsghdr>();
+const DIAG_REQUEST_LEN: usize = NLMSG_HDRLEN + size_of::<InetDiagReqV2>();
 
 /// ---- C structures aligned with kernel ----
 
@@ -35,14 +37,44 @@ struct InetDiagReqV2 {
     id: InetDiagSockId,
 }
 
-/// Entry: Count connection messages by protocol
-pub fn connections_count_with_protocol(family: u8, protocol: u8) -> io::Result<u64> {
+/// Sock diag socket kept open across samples, reopened after an error
+static DIAG_SOCKET: Mutex<Option<DiagSocket>> = Mutex::new(None);
+
+struct DiagSocket {
+    fd: FdGuard,
+    /// Sequence number of the latest request, replies to an older one are skipped
+    seq: u32,
+    buf: Vec<u8>,
+}
+
+/// Entry: Count connection messages for each (family, protocol), one dump after the other on
+/// the shared socket
+pub fn connections_counts<const N: usize>(dumps: [(u8, u8); N]) -> [io::Result<u64>; N] {
+    let Ok(mut socket) = DIAG_SOCKET.lock() else {
+        return dumps.map(|_| Err(io::Error::other("netlink socket lock poisoned")));
+    };
+
+    dumps.map(|(family, protocol)| {
+        let diag = match socket.as_mut() {
+            Some(diag) => diag,
+            None => socket.insert(DiagSocket::open()?),
+        };
+        let count = diag.count(family, protocol);
+        // A failed dump may leave replies queued, start over with a fresh socket
+        if count.is_err() {
+            *socket = None;
+        }
+        count
+    })
+}
+
+fn inet_diag_request(family: u8, protocol: u8, seq: u32) -> [u8; DIAG_REQUEST_LEN] {
     // Construct netlink header
     let hdr = libc::nlmsghdr {
         nlmsg_len: 0, // Set to 0 first, filled back during serialization
         nlmsg_type: SOCK_DIAG_BY_FAMILY,
         nlmsg_flags: (libc::NLM_F_DUMP | libc::NLM_F_REQUEST) as u16,
-        nlmsg_seq: 0,
+        nlmsg_seq: seq,
         nlmsg_pid: 0,
     };
 
@@ -69,90 +101,103 @@ pub fn connections_count_with_protocol(family: u8, protocol: u8) -> io::Result<u
     }
 
     // Serialize into a Netlink message (header + payload)
-    let msg = serialize_netlink_message(&hdr, &req)?;
-
-    // Send and only count the number of returned messages
-    netlink_inet_diag_only_count(&msg)
+    serialize_netlink_message(&hdr, &req)
 }
 
-fn netlink_inet_diag_only_count(request: &[u8]) -> io::Result<u64> {
-    let fd = unsafe { socket(libc::AF_NETLINK, libc::SOCK_RAW, libc::NETLINK_SOCK_DIAG) };
-    if fd < 0 {
-        return Err(io::Error::last_os_error());
-    }
-    let _guard = FdGuard(fd);
-
-    let mut addr: sockaddr_nl = unsafe { zeroed() };
-    addr.nl_family = libc::AF_NETLINK as u16;
-    addr.nl_pid = 0;
-    addr.nl_groups = 0;
+impl DiagSocket {
+    fn open() -> io::Result<Self> {
+        let fd = unsafe { socket(libc::AF_NETLINK, libc::SOCK_RAW, libc::NETLINK_SOCK_DIAG) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
 
-    // sendto
-    let ret = unsafe {
-        sendto(
-            fd,
-            request.as_ptr() as *const c_void,
-            request.len(),
-            0,
-            &addr as *const sockaddr_nl as *const sockaddr,
-            size_of::<sockaddr_nl>() as u32,
-        )
-    };
-    if ret < 0 {
-        return Err(io::Error::last_os_error());
+        // Prepare read buffer
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+        Ok(Self {
+            fd: FdGuard(fd),
+            seq: 0,
+            buf: vec![0u8; page_size],
+        })
     }
 
-    // Prepare read buffer
-    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
-    let mut buf: Vec<u8> = vec![0u8; page_size];
-
-    let mut total_count: u64 = 0;
-
-    loop {
-        // Use the whole buf each time, nr is the effective length for this batch
-        let nr = unsafe {
-            recvfrom(
-                fd,
-                buf.as_mut_ptr() as *mut c_void,
-                buf.len(),
+    /// Send one dump request and only count the number of returned messages
+    fn count(&mut self, family: u8, protocol: u8) -> io::Result<u64> {
+        self.seq = self.seq.wrapping_add(1);
+        let request = inet_diag_request(family, protocol, self.seq);
+
+        let mut addr: sockaddr_nl = unsafe { zeroed() };
+        addr.nl_family = libc::AF_NETLINK as u16;
+        addr.nl_pid = 0;
+        addr.nl_groups = 0;
+
+        // sendto
+        let ret = unsafe {
+            sendto(
+                self.fd.0,
+                request.as_ptr().cast::<c_void>(),
+                request.len(),
                 0,
-                ptr::null_mut(),
-                ptr::null_mut(),
+                ptr::from_ref(&addr).cast::<sockaddr>(),
+                size_of::<sockaddr_nl>() as u32,
             )
         };
-        if nr < 0 {
+        if ret < 0 {
             return Err(io::Error::last_os_error());
         }
-        let nr = nr as usize;
-        if nr < NLMSG_HDRLEN {
-            return Err(io::Error::from_raw_os_error(libc::EINVAL));
-        }
-
-        let slice = &buf[..nr];
 
-        let (count, done) = count_netlink_messages(slice)?;
-        total_count += count;
-        if done {
-            break;
+        let mut total_count: u64 = 0;
+
+        loop {
+            // Use the whole buf each time, nr is the effective length for this batch
+            let nr = unsafe {
+                recvfrom(
+                    self.fd.0,
+                    self.buf.as_mut_ptr().cast::<c_void>(),
+                    self.buf.len(),
+                    0,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                )
+            };
+            if nr < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let nr = nr as usize;
+            if nr < NLMSG_HDRLEN {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+
+            let slice = &self.buf[..nr];
+
+            let (count, done) = count_netlink_messages(slice, self.seq)?;
+            total_count += count;
+            if done {
+                break;
+            }
         }
-    }
 
-    Ok(total_count)
+        Ok(total_count)
+    }
 }
 
-/// Only count netlink messages in this batch buffer; return done=true upon DONE/ERROR
-fn count_netlink_messages(mut b: &[u8]) -> io::Result<(u64, bool)> {
+/// Only count netlink messages of request `seq` in this batch buffer; return done=true upon
+/// its DONE/ERROR
+fn count_netlink_messages(mut b: &[u8], seq: u32) -> io::Result<(u64, bool)> {
     let mut msgs: u64 = 0;
     let mut done = false;
 
     while b.len() >= NLMSG_HDRLEN {
         let (dlen, at_end) = netlink_message_header(b)?;
+        let h = unsafe { ptr::read_unaligned(b.as_ptr().cast::<libc::nlmsghdr>()) };
+        b = &b[dlen..];
+        if h.nlmsg_seq != seq {
+            continue;
+        }
         msgs += 1;
         if at_end {
             done = true;
             break;
         }
-        b = &b[dlen..];
     }
 
     Ok((msgs, done))
@@ -187,9 +232,9 @@ fn nlm_align_of(msglen: i32) -> i32 {
 }
 
 /// Serialize (header, payload) into a Netlink message (fill back header.len)
-fn serialize_netlink_message(hdr: &libc::nlmsghdr, req: &InetDiagReqV2) -> io::Result<Vec<u8>> {
-    let total = NLMSG_HDRLEN + size_of::<InetDiagReqV2>();
-    let mut msg = vec![0u8; total];
+fn serialize_netlink_message(hdr: &libc::nlmsghdr, req: &InetDiagReqV2) -> [u8; DIAG_REQUEST_LEN] {
+    let total = DIAG_REQUEST_LEN;
+    let mut msg = [0u8; DIAG_REQUEST_LEN];
 
     // Write header (copy first, fill back nlmsg_len)
     let mut h = *hdr;
@@ -210,7 +255,7 @@ fn serialize_netlink_message(hdr: &libc::nlmsghdr, req: &InetDiagReqV2) -> io::R
         );
     }
 
-    Ok(msg)
+    msg
 }
 
 /// Simple FD guard
@@ -220,3 +265,184 @@ impl Drop for FdGuard {
         unsafe { close(self.0) };
     }
 }
+
+// from linux/rtnetlink.h
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IfInfoMsg {
+    family: u8,
+    pad: u8,
+    type_: u16,
+    index: i32,
+    flags: u32,
+    change: u32,
+}
+
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MASTER: u16 = 10;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_CARRIER: u16 = 33;
+const IFLA_INFO_KIND: u16 = 1;
+pub const ARPHRD_ETHER: u16 = 1;
+
+/// An interface from an `RTM_GETLINK` dump
+#[derive(Debug)]
+pub struct Link {
+    pub index: i32,
+    pub name: String,
+    /// `ARPHRD_*` hardware type
+    pub arp_type: u16,
+    pub carrier: bool,
+    /// Index of the bond / bridge this interface is enslaved to
+    pub master: Option<i32>,
+    /// `IFLA_INFO_KIND` (bond, bridge, veth, ...), only present on virtual devices
+    pub kind: Option<String>,
+}
+
+/// Iterate over the `(type, data)` route attributes in `b`
+fn route_attrs(mut b: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if b.len() < 4 {
+            return None;
+        }
+        let len = usize::from(u16::from_ne_bytes([b[0], b[1]]));
+        let kind = u16::from_ne_bytes([b[2], b[3]]);
+        if len < 4 || len > b.len() {
+            return None;
+        }
+        let data = &b[4..len];
+        b = &b[((len + 3) & !3).min(b.len())..];
+        Some((kind, data))
+    })
+}
+
+fn attr_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// Entry: Dump all interfaces with `RTM_GETLINK`
+pub fn links() -> io::Result<Vec<Link>> {
+    let total = NLMSG_HDRLEN + size_of::<IfInfoMsg>();
+    let hdr = libc::nlmsghdr {
+        nlmsg_len: total as u32,
+        nlmsg_type: libc::RTM_GETLINK,
+        nlmsg_flags: (libc::NLM_F_DUMP | libc::NLM_F_REQUEST) as u16,
+        nlmsg_seq: 0,
+        nlmsg_pid: 0,
+    };
+    let req = IfInfoMsg {
+        family: libc::AF_UNSPEC as u8,
+        pad: 0,
+        type_: 0,
+        index: 0,
+        flags: 0,
+        change: 0,
+    };
+
+    let mut msg = vec![0u8; total];
+    unsafe {
+        ptr::copy_nonoverlapping(
+            (&raw const hdr).cast::<u8>(),
+            msg.as_mut_ptr(),
+            NLMSG_HDRLEN,
+        );
+        ptr::copy_nonoverlapping(
+            (&raw const req).cast::<u8>(),
+            msg.as_mut_ptr().add(NLMSG_HDRLEN),
+            size_of::<IfInfoMsg>(),
+        );
+    }
+
+    let mut links = Vec::new();
+    for payload in netlink_dump(libc::NETLINK_ROUTE, &msg)? {
+        if payload.len() < size_of::<IfInfoMsg>() {
+            continue;
+        }
+        let info = unsafe { ptr::read_unaligned(payload.as_ptr().cast::<IfInfoMsg>()) };
+
+        let mut link = Link {
+            index: info.index,
+            name: String::new(),
+            arp_type: info.type_,
+            carrier: false,
+            master: None,
+            kind: None,
+        };
+        for (kind, data) in route_attrs(&payload[size_of::<IfInfoMsg>()..]) {
+            match kind {
+                IFLA_IFNAME => link.name = attr_string(data),
+                IFLA_CARRIER => link.carrier = data.first().is_some_and(|&c| c != 0),
+                IFLA_MASTER if data.len() >= 4 => {
+                    link.master = Some(i32::from_ne_bytes([data[0], data[1], data[2], data[3]]));
+                }
+                IFLA_LINKINFO => {
+                    link.kind = route_attrs(data)
+                        .find(|(kind, _)| *kind == IFLA_INFO_KIND)
+                        .map(|(_, data)| attr_string(data));
+                }
+                _ => {}
+            }
+        }
+        links.push(link);
+    }
+
+    Ok(links)
+}
+
+/// Send a dump request and collect the payload of every reply message
+fn netlink_dump(protocol: libc::c_int, request: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let fd = unsafe { socket(libc::AF_NETLINK, libc::SOCK_RAW, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let _guard = FdGuard(fd);
+
+    let mut addr: sockaddr_nl = unsafe { zeroed() };
+    addr.nl_family = libc::AF_NETLINK as u16;
+
+    let ret = unsafe {
+        sendto(
+            fd,
+            request.as_ptr().cast::<c_void>(),
+            request.len(),
+            0,
+            (&raw const addr).cast::<sockaddr>(),
+            size_of::<sockaddr_nl>() as u32,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Link dumps carry large attribute sets, use a bigger buffer than inet_diag
+    let mut buf: Vec<u8> = vec![0u8; 32 * 1024];
+    let mut payloads = Vec::new();
+
+    loop {
+        let nr = unsafe {
+            recvfrom(
+                fd,
+                buf.as_mut_ptr().cast::<c_void>(),
+                buf.len(),
+                0,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if nr < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut b = &buf[..nr as usize];
+        while b.len() >= NLMSG_HDRLEN {
+            let (dlen, at_end) = netlink_message_header(b)?;
+            if at_end {
+                return Ok(payloads);
+            }
+            let len = u32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as usize;
+            payloads.push(b[NLMSG_HDRLEN..len].to_vec());
+            b = &b[dlen..];
+        }
+    }
+}
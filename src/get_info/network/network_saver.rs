@@ -1,13 +1,47 @@
-use crate::command_parser::{NetworkConfig, NetworkStatisticsMode, TrafficPeriod};
-use crate::get_info::network::{filter_network, update_traffic_offset};
+use crate::command_parser::{
+    NetworkConfig, NetworkSaveBackend, NetworkStatisticsMode, TrafficPeriod,
+};
+use crate::get_info::network::accounting::{cycle_totals, restore_cycle};
+use crate::get_info::network::cycles::{
+    CycleSpec, CycleState, decode_cycle_line, encode_cycles, publish, start_cycles, tick_cycles,
+};
+use crate::get_info::network::groups::{
+    GroupSpec, GroupState, decode_group_line, encode_groups, start_groups, tick_groups,
+};
+use crate::get_info::network::history::{
+    HistoryState, decode_history_line, encode_history, start_history, tick_history,
+};
+use crate::get_info::network::limit::TrafficLimit;
+use crate::get_info::network::own::take_own_traffic;
+use crate::get_info::network::wrap::CounterWraps;
+use crate::get_info::network::{TrafficOffset, filter_network};
 use log::{error, info, warn};
 use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use sysinfo::Networks;
 use time::format_description::well_known::Rfc3339;
 use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, Weekday};
 use tokio::fs::{File, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
+
+/// The interfaces as the saver sees them, refreshed on its own schedule
+struct Interfaces {
+    networks: Networks,
+    wraps: CounterWraps,
+}
+
+impl Interfaces {
+    fn refresh(&mut self) {
+        self.networks.refresh(true);
+        self.wraps.update(&self.networks);
+    }
+
+    fn totals(&self) -> (u64, u64) {
+        let (_, _, total_tx, total_rx) = filter_network(&self.networks, &self.wraps);
+        (total_tx, total_rx)
+    }
+}
 
 /// Represents the state of network statistics saved to disk
 #[derive(PartialEq, Clone, Debug)]
@@ -22,6 +56,16 @@ struct NetworkInfo {
     next_reset_timestamp: i64,
     offset_tx: i64,
     offset_rx: i64,
+    /// Per-account cycle totals as (name, tx, rx), see `accounting`
+    accounts: Vec<(String, u64, u64)>,
+    /// Additional cycles (`--traffic-cycle`), see `cycles`
+    cycles: Vec<CycleState>,
+    /// Interface group totals of the current cycle (`--traffic-group`), see `groups`
+    groups: Vec<GroupState>,
+    /// Per-interface daily totals (`--interface-history`), see `history`
+    history: HistoryState,
+    /// The `--traffic-limit` action was taken in this cycle
+    limit_triggered: bool,
 }
 
 impl NetworkInfo {
@@ -60,11 +104,34 @@ impl NetworkInfo {
         append_line!("next_reset_timestamp", self.next_reset_timestamp);
         append_line!("offset_tx", self.offset_tx);
         append_line!("offset_rx", self.offset_rx);
+        if self.limit_triggered {
+            append_line!("limit_triggered", true);
+        }
+        for (name, tx, rx) in &self.accounts {
+            append_line!(format!("account.{name}.tx"), tx);
+            append_line!(format!("account.{name}.rx"), rx);
+        }
+        output.push_str(&encode_cycles(&self.cycles));
+        output.push_str(&encode_groups(&self.groups));
+        output.push_str(&encode_history(&self.history));
+
+        // Covers every line above it, checked by `decode`
+        let checksum = checksum(&output);
+        append_line!("checksum", checksum);
 
         output
     }
 
     pub fn decode(input: &str) -> Result<Self, String> {
+        // Files written before the checksum was added have none
+        if let Some(index) = input.rfind("\nchecksum=") {
+            let (content, line) = input.split_at(index + 1);
+            let expected = line.trim_start_matches("checksum=").trim();
+            if checksum(content) != expected {
+                return Err("Checksum mismatch".to_string());
+            }
+        }
+
         // For NetworkConfig
         let mut disable_network_statistics = None;
         let mut network_interval = None;
@@ -83,6 +150,11 @@ impl NetworkInfo {
         // Default to sentinel value if not found
         let mut offset_tx = i64::MIN;
         let mut offset_rx = i64::MIN;
+        let mut accounts: Vec<(String, u64, u64)> = Vec::new();
+        let mut cycles = Vec::new();
+        let mut groups = Vec::new();
+        let mut history = HistoryState::default();
+        let mut limit_triggered = false;
 
         for line in input.lines() {
             let line = line.trim();
@@ -110,6 +182,7 @@ impl NetworkInfo {
                 "network_save_path" => network_save_path = Some(value.to_string()),
                 "traffic_period" => {
                     traffic_period = match value {
+                        "Day" => Some(TrafficPeriod::Day),
                         "Week" => Some(TrafficPeriod::Week),
                         "Month" => Some(TrafficPeriod::Month),
                         "Year" => Some(TrafficPeriod::Year),
@@ -146,7 +219,25 @@ impl NetworkInfo {
                 }
                 "offset_tx" => offset_tx = value.parse::<i64>().map_err(|_| parse_err("i64"))?,
                 "offset_rx" => offset_rx = value.parse::<i64>().map_err(|_| parse_err("i64"))?,
-                _ => {} // Ignore unknown keys
+                "checksum" => {}
+                "limit_triggered" => {
+                    limit_triggered = value.parse::<bool>().map_err(|_| parse_err("bool"))?;
+                }
+                _ => {
+                    // Per-account totals: account.<name>.tx / account.<name>.rx
+                    if let Some((name, direction)) = key
+                        .strip_prefix("account.")
+                        .and_then(|rest| rest.rsplit_once('.'))
+                    {
+                        let bytes = value.parse::<u64>().map_err(|_| parse_err("u64"))?;
+                        set_account_total(&mut accounts, name, direction, bytes);
+                    } else if !decode_cycle_line(&mut cycles, key, value)?
+                        && !decode_group_line(&mut groups, key, value)?
+                    {
+                        decode_history_line(&mut history, key, value)?;
+                    }
+                    // Ignore unknown keys
+                }
             }
         }
 
@@ -172,32 +263,109 @@ impl NetworkInfo {
                 .ok_or("Missing field: next_reset_timestamp")?,
             offset_tx,
             offset_rx,
+            accounts,
+            cycles,
+            groups,
+            history,
+            limit_triggered,
+        })
+    }
+}
+
+/// Hex SHA-256 of the state file content
+fn checksum(content: &str) -> String {
+    use std::fmt::Write as _;
+
+    let digest = ring::digest::digest(&ring::digest::SHA256, content.as_bytes());
+    digest
+        .as_ref()
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
         })
+}
+
+fn set_account_total(
+    accounts: &mut Vec<(String, u64, u64)>,
+    name: &str,
+    direction: &str,
+    bytes: u64,
+) {
+    let index = if let Some(index) = accounts.iter().position(|(n, _, _)| n == name) {
+        index
+    } else {
+        accounts.push((name.to_string(), 0, 0));
+        accounts.len() - 1
+    };
+    match direction {
+        "tx" => accounts[index].1 = bytes,
+        "rx" => accounts[index].2 = bytes,
+        _ => {}
     }
 }
 
+/// Set by the control socket, the current cycle ends at the next save interval
+static RESET_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// End the current cycle now, the totals restart from zero
+pub fn request_traffic_reset() {
+    RESET_REQUESTED.store(true, Ordering::Relaxed);
+}
+
 /// Main entry point for the network statistics persistence thread.
-pub async fn network_saver(network_config: &NetworkConfig) {
+pub async fn network_saver(
+    network_config: &NetworkConfig,
+    backend: &NetworkSaveBackend,
+    cycles: &[CycleSpec],
+    groups: &[GroupSpec],
+    history_days: u32,
+    limit: Option<&TrafficLimit>,
+    traffic_offset: &TrafficOffset,
+) {
     if network_config.disable_network_statistics {
         return;
     }
 
-    let mut networks = Networks::new_with_refreshed_list();
+    let mut interfaces = Interfaces {
+        networks: Networks::new_with_refreshed_list(),
+        wraps: CounterWraps::default(),
+    };
+    let mut store = match Store::open(network_config, backend) {
+        Ok(store) => store,
+        Err(e) => {
+            error!(
+                "Failed to open network statistics storage: {e}. This feature will be disabled."
+            );
+            return;
+        }
+    };
 
     loop {
         // Initialize state, handles file creation, migration, and reset logic
-        let (mut file, mut network_info) =
-            match initialize_network_state_and_offset(network_config, &mut networks).await {
-                Ok(state) => state,
-                Err(e) => {
-                    error!("Failed to initialize network statistics: {}. This feature will be disabled.", e);
-                    return;
-                }
-            };
+        let mut network_info = match initialize_network_state_and_offset(
+            network_config,
+            cycles,
+            groups,
+            history_days,
+            &mut store,
+            &mut interfaces,
+            traffic_offset,
+        )
+        .await
+        {
+            Ok(state) => state,
+            Err(e) => {
+                error!(
+                    "Failed to initialize network statistics: {e}. This feature will be disabled."
+                );
+                return;
+            }
+        };
 
         // The offset for the current session is now stored in the network_info struct.
-        let offset_tx = network_info.offset_tx;
-        let offset_rx = network_info.offset_rx;
+        let mut offset_tx = network_info.offset_tx;
+        let mut offset_rx = network_info.offset_rx;
         info!(
             "Network statistics cycle started. Next reset on: {}",
             OffsetDateTime::from_unix_timestamp(network_info.next_reset_timestamp)
@@ -217,22 +385,79 @@ pub async fn network_saver(network_config: &NetworkConfig) {
             .await;
 
             let now = OffsetDateTime::now_utc().unix_timestamp();
+            if RESET_REQUESTED.swap(false, Ordering::Relaxed) {
+                info!("Network statistics reset requested. Resetting...");
+                // Re-initialization sees a cycle that ended now and starts a new one
+                network_info.next_reset_timestamp = now;
+                if let Err(e) = store.save(&network_info).await {
+                    error!("Failed to save network statistics file: {e}");
+                }
+                break;
+            }
             if now >= network_info.next_reset_timestamp {
                 info!("Network statistics cycle ended. Resetting...");
                 break; // Break inner loop to re-initialize
             }
 
-            networks.refresh(true);
-            let (_, _, current_total_tx, current_total_rx) = filter_network(&networks);
+            interfaces.refresh();
+            let (current_total_tx, current_total_rx) = interfaces.totals();
+
+            // The agent's own traffic since the last tick comes off the offset, see `own`
+            let (own_tx, own_rx) = take_own_traffic();
+            if own_tx > 0 || own_rx > 0 {
+                offset_tx = offset_tx.saturating_sub_unsigned(own_tx);
+                offset_rx = offset_rx.saturating_sub_unsigned(own_rx);
+                network_info.offset_tx = offset_tx;
+                network_info.offset_rx = offset_rx;
+                traffic_offset.update(offset_tx, offset_rx);
+            }
 
-            // Update the live total traffic value using the constant offset for this cycle
+            // Update the live total traffic value using the offset for this cycle
             network_info.cycle_total_tx = (current_total_tx as i64 + offset_tx).max(0) as u64;
             network_info.cycle_total_rx = (current_total_rx as i64 + offset_rx).max(0) as u64;
 
+            network_info.accounts = cycle_totals();
+            tick_groups(
+                &mut network_info.groups,
+                &interfaces.networks,
+                &interfaces.wraps,
+            );
+            if history_days > 0 {
+                tick_history(
+                    &mut network_info.history,
+                    &interfaces.networks,
+                    &interfaces.wraps,
+                    history_days,
+                );
+            }
+
+            let cycle_reset = tick_cycles(
+                &mut network_info.cycles,
+                cycles,
+                OffsetDateTime::now_utc(),
+                current_total_tx,
+                current_total_rx,
+            );
+
+            // The flag is saved before the action, a shutdown would otherwise repeat on boot
+            let used = network_info
+                .cycle_total_tx
+                .saturating_add(network_info.cycle_total_rx);
+            if let Some(limit) = limit
+                && limit.check(used, &mut network_info.limit_triggered)
+            {
+                if let Err(e) = store.save(&network_info).await {
+                    error!("Failed to save network statistics file: {e}");
+                }
+                limit.enforce(used);
+            }
+
             memory_update_count += 1;
-            if memory_update_count >= network_config.network_interval_number {
+            // An additional cycle that just reset is saved right away, a restart before the
+            // next save would otherwise reset it again
+            if memory_update_count >= network_config.network_interval_number || cycle_reset {
                 // Save the updated state to the file
-                if let Err(e) = save_network_info(&mut file, &network_info).await {
+                if let Err(e) = store.save(&network_info).await {
                     error!("Failed to save network statistics file: {}", e);
                     // Continue, maybe it's a temporary issue
                 } else {
@@ -248,21 +473,14 @@ pub async fn network_saver(network_config: &NetworkConfig) {
 /// handling reboots vs. restarts, and calculating the initial traffic offset.
 async fn initialize_network_state_and_offset(
     network_config: &NetworkConfig,
-    networks: &mut Networks,
-) -> Result<(File, NetworkInfo), String> {
-    let mut file = match OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&network_config.network_save_path)
-        .await
-    {
-        Ok(f) => f,
-        Err(e) => return Err(format!("Failed to open network state file: {e}")),
-    };
-
-    let mut raw_data = String::new();
-    file.read_to_string(&mut raw_data).await.map_err(|e| format!("Failed to read network state file: {e}"))?;
+    cycles: &[CycleSpec],
+    groups: &[GroupSpec],
+    history_days: u32,
+    store: &mut Store,
+    interfaces: &mut Interfaces,
+    traffic_offset: &TrafficOffset,
+) -> Result<NetworkInfo, String> {
+    let raw_data = store.load().await?;
 
     let now = OffsetDateTime::now_utc();
     let new_boot_id = get_boot_id();
@@ -278,8 +496,13 @@ async fn initialize_network_state_and_offset(
             next_reset_timestamp,
             offset_tx: i64::MIN + 2,    // initial value for tx
             offset_rx: i64::MIN + 2,
+            accounts: Vec::new(),
+            cycles: Vec::new(),
+            groups: Vec::new(),
+            history: HistoryState::default(),
+            limit_triggered: false,
         }
-    } else if let Ok(info) = NetworkInfo::decode(&raw_data) {
+    } else if let Ok(info) = NetworkInfo::decode(&raw_data).inspect_err(|e| store.keep_corrupt(e)) {
         info!("Loaded network statistics from file.");
         info
     } else {
@@ -300,6 +523,11 @@ async fn initialize_network_state_and_offset(
             next_reset_timestamp,
             offset_tx: i64::MIN + 2,
             offset_rx: i64::MIN + 2,
+            accounts: Vec::new(),
+            cycles: Vec::new(),
+            groups: Vec::new(),
+            history: HistoryState::default(),
+            limit_triggered: false,
         }
     };
 
@@ -313,11 +541,16 @@ async fn initialize_network_state_and_offset(
     // 2. Check if the cycle has reset since the last run
     if now.unix_timestamp() >= network_info.next_reset_timestamp {
         info!("New statistics cycle detected. Resetting totals.");
+        #[cfg(feature = "sqlite-backend")]
+        store.record_cycle(&network_info);
         network_info.cycle_total_tx = 0;
         network_info.cycle_total_rx = 0;
         network_info.next_reset_timestamp = calculate_next_reset_timestamp(network_config, now)?;
         network_info.offset_tx = i64::MIN;
         network_info.offset_rx = i64::MIN;
+        network_info.accounts.clear();
+        network_info.groups.clear();
+        network_info.limit_triggered = false;
     }
 
     // 3. Handle reboot: if boot ID changed, invalidate the offset from the file.
@@ -331,8 +564,8 @@ async fn initialize_network_state_and_offset(
     network_info.boot_id = new_boot_id;
 
     // 4. Calculate and set the initial offset for this session
-    networks.refresh(true);
-    let (_, _, current_total_tx, current_total_rx) = filter_network(networks);
+    interfaces.refresh();
+    let (current_total_tx, current_total_rx) = interfaces.totals();
 
     if network_info.offset_tx == i64::MIN {
         // Offset is invalid (due to reboot, new cycle, or new file) and must be recalculated.
@@ -354,23 +587,65 @@ async fn initialize_network_state_and_offset(
             network_info.offset_rx = (network_info.cycle_total_rx as i64) - (current_total_rx as i64);
         }
         info!("initial statistics cycle, network offset: tx={}, rx={}", network_info.offset_tx, network_info.offset_rx);
+    } else if current_total_tx.saturating_add_signed(network_info.offset_tx)
+        < network_info.cycle_total_tx
+        || current_total_rx.saturating_add_signed(network_info.offset_rx)
+            < network_info.cycle_total_rx
+    {
+        // The counters went backwards while the agent was stopped (a wrap or a recreated
+        // interface), the totals continue from the saved ones
+        network_info.offset_tx =
+            network_info.cycle_total_tx.cast_signed() - current_total_tx.cast_signed();
+        network_info.offset_rx =
+            network_info.cycle_total_rx.cast_signed() - current_total_rx.cast_signed();
+        let (offset_tx, offset_rx) = (network_info.offset_tx, network_info.offset_rx);
+        info!(
+            "Interface counters went backwards since the last run, network offset: tx={offset_tx}, rx={offset_rx}"
+        );
     } else {
         // Offset from file is valid (program restart without reboot). Use it directly.
         info!("Using existing network offset from file: tx={}, rx={}", network_info.offset_tx, network_info.offset_rx);
     }
 
-    update_traffic_offset(network_info.offset_tx, network_info.offset_rx);
+    traffic_offset.update(network_info.offset_tx, network_info.offset_rx);
+
+    // Additional cycles reset on their own schedule, not with the main one
+    network_info.cycles = start_cycles(
+        cycles,
+        &network_info.cycles,
+        now,
+        is_reboot,
+        current_total_tx,
+        current_total_rx,
+    )?;
+    publish(&network_info.cycles);
+
+    let Interfaces { networks, wraps } = interfaces;
+    network_info.groups = start_groups(groups, &network_info.groups, networks, wraps, is_reboot);
+    tick_groups(&mut network_info.groups, networks, wraps);
+
+    // The history is kept across cycles, disabling it drops it from the file
+    if history_days > 0 {
+        network_info.history = start_history(&network_info.history, is_reboot);
+        tick_history(&mut network_info.history, networks, wraps, history_days);
+    } else {
+        network_info.history = HistoryState::default();
+    }
+
+    // Kernel accounting counters restart from zero, continue from the saved cycle totals
+    restore_cycle(&network_info.accounts);
 
     // 5. Save the potentially updated state (new boot_id, new cycle, and new offset)
-    save_network_info(&mut file, &network_info)
+    store
+        .save(&network_info)
         .await
         .map_err(|e| format!("Failed to save initial network state: {e}"))?;
 
-    Ok((file, network_info))
+    Ok(network_info)
 }
 
 /// Calculates the timestamp of the next reset event based on the configuration.
-fn calculate_next_reset_timestamp(
+pub(super) fn calculate_next_reset_timestamp(
     config: &NetworkConfig,
     now: OffsetDateTime,
 ) -> Result<i64, String> {
@@ -386,6 +661,9 @@ fn calculate_next_reset_timestamp(
     let mut next_reset_date = now.date();
 
     match period {
+        TrafficPeriod::Day => {
+            next_reset_date += time::Duration::days(1);
+        }
         TrafficPeriod::Week => {
             let target_weekday = match reset_day_str.to_lowercase().as_str() {
                 "mon" | "1" => Weekday::Monday,
@@ -472,12 +750,112 @@ fn days_in_month(year: i32, month: Month) -> u8 {
     last_day.day()
 }
 
-/// Saves the NetworkInfo struct to the given file as a JSON string.
-async fn save_network_info(file: &mut File, info: &NetworkInfo) -> Result<(), std::io::Error> {
+/// Where the state is kept (`--network-save-backend`)
+enum Store {
+    File(String),
+    #[cfg(feature = "sqlite-backend")]
+    Sqlite(super::sqlite::SqliteStore),
+}
+
+impl Store {
+    fn open(config: &NetworkConfig, backend: &NetworkSaveBackend) -> Result<Self, String> {
+        let path = config.network_save_path.clone();
+        match backend {
+            NetworkSaveBackend::File => Ok(Self::File(path)),
+            #[cfg(feature = "sqlite-backend")]
+            NetworkSaveBackend::Sqlite => super::sqlite::SqliteStore::open(&path).map(Self::Sqlite),
+            #[cfg(not(feature = "sqlite-backend"))]
+            NetworkSaveBackend::Sqlite => {
+                Err("this build has no SQLite support (feature `sqlite-backend`)".to_string())
+            }
+        }
+    }
+
+    /// The saved state as `key=value` lines, empty if there is none yet
+    async fn load(&mut self) -> Result<String, String> {
+        match self {
+            Self::File(path) => match tokio::fs::read_to_string(path).await {
+                Ok(raw_data) => Ok(raw_data),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+                Err(e) => Err(format!("Failed to read network state file: {e}")),
+            },
+            #[cfg(feature = "sqlite-backend")]
+            Self::Sqlite(store) => store
+                .load()
+                .map_err(|e| format!("Failed to read network database: {e}")),
+        }
+    }
+
+    async fn save(&mut self, info: &NetworkInfo) -> Result<(), String> {
+        match self {
+            Self::File(path) => save_network_info(path, info)
+                .await
+                .map_err(|e| e.to_string()),
+            #[cfg(feature = "sqlite-backend")]
+            Self::Sqlite(store) => store
+                .save(&info.encode(), &info.history)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Keep a state that failed to decode for manual recovery, the new one replaces it
+    fn keep_corrupt(&self, e: &str) {
+        match self {
+            Self::File(path) => {
+                let _ = fs::copy(path, format!("{path}.corrupt"));
+                warn!("Failed to decode {path} ({e}), a copy is kept as {path}.corrupt");
+            }
+            #[cfg(feature = "sqlite-backend")]
+            Self::Sqlite(_) => warn!("Failed to decode the network database state ({e})"),
+        }
+    }
+
+    /// Record the totals of a main cycle that just ended, only the database keeps them
+    #[cfg(feature = "sqlite-backend")]
+    fn record_cycle(&self, info: &NetworkInfo) {
+        if let Self::Sqlite(store) = self
+            && let Err(e) = store.record_cycle(
+                info.next_reset_timestamp,
+                info.cycle_total_tx,
+                info.cycle_total_rx,
+            )
+        {
+            warn!("Failed to record the ended cycle: {e}");
+        }
+    }
+}
+
+/// The fallback to in-place writes is logged once
+static IN_PLACE_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Saves the `NetworkInfo` struct to the given file.
+///
+/// The content goes to `<path>.tmp` first and replaces the file by a rename, a crash
+/// mid-write leaves the previous state intact. Where the directory is read-only and only
+/// the file itself is writable, it is rewritten in place.
+async fn save_network_info(path: &str, info: &NetworkInfo) -> Result<(), std::io::Error> {
     let content = info.encode();
-    file.rewind().await?;
-    file.set_len(0).await?;
-    file.write_all(content.as_bytes()).await?;
+    let tmp_path = format!("{path}.tmp");
+    let atomic = async {
+        let mut tmp = File::create(&tmp_path).await?;
+        tmp.write_all(content.as_bytes()).await?;
+        tmp.sync_all().await?;
+        tokio::fs::rename(&tmp_path, path).await
+    };
+    if let Err(e) = atomic.await {
+        if !IN_PLACE_WARNED.swap(true, Ordering::Relaxed) {
+            warn!("Failed to replace {path} atomically ({e}), rewriting it in place");
+        }
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await?;
+        file.write_all(content.as_bytes()).await?;
+        file.sync_all().await?;
+    }
     Ok(())
 }
 
@@ -0,0 +1,173 @@
+//! The agent's own traffic left out of the cycle totals (`--exclude-own-traffic`).
+//!
+//! Real-Time Info pushes, the IP lookups, speed tests and fetches all cross the monitored
+//! interfaces and are billed to the cycle like any other traffic. With the flag, the bytes the
+//! agent sends and receives over its WebSocket connections and ureq requests are counted and
+//! `network_saver` takes them off the cycle offset at every tick, so the exclusion is saved
+//! with the cycle and survives restarts. The counts are the payload the agent reads and writes:
+//! TCP/IP headers are not in them, the TLS records of HTTP requests neither, and requests made
+//! with `nyquest-support` are not counted at all. Connections to loopback addresses never
+//! reach a monitored interface and are left alone. The additional cycles of `--traffic-cycle`
+//! keep counting everything.
+
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Since the last `take_own_traffic`
+static TX: AtomicU64 = AtomicU64::new(0);
+static RX: AtomicU64 = AtomicU64::new(0);
+
+pub fn exclude_own_traffic() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn count_own(tx: usize, rx: usize) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    TX.fetch_add(tx as u64, Ordering::Relaxed);
+    RX.fetch_add(rx as u64, Ordering::Relaxed);
+}
+
+/// Bytes sent and received by the agent since the previous call
+pub fn take_own_traffic() -> (u64, u64) {
+    (TX.swap(0, Ordering::Relaxed), RX.swap(0, Ordering::Relaxed))
+}
+
+/// A stream with the bytes read and written counted as the agent's own, unless its `peer`
+/// is a loopback address
+#[derive(Debug)]
+pub struct Counted<S> {
+    inner: S,
+    counted: bool,
+}
+
+impl<S> Counted<S> {
+    pub fn new(inner: S, peer: Option<IpAddr>) -> Self {
+        Self {
+            inner,
+            counted: !peer.is_some_and(|peer| peer.is_loopback()),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if self.counted {
+            count_own(0, buf.filled().len() - before);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll
+            && self.counted
+        {
+            count_own(written, 0);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "ureq-support")]
+pub use http::counted_agent;
+
+#[cfg(feature = "ureq-support")]
+mod http {
+    use super::count_own;
+    use ureq::config::Config;
+    use ureq::unversioned::resolver::Resolver;
+    use ureq::unversioned::transport::{
+        Buffers, ConnectionDetails, Connector, DefaultConnector, NextTimeout, Transport,
+    };
+    use ureq::{Agent, Error};
+
+    /// An agent on the default connector with its traffic counted as the agent's own
+    pub fn counted_agent(config: Config, resolver: impl Resolver) -> Agent {
+        Agent::with_parts(
+            config,
+            DefaultConnector::new().chain(CountingConnector),
+            resolver,
+        )
+    }
+
+    #[derive(Debug)]
+    struct CountingConnector;
+
+    impl Connector<Box<dyn Transport>> for CountingConnector {
+        type Out = CountingTransport;
+
+        fn connect(
+            &self,
+            details: &ConnectionDetails,
+            chained: Option<Box<dyn Transport>>,
+        ) -> Result<Option<Self::Out>, Error> {
+            let counted = !details.addrs.iter().all(|addr| addr.ip().is_loopback());
+            Ok(chained.map(|inner| CountingTransport { inner, counted }))
+        }
+    }
+
+    #[derive(Debug)]
+    struct CountingTransport {
+        inner: Box<dyn Transport>,
+        counted: bool,
+    }
+
+    impl Transport for CountingTransport {
+        fn buffers(&mut self) -> &mut dyn Buffers {
+            self.inner.buffers()
+        }
+
+        fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), Error> {
+            self.inner.transmit_output(amount, timeout)?;
+            if self.counted {
+                count_own(amount, 0);
+            }
+            Ok(())
+        }
+
+        /// What the transport appended to the input buffer
+        fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, Error> {
+            let before = self.inner.buffers().input().len();
+            let progress = self.inner.await_input(timeout)?;
+            if self.counted {
+                count_own(0, self.inner.buffers().input().len().saturating_sub(before));
+            }
+            Ok(progress)
+        }
+
+        fn is_open(&mut self) -> bool {
+            self.inner.is_open()
+        }
+
+        fn is_tls(&self) -> bool {
+            self.inner.is_tls()
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::ffi::CStr;
+use std::io;
+use std::ptr;
+
+// Offset of `xig_count` in `struct xinpgen`
+// macOS: u_int32_t xig_len; u_int xig_count; ...
+// FreeBSD: ksize_t xig_len (uint64_t); u_int xig_count; ...
+#[cfg(target_os = "macos")]
+const XIG_COUNT_OFFSET: usize = 4;
+#[cfg(target_os = "freebsd")]
+const XIG_COUNT_OFFSET: usize = 8;
+
+/// Count PCBs returned by a `net.inet.*.pcblist` sysctl (IPv4 and IPv6 share one list).
+///
+/// Unlike the Linux netlink backend this includes every TCP state, not only ESTABLISHED.
+pub fn pcb_count(name: &CStr) -> io::Result<u64> {
+    for _ in 0..3 {
+        let mut len: libc::size_t = 0;
+        let ret = unsafe {
+            libc::sysctlbyname(name.as_ptr(), ptr::null_mut(), &mut len, ptr::null_mut(), 0)
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The list may grow between the two calls, leave some headroom
+        len += len / 8;
+        let mut buf = vec![0u8; len];
+        let ret = unsafe {
+            libc::sysctlbyname(
+                name.as_ptr(),
+                buf.as_mut_ptr().cast(),
+                &mut len,
+                ptr::null_mut(),
+                0,
+            )
+        };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOMEM) {
+                continue;
+            }
+            return Err(err);
+        }
+
+        let count = buf
+            .get(XIG_COUNT_OFFSET..XIG_COUNT_OFFSET + 4)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        return Ok(u64::from(u32::from_ne_bytes([
+            count[0], count[1], count[2], count[3],
+        ])));
+    }
+
+    Err(io::Error::from_raw_os_error(libc::ENOMEM))
+}
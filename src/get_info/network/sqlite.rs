@@ -0,0 +1,122 @@
+//! Database persistence for `network_saver` (`--network-save-backend sqlite`).
+//!
+//! The state file lines are kept in the `state` table, so loading rebuilds the same
+//! `key=value` text the file backend reads. Daily interface totals go to `interface_days`
+//! and are never pruned there, `--interface-history` only limits what the agent keeps in
+//! memory. Every reset of the main cycle adds a row with its totals to `cycles`.
+
+use crate::get_info::network::history::HistoryState;
+use rusqlite::{Connection, params};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS state (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS interface_days (
+    date TEXT NOT NULL,
+    interface TEXT NOT NULL,
+    tx INTEGER NOT NULL,
+    rx INTEGER NOT NULL,
+    PRIMARY KEY (date, interface)
+);
+CREATE TABLE IF NOT EXISTS cycles (
+    ended_at INTEGER NOT NULL,
+    tx INTEGER NOT NULL,
+    rx INTEGER NOT NULL
+);
+";
+
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+/// Database integers are signed
+fn to_sql(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+impl SqliteStore {
+    /// Open or create the database at `path`
+    pub fn open(path: &str) -> Result<Self, String> {
+        let connection = Connection::open(path)
+            .map_err(|e| format!("Failed to open network database {path}: {e}"))?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(|e| format!("Failed to create tables in {path}: {e}"))?;
+        Ok(Self { connection })
+    }
+
+    /// The state as `key=value` lines, empty for a new database
+    pub fn load(&self) -> Result<String, rusqlite::Error> {
+        use std::fmt::Write as _;
+
+        let mut output = String::new();
+        let mut statement = self
+            .connection
+            .prepare("SELECT key, value FROM state ORDER BY rowid")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let key: String = row.get(0)?;
+            let value: String = row.get(1)?;
+            let _ = writeln!(output, "{key}={value}");
+        }
+
+        let mut statement = self.connection.prepare(
+            "SELECT date, interface, tx, rx FROM interface_days ORDER BY date, interface",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let date: String = row.get(0)?;
+            let interface: String = row.get(1)?;
+            let tx: i64 = row.get(2)?;
+            let rx: i64 = row.get(3)?;
+            let _ = writeln!(output, "history.{date}.{interface}={tx},{rx}");
+        }
+        Ok(output)
+    }
+
+    /// Replace the state with the `key=value` lines of `encoded` and update the days in
+    /// `history`, in one transaction
+    pub fn save(&mut self, encoded: &str, history: &HistoryState) -> Result<(), rusqlite::Error> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM state", [])?;
+        {
+            let mut insert =
+                transaction.prepare("INSERT INTO state (key, value) VALUES (?1, ?2)")?;
+            for (key, value) in encoded.lines().filter_map(|line| line.split_once('=')) {
+                // The days have their own table, the database checks its own integrity
+                if key.starts_with("history.") || key == "checksum" {
+                    continue;
+                }
+                insert.execute(params![key, value])?;
+            }
+
+            let mut upsert = transaction.prepare(
+                "INSERT INTO interface_days (date, interface, tx, rx) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (date, interface) DO UPDATE SET tx = excluded.tx, rx = excluded.rx",
+            )?;
+            for day in &history.days {
+                for totals in &day.interfaces {
+                    upsert.execute(params![
+                        day.date,
+                        totals.name,
+                        to_sql(totals.tx),
+                        to_sql(totals.rx)
+                    ])?;
+                }
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Record a main cycle that ended at `ended_at` (Unix s) with the given totals
+    pub fn record_cycle(&self, ended_at: i64, tx: u64, rx: u64) -> Result<(), rusqlite::Error> {
+        self.connection
+            .execute(
+                "INSERT INTO cycles (ended_at, tx, rx) VALUES (?1, ?2, ?3)",
+                params![ended_at, to_sql(tx), to_sql(rx)],
+            )
+            .map(|_| ())
+    }
+}
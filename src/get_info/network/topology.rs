@@ -0,0 +1,83 @@
+use log::trace;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const REFRESH_INTERVAL: Duration = Duration::from_mins(1);
+
+/// A bond or bridge and the interfaces enslaved to it
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+    pub name: String,
+    pub kind: String,
+    pub members: Vec<String>,
+}
+
+static SKIPPED: Mutex<Option<(Instant, Arc<HashSet<String>>)>> = Mutex::new(None);
+
+#[cfg(target_os = "linux")]
+pub fn topology() -> Vec<Aggregate> {
+    use super::netlink::links;
+
+    let Ok(links) = links() else {
+        return Vec::new();
+    };
+
+    let aggregates = links
+        .iter()
+        .filter_map(|link| {
+            let kind = link.kind.as_deref()?;
+            if kind != "bond" && kind != "bridge" {
+                return None;
+            }
+            Some(Aggregate {
+                name: link.name.clone(),
+                kind: kind.to_string(),
+                members: links
+                    .iter()
+                    .filter(|member| member.master == Some(link.index))
+                    .map(|member| member.name.clone())
+                    .collect(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    trace!("NETWORK TOPOLOGY successfully retrieved: {aggregates:?}");
+    aggregates
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn topology() -> Vec<Aggregate> {
+    Vec::new()
+}
+
+/// Interfaces whose traffic is already counted on another interface:
+/// bond members (the bond carries their sum) and bridges (their members carry the traffic).
+///
+/// The topology rarely changes, so it is only refreshed once a minute. The set is shared
+/// with the cache, it is read on every sample.
+pub fn double_counted() -> Arc<HashSet<String>> {
+    let Ok(mut skipped) = SKIPPED.lock() else {
+        return Arc::default();
+    };
+
+    if let Some((refreshed_at, names)) = skipped.as_ref()
+        && refreshed_at.elapsed() < REFRESH_INTERVAL
+    {
+        return Arc::clone(names);
+    }
+
+    let names = topology()
+        .into_iter()
+        .flat_map(|aggregate| {
+            if aggregate.kind == "bond" {
+                aggregate.members
+            } else {
+                vec![aggregate.name]
+            }
+        })
+        .collect::<HashSet<_>>();
+    let names = Arc::new(names);
+    *skipped = Some((Instant::now(), Arc::clone(&names)));
+    names
+}
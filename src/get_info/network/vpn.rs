@@ -0,0 +1,182 @@
+use crate::data_struct::VpnTunnel;
+use log::{trace, warn};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+/// Wireguard re-handshakes every 2 minutes, a peer older than this is considered down
+const ACTIVE_HANDSHAKE_SECS: u64 = 180;
+
+struct VpnState {
+    openvpn_status: Option<String>,
+    cached: Vec<VpnTunnel>,
+    refreshed_at: Option<Instant>,
+}
+
+static VPN: Mutex<Option<VpnState>> = Mutex::new(None);
+
+/// Enable the collector, wireguard is read with `wg show all dump` and openvpn
+/// from the given `--status` file
+pub fn init_vpn(openvpn_status: Option<String>) {
+    if let Ok(mut vpn) = VPN.lock() {
+        *vpn = Some(VpnState {
+            openvpn_status,
+            cached: Vec::new(),
+            refreshed_at: None,
+        });
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Parse `wg show all dump`: interface lines have 5 fields, peer lines 9
+fn parse_wg_dump(output: &str, now: u64) -> Vec<VpnTunnel> {
+    let mut tunnels: Vec<VpnTunnel> = Vec::new();
+    let mut latest_handshakes: Vec<u64> = Vec::new();
+
+    for line in output.lines() {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        match fields.len() {
+            5 => {
+                tunnels.push(VpnTunnel {
+                    name: fields[0].to_string(),
+                    kind: "wireguard".to_string(),
+                    peers: 0,
+                    active_peers: 0,
+                    handshake_age: None,
+                    rx: 0,
+                    tx: 0,
+                });
+                latest_handshakes.push(0);
+            }
+            9 => {
+                let Some(index) = tunnels.iter().position(|t| t.name == fields[0]) else {
+                    continue;
+                };
+                let handshake = fields[5].parse::<u64>().unwrap_or(0);
+                let tunnel = &mut tunnels[index];
+                tunnel.peers += 1;
+                if handshake > 0 && now.saturating_sub(handshake) < ACTIVE_HANDSHAKE_SECS {
+                    tunnel.active_peers += 1;
+                }
+                tunnel.rx += fields[6].parse::<u64>().unwrap_or(0);
+                tunnel.tx += fields[7].parse::<u64>().unwrap_or(0);
+                latest_handshakes[index] = latest_handshakes[index].max(handshake);
+            }
+            _ => {}
+        }
+    }
+
+    for (tunnel, handshake) in tunnels.iter_mut().zip(latest_handshakes) {
+        tunnel.handshake_age = (handshake > 0).then(|| now.saturating_sub(handshake));
+    }
+    tunnels
+}
+
+fn wireguard_tunnels() -> Vec<VpnTunnel> {
+    let output = match std::process::Command::new("wg")
+        .args(["show", "all", "dump"])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            trace!(
+                "wg show failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Vec::new();
+        }
+        // WireGuard tools are not installed
+        Err(_) => return Vec::new(),
+    };
+
+    parse_wg_dump(&String::from_utf8_lossy(&output.stdout), unix_now())
+}
+
+/// Parse an openvpn server status file (`status-version` 1, 2 and 3)
+fn parse_openvpn_status(content: &str, age: Option<u64>) -> VpnTunnel {
+    let mut tunnel = VpnTunnel {
+        name: "openvpn".to_string(),
+        kind: "openvpn".to_string(),
+        peers: 0,
+        active_peers: 0,
+        handshake_age: age,
+        rx: 0,
+        tx: 0,
+    };
+
+    let mut in_v1_client_list = false;
+    for line in content.lines() {
+        let fields = line.split([',', '\t']).collect::<Vec<_>>();
+        // v2 / v3: CLIENT_LIST,CN,Real Address,Virtual Address,Virtual IPv6,Bytes Received,Bytes Sent,...
+        // v1: CN,Real Address,Bytes Received,Bytes Sent,Connected Since
+        let (rx, tx) = match fields.first().copied() {
+            Some("CLIENT_LIST") if fields.len() > 6 => {
+                if fields[1] == "UNDEF" {
+                    continue;
+                }
+                (fields[5], fields[6])
+            }
+            Some("OpenVPN CLIENT LIST") => {
+                in_v1_client_list = true;
+                continue;
+            }
+            Some("ROUTING TABLE") => {
+                in_v1_client_list = false;
+                continue;
+            }
+            Some("Common Name" | "Updated") => continue,
+            _ if in_v1_client_list && fields.len() >= 5 => (fields[2], fields[3]),
+            _ => continue,
+        };
+
+        tunnel.peers += 1;
+        tunnel.active_peers += 1;
+        tunnel.rx += rx.parse::<u64>().unwrap_or(0);
+        tunnel.tx += tx.parse::<u64>().unwrap_or(0);
+    }
+
+    tunnel
+}
+
+fn openvpn_tunnel(status_path: &str) -> Option<VpnTunnel> {
+    let content = match std::fs::read_to_string(status_path) {
+        Ok(content) => content,
+        Err(e) => {
+            warn!("Failed to read OpenVPN status file {status_path}: {e}");
+            return None;
+        }
+    };
+    // OpenVPN rewrites the file every `status` interval, its age tells whether the daemon is alive
+    let age = std::fs::metadata(status_path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map(|age| age.as_secs());
+
+    Some(parse_openvpn_status(&content, age))
+}
+
+pub fn realtime_vpn() -> Option<Vec<VpnTunnel>> {
+    let mut guard = VPN.lock().ok()?;
+    let vpn = guard.as_mut()?;
+
+    if vpn
+        .refreshed_at
+        .is_none_or(|refreshed_at| refreshed_at.elapsed() >= REFRESH_INTERVAL)
+    {
+        let mut tunnels = wireguard_tunnels();
+        if let Some(status_path) = &vpn.openvpn_status {
+            tunnels.extend(openvpn_tunnel(status_path));
+        }
+        vpn.cached = tunnels;
+        vpn.refreshed_at = Some(Instant::now());
+        trace!("REALTIME VPN successfully retrieved: {:?}", vpn.cached);
+    }
+
+    Some(vpn.cached.clone())
+}
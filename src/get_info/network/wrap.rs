@@ -0,0 +1,99 @@
+//! Interface byte counters going backwards.
+//!
+//! Some drivers keep 32-bit byte counters that wrap at 4 GiB, and every counter starts over
+//! when its interface is recreated. Either would make the totals drop and the traffic of that
+//! refresh vanish. A counter below its value at the previous refresh has wrapped when that
+//! value was close to 4 GiB and was reset otherwise, the bytes it lost are added back from
+//! then on so the totals stay monotonic. Every `Networks` needs its own `CounterWraps`, updated
+//! right after each of its refreshes.
+
+use log::info;
+use std::collections::HashMap;
+use sysinfo::{NetworkData, Networks};
+
+const WRAP: u64 = 1 << 32;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counter {
+    last: u64,
+    /// Bytes lost to wraps and resets so far
+    added: u64,
+    /// Bytes counted over the latest refresh
+    delta: u64,
+}
+
+impl Counter {
+    fn new(value: u64, delta: u64) -> Self {
+        Self {
+            last: value,
+            added: 0,
+            delta,
+        }
+    }
+
+    /// Whether the counter went backwards
+    fn observe(&mut self, value: u64) -> bool {
+        let backwards = value < self.last;
+        if backwards {
+            // A wrap moves the counter less than half its range, anything else is a reset
+            let wrapped = self.last < WRAP && WRAP - self.last + value < WRAP / 2;
+            let lost = if wrapped { WRAP } else { self.last };
+            self.added = self.added.saturating_add(lost);
+            self.delta = value.saturating_add(lost).saturating_sub(self.last);
+        } else {
+            self.delta = value - self.last;
+        }
+        self.last = value;
+        backwards
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CounterWraps {
+    /// `(tx, rx)` by interface
+    interfaces: HashMap<String, (Counter, Counter)>,
+}
+
+impl CounterWraps {
+    /// Check the counters of a refresh that just happened
+    pub fn update(&mut self, networks: &Networks) {
+        for (name, data) in networks {
+            let (tx, rx) = (data.total_transmitted(), data.total_received());
+            let Some((counter_tx, counter_rx)) = self.interfaces.get_mut(name) else {
+                self.interfaces.insert(
+                    name.clone(),
+                    (
+                        Counter::new(tx, data.transmitted()),
+                        Counter::new(rx, data.received()),
+                    ),
+                );
+                continue;
+            };
+            let backwards_tx = counter_tx.observe(tx);
+            let backwards_rx = counter_rx.observe(rx);
+            if backwards_tx || backwards_rx {
+                info!("Byte counters of {name} went backwards, counting on from the last values");
+            }
+        }
+    }
+
+    /// Bytes sent and received by the interface since boot, what the counters lost added
+    pub fn totals(&self, name: &str, data: &NetworkData) -> (u64, u64) {
+        let (tx, rx) = (data.total_transmitted(), data.total_received());
+        match self.interfaces.get(name) {
+            Some((counter_tx, counter_rx)) => (
+                tx.saturating_add(counter_tx.added),
+                rx.saturating_add(counter_rx.added),
+            ),
+            None => (tx, rx),
+        }
+    }
+
+    /// Bytes sent and received by the interface over the latest refresh
+    pub fn deltas(&self, name: &str, data: &NetworkData) -> (u64, u64) {
+        match self.interfaces.get(name) {
+            Some((counter_tx, counter_rx)) => (counter_tx.delta, counter_rx.delta),
+            None => (data.transmitted(), data.received()),
+        }
+    }
+}
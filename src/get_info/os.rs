@@ -19,11 +19,16 @@ pub async fn os() -> OsInfo {
     let virt = {
         #[cfg(target_os = "linux")]
         {
-            heim_virt::detect()
-                .await
-                .unwrap_or(heim_virt::Virtualization::Unknown)
-                .as_str()
-                .to_string()
+            // A container runs on the machine heim_virt detects, the container is reported
+            if let Some(container) = detect_container() {
+                container.to_string()
+            } else {
+                heim_virt::detect()
+                    .await
+                    .unwrap_or(heim_virt::Virtualization::Unknown)
+                    .as_str()
+                    .to_string()
+            }
         }
 
         #[cfg(target_os = "windows")]
@@ -77,3 +82,70 @@ pub async fn os() -> OsInfo {
 
     os_info
 }
+
+/// The container runtime the agent runs in, from the markers the runtimes leave behind
+#[cfg(target_os = "linux")]
+fn detect_container() -> Option<&'static str> {
+    use std::fs;
+    use std::path::Path;
+
+    // Pods run on Docker or containerd too, Kubernetes is the more useful answer
+    if std::env::var_os("KUBERNETES_SERVICE_HOST").is_some()
+        || Path::new("/var/run/secrets/kubernetes.io").exists()
+    {
+        return Some("kubernetes");
+    }
+
+    // systemd and most runtimes set `container=` for PID 1, /proc/1/environ needs root
+    let marker = fs::read_to_string("/run/systemd/container")
+        .ok()
+        .map(|marker| marker.trim().to_string())
+        .or_else(|| {
+            fs::read("/proc/1/environ").ok().and_then(|environ| {
+                environ
+                    .split(|byte| *byte == 0)
+                    .find_map(|var| var.strip_prefix(b"container="))
+                    .map(|value| String::from_utf8_lossy(value).into_owned())
+            })
+        });
+    if let Some(marker) = marker {
+        match marker.as_str() {
+            "lxc" => return Some("lxc"),
+            "lxc-libvirt" => return Some("lxc-libvirt"),
+            "systemd-nspawn" => return Some("systemd-nspawn"),
+            "docker" => return Some("docker"),
+            "podman" => return Some("podman"),
+            "oci" => return Some("oci"),
+            _ => {}
+        }
+    }
+
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker");
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman");
+    }
+
+    if let Ok(cgroup) = fs::read_to_string("/proc/1/cgroup") {
+        if cgroup.contains("kubepods") {
+            return Some("kubernetes");
+        }
+        if cgroup.contains("/docker/") || cgroup.contains("/docker-") {
+            return Some("docker");
+        }
+        if cgroup.contains("/lxc/") || cgroup.contains("lxc.payload") {
+            return Some("lxc");
+        }
+        if cgroup.contains("/machine.slice/machine-") {
+            return Some("systemd-nspawn");
+        }
+    }
+
+    // OpenVZ containers see /proc/vz, only the host also has /proc/bc
+    if Path::new("/proc/vz").exists() && !Path::new("/proc/bc").exists() {
+        return Some("openvz");
+    }
+
+    None
+}
@@ -0,0 +1,221 @@
+//! Battery and UPS status (`--power-stats`).
+//!
+//! Batteries come from `/sys/class/power_supply` on Linux and `GetSystemPowerStatus` on
+//! Windows, peripherals such as wireless mice are left out. UPSes are read from a NUT
+//! `upsd` with `upsc`: the ones given with `--nut-ups`, or every UPS of the local daemon,
+//! and from apcupsd with `apcaccess`. A missing tool just contributes nothing.
+
+use crate::data_struct::PowerSource;
+use log::trace;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+struct PowerState {
+    nut_ups: Vec<String>,
+    cached: Vec<PowerSource>,
+    refreshed_at: Option<Instant>,
+}
+
+static POWER: Mutex<Option<PowerState>> = Mutex::new(None);
+
+/// Enable the collector, `nut_ups` are `name[@host[:port]]`, empty for the local ones
+pub fn init_power(nut_ups: Vec<String>) {
+    if let Ok(mut power) = POWER.lock() {
+        *power = Some(PowerState {
+            nut_ups,
+            cached: Vec::new(),
+            refreshed_at: None,
+        });
+    }
+}
+
+/// stdout of `program`, `None` when it is missing or fails
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(output) => {
+            trace!(
+                "{program} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn batteries() -> Vec<PowerSource> {
+    use std::path::Path;
+
+    let read = |dir: &Path, name: &str| {
+        std::fs::read_to_string(dir.join(name))
+            .ok()
+            .map(|value| value.trim().to_string())
+    };
+    let number =
+        |dir: &Path, name: &str| read(dir, name).and_then(|value| value.parse::<f64>().ok());
+
+    let Ok(entries) = std::fs::read_dir("/sys/class/power_supply") else {
+        return Vec::new();
+    };
+    let mut batteries = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|dir| {
+            read(dir, "type").as_deref() == Some("Battery")
+                && read(dir, "scope").as_deref() != Some("Device")
+        })
+        .map(|dir| {
+            let status = read(&dir, "status").unwrap_or_else(|| "Unknown".to_string());
+            let discharging = status == "Discharging";
+            // Reported in µWh / µW or µAh / µA depending on the driver
+            let runtime = number(&dir, "time_to_empty_now").or_else(|| {
+                let (left, rate) = number(&dir, "energy_now")
+                    .zip(number(&dir, "power_now"))
+                    .or_else(|| number(&dir, "charge_now").zip(number(&dir, "current_now")))?;
+                (discharging && rate > 0.0).then(|| left / rate * 3600.0)
+            });
+            PowerSource {
+                name: dir
+                    .file_name()
+                    .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+                kind: "battery".to_string(),
+                charge: number(&dir, "capacity"),
+                status: status.to_lowercase(),
+                on_battery: discharging,
+                runtime: runtime.map(|runtime| runtime as u64),
+                load: None,
+            }
+        })
+        .collect::<Vec<_>>();
+    batteries.sort_by(|a, b| a.name.cmp(&b.name));
+    batteries
+}
+
+#[cfg(target_os = "windows")]
+fn batteries() -> Vec<PowerSource> {
+    use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    let mut status = SYSTEM_POWER_STATUS::default();
+    if unsafe { GetSystemPowerStatus(&raw mut status) }.is_err() {
+        return Vec::new();
+    }
+    // 128: no system battery, 255: unknown
+    if status.BatteryFlag & 128 != 0 || status.BatteryFlag == 255 {
+        return Vec::new();
+    }
+    let on_battery = status.ACLineStatus == 0;
+    let charge = status.BatteryLifePercent;
+    let runtime = status.BatteryLifeTime;
+    let state = if status.BatteryFlag & 8 != 0 {
+        "charging"
+    } else if on_battery {
+        "discharging"
+    } else {
+        "not charging"
+    };
+    vec![PowerSource {
+        name: "battery".to_string(),
+        kind: "battery".to_string(),
+        charge: (charge != 255).then_some(f64::from(charge)),
+        status: state.to_string(),
+        on_battery,
+        runtime: (runtime != u32::MAX).then_some(u64::from(runtime)),
+        load: None,
+    }]
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn batteries() -> Vec<PowerSource> {
+    Vec::new()
+}
+
+/// Parse the `variable: value` lines of `upsc <ups>`
+fn parse_upsc(name: &str, output: &str) -> PowerSource {
+    let value = |key: &str| {
+        output.lines().find_map(|line| {
+            let (variable, value) = line.split_once(':')?;
+            (variable.trim() == key).then(|| value.trim().to_string())
+        })
+    };
+    let number = |key: &str| value(key).and_then(|value| value.parse::<f64>().ok());
+
+    // e.g. "OL CHRG", "OB DISCHRG", "OB LB"
+    let flags = value("ups.status").unwrap_or_default();
+    let on_battery = flags.split_whitespace().any(|flag| flag == "OB");
+    PowerSource {
+        name: name.to_string(),
+        kind: "nut".to_string(),
+        charge: number("battery.charge"),
+        status: flags,
+        on_battery,
+        runtime: number("battery.runtime").map(|runtime| runtime as u64),
+        load: number("ups.load"),
+    }
+}
+
+fn nut_ups(configured: &[String]) -> Vec<PowerSource> {
+    let names = if configured.is_empty() {
+        run("upsc", &["-l"])
+            .map(|output| output.lines().map(|line| line.trim().to_string()).collect())
+            .unwrap_or_default()
+    } else {
+        configured.to_vec()
+    };
+    names
+        .iter()
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| Some(parse_upsc(name, &run("upsc", &[name])?)))
+        .collect()
+}
+
+/// Parse `apcaccess -u status`, `KEY : value` lines without units
+fn parse_apcaccess(output: &str) -> Option<PowerSource> {
+    let value = |key: &str| {
+        output.lines().find_map(|line| {
+            let (variable, value) = line.split_once(':')?;
+            (variable.trim() == key).then(|| value.trim().to_string())
+        })
+    };
+    let number = |key: &str| value(key).and_then(|value| value.parse::<f64>().ok());
+
+    // e.g. "ONLINE", "ONBATT", "ONBATT LOWBATT", "COMMLOST"
+    let status = value("STATUS")?;
+    Some(PowerSource {
+        name: value("UPSNAME").unwrap_or_else(|| "apcupsd".to_string()),
+        kind: "apcupsd".to_string(),
+        charge: number("BCHARGE"),
+        on_battery: status.split_whitespace().any(|flag| flag == "ONBATT"),
+        status,
+        // TIMELEFT is in minutes
+        runtime: number("TIMELEFT").map(|minutes| (minutes * 60.0) as u64),
+        load: number("LOADPCT"),
+    })
+}
+
+pub fn realtime_power() -> Option<Vec<PowerSource>> {
+    let mut guard = POWER.lock().ok()?;
+    let power = guard.as_mut()?;
+
+    if power
+        .refreshed_at
+        .is_none_or(|refreshed_at| refreshed_at.elapsed() >= REFRESH_INTERVAL)
+    {
+        let mut sources = batteries();
+        sources.extend(nut_ups(&power.nut_ups));
+        sources.extend(
+            run("apcaccess", &["-u", "status"]).and_then(|output| parse_apcaccess(&output)),
+        );
+        power.cached = sources;
+        power.refreshed_at = Some(Instant::now());
+        trace!("REALTIME POWER successfully retrieved: {:?}", power.cached);
+    }
+
+    Some(power.cached.clone())
+}
@@ -0,0 +1,86 @@
+//! Load shedding under host pressure (`--shed-pressure`).
+//!
+//! The kernel's pressure stall information tells how much of the last 10 s tasks were
+//! stalled: `some` for the CPU (at least one runnable task waiting), `full` for memory
+//! (every task stalled on reclaim). When either reaches the threshold, the optional
+//! collectors that spawn commands or walk many files are skipped for that sample, so the
+//! agent does not add to the pressure.
+
+use crate::data_struct::LoadShed;
+use log::{info, warn};
+use std::sync::Mutex;
+
+/// Collectors left out of a shed sample
+pub const SHEDDABLE: &[&str] = &["top", "vpn", "accounting", "temperatures"];
+
+struct ShedState {
+    /// avg10 in %
+    threshold: f64,
+    shedding: bool,
+}
+
+static SHED: Mutex<Option<ShedState>> = Mutex::new(None);
+
+pub fn init_load_shedding(threshold: f64) {
+    if cfg!(not(target_os = "linux")) {
+        warn!("`--shed-pressure` is only supported on Linux");
+        return;
+    }
+    if let Ok(mut shed) = SHED.lock() {
+        *shed = Some(ShedState {
+            threshold,
+            shedding: false,
+        });
+    }
+}
+
+/// avg10 of the `some` or `full` line of a `/proc/pressure` file
+#[cfg(target_os = "linux")]
+fn avg10(resource: &str, kind: &str) -> Option<f64> {
+    let content = std::fs::read_to_string(format!("/proc/pressure/{resource}")).ok()?;
+    let line = content.lines().find(|line| line.starts_with(kind))?;
+    line.split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+/// `(cpu some, memory full)`, `None` without PSI (kernel < 4.20 or `psi=0`)
+#[cfg(target_os = "linux")]
+fn pressure() -> Option<(f64, f64)> {
+    Some((
+        avg10("cpu", "some")?,
+        avg10("memory", "full").unwrap_or(0.0),
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pressure() -> Option<(f64, f64)> {
+    None
+}
+
+/// The pressure when this sample has to skip the optional collectors
+pub fn load_shed() -> Option<LoadShed> {
+    let mut guard = SHED.lock().ok()?;
+    let state = guard.as_mut()?;
+    let (cpu, memory) = pressure()?;
+
+    let shedding = cpu >= state.threshold || memory >= state.threshold;
+    if shedding != state.shedding {
+        if shedding {
+            info!(
+                "Host under pressure (CPU {cpu:.1} %, memory {memory:.1} %), skipping {}",
+                SHEDDABLE.join(", ")
+            );
+        } else {
+            info!("Host pressure back to CPU {cpu:.1} %, memory {memory:.1} %");
+        }
+        state.shedding = shedding;
+    }
+
+    shedding.then(|| LoadShed {
+        cpu,
+        memory,
+        skipped: SHEDDABLE.iter().map(|name| (*name).to_string()).collect(),
+    })
+}
@@ -0,0 +1,207 @@
+//! Raspberry Pi-class board readings (`--sbc-stats`, Linux only).
+//!
+//! The chip temperature comes from the CPU thermal zone and the ARM clock from cpufreq, with
+//! `vcgencmd` (Raspberry Pi firmware) as fallback for both and as the only source of the
+//! core voltage. The firmware flags of `get_throttled` are read from sysfs where the
+//! kernel exposes them, from `vcgencmd` otherwise. Readings are cached for 10 s, each
+//! `vcgencmd` call spawns a process.
+
+use crate::data_struct::SbcStats;
+use log::{trace, warn};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+/// `get_throttled` of the Raspberry Pi firmware, the hex flags without `0x`
+#[cfg(target_os = "linux")]
+const FIRMWARE_THROTTLED: &str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+/// Flags of `get_throttled` for the conditions right now, the same shifted by 16 tell
+/// whether they occurred since boot
+const FIRMWARE_FLAGS: [(u32, &str); 4] = [
+    (1 << 0, "under-voltage"),
+    (1 << 1, "frequency-capped"),
+    (1 << 2, "throttled"),
+    (1 << 3, "soft-temperature-limit"),
+];
+/// The flags that mean performance is limited right now, under-voltage alone does not yet
+pub const FIRMWARE_LIMITED: u32 = 0b1110;
+/// The flags for the conditions right now
+pub const FIRMWARE_NOW: u32 = 0b1111;
+
+struct SbcState {
+    model: Option<String>,
+    /// `temp` of the CPU thermal zone, in m°C
+    #[cfg(target_os = "linux")]
+    temperature_path: Option<std::path::PathBuf>,
+    latest: Option<SbcStats>,
+    refreshed_at: Option<Instant>,
+}
+
+static SBC: Mutex<Option<SbcState>> = Mutex::new(None);
+
+/// Names of the set `flags`, those since boot with an `-occurred` suffix
+pub fn firmware_flag_names(flags: u32) -> Vec<String> {
+    let now = FIRMWARE_FLAGS
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| (*name).to_string());
+    let occurred = FIRMWARE_FLAGS
+        .iter()
+        .filter(|(flag, _)| flags & (flag << 16) != 0)
+        .map(|(_, name)| format!("{name}-occurred"));
+    now.chain(occurred).collect()
+}
+
+/// Output of `vcgencmd <args>` after the `=`, e.g. `0x50005` of `throttled=0x50005`
+#[cfg(target_os = "linux")]
+fn vcgencmd(args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("vcgencmd")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let (_, value) = output.trim().split_once('=')?;
+    Some(value.to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn parse_hex(hex: &str) -> Option<u32> {
+    u32::from_str_radix(hex.trim().trim_start_matches("0x"), 16).ok()
+}
+
+/// The `get_throttled` flags, `None` on anything but a Raspberry Pi
+#[cfg(target_os = "linux")]
+pub fn firmware_flags() -> Option<u32> {
+    use std::sync::OnceLock;
+    static SYSFS: OnceLock<bool> = OnceLock::new();
+    static VCGENCMD: OnceLock<bool> = OnceLock::new();
+
+    if *SYSFS.get_or_init(|| std::path::Path::new(FIRMWARE_THROTTLED).exists()) {
+        return super::with_file(FIRMWARE_THROTTLED, parse_hex);
+    }
+    // A missing `vcgencmd` is not looked for on every sample
+    if !*VCGENCMD.get_or_init(|| vcgencmd(&["get_throttled"]).is_some()) {
+        return None;
+    }
+    parse_hex(&vcgencmd(&["get_throttled"])?)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn firmware_flags() -> Option<u32> {
+    None
+}
+
+/// The thermal zone of the CPU, the first one when none is named after it
+#[cfg(target_os = "linux")]
+fn cpu_thermal_zone() -> Option<std::path::PathBuf> {
+    let mut zones = std::fs::read_dir("/sys/class/thermal")
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("thermal_zone"))
+        })
+        .collect::<Vec<_>>();
+    zones.sort();
+    let cpu = zones.iter().find(|zone| {
+        std::fs::read_to_string(zone.join("type")).is_ok_and(|kind| {
+            let kind = kind.trim();
+            kind.contains("cpu") || kind.contains("soc")
+        })
+    });
+    cpu.or(zones.first()).map(|zone| zone.join("temp"))
+}
+
+pub fn init_sbc() {
+    if cfg!(not(target_os = "linux")) {
+        warn!("`--sbc-stats` is only supported on Linux");
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    if let Ok(mut sbc) = SBC.lock() {
+        let model = std::fs::read_to_string("/proc/device-tree/model")
+            .ok()
+            .map(|model| model.trim_end_matches('\0').trim().to_string())
+            .filter(|model| !model.is_empty());
+        *sbc = Some(SbcState {
+            model,
+            temperature_path: cpu_thermal_zone(),
+            latest: None,
+            refreshed_at: None,
+        });
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sample(state: &SbcState) -> SbcStats {
+    let soc_temperature = state
+        .temperature_path
+        .as_ref()
+        .and_then(|path| super::with_file(path, |temp| temp.trim().parse::<f64>().ok()))
+        .map(|millis| millis / 1000.0)
+        // temp=48.3'C
+        .or_else(|| {
+            vcgencmd(&["measure_temp"])?
+                .trim_end_matches("'C")
+                .parse()
+                .ok()
+        });
+    let arm_clock = super::with_file(
+        "/sys/devices/system/cpu/cpu0/cpufreq/scaling_cur_freq",
+        |khz| khz.trim().parse::<u64>().ok(),
+    )
+    .map(|khz| khz / 1000)
+    // frequency(48)=1500398464
+    .or_else(|| {
+        vcgencmd(&["measure_clock", "arm"])?
+            .parse::<u64>()
+            .ok()
+            .map(|hz| hz / 1_000_000)
+    });
+    // volt=0.8500V
+    let core_voltage = vcgencmd(&["measure_volts", "core"])
+        .and_then(|volts| volts.trim_end_matches('V').parse().ok());
+    let flags = firmware_flags();
+
+    SbcStats {
+        model: state.model.clone(),
+        soc_temperature,
+        core_voltage,
+        arm_clock,
+        under_voltage: flags.map(|flags| flags & 1 != 0),
+        throttled: flags.map(|flags| flags & FIRMWARE_LIMITED != 0),
+        flags: flags.map(firmware_flag_names),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sample(state: &SbcState) -> SbcStats {
+    SbcStats {
+        model: state.model.clone(),
+        soc_temperature: None,
+        core_voltage: None,
+        arm_clock: None,
+        under_voltage: None,
+        throttled: None,
+        flags: None,
+    }
+}
+
+pub fn realtime_sbc() -> Option<SbcStats> {
+    let mut guard = SBC.lock().ok()?;
+    let state = guard.as_mut()?;
+
+    if state
+        .refreshed_at
+        .is_none_or(|refreshed_at| refreshed_at.elapsed() >= REFRESH_INTERVAL)
+    {
+        let stats = sample(state);
+        trace!("REALTIME SBC successfully retrieved: {stats:?}");
+        state.latest = Some(stats);
+        state.refreshed_at = Some(Instant::now());
+    }
+    state.latest.clone()
+}
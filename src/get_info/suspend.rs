@@ -0,0 +1,87 @@
+//! Suspend / resume detection.
+//!
+//! Two clocks are compared, one that keeps running while the machine sleeps and one that
+//! does not: `CLOCK_BOOTTIME` / `CLOCK_MONOTONIC` on Linux, `CLOCK_MONOTONIC` /
+//! `CLOCK_UPTIME_RAW` on macOS, the interrupt time with and without its sleep bias on
+//! Windows. FreeBSD has no clock counting suspend, the wall clock is used there.
+
+use std::time::Duration;
+
+/// Gaps shorter than this are scheduling noise (or NTP slewing the wall clock)
+const MIN_SUSPEND: Duration = Duration::from_secs(5);
+
+#[cfg(unix)]
+fn clock(id: libc::clockid_t) -> Option<Duration> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(id, &raw mut ts) } != 0 {
+        return None;
+    }
+    Some(Duration::new(
+        u64::try_from(ts.tv_sec).ok()?,
+        u32::try_from(ts.tv_nsec).ok()?,
+    ))
+}
+
+/// `(running through suspend, stopped during suspend)`, each from its own origin
+#[cfg(target_os = "linux")]
+fn clocks() -> Option<(Duration, Duration)> {
+    Some((clock(libc::CLOCK_BOOTTIME)?, clock(libc::CLOCK_MONOTONIC)?))
+}
+
+#[cfg(target_os = "macos")]
+fn clocks() -> Option<(Duration, Duration)> {
+    Some((
+        clock(libc::CLOCK_MONOTONIC)?,
+        clock(libc::CLOCK_UPTIME_RAW)?,
+    ))
+}
+
+#[cfg(target_os = "freebsd")]
+fn clocks() -> Option<(Duration, Duration)> {
+    Some((clock(libc::CLOCK_REALTIME)?, clock(libc::CLOCK_UPTIME)?))
+}
+
+#[cfg(target_os = "windows")]
+fn clocks() -> Option<(Duration, Duration)> {
+    use windows::Win32::System::WindowsProgramming::QueryUnbiasedInterruptTime;
+
+    static ORIGIN: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    let mut unbiased = 0u64;
+    unsafe { QueryUnbiasedInterruptTime(&raw mut unbiased) }
+        .ok()
+        .ok()?;
+    // QueryPerformanceCounter behind `Instant` counts sleep, the unbiased time (100 ns units) does not
+    Some((
+        ORIGIN.get_or_init(std::time::Instant::now).elapsed(),
+        Duration::from_nanos(unbiased * 100),
+    ))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "windows"
+)))]
+fn clocks() -> Option<(Duration, Duration)> {
+    None
+}
+
+#[derive(Debug, Default)]
+pub struct SuspendDetector {
+    previous: Option<(Duration, Duration)>,
+}
+
+impl SuspendDetector {
+    /// Time the machine spent suspended since the previous call, if it did
+    pub fn check(&mut self) -> Option<Duration> {
+        let current = clocks()?;
+        let previous = self.previous.replace(current)?;
+        let total = current.0.saturating_sub(previous.0);
+        let awake = current.1.saturating_sub(previous.1);
+        Some(total.saturating_sub(awake)).filter(|slept| *slept >= MIN_SUSPEND)
+    }
+}
@@ -0,0 +1,77 @@
+//! Hardware temperatures (`--temperatures`).
+//!
+//! sysinfo reads hwmon on Linux, SMC on macOS and the ACPI thermal zones on Windows. Most
+//! Windows desktops expose nothing there, so the sensors of a running `LibreHardwareMonitor`
+//! or `OpenHardwareMonitor` are preferred when its WMI namespace answers.
+
+use crate::data_struct::Temperature;
+use log::trace;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use sysinfo::Components;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+struct TemperatureState {
+    components: Components,
+    cached: Vec<Temperature>,
+    refreshed_at: Option<Instant>,
+}
+
+static TEMPERATURES: Mutex<Option<TemperatureState>> = Mutex::new(None);
+
+pub fn init_temperatures() {
+    if let Ok(mut temperatures) = TEMPERATURES.lock() {
+        *temperatures = Some(TemperatureState {
+            components: Components::new_with_refreshed_list(),
+            cached: Vec::new(),
+            refreshed_at: None,
+        });
+    }
+}
+
+fn read_components(components: &mut Components) -> Vec<Temperature> {
+    components.refresh(true);
+    components
+        .list()
+        .iter()
+        .filter_map(|component| {
+            Some(Temperature {
+                label: component.label().to_string(),
+                value: f64::from(component.temperature()?),
+            })
+        })
+        .collect()
+}
+
+fn read_temperatures(components: &mut Components) -> Vec<Temperature> {
+    #[cfg(target_os = "windows")]
+    {
+        let sensors = crate::get_info::windows_hw::hardware_monitor_temperatures();
+        if !sensors.is_empty() {
+            return sensors
+                .into_iter()
+                .map(|(label, value)| Temperature { label, value })
+                .collect();
+        }
+    }
+    read_components(components)
+}
+
+pub fn realtime_temperatures() -> Option<Vec<Temperature>> {
+    let mut guard = TEMPERATURES.lock().ok()?;
+    let state = guard.as_mut()?;
+
+    if state
+        .refreshed_at
+        .is_none_or(|at| at.elapsed() >= REFRESH_INTERVAL)
+    {
+        state.cached = read_temperatures(&mut state.components);
+        state.refreshed_at = Some(Instant::now());
+        trace!(
+            "REALTIME TEMPERATURES successfully retrieved: {:?}",
+            state.cached
+        );
+    }
+    Some(state.cached.clone())
+}
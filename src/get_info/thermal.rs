@@ -0,0 +1,156 @@
+//! CPU thermal throttling (`--thermal-throttle`).
+//!
+//! On Linux the kernel counts the throttle events it gets from the thermal status MSRs in
+//! `/sys/devices/system/cpu/cpu*/thermal_throttle`, per core and per package. Siblings of
+//! a core (and cores of a package) report the same counter, each is counted once. The
+//! counters are looked up on the first sample only, CPUs plugged in later are left out.
+//! A Raspberry Pi has no such counters but its firmware flags (`get_throttled`, see `sbc`)
+//! tell whether the chip is under-volted, capped or throttled right now. Windows only tells how far performance is limited. Without counters an event is a
+//! sample going from unlimited to limited.
+
+use crate::data_struct::ThermalThrottle;
+#[cfg(target_os = "linux")]
+use crate::get_info::sbc::{FIRMWARE_LIMITED, FIRMWARE_NOW, firmware_flag_names, firmware_flags};
+use log::{trace, warn};
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct ThrottleState {
+    /// Kernel counter at the previous sample (Linux)
+    #[cfg(target_os = "linux")]
+    previous: Option<u64>,
+    /// Counter files summed up on every sample (Linux)
+    #[cfg(target_os = "linux")]
+    counters: Option<Vec<std::path::PathBuf>>,
+    /// Performance was limited at the previous sample (Raspberry Pi, Windows)
+    limited: bool,
+    /// Events counted by the agent (Raspberry Pi, Windows)
+    total: u64,
+}
+
+impl ThrottleState {
+    /// Count an event when performance just became limited
+    fn limited(&mut self, limited: bool) -> u64 {
+        let events = u64::from(limited && !self.limited);
+        self.limited = limited;
+        self.total += events;
+        events
+    }
+}
+
+static THROTTLE: Mutex<Option<ThrottleState>> = Mutex::new(None);
+
+pub fn init_thermal_throttle() {
+    if cfg!(not(any(target_os = "linux", target_os = "windows"))) {
+        warn!("`--thermal-throttle` is only supported on Linux and Windows");
+        return;
+    }
+    if let Ok(mut throttle) = THROTTLE.lock() {
+        *throttle = Some(ThrottleState::default());
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_u64(path: &std::path::Path) -> Option<u64> {
+    super::with_file(path, |content| content.trim().parse().ok())
+}
+
+/// Core and package throttle counters, one per core and one per package
+#[cfg(target_os = "linux")]
+fn throttle_counters() -> Vec<std::path::PathBuf> {
+    use std::collections::HashSet;
+
+    let mut cores = HashSet::new();
+    let mut packages = HashSet::new();
+    let mut counters = Vec::new();
+
+    let Ok(entries) = std::fs::read_dir("/sys/devices/system/cpu") else {
+        return counters;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let is_cpu = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("cpu"))
+            .is_some_and(|id| id.parse::<u32>().is_ok());
+        let throttle = path.join("thermal_throttle");
+        if !is_cpu || !throttle.is_dir() {
+            continue;
+        }
+
+        let package = read_u64(&path.join("topology/physical_package_id")).unwrap_or(0);
+        let core = read_u64(&path.join("topology/core_id")).unwrap_or(0);
+        if cores.insert((package, core)) {
+            counters.push(throttle.join("core_throttle_count"));
+        }
+        if packages.insert(package) {
+            counters.push(throttle.join("package_throttle_count"));
+        }
+    }
+    counters
+}
+
+/// Core and package throttle events since boot, `None` without the sysfs counters
+#[cfg(target_os = "linux")]
+fn throttle_count(counters: &[std::path::PathBuf]) -> Option<u64> {
+    (!counters.is_empty()).then(|| {
+        counters
+            .iter()
+            .map(|counter| read_u64(counter).unwrap_or(0))
+            .sum()
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn sample(state: &mut ThrottleState) -> Option<ThermalThrottle> {
+    let counters = state.counters.get_or_insert_with(throttle_counters);
+    if let Some(count) = throttle_count(counters) {
+        let events = state
+            .previous
+            .map_or(0, |previous| count.saturating_sub(previous));
+        state.previous = Some(count);
+        return Some(ThermalThrottle {
+            events,
+            total: count,
+            throttled: events > 0,
+            performance_limit: None,
+            flags: None,
+        });
+    }
+
+    let flags = firmware_flags()?;
+    let events = state.limited(flags & FIRMWARE_LIMITED != 0);
+    Some(ThermalThrottle {
+        events,
+        total: state.total,
+        throttled: flags & FIRMWARE_LIMITED != 0,
+        performance_limit: None,
+        flags: Some(firmware_flag_names(flags & FIRMWARE_NOW)),
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn sample(state: &mut ThrottleState) -> Option<ThermalThrottle> {
+    let limit = crate::get_info::windows_hw::performance_limit()?;
+    let events = state.limited(limit < 100.0);
+    Some(ThermalThrottle {
+        events,
+        total: state.total,
+        throttled: limit < 100.0,
+        performance_limit: Some(limit),
+        flags: None,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn sample(_state: &mut ThrottleState) -> Option<ThermalThrottle> {
+    None
+}
+
+pub fn realtime_thermal_throttle() -> Option<ThermalThrottle> {
+    let mut guard = THROTTLE.lock().ok()?;
+    let throttle = sample(guard.as_mut()?)?;
+    trace!("REALTIME THERMAL THROTTLE successfully retrieved: {throttle:?}");
+    Some(throttle)
+}
@@ -0,0 +1,225 @@
+//! Process and service watchdog (`--watch-process` / `--watch-service`).
+//!
+//! A watched process is running while at least one process has that name. A watched
+//! service is a systemd unit on Linux (`systemctl show`) and a Windows service
+//! (`sc queryex`), other platforms report it as unsupported. A restart is counted when a
+//! watch comes back after being down, or when all of its PIDs changed between two refreshes
+//! (a restart quicker than the refresh interval), so counts start when the agent does.
+
+use crate::data_struct::WatchedService;
+use log::trace;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+/// Linux reports process names cut to this length
+const COMM_LEN: usize = 15;
+
+struct Watch {
+    name: String,
+    kind: &'static str,
+    /// `None` before the first refresh
+    pids: Option<Vec<u32>>,
+    restarts: u64,
+    latest: Option<WatchedService>,
+}
+
+impl Watch {
+    fn new(name: String, kind: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            pids: None,
+            restarts: 0,
+            latest: None,
+        }
+    }
+
+    /// Record the PIDs found now, empty when not running
+    fn observe(&mut self, pids: Vec<u32>) {
+        if let Some(previous) = &self.pids
+            && !pids.is_empty()
+            && !pids.iter().any(|pid| previous.contains(pid))
+        {
+            self.restarts += 1;
+        }
+        self.pids = Some(pids);
+    }
+}
+
+struct WatchState {
+    system: System,
+    watches: Vec<Watch>,
+    refreshed_at: Option<Instant>,
+}
+
+static WATCH: Mutex<Option<WatchState>> = Mutex::new(None);
+
+/// Enable the watchdog for the named processes and services
+pub fn init_watch(processes: &[String], services: &[String]) {
+    let watches = processes
+        .iter()
+        .map(|name| Watch::new(name.clone(), "process"))
+        .chain(
+            services
+                .iter()
+                .map(|name| Watch::new(name.clone(), "service")),
+        )
+        .collect();
+    if let Ok(mut watch) = WATCH.lock() {
+        *watch = Some(WatchState {
+            system: System::new(),
+            watches,
+            refreshed_at: None,
+        });
+    }
+}
+
+fn process_matches(process: &str, watched: &str) -> bool {
+    let process = process.strip_suffix(".exe").unwrap_or(process);
+    if cfg!(target_os = "windows") {
+        return process.eq_ignore_ascii_case(watched);
+    }
+    process == watched
+        || cfg!(target_os = "linux") && process.len() == COMM_LEN && watched.starts_with(process)
+}
+
+/// State and PIDs of a service, `None` when it cannot be queried at all
+#[cfg(target_os = "linux")]
+fn service_status(name: &str) -> Option<(String, Vec<u32>)> {
+    let output = std::process::Command::new("systemctl")
+        .args([
+            "show",
+            name,
+            "--property=LoadState,ActiveState,SubState,MainPID",
+        ])
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let value = |key: &str| {
+        output.lines().find_map(|line| {
+            let (property, value) = line.split_once('=')?;
+            (property == key).then(|| value.trim().to_string())
+        })
+    };
+
+    if value("LoadState")? == "not-found" {
+        return Some(("not-found".to_string(), Vec::new()));
+    }
+    let active = value("ActiveState").unwrap_or_default();
+    let state = format!("{active} ({})", value("SubState").unwrap_or_default());
+    if active != "active" {
+        return Some((state, Vec::new()));
+    }
+    // Services without a main process (oneshot, remain-after-exit) still count as running
+    let pid = value("MainPID")
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .unwrap_or(0);
+    Some((state, vec![pid]))
+}
+
+#[cfg(target_os = "windows")]
+fn service_status(name: &str) -> Option<(String, Vec<u32>)> {
+    let output = std::process::Command::new("sc")
+        .args(["queryex", name])
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    // e.g. "        STATE              : 4  RUNNING"
+    let value = |key: &str| {
+        output.lines().find_map(|line| {
+            let (field, value) = line.split_once(':')?;
+            (field.trim() == key).then(|| value.trim().to_string())
+        })
+    };
+
+    let Some(state) = value("STATE") else {
+        return Some(("not-found".to_string(), Vec::new()));
+    };
+    let state = state
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_lowercase();
+    if state != "running" {
+        return Some((state, Vec::new()));
+    }
+    let pid = value("PID")
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .unwrap_or(0);
+    Some((state, vec![pid]))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn service_status(_name: &str) -> Option<(String, Vec<u32>)> {
+    None
+}
+
+pub fn realtime_watch() -> Option<Vec<WatchedService>> {
+    let mut guard = WATCH.lock().ok()?;
+    let state = guard.as_mut()?;
+
+    if state
+        .refreshed_at
+        .is_none_or(|refreshed_at| refreshed_at.elapsed() >= REFRESH_INTERVAL)
+    {
+        if state.watches.iter().any(|watch| watch.kind == "process") {
+            state.system.refresh_processes_specifics(
+                ProcessesToUpdate::All,
+                true,
+                ProcessRefreshKind::nothing(),
+            );
+        }
+
+        for watch in &mut state.watches {
+            let (status, pids, instances) = if watch.kind == "process" {
+                let mut pids = state
+                    .system
+                    .processes()
+                    .values()
+                    // Linux lists threads as processes too
+                    .filter(|process| process.thread_kind().is_none())
+                    .filter(|process| {
+                        process_matches(&process.name().to_string_lossy(), &watch.name)
+                    })
+                    .map(|process| process.pid().as_u32())
+                    .collect::<Vec<_>>();
+                pids.sort_unstable();
+                let status = if pids.is_empty() {
+                    "stopped"
+                } else {
+                    "running"
+                };
+                let instances = Some(pids.len() as u64);
+                (status.to_string(), pids, instances)
+            } else {
+                match service_status(&watch.name) {
+                    Some((status, pids)) => (status, pids, None),
+                    None => ("unsupported".to_string(), Vec::new(), None),
+                }
+            };
+
+            let running = !pids.is_empty();
+            watch.observe(pids);
+            watch.latest = Some(WatchedService {
+                name: watch.name.clone(),
+                kind: watch.kind.to_string(),
+                running,
+                status,
+                instances,
+                restarts: watch.restarts,
+            });
+        }
+        state.refreshed_at = Some(Instant::now());
+        trace!("REALTIME WATCH successfully retrieved");
+    }
+
+    Some(
+        state
+            .watches
+            .iter()
+            .filter_map(|watch| watch.latest.clone())
+            .collect(),
+    )
+}
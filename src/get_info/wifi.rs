@@ -0,0 +1,198 @@
+//! Wi-Fi link of the wireless interfaces (`--wifi-stats`).
+//!
+//! On Linux the wireless interfaces are the ones with a `wireless` directory in sysfs, link
+//! quality and signal come from `/proc/net/wireless` and the SSID, frequency and bitrate
+//! from `iw dev <iface> link` (nl80211), which also gives the signal when the kernel has no
+//! wireless extensions. On Windows everything comes from `netsh wlan show interfaces`, whose
+//! signal is a quality in % the dBm are estimated from. Readings are cached for 10 s.
+
+use crate::data_struct::WifiLink;
+use log::{trace, warn};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+struct WifiState {
+    latest: Vec<WifiLink>,
+    refreshed_at: Option<Instant>,
+}
+
+static WIFI: Mutex<Option<WifiState>> = Mutex::new(None);
+
+pub fn init_wifi() {
+    if cfg!(not(any(target_os = "linux", target_os = "windows"))) {
+        warn!("`--wifi-stats` is only supported on Linux and Windows");
+        return;
+    }
+    if let Ok(mut wifi) = WIFI.lock() {
+        *wifi = Some(WifiState {
+            latest: Vec::new(),
+            refreshed_at: None,
+        });
+    }
+}
+
+/// stdout of `program`, `None` when it is missing or fails
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Value after `key` on the first line starting with it, e.g. `SSID: home` for `SSID:`
+#[cfg(target_os = "linux")]
+fn field<'a>(output: &'a str, key: &str) -> Option<&'a str> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(key))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Leading number of a value such as `-52 dBm` or `866.7 MBit/s VHT-MCS 9`
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn number(value: &str) -> Option<f64> {
+    value.split_whitespace().next()?.parse().ok()
+}
+
+/// `(link quality in %, signal in dBm)` of every interface in `/proc/net/wireless`
+#[cfg(target_os = "linux")]
+fn wireless_extensions() -> Vec<(String, Option<f64>, Option<f64>)> {
+    // Quality is out of 70 with every cfg80211 driver
+    const QUALITY_MAX: f64 = 70.0;
+
+    super::with_file("/proc/net/wireless", |content| {
+        Some(
+            content
+                .lines()
+                .skip(2)
+                .filter_map(|line| {
+                    let (name, values) = line.split_once(':')?;
+                    let mut values = values.split_whitespace().skip(1);
+                    let quality = values
+                        .next()
+                        .and_then(|link| link.trim_end_matches('.').parse::<f64>().ok())
+                        .map(|link| (link / QUALITY_MAX * 100.0).min(100.0));
+                    let signal = values
+                        .next()
+                        .and_then(|level| level.trim_end_matches('.').parse::<f64>().ok())
+                        // -256 when the driver has no reading
+                        .filter(|level| *level > -256.0 && *level < 0.0);
+                    Some((name.trim().to_string(), quality, signal))
+                })
+                .collect(),
+        )
+    })
+    .unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+fn sample() -> Vec<WifiLink> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/net") else {
+        return Vec::new();
+    };
+    let mut interfaces = entries
+        .flatten()
+        .filter(|entry| entry.path().join("wireless").is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    interfaces.sort();
+    let extensions = wireless_extensions();
+
+    interfaces
+        .into_iter()
+        .map(|interface| {
+            let (quality, signal) = extensions
+                .iter()
+                .find(|(name, _, _)| *name == interface)
+                .map_or((None, None), |(_, quality, signal)| (*quality, *signal));
+            // "Not connected." when there is no link
+            let link = run("iw", &["dev", &interface, "link"])
+                .filter(|link| link.starts_with("Connected"));
+            let connected = match &link {
+                Some(_) => true,
+                None => signal.is_some(),
+            };
+            let link = link.as_deref().unwrap_or_default();
+            WifiLink {
+                ssid: field(link, "SSID:").map(str::to_string),
+                connected,
+                signal: signal.or_else(|| field(link, "signal:").and_then(number)),
+                quality,
+                frequency: field(link, "freq:")
+                    .and_then(number)
+                    .map(|frequency| frequency as u32),
+                bitrate: field(link, "rx bitrate:").and_then(number),
+                interface,
+            }
+        })
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+fn sample() -> Vec<WifiLink> {
+    let Some(output) = run("netsh", &["wlan", "show", "interfaces"]) else {
+        return Vec::new();
+    };
+    // One block per interface, each starting with its `Name`
+    let mut blocks = Vec::new();
+    for line in output.lines() {
+        if line.trim_start().starts_with("Name") {
+            blocks.push(String::new());
+        }
+        if let Some(block) = blocks.last_mut() {
+            block.push_str(line);
+            block.push('\n');
+        }
+    }
+
+    blocks
+        .iter()
+        .map(|block| {
+            // "    SSID                   : home", the BSSID line starts differently
+            let value = |key: &str| {
+                block.lines().find_map(|line| {
+                    let (name, value) = line.split_once(" :")?;
+                    (name.trim() == key).then(|| value.trim().to_string())
+                })
+            };
+            let quality =
+                value("Signal").and_then(|signal| signal.trim_end_matches('%').parse::<f64>().ok());
+            WifiLink {
+                interface: value("Name").unwrap_or_default(),
+                ssid: value("SSID"),
+                connected: value("State").is_some_and(|state| state == "connected"),
+                // What Windows does the other way round, 100 % at -50 dBm and 0 % at -100
+                signal: quality.map(|quality| quality / 2.0 - 100.0),
+                quality,
+                frequency: None,
+                bitrate: value("Receive rate (Mbps)").and_then(|rate| number(&rate)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn sample() -> Vec<WifiLink> {
+    Vec::new()
+}
+
+pub fn realtime_wifi() -> Option<Vec<WifiLink>> {
+    let mut guard = WIFI.lock().ok()?;
+    let state = guard.as_mut()?;
+
+    if state
+        .refreshed_at
+        .is_none_or(|refreshed_at| refreshed_at.elapsed() >= REFRESH_INTERVAL)
+    {
+        state.latest = sample();
+        state.refreshed_at = Some(Instant::now());
+        trace!("REALTIME WIFI successfully retrieved: {:?}", state.latest);
+    }
+    Some(state.latest.clone())
+}
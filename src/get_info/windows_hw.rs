@@ -0,0 +1,169 @@
+//! Windows hardware details: GPUs through DXGI, processor performance limits from WMI and
+//! temperatures from the namespace published by `LibreHardwareMonitor` (or
+//! `OpenHardwareMonitor`) when one is running.
+
+use windows::Win32::Graphics::Dxgi::{
+    CreateDXGIFactory1, DXGI_ADAPTER_FLAG_SOFTWARE, IDXGIFactory1,
+};
+use windows::Win32::System::Com::{
+    CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED, CoCreateInstance, CoInitializeEx,
+    CoSetProxyBlanket, EOAC_NONE, RPC_C_AUTHN_LEVEL_CALL, RPC_C_IMP_LEVEL_IMPERSONATE,
+};
+use windows::Win32::System::Rpc::{RPC_C_AUTHN_WINNT, RPC_C_AUTHZ_NONE};
+use windows::Win32::System::Variant::{
+    VARIANT, VT_BSTR, VT_I4, VT_R4, VT_R8, VT_UI4, VariantClear,
+};
+use windows::Win32::System::Wmi::{
+    IWbemClassObject, IWbemLocator, WBEM_FLAG_FORWARD_ONLY, WBEM_FLAG_RETURN_IMMEDIATELY,
+    WbemLocator,
+};
+use windows::core::{BSTR, PCWSTR, w};
+
+/// Hardware adapters with their dedicated memory in bytes, the software renderer excluded
+pub fn gpus() -> Vec<(String, u64)> {
+    let Ok(factory) = (unsafe { CreateDXGIFactory1::<IDXGIFactory1>() }) else {
+        return Vec::new();
+    };
+
+    let mut gpus = Vec::new();
+    let mut index = 0;
+    while let Ok(adapter) = unsafe { factory.EnumAdapters1(index) } {
+        index += 1;
+        let Ok(desc) = (unsafe { adapter.GetDesc1() }) else {
+            continue;
+        };
+        if desc.Flags & (DXGI_ADAPTER_FLAG_SOFTWARE.0 as u32) != 0 {
+            continue;
+        }
+        let len = desc
+            .Description
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(desc.Description.len());
+        let name = String::from_utf16_lossy(&desc.Description[..len]);
+        gpus.push((name, desc.DedicatedVideoMemory as u64));
+    }
+    gpus
+}
+
+/// Read a property of a WMI object, strings and numbers only
+unsafe fn property(
+    object: &IWbemClassObject,
+    name: PCWSTR,
+) -> Option<(Option<String>, Option<f64>)> {
+    let mut variant = VARIANT::default();
+    unsafe {
+        object.Get(name, 0, &raw mut variant, None, None).ok()?;
+        let inner = &variant.Anonymous.Anonymous;
+        let value = match inner.vt {
+            // Formatted performance counters come as strings of digits
+            VT_BSTR => {
+                let text = inner.Anonymous.bstrVal.to_string();
+                let number = text.parse().ok();
+                (Some(text), number)
+            }
+            VT_R4 => (None, Some(f64::from(inner.Anonymous.fltVal))),
+            VT_R8 => (None, Some(inner.Anonymous.dblVal)),
+            VT_I4 => (None, Some(f64::from(inner.Anonymous.lVal))),
+            VT_UI4 => (None, Some(f64::from(inner.Anonymous.ulVal))),
+            _ => (None, None),
+        };
+        let _ = VariantClear(&raw mut variant);
+        Some(value)
+    }
+}
+
+/// Objects returned by a WQL query against `namespace`, empty when it does not exist
+fn query(namespace: &str, wql: &str) -> Vec<IWbemClassObject> {
+    let mut objects = Vec::new();
+    unsafe {
+        // Already initialized threads return S_FALSE or RPC_E_CHANGED_MODE, both are usable
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+        let Ok(locator) =
+            CoCreateInstance::<_, IWbemLocator>(&WbemLocator, None, CLSCTX_INPROC_SERVER)
+        else {
+            return objects;
+        };
+        let Ok(services) = locator.ConnectServer(
+            &BSTR::from(namespace),
+            &BSTR::new(),
+            &BSTR::new(),
+            &BSTR::new(),
+            0,
+            &BSTR::new(),
+            None,
+        ) else {
+            return objects;
+        };
+        if CoSetProxyBlanket(
+            &services,
+            RPC_C_AUTHN_WINNT,
+            RPC_C_AUTHZ_NONE,
+            None,
+            RPC_C_AUTHN_LEVEL_CALL,
+            RPC_C_IMP_LEVEL_IMPERSONATE,
+            None,
+            EOAC_NONE,
+        )
+        .is_err()
+        {
+            return objects;
+        }
+        let Ok(enumerator) = services.ExecQuery(
+            &BSTR::from("WQL"),
+            &BSTR::from(wql),
+            WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY,
+            None,
+        ) else {
+            return objects;
+        };
+
+        loop {
+            let mut next = [None];
+            let mut returned = 0;
+            // 1 s per object, a hung provider must not stall the sampling loop
+            let _ = enumerator.Next(1000, &mut next, &raw mut returned);
+            let [Some(object)] = next else {
+                break;
+            };
+            if returned == 0 {
+                break;
+            }
+            objects.push(object);
+        }
+    }
+    objects
+}
+
+/// Temperature sensors `(name, °C)` of the first hardware monitor namespace that answers
+pub fn hardware_monitor_temperatures() -> Vec<(String, f64)> {
+    for namespace in ["root\\LibreHardwareMonitor", "root\\OpenHardwareMonitor"] {
+        let sensors = query(
+            namespace,
+            "SELECT Name, Value FROM Sensor WHERE SensorType = 'Temperature'",
+        )
+        .iter()
+        .filter_map(|object| unsafe {
+            let name = property(object, w!("Name"))?.0?;
+            let value = property(object, w!("Value"))?.1?;
+            Some((name, value))
+        })
+        .collect::<Vec<_>>();
+        if !sensors.is_empty() {
+            return sensors;
+        }
+    }
+    Vec::new()
+}
+
+/// Performance the processors are allowed to reach, in % of nominal. Below 100 the
+/// firmware limits them, thermal throttling being the usual reason.
+pub fn performance_limit() -> Option<f64> {
+    query(
+        "root\\cimv2",
+        "SELECT PercentPerformanceLimit FROM Win32_PerfFormattedData_Counters_ProcessorInformation WHERE Name = '_Total'",
+    )
+    .first()
+    .and_then(|object| unsafe { property(object, w!("PercentPerformanceLimit")) })
+    .and_then(|(_, value)| value)
+}
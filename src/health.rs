@@ -0,0 +1,109 @@
+//! Health of the Komari server (`--health-check-interval`).
+//!
+//! The server's HTTP health endpoint is polled in the background. After a few failed
+//! checks in a row the agent goes into degraded mode: a single warning is logged instead
+//! of one error per reconnect attempt, reconnects are spaced out, Real-Time Info is
+//! uploaded at `--degraded-interval` and Basic Info pushes are held back until the
+//! server answers again. Samples keep being recorded locally (`--record-path`) and events
+//! stay queued in the meantime.
+
+use crate::auth::panel_headers;
+use log::{info, trace, warn};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Failed checks in a row before degraded mode starts
+const FAILURES_TO_DEGRADE: u32 = 3;
+/// Delay between two connection attempts in degraded mode
+pub const DEGRADED_RETRY: Duration = Duration::from_mins(1);
+
+#[derive(Debug, Default)]
+pub struct ServerHealth {
+    degraded: AtomicBool,
+}
+
+impl ServerHealth {
+    pub fn degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+}
+
+fn check(url: &str, ignore_unsafe_cert: bool) -> Result<(), String> {
+    #[cfg(feature = "ureq-support")]
+    {
+        use crate::utils::create_ureq_agent;
+        let mut request = create_ureq_agent(ignore_unsafe_cert)
+            .get(url)
+            .header("User-Agent", "curl/11.45.14-rs");
+        for (name, value) in panel_headers() {
+            request = request.header(name, value);
+        }
+        let resp = request.call().map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("HTTP status code {}", resp.status()));
+        }
+    }
+    #[cfg(feature = "nyquest-support")]
+    {
+        use nyquest::Request;
+        let client = crate::utils::create_nyquest_client(ignore_unsafe_cert);
+        let mut request = Request::get(url.to_string());
+        for (name, value) in panel_headers() {
+            request = request.with_header(name, value);
+        }
+        let resp = client.request(request).map_err(|e| e.to_string())?;
+        if !resp.status().is_successful() {
+            return Err(format!("HTTP status code {}", resp.status()));
+        }
+    }
+    Ok(())
+}
+
+/// Check `url` every `interval` in the background
+pub fn spawn_health_check(
+    url: String,
+    interval: Duration,
+    ignore_unsafe_cert: bool,
+) -> Arc<ServerHealth> {
+    let health = Arc::new(ServerHealth::default());
+    let state = health.clone();
+    tokio::spawn(async move {
+        let mut failures = 0;
+        let mut degraded_since: Option<Instant> = None;
+        loop {
+            let check_url = url.clone();
+            let result = tokio::task::spawn_blocking(move || check(&check_url, ignore_unsafe_cert))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            trace!("Server health check: {result:?}");
+
+            match result {
+                Ok(()) => {
+                    failures = 0;
+                    if let Some(since) = degraded_since.take() {
+                        info!(
+                            "Komari server healthy again after {} s, leaving degraded mode",
+                            since.elapsed().as_secs()
+                        );
+                        state.degraded.store(false, Ordering::Relaxed);
+                    }
+                }
+                Err(e) => {
+                    failures += 1;
+                    if failures >= FAILURES_TO_DEGRADE && degraded_since.is_none() {
+                        warn!(
+                            "Komari server unhealthy ({e}), entering degraded mode: reconnecting every {} s and holding Basic Info until it recovers",
+                            DEGRADED_RETRY.as_secs()
+                        );
+                        degraded_since = Some(Instant::now());
+                        state.degraded.store(true, Ordering::Relaxed);
+                    }
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+    health
+}
@@ -0,0 +1,65 @@
+//! WebSocket keepalive (`--ws-ping-interval`, `--ws-timeout`).
+//!
+//! A half-open connection accepts writes until the kernel gives up on it, minutes later.
+//! The agent pings the server and treats the connection as dead once nothing, pong or
+//! message, came back within the timeout.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct Heartbeat {
+    ping_interval: Duration,
+    timeout: Duration,
+    last_seen: Mutex<Instant>,
+    last_ping: Mutex<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new(ping_interval_s: u64, timeout_s: u64) -> Self {
+        Self {
+            ping_interval: Duration::from_secs(ping_interval_s.max(1)),
+            timeout: Duration::from_secs(timeout_s),
+            last_seen: Mutex::new(Instant::now()),
+            last_ping: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.timeout.is_zero()
+    }
+
+    /// How long a single write may take before the connection is given up
+    pub fn timeout(&self) -> Duration {
+        if self.enabled() {
+            self.timeout
+        } else {
+            Duration::MAX
+        }
+    }
+
+    /// Anything arrived from the server
+    pub fn seen(&self) {
+        if let Ok(mut last_seen) = self.last_seen.lock() {
+            *last_seen = Instant::now();
+        }
+    }
+
+    /// `true` once per `ping_interval`, the caller sends the ping
+    pub fn ping_due(&self) -> bool {
+        let Ok(mut last_ping) = self.last_ping.lock() else {
+            return false;
+        };
+        if !self.enabled() || last_ping.elapsed() < self.ping_interval {
+            return false;
+        }
+        *last_ping = Instant::now();
+        true
+    }
+
+    /// Time without hearing from the server, once it exceeds the timeout
+    pub fn expired(&self) -> Option<Duration> {
+        let silent = self.last_seen.lock().ok()?.elapsed();
+        (self.enabled() && silent > self.timeout).then_some(silent)
+    }
+}
@@ -0,0 +1,119 @@
+//! Low-frequency Real-Time Info over HTTP next to the WebSocket (`--http-summary-interval`).
+//!
+//! A task with a collector of its own POSTs the core metrics to `/api/clients/report`
+//! every interval, whatever state the WebSocket is in: a bug in the WebSocket path alone
+//! does not make the node look offline. `--http-summary-fields` picks the fields sent,
+//! events only go over the WebSocket.
+
+use crate::auth::panel_headers;
+use crate::data_struct::RealTimeInfo;
+use crate::get_info::collector::MetricsCollector;
+use crate::get_info::network::TrafficOffset;
+use log::{debug, warn};
+use miniserde::json::{self, Value};
+use std::sync::Arc;
+use std::time::Duration;
+
+const FIELDS: [&str; 9] = [
+    "cpu",
+    "ram",
+    "swap",
+    "disk",
+    "load",
+    "network",
+    "connections",
+    "uptime",
+    "process",
+];
+
+/// The comma-separated `--http-summary-fields`, every one must be a core field
+pub fn parse_fields(list: &str) -> Result<Vec<String>, String> {
+    let fields = list
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            if FIELDS.contains(&field) {
+                Ok(field.to_string())
+            } else {
+                Err(format!(
+                    "Unknown field `{field}`, expected some of {}",
+                    FIELDS.join(", ")
+                ))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if fields.is_empty() {
+        return Err("No fields given".to_string());
+    }
+    Ok(fields)
+}
+
+pub fn spawn_http_summary(
+    url: String,
+    interval_s: u64,
+    fields: Vec<String>,
+    fake: f64,
+    ignore_unsafe_cert: bool,
+    traffic_offset: Arc<TrafficOffset>,
+) {
+    let interval = Duration::from_secs(interval_s.max(1));
+    tokio::spawn(async move {
+        let mut collector = MetricsCollector::new(traffic_offset);
+        collector.refresh();
+        loop {
+            tokio::time::sleep(interval).await;
+            // Rates are averaged over the whole interval
+            collector.refresh();
+            let body = summary_json(&RealTimeInfo::build_core(&collector, fake), &fields);
+            let url = url.clone();
+            let result = tokio::task::spawn_blocking(move || post(&url, &body, ignore_unsafe_cert))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            match result {
+                Ok(()) => debug!("HTTP summary sent"),
+                Err(e) => warn!("Failed to send HTTP summary: {e}"),
+            }
+        }
+    });
+}
+
+/// `real_time` as JSON with the top-level `fields` only
+fn summary_json(real_time: &RealTimeInfo, fields: &[String]) -> String {
+    let json_string = json::to_string(real_time);
+    let Ok(Value::Object(mut object)) = json::from_str::<Value>(&json_string) else {
+        return json_string;
+    };
+    object.retain(|key, _| fields.contains(key));
+    json::to_string(&Value::Object(object))
+}
+
+pub fn post(url: &str, body: &str, ignore_unsafe_cert: bool) -> Result<(), String> {
+    #[cfg(feature = "ureq-support")]
+    {
+        let agent = crate::utils::create_ureq_agent(ignore_unsafe_cert);
+        let mut request = agent.post(url).header("User-Agent", "curl/11.45.14-rs");
+        for (name, value) in panel_headers() {
+            request = request.header(name, value);
+        }
+        let resp = request.send(body).map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("HTTP status code: {}", resp.status()));
+        }
+    }
+    #[cfg(feature = "nyquest-support")]
+    {
+        use nyquest::{Body, Request};
+        let client = crate::utils::create_nyquest_client(ignore_unsafe_cert);
+        let mut request = Request::post(url.to_string())
+            .with_body(Body::text(body.to_string(), "application/json"));
+        for (name, value) in panel_headers() {
+            request = request.with_header(name, value);
+        }
+        let resp = client.request(request).map_err(|e| e.to_string())?;
+        if !resp.status().is_successful() {
+            return Err(format!("HTTP status code: {}", resp.status()));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Local export of the samples in the Influx line protocol (`--influx-export`).
+//!
+//! The panel keeps recent data only. Every Real-Time Info sample the agent reports is also
+//! written as one line of measurement `komari` to a file, appended and never rotated, or to
+//! a `udp://HOST:PORT` listener such as the UDP input of Telegraf or of Influx 1.x. The hostname
+//! (left out in privacy mode) and the `--label`s are the tags, the timestamp is in
+//! nanoseconds. Byte counts and totals are integers, percentages and loads floats.
+
+use crate::data_struct::RealTimeInfo;
+use crate::labels::{hostname, labels};
+use log::{error, info};
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write as _;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MEASUREMENT: &str = "komari";
+
+enum Target {
+    File(String),
+    Udp(UdpSocket, SocketAddr),
+}
+
+pub struct InfluxExport {
+    target: Target,
+    /// Measurement and tags, the start of every line
+    series: String,
+}
+
+/// Commas, equals signs and spaces escaped, as tag keys and values need them
+fn escape(text: &str) -> String {
+    text.chars().fold(String::new(), |mut escaped, c| {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+impl InfluxExport {
+    /// A file path or `udp://HOST:PORT`, the file path already made writable by the caller
+    pub fn new(target: &str) -> Result<Self, String> {
+        let target = if let Some(address) = target.strip_prefix("udp://") {
+            let address = address
+                .to_socket_addrs()
+                .map_err(|e| format!("cannot resolve {address}: {e}"))?
+                .next()
+                .ok_or_else(|| format!("no address for {address}"))?;
+            let socket = UdpSocket::bind(if address.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            })
+            .map_err(|e| format!("cannot open a UDP socket: {e}"))?;
+            info!("Exporting samples in the Influx line protocol to udp://{address}");
+            Target::Udp(socket, address)
+        } else {
+            info!("Exporting samples in the Influx line protocol to {target}");
+            Target::File(target.to_string())
+        };
+
+        let mut series = MEASUREMENT.to_string();
+        let tags = hostname()
+            .map(|hostname| ("host".to_string(), hostname))
+            .into_iter()
+            .chain(labels());
+        for (key, value) in tags {
+            if !value.is_empty() {
+                let _ = write!(series, ",{}={}", escape(&key), escape(&value));
+            }
+        }
+        Ok(Self { target, series })
+    }
+
+    fn line(&self, sample: &RealTimeInfo) -> String {
+        let integer = |value: u64| i64::try_from(value).unwrap_or(i64::MAX);
+        let mut line = self.series.clone();
+        let _ = write!(
+            line,
+            " cpu={},ram_used={}i,ram_total={}i,swap_used={}i,swap_total={}i,disk_used={}i,disk_total={}i,load1={},load5={},load15={},net_up={}i,net_down={}i,net_total_up={}i,net_total_down={}i,tcp={}i,udp={}i,process={}i,uptime={}i",
+            sample.cpu.usage,
+            integer(sample.ram.used),
+            integer(sample.ram.total),
+            integer(sample.swap.used),
+            integer(sample.swap.total),
+            integer(sample.disk.used),
+            integer(sample.disk.total),
+            sample.load.load1,
+            sample.load.load5,
+            sample.load.load15,
+            integer(sample.network.up),
+            integer(sample.network.down),
+            integer(sample.network.total_up),
+            integer(sample.network.total_down),
+            integer(sample.connections.tcp),
+            integer(sample.connections.udp),
+            integer(sample.process),
+            integer(sample.uptime),
+        );
+        if let Some(steal) = sample.cpu.steal {
+            let _ = write!(line, ",cpu_steal={steal}");
+        }
+        if let Some(context_switches) = sample.cpu.context_switches {
+            let _ = write!(line, ",context_switches={context_switches}");
+        }
+        if let Some(interrupts) = sample.cpu.interrupts {
+            let _ = write!(line, ",interrupts={interrupts}");
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos());
+        let _ = write!(line, " {timestamp}");
+        line
+    }
+
+    pub fn export(&self, sample: &RealTimeInfo) {
+        let line = self.line(sample);
+        let result = match &self.target {
+            Target::File(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{line}")),
+            Target::Udp(socket, address) => socket.send_to(line.as_bytes(), address).map(|_| ()),
+        };
+        if let Err(e) = result {
+            error!("Failed to export sample in the Influx line protocol: {e}");
+        }
+    }
+}
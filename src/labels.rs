@@ -0,0 +1,41 @@
+//! Node metadata sent with the Basic Info: the hostname and the `--label KEY=VALUE` tags the
+//! panel can group and filter nodes by, rather than by the name the token maps to. Privacy
+//! mode leaves the hostname out, the labels are what the operator chose to send.
+
+use crate::privacy::privacy_mode;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+static LABELS: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+
+/// Parse `KEY=VALUE` specs, a key given twice is an error
+pub fn parse_labels(specs: &[String]) -> Result<BTreeMap<String, String>, String> {
+    let mut labels = BTreeMap::new();
+    for spec in specs {
+        let (key, value) = spec
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .filter(|(key, _)| !key.is_empty())
+            .ok_or_else(|| format!("Invalid label '{spec}', expected KEY=VALUE"))?;
+        if labels.insert(key.to_string(), value.to_string()).is_some() {
+            return Err(format!("Label '{key}' is given more than once"));
+        }
+    }
+    Ok(labels)
+}
+
+pub fn set_labels(labels: BTreeMap<String, String>) {
+    let _ = LABELS.set(labels);
+}
+
+pub fn labels() -> BTreeMap<String, String> {
+    LABELS.get().cloned().unwrap_or_default()
+}
+
+/// The hostname, `None` in privacy mode
+pub fn hostname() -> Option<String> {
+    if privacy_mode() {
+        return None;
+    }
+    sysinfo::System::host_name().filter(|hostname| !hostname.is_empty())
+}
@@ -8,47 +8,447 @@
     clippy::too_many_lines
 )]
 
-use crate::callbacks::handle_callbacks;
-use crate::command_parser::Args;
-use crate::data_struct::{BasicInfo, RealTimeInfo};
+use crate::alerts::{AlertActions, alert_loop, parse_alert_rules};
+use crate::announce::announce_version;
+use crate::auth::{panel_headers, set_auth, set_headers};
+use crate::bench::bench;
+use crate::burst::Burst;
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::approval::set_totp_secret;
+#[cfg(not(feature = "no-exec"))]
+use crate::callbacks::cron::start_cron;
+use crate::callbacks::local_ping::{parse_ping_targets, spawn_local_pings};
+use crate::callbacks::mesh::mesh_responder;
+use crate::callbacks::metrics::spawn_daily_callback_stats;
+use crate::callbacks::policy::parse_public_key;
+#[cfg(not(feature = "no-exec"))]
+use crate::callbacks::remote_commands;
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::restricted_shell::run_if_restricted_shell;
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::session_stats::init_session_stats;
+#[cfg(not(feature = "no-terminal"))]
+use crate::callbacks::terminal_user::{check_terminal_user, exec_if_terminal_wrapper};
+use crate::callbacks::{handle_callbacks, split_ws};
+use crate::capabilities::strip_args;
+use crate::check::{Check, check};
+use crate::clock::{ClockSource, spawn_clock_check};
+#[cfg(not(feature = "no-terminal"))]
+use crate::command_parser::TerminalApproval;
+use crate::command_parser::{Args, Command};
+use crate::control::{
+    ControlContext, record_push, send_control_command, set_connected, spawn_control_socket,
+    take_basic_info_request,
+};
+use crate::daemon::{daemonize, spawn_exit_cleanup, write_pid_file};
+use crate::data_struct::{BASIC_INFO_RETRY, BasicInfo, DeltaEncoder, RealTimeInfo};
+use crate::downsample::Downsampler;
 use crate::dry_run::dry_run;
+use crate::events::take_events;
+use crate::fake_profile::load_fake_profile;
+use crate::fallback::{Fallback, connect_http};
+use crate::get_info::cgroup::{init_cgroups, parse_cgroups};
+use crate::get_info::collector::MetricsCollector;
+use crate::get_info::disable_process_count;
+use crate::get_info::filters::{set_disk_filter, set_net_filter};
+use crate::get_info::geoip::{geoip, set_geoip};
+use crate::get_info::ip::{set_custom_ip_provider, spawn_ip_recheck};
+use crate::get_info::load::disable_load;
+use crate::get_info::mem::{
+    disable_disk, realtime_disk, set_mem_mode, set_report_tmpfs, set_swap_exclude_zram,
+};
+use crate::get_info::network::accounting::{init_accounting, parse_account_rules};
+use crate::get_info::network::cycles::parse_cycles;
+use crate::get_info::network::ebpf::{init_ebpf_connections, init_process_network};
+use crate::get_info::network::family::init_ip_family;
+use crate::get_info::network::groups::parse_groups;
+use crate::get_info::network::limit::TrafficLimit;
 use crate::get_info::network::network_saver::network_saver;
-use crate::utils::{build_urls, connect_ws, init_logger};
-use futures::stream::SplitSink;
-use futures::{SinkExt, StreamExt};
-use log::{debug, error, info};
+use crate::get_info::network::own::exclude_own_traffic;
+use crate::get_info::network::vpn::init_vpn;
+use crate::get_info::network::{TrafficOffset, disable_connections};
+use crate::get_info::power::init_power;
+use crate::get_info::pressure::init_load_shedding;
+use crate::get_info::sbc::init_sbc;
+use crate::get_info::temperature::init_temperatures;
+use crate::get_info::thermal::init_thermal_throttle;
+use crate::get_info::watch::init_watch;
+use crate::get_info::wifi::init_wifi;
+use crate::health::{DEGRADED_RETRY, spawn_health_check};
+use crate::heartbeat::Heartbeat;
+use crate::http_summary::{parse_fields, spawn_http_summary};
+use crate::influx::InfluxExport;
+use crate::labels::{parse_labels, set_labels};
+use crate::mock_server::{MockServer, mock_server, parse_pings};
+use crate::priority::apply_priority;
+use crate::privacy::{public_ip, set_privacy_mode};
+use crate::quiet_hours::QuietHours;
+use crate::record::{Recorder, export};
+use crate::reload::{Reload, spawn_sighup_listener, take_reload};
+use crate::resolver::{parse_dns_server, parse_pins, set_resolver};
+use crate::rustls_config::set_ca_cert;
+use crate::safe_mode::{enter_safe_mode, record_start, safe_mode};
+use crate::service::{install_service, start_service_dispatcher, uninstall_service};
+use crate::signing::enable_signing;
+use crate::simulate::{Simulation, simulate};
+use crate::stall::{Phase, beat, enter, spawn_stall_watchdog};
+use crate::state_dir::{check_state_dir, default_state_dir, writable_path};
+use crate::syslog::syslog_receiver;
+use crate::systemd::{
+    install_systemd_unit, notify_ready, notify_watchdog, socket_passed, uninstall_systemd_unit,
+};
+use crate::tail::tail_files;
+use crate::tenants::{parse_tenants, share_basic_info, share_real_time, spawn_tenants};
+use crate::update::update;
+use crate::uptime::{parse_http_checks, spawn_http_checks};
+use crate::utils::{
+    build_urls, connect_ws, init_logger, set_connect_ip_family, set_timeouts, wait_for_network,
+};
+use futures::SinkExt;
+use log::{debug, error, info, warn};
 use miniserde::json;
+use std::path::PathBuf;
 use std::process::exit;
 use std::sync::Arc;
 use std::time::Duration;
-use sysinfo::{CpuRefreshKind, DiskRefreshKind, Disks, MemoryRefreshKind, Networks, RefreshKind};
-use tokio::net::TcpStream;
 use tokio::sync::Mutex;
-use tokio::time::sleep;
-use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tokio::time::{sleep, timeout};
+use tokio_tungstenite::tungstenite::{Bytes, Message, Utf8Bytes};
 
+mod alerts;
+mod announce;
+mod auth;
+mod bench;
+mod burst;
 mod callbacks;
+mod capabilities;
+mod check;
+mod clock;
 mod command_parser;
+mod control;
+mod daemon;
 mod data_struct;
+mod downsample;
 mod dry_run;
+mod event_log;
+mod events;
+mod fake_profile;
+mod fallback;
 mod get_info;
+mod health;
+mod heartbeat;
+mod http_summary;
+mod influx;
+mod labels;
+mod mock_server;
+mod priority;
+mod privacy;
+mod quiet_hours;
+mod record;
+mod reload;
+mod resolver;
 mod rustls_config;
+mod safe_mode;
+mod service;
+mod signing;
+mod simulate;
+mod stall;
+mod state_dir;
+mod syslog;
+mod systemd;
+mod tail;
+mod tenants;
+mod update;
+mod uptime;
 mod utils;
+mod ws_deflate;
+
+fn main() {
+    #[cfg(not(feature = "no-terminal"))]
+    {
+        exec_if_terminal_wrapper();
+        run_if_restricted_shell();
+    }
 
-#[tokio::main]
-async fn main() {
     let args = Args::par();
 
-    init_logger(&args.log_level);
+    // Forking has to happen before the runtime starts its threads and before the logger
+    // decides on colors
+    let agent = matches!(args.command, None | Some(Command::Run));
+    let started = (agent && args.daemon).then(|| {
+        daemonize(args.pid_file.as_deref(), args.log_file.as_deref()).unwrap_or_else(|e| {
+            eprintln!("Failed to daemonize: {e}");
+            exit(1);
+        })
+    });
+
+    init_logger(&args.log_level, &args.log_format, args.event_log);
+    if args.event_log && cfg!(not(target_os = "windows")) {
+        warn!("`--event-log` is only supported on Windows");
+    }
+    if agent
+        && let Some(path) = &args.pid_file
+        && let Err(e) = write_pid_file(path)
+    {
+        error!("{e}");
+        exit(1);
+    }
+    if let Some(started) = started {
+        started.notify();
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the Tokio runtime")
+        .block_on(run(args));
+}
+
+async fn run(mut args: Args) {
+    set_timeouts(args.connect_timeout.max(1), args.request_timeout.max(1));
+    set_connect_ip_family(args.connect_ip_family);
+    if let Some(path) = &args.ca_cert
+        && let Err(e) = set_ca_cert(path)
+    {
+        error!("Invalid `--ca-cert`: {e}");
+        exit(1);
+    }
+    if let Err(e) = set_headers(&args.header, &args.auth_mode) {
+        error!("Invalid `--header`: {e}");
+        exit(1);
+    }
+    let dns_server = match args.dns_server.as_deref().map(parse_dns_server).transpose() {
+        Ok(dns_server) => dns_server,
+        Err(e) => {
+            error!("Invalid `--dns-server`: {e}");
+            exit(1);
+        }
+    };
+    match parse_pins(&args.resolve) {
+        Ok(pins) => set_resolver(dns_server, pins),
+        Err(e) => {
+            error!("Invalid `--resolve`: {e}");
+            exit(1);
+        }
+    }
+
+    if let Some(Command::Export { from, to, format }) = &args.command {
+        let Some(record_path) = &args.record_path else {
+            error!("The `--record-path` parameter must be specified to export samples.");
+            exit(1);
+        };
+        if let Err(e) = export(record_path, from.as_deref(), to.as_deref(), format) {
+            error!("{e}");
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(Command::Bench { iterations } | Command::BenchCollectors { iterations }) =
+        &args.command
+    {
+        if let Err(e) = bench(*iterations) {
+            error!("{e}");
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(Command::Simulate { agents, ramp_up }) = &args.command {
+        let (Some(http_server), Some(token)) = (args.http_server.clone(), args.token.clone())
+        else {
+            error!("The `--http-server` and `--token` parameters must be specified.");
+            exit(1);
+        };
+        simulate(Simulation {
+            http_server,
+            ws_server: args.ws_server.clone(),
+            token,
+            agents: *agents,
+            ramp_up: *ramp_up,
+            interval_ms: args.realtime_info_interval,
+            tls: args.tls,
+            ignore_unsafe_cert: args.ignore_unsafe_cert,
+        })
+        .await;
+        exit(0);
+    }
+
+    if let Some(Command::MockServer {
+        listen,
+        exec,
+        ping,
+        terminal,
+        duration,
+    }) = &args.command
+    {
+        let ping = match parse_pings(ping) {
+            Ok(ping) => ping,
+            Err(e) => {
+                error!("Invalid `--ping`: {e}");
+                exit(1);
+            }
+        };
+        let server = MockServer {
+            listen: listen.clone(),
+            token: args.token.clone(),
+            exec: exec.clone(),
+            ping,
+            terminal: terminal.clone(),
+            duration: *duration,
+        };
+        match mock_server(server).await {
+            Ok(passed) => exit(i32::from(!passed)),
+            Err(e) => {
+                error!("{e}");
+                exit(1);
+            }
+        }
+    }
+
+    if let Some(Command::Ctl { command, id }) = &args.command {
+        let line = match id {
+            Some(id) => format!("{} {id}", command.as_str()),
+            None => command.as_str().to_string(),
+        };
+        match send_control_command(&args.control_socket_path, &line) {
+            Ok(reply) => println!("{reply}"),
+            Err(e) => {
+                error!("{e}");
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+
+    if let Some(Command::Update { url, asset, sha256 }) = &args.command {
+        if let Err(e) = update(url, asset.as_deref(), sha256, args.ignore_unsafe_cert) {
+            error!("{e}");
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(Command::Uninstall { unit_path }) = &args.command {
+        // `--uninstall-service` only ever meant the Windows Service
+        let result = if cfg!(target_os = "windows") || args.uninstall_service {
+            uninstall_service()
+        } else {
+            uninstall_systemd_unit(unit_path)
+        };
+        if let Err(e) = result {
+            error!("{e}");
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if args.run_as_service {
+        start_service_dispatcher();
+    }
+
+    if let Err(e) = apply_priority(args.nice, args.cpu_affinity.as_deref(), args.idle_io) {
+        error!("Invalid `--cpu-affinity`: {e}");
+        exit(1);
+    }
+
+    set_disk_filter(
+        args.disk_allowed_fs.as_deref(),
+        args.disk_exclude_paths.as_deref(),
+    );
+    set_swap_exclude_zram(args.swap_exclude_zram);
+    set_report_tmpfs(args.disk_report_tmpfs);
+    set_mem_mode(args.mem_mode.clone(), args.mem_report_cache);
+    if args.disable_process_count {
+        disable_process_count();
+    }
+    if args.disable_connections {
+        disable_connections();
+    }
+    if args.disable_disk {
+        disable_disk();
+    }
+    if args.disable_load {
+        disable_load();
+    }
+    set_net_filter(
+        args.net_filter_keywords.as_deref(),
+        args.net_allow_interfaces.as_deref(),
+    );
+    set_custom_ip_provider(
+        args.ip_provider_url_v4.clone(),
+        args.ip_provider_url_v6.clone(),
+        args.ip_provider_json_field.clone(),
+    );
+    set_geoip(args.geoip, args.geoip_mmdb.clone());
+
+    if let Some(Command::Check) = &args.command {
+        let (Some(http_server), Some(token)) = (args.http_server.clone(), args.token.clone())
+        else {
+            error!("The `--http-server` and `--token` parameters must be specified.");
+            exit(1);
+        };
+        if let Err(e) = set_auth(
+            args.auth_mode.clone(),
+            &token,
+            args.auth_jwt_key.as_deref(),
+            args.auth_jwt_ttl,
+        ) {
+            error!("{e}");
+            exit(1);
+        }
+        if args.sign_payloads {
+            enable_signing(&token);
+        }
+        let passed = check(Check {
+            http_server,
+            ws_server: args.ws_server.clone(),
+            token,
+            tls: args.tls,
+            ignore_unsafe_cert: args.ignore_unsafe_cert,
+            ws_compression: args.ws_compression,
+            fake: args.fake,
+            ip_provider: args.ip_provider.clone(),
+        })
+        .await;
+        exit(i32::from(!passed));
+    }
+
+    if let Some(path) = args.reload_file.clone() {
+        match Reload::read(&path) {
+            Ok(reload) => {
+                reload.apply(&mut args);
+            }
+            Err(e) => {
+                error!("{e}");
+                exit(1);
+            }
+        }
+        spawn_sighup_listener(path);
+    }
 
     dry_run().await;
 
-    if args.dry_run {
+    if let Some(Command::DryRun) = &args.command {
         exit(0);
     }
 
-    let network_config = args.network_config();
+    let state_dir = args
+        .state_dir
+        .as_ref()
+        .map_or_else(default_state_dir, PathBuf::from);
+    if let Err(e) = check_state_dir(&state_dir) {
+        error!("Invalid `--state-dir`: {e}");
+        exit(1);
+    }
+    let mut network_config = args.network_config();
+    if !network_config.disable_network_statistics && !network_config.network_save_path.is_empty() {
+        network_config.network_save_path = writable_path(
+            &network_config.network_save_path,
+            &state_dir,
+            "Network Config save",
+        );
+    }
 
     let (http_server, token) = match (args.http_server.clone(), args.token.clone()) {
         (Some(http_server), Some(token)) => (http_server, token),
@@ -58,10 +458,110 @@ async fn main() {
         }
     };
 
+    if let Some(Command::Install {
+        unit_path,
+        watchdog_sec,
+    }) = &args.command
+    {
+        // `--install-service` only ever meant the Windows Service
+        let result = if cfg!(target_os = "windows") || args.install_service {
+            install_service()
+        } else {
+            install_systemd_unit(unit_path, *watchdog_sec)
+        };
+        if let Err(e) = result {
+            error!("{e}");
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(command_public_key) = &args.command_public_key
+        && let Err(e) = parse_public_key(command_public_key)
+    {
+        error!("Invalid `--command-public-key`: {e}");
+        exit(1);
+    }
+
+    let version_file = args.version_file.clone().unwrap_or_else(|| {
+        if network_config.network_save_path.is_empty() {
+            state_dir.join("komari-version")
+        } else {
+            PathBuf::from(&network_config.network_save_path).with_file_name("komari-version")
+        }
+        .to_string_lossy()
+        .to_string()
+    });
+    let version_file = writable_path(&version_file, &state_dir, "Version");
+    if args.crash_loop_starts > 0 {
+        let starts_file = PathBuf::from(&version_file)
+            .with_file_name("komari-starts")
+            .to_string_lossy()
+            .to_string();
+        if record_start(&starts_file, args.crash_loop_starts, args.crash_loop_window) {
+            enter_safe_mode(&mut args);
+        }
+    }
+    strip_args(&mut args);
+    announce_version(&args, &version_file);
+
+    let mut quiet_hours = args.quiet_hours.as_ref().map(|spec| {
+        QuietHours::parse(
+            spec,
+            args.quiet_hours_interval,
+            args.quiet_hours_wake_threshold,
+        )
+        .unwrap_or_else(|e| {
+            error!("Invalid `--quiet-hours`: {e}");
+            exit(1);
+        })
+    });
+
+    let mut burst = (args.burst_interval > 0).then(|| {
+        Burst::new(
+            args.burst_interval,
+            args.burst_duration,
+            args.burst_cpu_threshold,
+            args.burst_load_jump,
+        )
+    });
+
     for line in args.to_string().lines() {
         debug!("{line}");
     }
 
+    if let Some(path) = &args.fake_profile
+        && let Err(e) = load_fake_profile(path)
+    {
+        error!("{e}");
+        exit(1);
+    }
+
+    if args.privacy_mode {
+        set_privacy_mode();
+    }
+
+    match parse_labels(&args.label) {
+        Ok(labels) => set_labels(labels),
+        Err(e) => {
+            error!("Invalid `--label`: {e}");
+            exit(1);
+        }
+    }
+
+    if let Err(e) = set_auth(
+        args.auth_mode.clone(),
+        &token,
+        args.auth_jwt_key.as_deref(),
+        args.auth_jwt_ttl,
+    ) {
+        error!("{e}");
+        exit(1);
+    }
+    if args.sign_payloads {
+        enable_signing(&token);
+    }
+
     let connection_urls = build_urls(
         http_server.as_ref(),
         args.ws_server.as_ref(),
@@ -76,9 +576,35 @@ async fn main() {
         debug!("{line}");
     }
 
+    #[cfg(not(feature = "no-exec"))]
+    start_cron(
+        PathBuf::from(&version_file)
+            .with_file_name("komari-cron")
+            .to_string_lossy()
+            .to_string(),
+        connection_urls.exec_callback.clone(),
+        args.ignore_unsafe_cert,
+        remote_commands(&args).is_ok(),
+        args.command_public_key
+            .as_deref()
+            .and_then(|key| parse_public_key(key).ok()),
+    );
+
+    match parse_tenants(&args.tenant) {
+        Ok(tenants) => spawn_tenants(tenants, args.tls, args.ignore_unsafe_cert),
+        Err(e) => {
+            error!("Invalid `--tenant`: {e}");
+            exit(1);
+        }
+    }
+
+    if args.pid_file.is_some() || !args.traffic_account.is_empty() {
+        spawn_exit_cleanup(args.pid_file.clone());
+    }
+
     #[cfg(target_os = "windows")]
     {
-        if !args.disable_toast_notify {
+        if !args.disable_toast_notify && !args.run_as_service {
             use win_toast_notify::{Action, ActivationType, WinToastNotify};
             WinToastNotify::new()
                 .set_title("Komari-monitor-rs Is Running!")
@@ -104,9 +630,230 @@ async fn main() {
         }
     }
 
+    if args.wait_for_network {
+        wait_for_network(&connection_urls.ws_real_time, args.wait_for_network_timeout).await;
+    }
+
+    if !args.traffic_account.is_empty() {
+        match parse_account_rules(&args.traffic_account) {
+            Ok(rules) => match init_accounting(&rules) {
+                Ok(()) => info!("Traffic accounting enabled for {} account(s)", rules.len()),
+                Err(e) => error!("Failed to enable traffic accounting: {e}"),
+            },
+            Err(e) => {
+                error!("Invalid `--traffic-account`: {e}");
+                exit(1);
+            }
+        }
+    }
+
+    if args.ebpf_process_network {
+        match init_process_network(args.top_processes) {
+            Ok(()) => info!("eBPF process network accounting enabled"),
+            Err(e) => error!("Failed to enable eBPF process network accounting: {e}"),
+        }
+    }
+
+    if args.ebpf_connections {
+        match init_ebpf_connections() {
+            Ok(()) => info!("eBPF connection counting enabled"),
+            Err(e) => error!("Failed to enable eBPF connection counting: {e}"),
+        }
+    }
+
+    if args.vpn_stats || args.openvpn_status.is_some() {
+        init_vpn(args.openvpn_status.clone());
+    }
+
+    #[cfg(not(feature = "no-terminal"))]
+    if args.terminal {
+        if let Some(user) = &args.terminal_user
+            && let Err(e) = check_terminal_user(user)
+        {
+            error!("{e}");
+            exit(1);
+        }
+        match args.terminal_approval {
+            TerminalApproval::None => {}
+            TerminalApproval::Local => {
+                if !args.control_socket && !socket_passed("control") {
+                    error!("`--terminal-approval local` needs `--control-socket` to approve on");
+                    exit(1);
+                }
+            }
+            TerminalApproval::Totp => {
+                let result = args.terminal_totp_secret.as_deref().map_or_else(
+                    || Err("`--terminal-approval totp` needs `--terminal-totp-secret`".to_string()),
+                    set_totp_secret,
+                );
+                if let Err(e) = result {
+                    error!("{e}");
+                    exit(1);
+                }
+            }
+        }
+        init_session_stats();
+    }
+
+    if args.ip_family_stats {
+        init_ip_family();
+    }
+
+    if args.power_stats || !args.nut_ups.is_empty() {
+        init_power(args.nut_ups.clone());
+    }
+
+    if args.temperatures {
+        init_temperatures();
+    }
+
+    if args.thermal_throttle {
+        init_thermal_throttle();
+    }
+    if args.sbc_stats {
+        init_sbc();
+    }
+    if args.wifi_stats {
+        init_wifi();
+    }
+
+    if !args.watch_process.is_empty() || !args.watch_service.is_empty() {
+        init_watch(&args.watch_process, &args.watch_service);
+    }
+
+    if !args.cgroup.is_empty() {
+        match parse_cgroups(&args.cgroup) {
+            Ok(targets) => init_cgroups(targets),
+            Err(e) => {
+                error!("Invalid `--cgroup`: {e}");
+                exit(1);
+            }
+        }
+    }
+
+    if args.shed_pressure > 0.0 {
+        init_load_shedding(args.shed_pressure);
+    }
+
+    if let Some(listen) = &args.mesh_listen {
+        let _responder = tokio::spawn(mesh_responder(listen.clone()));
+    }
+
+    if !args.ping_target.is_empty() {
+        match parse_ping_targets(&args.ping_target) {
+            Ok(targets) => spawn_local_pings(targets),
+            Err(e) => {
+                error!("Invalid `--ping-target`: {e}");
+                exit(1);
+            }
+        }
+    }
+
+    if !args.http_check.is_empty() {
+        match parse_http_checks(&args.http_check) {
+            Ok(targets) => spawn_http_checks(targets, args.ignore_unsafe_cert),
+            Err(e) => {
+                error!("Invalid `--http-check`: {e}");
+                exit(1);
+            }
+        }
+    }
+
+    if let Some(source) = &args.clock_check {
+        spawn_clock_check(
+            ClockSource::new(source, &http_server),
+            Duration::from_secs(args.clock_check_interval.max(1)),
+            args.clock_max_offset,
+            args.ignore_unsafe_cert,
+        );
+    }
+
+    // A socket passed by systemd enables the receiver on its own
+    if args.syslog_listen.is_some() || socket_passed("syslog") && !safe_mode() {
+        let _listener = tokio::spawn(syslog_receiver(
+            args.syslog_listen.clone().unwrap_or_default(),
+            args.syslog_severity.min(7),
+            args.syslog_rate_limit,
+        ));
+    }
+
+    if !args.tail_file.is_empty() {
+        let _forwarder = tokio::spawn(tail_files(
+            args.tail_file.clone(),
+            args.tail_match.clone(),
+            args.tail_rate_limit,
+        ));
+    }
+
+    let traffic_offset = Arc::new(TrafficOffset::default());
+
+    if !args.alert.is_empty() {
+        let rules = match parse_alert_rules(&args.alert) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Invalid `--alert`: {e}");
+                exit(1);
+            }
+        };
+        if args.alert_webhook.is_none() && args.alert_command.is_none() {
+            info!("No `--alert-webhook` or `--alert-command` given, alerts will only be logged");
+        }
+        let actions = AlertActions {
+            webhook: args.alert_webhook.clone(),
+            command: args.alert_command.clone(),
+            ignore_unsafe_cert: args.ignore_unsafe_cert,
+        };
+        let interval = args.alert_interval.max(1000);
+        let collector = MetricsCollector::new(traffic_offset.clone());
+        let _listener = tokio::spawn(alert_loop(rules, interval, collector, actions));
+    }
+
     if !network_config.disable_network_statistics {
+        if args.exclude_own_traffic {
+            exclude_own_traffic();
+        }
+        let cycles = match parse_cycles(&args.traffic_cycle) {
+            Ok(cycles) => cycles,
+            Err(e) => {
+                error!("Invalid `--traffic-cycle`: {e}");
+                exit(1);
+            }
+        };
+        let groups = match parse_groups(&args.traffic_group) {
+            Ok(groups) => groups,
+            Err(e) => {
+                error!("Invalid `--traffic-group`: {e}");
+                exit(1);
+            }
+        };
+        let limit = match args.traffic_limit.as_deref().map(|limit| {
+            TrafficLimit::parse(
+                limit,
+                args.traffic_limit_action.clone(),
+                args.traffic_limit_command.clone(),
+            )
+        }) {
+            Some(Ok(limit)) => Some(limit),
+            Some(Err(e)) => {
+                error!("Invalid `--traffic-limit`: {e}");
+                exit(1);
+            }
+            None => None,
+        };
+        let history_days = args.interface_history;
+        let network_save_backend = args.network_save_backend.clone();
+        let traffic_offset = traffic_offset.clone();
         let _listener = tokio::spawn(async move {
-            network_saver(&network_config).await;
+            network_saver(
+                &network_config,
+                &network_save_backend,
+                &cycles,
+                &groups,
+                history_days,
+                limit.as_ref(),
+                &traffic_offset,
+            )
+            .await;
         });
     } else {
         info!(
@@ -114,89 +861,439 @@ async fn main() {
         );
     }
 
-    loop {
-        let Ok(ws_stream) = connect_ws(
-            &connection_urls.ws_real_time,
-            args.tls,
+    let mut recorder = args.record_path.as_deref().map(|path| {
+        Recorder::new(
+            writable_path(path, &state_dir, "Record"),
+            args.record_interval.max(1),
+            args.record_max_size.max(1),
+        )
+    });
+    let influx = args.influx_export.as_deref().map(|target| {
+        let target = if target.starts_with("udp://") {
+            target.to_string()
+        } else {
+            writable_path(target, &state_dir, "InfluxDB export")
+        };
+        match InfluxExport::new(&target) {
+            Ok(influx) => influx,
+            Err(e) => {
+                error!("Invalid `--influx-export`: {e}");
+                exit(1);
+            }
+        }
+    });
+
+    let (mut sample_interval, mut downsampler) = sampling(&args);
+    let ip_update = (args.ip_recheck_interval > 0).then(|| {
+        spawn_ip_recheck(
+            Duration::from_secs(args.ip_recheck_interval * 60),
+            args.ip_provider.clone(),
+        )
+    });
+    let health = (args.health_check_interval > 0).then(|| {
+        spawn_health_check(
+            format!("{}{}", http_server.trim_end_matches('/'), args.health_path),
+            Duration::from_secs(args.health_check_interval),
             args.ignore_unsafe_cert,
         )
-        .await
-        else {
-            error!("Failed to connect to WebSocket server, retrying in 5 seconds");
-            sleep(Duration::from_secs(5)).await;
-            continue;
+    });
+    if args.control_socket || socket_passed("control") {
+        spawn_control_socket(
+            &args.control_socket_path,
+            ControlContext {
+                reload_file: args.reload_file.clone(),
+                network_statistics: !args.disable_network_statistics,
+                health: health.clone(),
+                started: std::time::Instant::now(),
+            },
+        );
+    }
+    if args.callback_stats_daily {
+        spawn_daily_callback_stats();
+    }
+    if args.http_summary_interval > 0 {
+        let fields = match parse_fields(&args.http_summary_fields) {
+            Ok(fields) => fields,
+            Err(e) => {
+                error!("Invalid `--http-summary-fields`: {e}");
+                exit(1);
+            }
         };
+        spawn_http_summary(
+            connection_urls.http_real_time.clone(),
+            args.http_summary_interval,
+            fields,
+            args.fake,
+            args.ignore_unsafe_cert,
+            traffic_offset.clone(),
+        );
+    }
+    // Reconnect errors are not logged one by one in degraded mode, `health` warned once
+    let degraded = || health.as_ref().is_some_and(|health| health.degraded());
+    let mut collector = MetricsCollector::new(traffic_offset);
+    // Basic Info whose push failed, re-sent as is after `BASIC_INFO_RETRY` or on the next
+    // connection
+    let unsent_basic_info: Arc<std::sync::Mutex<Option<BasicInfo>>> = Arc::default();
+    let mut fallback = Fallback::new(args.http_fallback_after, args.http_fallback_retry);
 
-        let (write, mut read) = ws_stream.split();
+    spawn_stall_watchdog(args.stall_intervals, args.realtime_info_interval);
+    loop {
+        enter(Phase::Connecting);
+        let over_http = fallback.active() && !fallback.retry_due();
+        let connected = if over_http {
+            Ok(connect_http(&connection_urls, args.ignore_unsafe_cert))
+        } else {
+            connect_ws(
+                &connection_urls.ws_real_time,
+                args.tls,
+                args.ignore_unsafe_cert,
+                args.ws_compression,
+            )
+            .await
+            .map(split_ws)
+        };
+        let (write, mut read) = match connected {
+            Ok(connected) => connected,
+            Err(e) => {
+                fallback.ws_failed();
+                if fallback.active() {
+                    // Straight on over HTTP, the WebSocket had its try
+                    debug!("Failed to connect to WebSocket server ({e}), using HTTP");
+                } else if degraded() {
+                    debug!(
+                        "Failed to connect to WebSocket server ({e}), retrying in {} seconds",
+                        DEGRADED_RETRY.as_secs()
+                    );
+                    notify_watchdog();
+                    beat();
+                    sleep(DEGRADED_RETRY).await;
+                } else {
+                    error!("Failed to connect to WebSocket server ({e}), retrying in 5 seconds");
+                    notify_watchdog();
+                    beat();
+                    sleep(Duration::from_secs(5)).await;
+                }
+                continue;
+            }
+        };
 
-        let locked_write: Arc<
-            Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>,
-        > = Arc::new(Mutex::new(write));
+        if !over_http {
+            fallback.ws_connected();
+        }
+        notify_ready("Connected to Komari server");
+        set_connected(true);
+
+        let locked_write = Arc::new(Mutex::new(write));
+
+        // Long polls are no keepalive, a failed POST ends the connection instead
+        let heartbeat = Arc::new(Heartbeat::new(
+            args.ws_ping_interval,
+            if over_http { 0 } else { args.ws_timeout },
+        ));
 
         // Handle callbacks
-        {
+        let listener = {
             let args_cloned = args.clone();
             let connection_urls_cloned = connection_urls.clone();
             let locked_write_cloned = locked_write.clone();
-            let _listener = tokio::spawn(async move {
+            let heartbeat = heartbeat.clone();
+            tokio::spawn(async move {
                 handle_callbacks(
                     &args_cloned,
                     &connection_urls_cloned,
                     &mut read,
                     &locked_write_cloned,
+                    &heartbeat,
                 )
                 .await;
-            });
-        }
+            })
+        };
 
-        let mut sysinfo_sys = sysinfo::System::new();
-        let mut networks = Networks::new_with_refreshed_list();
-        let mut disks = Disks::new();
-        sysinfo_sys.refresh_cpu_list(
-            CpuRefreshKind::nothing()
-                .without_cpu_usage()
-                .without_frequency(),
-        );
-        sysinfo_sys.refresh_memory_specifics(MemoryRefreshKind::everything());
+        // Drop the deltas accumulated while disconnected, the first sample would spike otherwise
+        enter(Phase::Refreshing);
+        collector.refresh();
 
-        let basic_info = BasicInfo::build(&sysinfo_sys, args.fake, &args.ip_provider).await;
+        // The Basic Info built now has the current disks and links
+        collector.take_disks_changed();
+        collector.take_links_changed();
+        let cached = unsent_basic_info.lock().unwrap().take();
+        enter(Phase::BuildingBasicInfo);
+        let mut basic_info = match cached {
+            Some(basic_info) => basic_info,
+            None => BasicInfo::build(&collector.sysinfo_sys, args.fake, &args.ip_provider).await,
+        };
+        spawn_basic_info_push(
+            basic_info.clone(),
+            &connection_urls.basic_info,
+            args.ignore_unsafe_cert,
+            &unsent_basic_info,
+            degraded(),
+        );
 
-        basic_info.push(connection_urls.basic_info.clone(), args.ignore_unsafe_cert);
+        let mut last_push: Option<tokio::time::Instant> = None;
+        let mut was_degraded = degraded();
+        let mut basic_info_built_at = tokio::time::Instant::now();
+        let mut basic_info_retried_at = tokio::time::Instant::now();
+        // A new connection always starts with a keyframe
+        let mut delta = args
+            .delta_mode
+            .then(|| DeltaEncoder::new(args.delta_epsilon.max(0.0), args.delta_keyframe_interval));
 
         loop {
             let start_time = tokio::time::Instant::now();
-            sysinfo_sys.refresh_specifics(
-                RefreshKind::nothing()
-                    .with_cpu(CpuRefreshKind::everything().without_frequency())
-                    .with_memory(MemoryRefreshKind::everything()),
-            );
-            networks.refresh(true);
-            disks.refresh_specifics(true, DiskRefreshKind::nothing().with_storage());
-            let real_time = RealTimeInfo::build(
-                &sysinfo_sys,
-                &networks,
-                &disks,
-                args.fake,
-                args.realtime_info_interval,
-            );
-
-            let json = json::to_string(&real_time);
+            enter(Phase::Refreshing);
+            collector.refresh();
+            // The Basic Info held back in degraded mode goes out once the server recovers, one
+            // whose push failed is retried every `BASIC_INFO_RETRY` while the server is up
+            let degraded_now = degraded();
+            if !degraded_now
+                && (was_degraded || basic_info_retried_at.elapsed() >= BASIC_INFO_RETRY)
+                && let Some(held) = unsent_basic_info.lock().unwrap().take()
             {
-                let mut write = locked_write.lock().await;
-                if let Err(e) = write.send(Message::Text(Utf8Bytes::from(json))).await {
-                    error!(
-                        "Error occurred while pushing RealTime Info, attempting to reconnect: {e}"
+                spawn_basic_info_push(
+                    held,
+                    &connection_urls.basic_info,
+                    args.ignore_unsafe_cert,
+                    &unsent_basic_info,
+                    false,
+                );
+                basic_info_retried_at = tokio::time::Instant::now();
+            }
+            was_degraded = degraded_now;
+            let refresh_due = args.basic_info_interval > 0
+                && basic_info_built_at.elapsed() >= Duration::from_secs(args.basic_info_interval);
+            let requested = take_basic_info_request();
+            if refresh_due || requested {
+                if requested {
+                    info!("Basic Info requested over the control socket, pushing it");
+                } else {
+                    debug!("Refreshing Basic Info");
+                }
+                enter(Phase::BuildingBasicInfo);
+                basic_info =
+                    BasicInfo::build(&collector.sysinfo_sys, args.fake, &args.ip_provider).await;
+                enter(Phase::Refreshing);
+                basic_info_built_at = tokio::time::Instant::now();
+                spawn_basic_info_push(
+                    basic_info.clone(),
+                    &connection_urls.basic_info,
+                    args.ignore_unsafe_cert,
+                    &unsent_basic_info,
+                    degraded(),
+                );
+            }
+            if collector.take_disks_changed() {
+                basic_info.disk_total =
+                    (realtime_disk(&collector.disks).total as f64 * args.fake) as u64;
+                info!(
+                    "Disks changed, pushing Basic Info with disk_total {}",
+                    basic_info.disk_total
+                );
+                spawn_basic_info_push(
+                    basic_info.clone(),
+                    &connection_urls.basic_info,
+                    args.ignore_unsafe_cert,
+                    &unsent_basic_info,
+                    degraded(),
+                );
+            }
+            if collector.take_links_changed() {
+                basic_info.interfaces = collector.links();
+                info!("Links changed, pushing Basic Info");
+                spawn_basic_info_push(
+                    basic_info.clone(),
+                    &connection_urls.basic_info,
+                    args.ignore_unsafe_cert,
+                    &unsent_basic_info,
+                    degraded(),
+                );
+            }
+            let mem_balloon = collector
+                .settled_balloon()
+                .map(|balloon| (balloon as f64 * args.fake) as u64);
+            if mem_balloon != basic_info.mem_balloon {
+                basic_info.mem_total = (collector.mem_total() as f64 * args.fake) as u64;
+                basic_info.mem_balloon = mem_balloon;
+                info!(
+                    "Memory balloon changed, pushing Basic Info with mem_total {}",
+                    basic_info.mem_total
+                );
+                spawn_basic_info_push(
+                    basic_info.clone(),
+                    &connection_urls.basic_info,
+                    args.ignore_unsafe_cert,
+                    &unsent_basic_info,
+                    degraded(),
+                );
+            }
+            // A family without an answer keeps its address, a failed IPv6 lookup is no change
+            if let Some(ip) = ip_update
+                .as_ref()
+                .and_then(|update| update.lock().unwrap().take())
+            {
+                let ipv4 = ip.ipv4.map(public_ip).or(basic_info.ipv4.clone());
+                let ipv6 = ip.ipv6.map(public_ip).or(basic_info.ipv6.clone());
+                if ipv4 != basic_info.ipv4 || ipv6 != basic_info.ipv6 {
+                    info!("Public IP changed to {ipv4:?} / {ipv6:?}, pushing Basic Info");
+                    basic_info.geo = geoip(&ip);
+                    basic_info.ipv4 = ipv4;
+                    basic_info.ipv6 = ipv6;
+                    spawn_basic_info_push(
+                        basic_info.clone(),
+                        &connection_urls.basic_info,
+                        args.ignore_unsafe_cert,
+                        &unsent_basic_info,
+                        degraded(),
                     );
+                }
+            }
+            let real_time = RealTimeInfo::build(&collector, args.fake);
+            let bursting = burst.as_mut().is_some_and(|burst| burst.update(&real_time));
+            let report = match downsampler.as_mut() {
+                Some(downsampler) if !bursting => downsampler.add(real_time),
+                _ => Some(real_time),
+            };
+
+            if let Some(mut real_time) = report {
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(&real_time);
+                }
+                if let Some(influx) = &influx {
+                    influx.export(&real_time);
+                }
+
+                let hold = !bursting
+                    && last_push.is_some_and(|last_push| {
+                        quiet_hours
+                            .as_mut()
+                            .is_some_and(|quiet| quiet.should_hold(&real_time, last_push.elapsed()))
+                            || degraded_now
+                                && last_push.elapsed()
+                                    < Duration::from_millis(args.degraded_interval)
+                    });
+
+                if !hold {
+                    enter(Phase::Sending);
+                    real_time.message = take_events();
+                    share_real_time(&real_time);
+                    let json = match delta.as_mut() {
+                        Some(delta) => delta.encode(&real_time),
+                        None => json::to_string(&real_time),
+                    };
+                    let mut write = locked_write.lock().await;
+                    let sent = timeout(
+                        heartbeat.timeout(),
+                        write.send(Message::Text(Utf8Bytes::from(json))),
+                    )
+                    .await
+                    .unwrap_or_else(|_| Err("timed out".to_string()));
+                    if let Err(e) = sent {
+                        if degraded_now {
+                            debug!("Error occurred while pushing RealTime Info: {e}");
+                        } else {
+                            error!(
+                                "Error occurred while pushing RealTime Info, attempting to reconnect: {e}"
+                            );
+                        }
+                        break;
+                    }
+                    last_push = Some(tokio::time::Instant::now());
+                    record_push();
+                }
+            }
+
+            if let Some(silent) = heartbeat.expired() {
+                error!(
+                    "Nothing received from the server for {} s, attempting to reconnect",
+                    silent.as_secs()
+                );
+                break;
+            }
+            if heartbeat.ping_due() {
+                let mut write = locked_write.lock().await;
+                let sent = timeout(heartbeat.timeout(), write.send(Message::Ping(Bytes::new())))
+                    .await
+                    .unwrap_or_else(|_| Err("timed out".to_string()));
+                if let Err(e) = sent {
+                    error!("Error occurred while pinging the server, attempting to reconnect: {e}");
                     break;
                 }
             }
+            notify_watchdog();
+            beat();
+
+            if over_http && fallback.retry_due() {
+                info!("Trying the WebSocket again");
+                break;
+            }
+
+            // A new sampling interval starts a new downsampling window
+            if let Some(reload) = take_reload()
+                && reload.apply(&mut args)
+            {
+                (sample_interval, downsampler) = sampling(&args);
+            }
+
             let end_time = start_time.elapsed();
 
+            let interval = match burst.as_ref() {
+                Some(burst) if bursting => burst.interval_ms().min(sample_interval),
+                _ => sample_interval,
+            };
+            enter(Phase::Sleeping);
             sleep(Duration::from_millis({
                 let end = u64::try_from(end_time.as_millis()).unwrap_or(0);
-                args.realtime_info_interval.saturating_sub(end)
+                interval.saturating_sub(end)
             }))
             .await;
         }
+
+        // The reader of a dead connection may never end on its own
+        listener.abort();
+        set_connected(false);
+        // Over HTTP a new connection costs nothing, pause as after a failed WebSocket one
+        if over_http && !fallback.retry_due() {
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
+
+/// Push in the background, a Basic Info whose push failed is kept in `unsent` for the
+/// next connection. In degraded mode (`hold`) it goes to `unsent` right away
+fn spawn_basic_info_push(
+    basic_info: BasicInfo,
+    basic_info_url: &str,
+    ignore_unsafe_cert: bool,
+    unsent: &Arc<std::sync::Mutex<Option<BasicInfo>>>,
+    hold: bool,
+) {
+    share_basic_info(&basic_info);
+    if hold {
+        *unsent.lock().unwrap() = Some(basic_info);
+        return;
     }
+    let unsent = unsent.clone();
+    let basic_info_url = basic_info_url.to_string();
+    tokio::spawn(async move {
+        if let Err(basic_info) = basic_info
+            .push_with_retry(basic_info_url, panel_headers(), true, ignore_unsafe_cert, 5)
+            .await
+        {
+            *unsent.lock().unwrap() = Some(basic_info);
+        }
+    });
+}
+
+/// The sampling interval and, when it is shorter than the upload interval, the
+/// downsampler merging the samples of one upload
+fn sampling(args: &Args) -> (u64, Option<Downsampler>) {
+    // Sampling faster than uploading only makes sense with a shorter interval
+    let sample_interval = if args.sample_interval > 0 {
+        args.sample_interval.min(args.realtime_info_interval)
+    } else {
+        args.realtime_info_interval
+    };
+    let downsampler = (sample_interval < args.realtime_info_interval)
+        .then(|| Downsampler::new(args.realtime_info_interval / sample_interval));
+    (sample_interval, downsampler)
 }
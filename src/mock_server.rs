@@ -0,0 +1,505 @@
+//! Stand-in panel for integration tests and protocol debugging (`mock-server` subcommand).
+//!
+//! Serves enough of the Komari protocol over plain HTTP for an agent started with
+//! `--http-server http://<listen>` to run end to end without a deployment: Basic Info
+//! uploads, the Real-Time Info WebSocket, task results and the terminal WebSocket, the
+//! `--http-fallback-after` endpoints included. Every agent connecting the Real-Time Info
+//! WebSocket gets the `--exec`, `--ping` and `--terminal` tasks, a fallback agent with its
+//! first poll. A terminal session is typed the `--terminal` input followed by a newline and
+//! its output is logged. Each line typed on stdin is sent as it is to the connected agents,
+//! so any task can be tried by hand. With the global `--token`, requests carrying another
+//! one in `?token=` or an `Authorization: Bearer` header are refused; a JWT passes
+//! unchecked.
+//!
+//! With `--duration` the server stops after that many seconds and exits with status 0 when
+//! a Basic Info and a Real-Time Info came and every task issued was answered without a
+//! `task_error`, 1 otherwise.
+
+use futures::{SinkExt, StreamExt};
+use log::{debug, error, info, warn};
+use miniserde::json::{self, Number, Object, Value};
+use std::collections::BTreeSet;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+
+const MAX_HEAD: usize = 64 * 1024;
+const MAX_BODY: usize = 16 * 1024 * 1024;
+/// How long a poll of the fallback is held without a task
+const POLL_HOLD: Duration = Duration::from_secs(5);
+
+pub struct MockServer {
+    pub listen: String,
+    pub token: Option<String>,
+    pub exec: Vec<String>,
+    /// `(type, target)`
+    pub ping: Vec<(String, String)>,
+    pub terminal: Option<String>,
+    pub duration: u64,
+}
+
+/// `TYPE:TARGET` specs, the target may contain colons itself
+pub fn parse_pings(specs: &[String]) -> Result<Vec<(String, String)>, String> {
+    specs
+        .iter()
+        .map(|spec| {
+            spec.split_once(':')
+                .filter(|(kind, target)| {
+                    matches!(*kind, "icmp" | "tcp" | "http") && !target.is_empty()
+                })
+                .map(|(kind, target)| (kind.to_string(), target.to_string()))
+                .ok_or_else(|| format!("expected icmp|tcp|http:TARGET, got '{spec}'"))
+        })
+        .collect()
+}
+
+struct State {
+    config: MockServer,
+    next_id: AtomicU64,
+    basic_info: AtomicBool,
+    real_time: AtomicU64,
+    issued: AtomicU64,
+    /// Tasks answered with a `task_error`
+    failed: AtomicU64,
+    /// Ids of the tasks issued and not answered yet
+    pending: Mutex<BTreeSet<String>>,
+    /// Whether a fallback poll got the tasks already
+    polled: AtomicBool,
+    /// Lines typed on stdin
+    typed: broadcast::Sender<String>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+impl State {
+    fn authorized(&self, request: &Request) -> bool {
+        let Some(token) = &self.config.token else {
+            return true;
+        };
+        if let Some(bearer) = request
+            .header("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            return bearer == token || bearer.split('.').count() == 3;
+        }
+        request.param("token") == Some(token.as_str())
+    }
+
+    /// The `--exec`, `--ping` and `--terminal` tasks with fresh ids, registered as pending
+    fn tasks(&self) -> Vec<String> {
+        let mut tasks = Vec::new();
+        let mut issue = |id: String, fields: Vec<(&str, Value)>| {
+            let mut task = Object::new();
+            for (key, value) in fields {
+                task.insert(key.to_string(), value);
+            }
+            self.pending.lock().unwrap().insert(id);
+            self.issued.fetch_add(1, Ordering::Relaxed);
+            tasks.push(json::to_string(&Value::Object(task)));
+        };
+        let text = |text: &str| Value::String(text.to_string());
+
+        for command in &self.config.exec {
+            let id = format!("mock-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+            issue(
+                id.clone(),
+                vec![
+                    ("message", text("exec")),
+                    ("task_id", Value::String(id)),
+                    ("command", text(command)),
+                ],
+            );
+        }
+        for (kind, target) in &self.config.ping {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            issue(
+                id.to_string(),
+                vec![
+                    ("message", text("ping")),
+                    ("ping_task_id", Value::Number(Number::U64(id))),
+                    ("ping_type", text(kind)),
+                    ("ping_target", text(target)),
+                ],
+            );
+        }
+        if self.config.terminal.is_some() {
+            let id = format!("mock-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+            issue(
+                id.clone(),
+                vec![
+                    ("message", text("terminal")),
+                    ("request_id", Value::String(id)),
+                ],
+            );
+        }
+        tasks
+    }
+
+    fn answered(&self, id: &str, text: &str) {
+        if self.pending.lock().unwrap().remove(id) {
+            info!("Task {id} answered: {text}");
+        } else {
+            info!("Answer to unknown task {id}: {text}");
+        }
+    }
+
+    /// A frame the agent sent on the Real-Time Info WebSocket or posted to the fallback
+    fn frame(&self, text: &str) {
+        let Ok(Value::Object(object)) = json::from_str::<Value>(text) else {
+            warn!("Not a JSON object: {text}");
+            return;
+        };
+        if let Some(Value::Object(cpu)) = object.get("cpu") {
+            let count = self.real_time.fetch_add(1, Ordering::Relaxed) + 1;
+            let usage = match cpu.get("usage") {
+                Some(Value::Number(Number::F64(usage))) => *usage,
+                Some(Value::Number(Number::U64(usage))) => *usage as f64,
+                _ => 0.0,
+            };
+            if count == 1 {
+                info!("First Real-Time Info, CPU {usage:.1}%: {text}");
+            } else {
+                debug!("Real-Time Info #{count}, CPU {usage:.1}%");
+            }
+            return;
+        }
+        let id = match object.get("task_id") {
+            Some(Value::String(id)) => Some(id.clone()),
+            Some(Value::Number(Number::U64(id))) => Some(id.to_string()),
+            _ => None,
+        };
+        let failed = matches!(object.get("message"), Some(Value::String(message)) if message == "task_error");
+        match id {
+            Some(id) if failed => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                self.pending.lock().unwrap().remove(&id);
+                warn!("Task {id} failed: {text}");
+            }
+            Some(id) => self.answered(&id, text),
+            None => info!("Message: {text}"),
+        }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request, String> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let head_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+        if buffer.len() > MAX_HEAD {
+            return Err("request head too large".to_string());
+        }
+        let read = stream.read(&mut chunk).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("connection closed".to_string());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(format!("malformed request line in '{head}'"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect::<Vec<_>>();
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        body: buffer[head_end + 4..].to_vec(),
+    };
+
+    let length = request
+        .header("Content-Length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return Err("request body too large".to_string());
+    }
+    while request.body.len() < length {
+        let read = stream.read(&mut chunk).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("connection closed".to_string());
+        }
+        request.body.extend_from_slice(&chunk[..read]);
+    }
+    request.body.truncate(length);
+    Ok(request)
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+async fn accept_ws(
+    mut stream: TcpStream,
+    request: &Request,
+) -> Result<WebSocketStream<TcpStream>, String> {
+    let key = request
+        .header("Sec-WebSocket-Key")
+        .ok_or_else(|| "WebSocket upgrade without Sec-WebSocket-Key".to_string())?;
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    stream
+        .write_all(response.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(WebSocketStream::from_raw_socket(stream, Role::Server, None).await)
+}
+
+async fn report(state: Arc<State>, ws: WebSocketStream<TcpStream>) {
+    info!("Agent connected the Real-Time Info WebSocket");
+    let (mut sender, mut receiver) = ws.split();
+    let mut typed = state.typed.subscribe();
+    for task in state.tasks() {
+        info!("Issuing task: {task}");
+        if sender.send(Message::text(task)).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            message = receiver.next() => match message {
+                Some(Ok(Message::Text(text))) => state.frame(text.as_str()),
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            line = typed.recv() => {
+                let Ok(line) = line else {
+                    continue;
+                };
+                info!("Sending: {line}");
+                if sender.send(Message::Text(Utf8Bytes::from(line))).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    info!("Agent closed the Real-Time Info WebSocket");
+}
+
+async fn terminal(state: Arc<State>, ws: WebSocketStream<TcpStream>, id: String) {
+    info!("Agent opened terminal session {id}");
+    let (mut sender, mut receiver) = ws.split();
+    if let Some(input) = &state.config.terminal
+        && sender
+            .send(Message::text(format!("{input}\n")))
+            .await
+            .is_err()
+    {
+        return;
+    }
+
+    let mut answered = false;
+    while let Some(Ok(message)) = receiver.next().await {
+        let output = match &message {
+            Message::Binary(data) => String::from_utf8_lossy(data).to_string(),
+            Message::Text(text) => text.to_string(),
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        if !answered {
+            answered = true;
+            state.answered(&id, "terminal output");
+        }
+        info!("Terminal {id}: {}", output.escape_debug());
+    }
+    info!("Terminal session {id} closed");
+}
+
+/// Long-polled by a fallback agent: the tasks on its first poll, then the lines typed
+async fn poll(state: &State) -> String {
+    if !state.polled.swap(true, Ordering::Relaxed) {
+        let tasks = state.tasks();
+        if !tasks.is_empty() {
+            info!("Issuing tasks over the fallback: {}", tasks.join(", "));
+            return format!("[{}]", tasks.join(","));
+        }
+    }
+    let mut typed = state.typed.subscribe();
+    match tokio::time::timeout(POLL_HOLD, typed.recv()).await {
+        Ok(Ok(line)) => {
+            info!("Sending over the fallback: {line}");
+            format!("[{line}]")
+        }
+        _ => "[]".to_string(),
+    }
+}
+
+async fn handle(state: Arc<State>, mut stream: TcpStream) -> Result<(), String> {
+    let request = read_request(&mut stream).await?;
+    debug!("{} {}", request.method, request.path);
+    if !state.authorized(&request) {
+        warn!("Refused {} {}: wrong token", request.method, request.path);
+        respond(
+            &mut stream,
+            "401 Unauthorized",
+            r#"{"status":"error","message":"Unauthorized"}"#,
+        )
+        .await;
+        return Ok(());
+    }
+
+    let upgrade = request
+        .header("Upgrade")
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    if upgrade {
+        match request.path.as_str() {
+            "/api/clients/report" => report(state, accept_ws(stream, &request).await?).await,
+            "/api/clients/terminal" => {
+                let id = request.param("id").unwrap_or_default().to_string();
+                terminal(state, accept_ws(stream, &request).await?, id).await;
+            }
+            path => {
+                warn!("WebSocket to unknown path {path}");
+                respond(&mut stream, "404 Not Found", "").await;
+            }
+        }
+        return Ok(());
+    }
+
+    let body = String::from_utf8_lossy(&request.body).to_string();
+    let ok = r#"{"status":"success","message":""}"#;
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/api/clients/uploadBasicInfo") => {
+            state.basic_info.store(true, Ordering::Relaxed);
+            info!("Basic Info: {body}");
+            respond(&mut stream, "200 OK", ok).await;
+        }
+        ("POST", "/api/clients/task/result" | "/api/clients/report") => {
+            state.frame(&body);
+            respond(&mut stream, "200 OK", ok).await;
+        }
+        ("GET", "/api/clients/tasks") => {
+            let tasks = poll(&state).await;
+            respond(&mut stream, "200 OK", &tasks).await;
+        }
+        (method, path) => {
+            warn!("Unknown request {method} {path}");
+            respond(&mut stream, "404 Not Found", "").await;
+        }
+    }
+    Ok(())
+}
+
+/// Serve until stopped, or for `duration` seconds and then tell whether the run passed
+pub async fn mock_server(config: MockServer) -> Result<bool, String> {
+    let listener = TcpListener::bind(&config.listen)
+        .await
+        .map_err(|e| format!("Failed to listen on {}: {e}", config.listen))?;
+    info!(
+        "Mock server listening on {0}, run the agent with --http-server http://{0}",
+        config.listen
+    );
+
+    let (typed, _) = broadcast::channel(16);
+    let forward = typed.clone();
+    let _ = std::thread::Builder::new()
+        .name("mock-stdin".to_string())
+        .spawn(move || {
+            for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+                if !line.trim().is_empty() {
+                    let _ = forward.send(line);
+                }
+            }
+        });
+
+    let duration = config.duration;
+    let state = Arc::new(State {
+        config,
+        next_id: AtomicU64::new(1),
+        basic_info: AtomicBool::new(false),
+        real_time: AtomicU64::new(0),
+        issued: AtomicU64::new(0),
+        failed: AtomicU64::new(0),
+        pending: Mutex::new(BTreeSet::new()),
+        polled: AtomicBool::new(false),
+        typed,
+    });
+
+    let accept = {
+        let state = state.clone();
+        async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        error!("Failed to accept a connection: {e}");
+                        continue;
+                    }
+                };
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(state, stream).await {
+                        debug!("Connection ended: {e}");
+                    }
+                });
+            }
+        }
+    };
+    if duration == 0 {
+        accept.await;
+        return Ok(true);
+    }
+    let _ = tokio::time::timeout(Duration::from_secs(duration), accept).await;
+
+    let basic_info = state.basic_info.load(Ordering::Relaxed);
+    let real_time = state.real_time.load(Ordering::Relaxed);
+    let issued = state.issued.load(Ordering::Relaxed);
+    let failed = state.failed.load(Ordering::Relaxed);
+    let pending = state.pending.lock().unwrap().clone();
+    info!(
+        "Basic Info received: {basic_info}, Real-Time Info received: {real_time}, tasks answered: {} of {issued}, failed: {failed}",
+        issued - pending.len() as u64
+    );
+    for id in &pending {
+        warn!("Task {id} was not answered");
+    }
+    Ok(basic_info && real_time > 0 && pending.is_empty() && failed == 0)
+}
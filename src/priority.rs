@@ -0,0 +1,153 @@
+//! Scheduling options applied at startup (`--nice`, `--cpu-affinity`, `--idle-io`), so the
+//! agent stays out of the way of production workloads on busy hosts.
+//!
+//! On Linux these attributes are per thread, they are applied to every thread of the
+//! process, threads spawned later inherit them.
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+use log::info;
+use log::warn;
+
+/// One past the highest CPU an affinity mask can hold
+#[cfg(target_os = "linux")]
+const MAX_CPUS: usize = libc::CPU_SETSIZE as usize;
+#[cfg(not(target_os = "linux"))]
+const MAX_CPUS: usize = 1024;
+
+/// Parse a CPU list such as `0,2-3`
+fn parse_cpu_list(spec: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let invalid = || format!("Invalid CPU list '{spec}', expected e.g. '0,2-3'");
+        let (start, end) = part.split_once('-').unwrap_or((part, part));
+        let start = start.trim().parse::<usize>().map_err(|_| invalid())?;
+        let end = end.trim().parse::<usize>().map_err(|_| invalid())?;
+        if start > end {
+            return Err(invalid());
+        }
+        if end >= MAX_CPUS {
+            return Err(format!(
+                "Invalid CPU list '{spec}', CPUs go up to {}",
+                MAX_CPUS - 1
+            ));
+        }
+        cpus.extend(start..=end);
+    }
+    if cpus.is_empty() {
+        return Err(format!("Empty CPU list '{spec}'"));
+    }
+    Ok(cpus)
+}
+
+#[cfg(target_os = "linux")]
+fn thread_ids() -> Vec<libc::pid_t> {
+    std::fs::read_dir("/proc/self/task")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+fn set_nice(nice: i32) -> std::io::Result<()> {
+    for tid in thread_ids() {
+        let Ok(who) = libc::id_t::try_from(tid) else {
+            continue;
+        };
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, who, nice) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+fn set_nice(nice: i32) -> std::io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> std::io::Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    for tid in thread_ids() {
+        let ret = unsafe {
+            libc::sched_setaffinity(tid, std::mem::size_of::<libc::cpu_set_t>(), &raw const set)
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_idle_io() -> std::io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+    for tid in thread_ids() {
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                tid,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Apply the scheduling options, failures only log a warning since the agent still works
+pub fn apply_priority(
+    nice: Option<i32>,
+    cpu_affinity: Option<&str>,
+    idle_io: bool,
+) -> Result<(), String> {
+    let cpus = cpu_affinity.map(parse_cpu_list).transpose()?;
+
+    if let Some(nice) = nice {
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+        match set_nice(nice) {
+            Ok(()) => info!("Process priority set to nice {nice}"),
+            Err(e) => warn!("Failed to set nice {nice}: {e}"),
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+        warn!("`--nice {nice}` is not supported on this platform");
+    }
+
+    if let Some(cpus) = cpus {
+        #[cfg(target_os = "linux")]
+        match set_affinity(&cpus) {
+            Ok(()) => info!("CPU affinity set to {cpus:?}"),
+            Err(e) => warn!("Failed to set CPU affinity {cpus:?}: {e}"),
+        }
+        #[cfg(not(target_os = "linux"))]
+        warn!("`--cpu-affinity` is only supported on Linux, ignoring {cpus:?}");
+    }
+
+    if idle_io {
+        #[cfg(target_os = "linux")]
+        match set_idle_io() {
+            Ok(()) => info!("IO priority set to the idle class"),
+            Err(e) => warn!("Failed to set the idle IO priority: {e}"),
+        }
+        #[cfg(not(target_os = "linux"))]
+        warn!("`--idle-io` is only supported on Linux");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Redaction for `--privacy-mode`, meant for agents reporting to third-party hosted panels.
+//!
+//! Public addresses in the Basic Info and syslog sender addresses are replaced by a keyed
+//! hash: the panel still sees when an address changes but cannot recover it. The key is
+//! the machine id where there is one, so the hashes survive restarts, a random key
+//! otherwise. The hostname is left out of the Basic Info and dropped from forwarded syslog
+//! headers, the agent does not collect usernames or listening ports on its own.
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt::{Display, Write as _};
+use std::sync::OnceLock;
+
+/// Set when `--privacy-mode` is on
+static KEY: OnceLock<hmac::Key> = OnceLock::new();
+
+pub fn set_privacy_mode() {
+    KEY.get_or_init(|| {
+        let secret = ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+            .iter()
+            .find_map(|path| {
+                std::fs::read(path)
+                    .ok()
+                    .filter(|id| !id.trim_ascii().is_empty())
+            })
+            .unwrap_or_else(|| {
+                let mut secret = vec![0u8; 32];
+                let _ = SystemRandom::new().fill(&mut secret);
+                secret
+            });
+        hmac::Key::new(hmac::HMAC_SHA256, &secret)
+    });
+}
+
+pub fn privacy_mode() -> bool {
+    KEY.get().is_some()
+}
+
+/// `ip` as sent to the panel: as is, or its hash in privacy mode
+pub fn public_ip(ip: impl Display) -> String {
+    let ip = ip.to_string();
+    let Some(key) = KEY.get() else {
+        return ip;
+    };
+    let tag = hmac::sign(key, ip.as_bytes());
+    let mut hashed = String::from("hashed-");
+    for byte in &tag.as_ref()[..8] {
+        let _ = write!(hashed, "{byte:02x}");
+    }
+    hashed
+}
@@ -0,0 +1,100 @@
+use crate::alerts::is_firing;
+use crate::data_struct::RealTimeInfo;
+use log::info;
+use std::time::Duration;
+use time::{OffsetDateTime, Time};
+
+/// Lengthens the reporting interval inside a daily time window to save bandwidth.
+///
+/// Samples are still collected at the normal interval so that an alert condition
+/// can break out of the quiet window immediately.
+#[derive(Debug, Clone)]
+pub struct QuietHours {
+    start: Time,
+    end: Time,
+    interval: Duration,
+    wake_threshold: f64,
+    active: bool,
+}
+
+impl QuietHours {
+    /// Parse a window in `HH:MM-HH:MM` format, the window may wrap past midnight
+    pub fn parse(spec: &str, interval_ms: u64, wake_threshold: f64) -> Result<Self, String> {
+        let (start, end) = spec
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid quiet hours '{spec}', expected 'HH:MM-HH:MM'"))?;
+
+        Ok(Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+            interval: Duration::from_millis(interval_ms),
+            wake_threshold,
+            active: false,
+        })
+    }
+
+    fn contains(&self, now: Time) -> bool {
+        if self.start <= self.end {
+            now >= self.start && now < self.end
+        } else {
+            now >= self.start || now < self.end
+        }
+    }
+
+    /// Whether the wake threshold or a local alert rule fires, which forces an immediate push
+    fn alert_fired(&self, real_time: &RealTimeInfo) -> bool {
+        let ram_usage = if real_time.ram.total == 0 {
+            0.0
+        } else {
+            real_time.ram.used as f64 / real_time.ram.total as f64 * 100.0
+        };
+
+        real_time.cpu.usage >= self.wake_threshold
+            || ram_usage >= self.wake_threshold
+            || is_firing()
+    }
+
+    /// Returns `true` if this sample should be held back instead of being pushed
+    pub fn should_hold(&mut self, real_time: &RealTimeInfo, since_last_push: Duration) -> bool {
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        let in_window = self.contains(now.time());
+
+        if in_window != self.active {
+            self.active = in_window;
+            if in_window {
+                info!(
+                    "Entering quiet hours, Real-Time Info will be pushed every {} ms",
+                    self.interval.as_millis()
+                );
+            } else {
+                info!("Leaving quiet hours, returning to the normal interval");
+            }
+        }
+
+        if !in_window || since_last_push >= self.interval {
+            return false;
+        }
+
+        if self.alert_fired(real_time) {
+            info!("Alert condition fired during quiet hours, pushing immediately");
+            return false;
+        }
+
+        true
+    }
+}
+
+fn parse_time(s: &str) -> Result<Time, String> {
+    let (hour, minute) = s
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid time '{s}', expected 'HH:MM'"))?;
+    let hour = hour
+        .parse::<u8>()
+        .map_err(|_| format!("Invalid hour in '{s}'"))?;
+    let minute = minute
+        .parse::<u8>()
+        .map_err(|_| format!("Invalid minute in '{s}'"))?;
+
+    Time::from_hms(hour, minute, 0).map_err(|e| format!("Invalid time '{s}': {e}"))
+}
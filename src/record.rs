@@ -0,0 +1,185 @@
+//! Local sample recording (`--record-path`) and the `export` subcommand.
+//!
+//! Samples are appended as JSON lines `{"time":<unix s>,"sample":{...}}`, the file
+//! is rotated to `<path>.1` once it grows past the size limit.
+
+use crate::command_parser::ExportFormat;
+use crate::data_struct::RealTimeInfo;
+use log::{error, info};
+use miniserde::{Deserialize, Serialize, json};
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write as _;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use time::format_description::well_known::Rfc3339;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RecordedSample {
+    time: u64,
+    sample: RealTimeInfo,
+}
+
+pub struct Recorder {
+    path: String,
+    interval: Duration,
+    max_size: u64,
+    last_record: Option<Instant>,
+}
+
+impl Recorder {
+    pub fn new(path: String, interval_s: u64, max_size_mb: u64) -> Self {
+        info!("Recording samples to {path} every {interval_s} s");
+        Self {
+            path,
+            interval: Duration::from_secs(interval_s),
+            max_size: max_size_mb * 1024 * 1024,
+            last_record: None,
+        }
+    }
+
+    /// Append the sample if the record interval has passed since the previous one
+    pub fn record(&mut self, sample: &RealTimeInfo) {
+        if self
+            .last_record
+            .is_some_and(|last| last.elapsed() < self.interval)
+        {
+            return;
+        }
+        self.last_record = Some(Instant::now());
+
+        let recorded = RecordedSample {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            sample: sample.clone(),
+        };
+        if let Err(e) = self.append(&json::to_string(&recorded)) {
+            error!("Failed to record sample to {}: {e}", self.path);
+        }
+    }
+
+    fn append(&self, line: &str) -> std::io::Result<()> {
+        if std::fs::metadata(&self.path).is_ok_and(|m| m.len() >= self.max_size) {
+            std::fs::rename(&self.path, format!("{}.1", self.path))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{line}")
+    }
+}
+
+/// Parse a unix timestamp or a local `YYYY-MM-DD[THH:MM[:SS]]` date time
+fn parse_time_bound(s: &str) -> Result<u64, String> {
+    if let Ok(timestamp) = s.parse::<u64>() {
+        return Ok(timestamp);
+    }
+
+    let invalid =
+        || format!("Invalid time '{s}', expected a unix timestamp or YYYY-MM-DD[THH:MM[:SS]]");
+    let (date, time) = s.split_once(['T', ' ']).unwrap_or((s, "00:00"));
+
+    let mut date_parts = date.split('-').map(str::parse::<u32>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day)), None) = (
+        date_parts.next(),
+        date_parts.next(),
+        date_parts.next(),
+        date_parts.next(),
+    ) else {
+        return Err(invalid());
+    };
+    let mut time_parts = time.split(':').map(str::parse::<u8>);
+    let (Some(Ok(hour)), Some(Ok(minute))) = (time_parts.next(), time_parts.next()) else {
+        return Err(invalid());
+    };
+    let second = match time_parts.next() {
+        Some(Ok(second)) => second,
+        Some(Err(_)) => return Err(invalid()),
+        None => 0,
+    };
+
+    let month = u8::try_from(month)
+        .ok()
+        .and_then(|m| Month::try_from(m).ok())
+        .ok_or_else(invalid)?;
+    let year = i32::try_from(year).map_err(|_| invalid())?;
+    let day = u8::try_from(day).map_err(|_| invalid())?;
+    let date = Date::from_calendar_date(year, month, day).map_err(|_| invalid())?;
+    let time = Time::from_hms(hour, minute, second).map_err(|_| invalid())?;
+
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+    let timestamp = PrimitiveDateTime::new(date, time)
+        .assume_offset(offset)
+        .unix_timestamp();
+    u64::try_from(timestamp).map_err(|_| invalid())
+}
+
+const CSV_HEADER: &str = "time,cpu,ram_used,ram_total,swap_used,swap_total,disk_used,disk_total,load1,load5,load15,net_up,net_down,net_total_up,net_total_down,tcp,udp,process,uptime";
+
+fn csv_row(recorded: &RecordedSample) -> String {
+    let s = &recorded.sample;
+    let time = OffsetDateTime::from_unix_timestamp(i64::try_from(recorded.time).unwrap_or(0))
+        .ok()
+        .and_then(|t| t.format(&Rfc3339).ok())
+        .unwrap_or_default();
+
+    let mut row = String::new();
+    let _ = write!(
+        row,
+        "{time},{:.2},{},{},{},{},{},{},{:.2},{:.2},{:.2},{},{},{},{},{},{},{},{}",
+        s.cpu.usage,
+        s.ram.used,
+        s.ram.total,
+        s.swap.used,
+        s.swap.total,
+        s.disk.used,
+        s.disk.total,
+        s.load.load1,
+        s.load.load5,
+        s.load.load15,
+        s.network.up,
+        s.network.down,
+        s.network.total_up,
+        s.network.total_down,
+        s.connections.tcp,
+        s.connections.udp,
+        s.process,
+        s.uptime
+    );
+    row
+}
+
+/// Dump recorded samples between `from` and `to` (inclusive) to stdout
+pub fn export(
+    path: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    format: &ExportFormat,
+) -> Result<(), String> {
+    let from = from.map(parse_time_bound).transpose()?.unwrap_or(0);
+    let to = to.map(parse_time_bound).transpose()?.unwrap_or(u64::MAX);
+
+    let rotated = std::fs::read_to_string(format!("{path}.1")).unwrap_or_default();
+    let current =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+
+    let mut samples = rotated
+        .lines()
+        .chain(current.lines())
+        .filter_map(|line| json::from_str::<RecordedSample>(line).ok())
+        .filter(|recorded| recorded.time >= from && recorded.time <= to);
+
+    let mut stdout = std::io::stdout().lock();
+    let result = match format {
+        ExportFormat::Csv => writeln!(stdout, "{CSV_HEADER}").and_then(|()| {
+            samples.try_for_each(|recorded| writeln!(stdout, "{}", csv_row(&recorded)))
+        }),
+        ExportFormat::Json => {
+            let samples = samples.collect::<Vec<_>>();
+            writeln!(stdout, "{}", json::to_string(&samples))
+        }
+    };
+    result.map_err(|e| format!("Failed to write export: {e}"))
+}
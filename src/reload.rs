@@ -0,0 +1,247 @@
+//! Configuration hot-reload (`--reload-file`, SIGHUP, `reload` message).
+//!
+//! The reload file holds `key=value` lines named like the flags, e.g. `fake=1.5` or
+//! `net-filter-keywords=` (empty restores the default). It is applied at startup and
+//! re-read on SIGHUP; the server can send the same settings in a `reload` message,
+//! `snake_case`. Changes are queued here and applied by the upload loop between two
+//! samples, the connection stays up.
+
+use crate::command_parser::{Args, LogLevel};
+use crate::get_info::filters::{set_disk_filter, set_net_filter};
+use crate::utils::set_log_level;
+use log::info;
+use miniserde::Deserialize;
+use std::sync::Mutex;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Reload {
+    realtime_info_interval: Option<u64>,
+    sample_interval: Option<u64>,
+    fake: Option<f64>,
+    log_level: Option<String>,
+    /// An empty string restores the default, as for the filters below
+    net_filter_keywords: Option<String>,
+    net_allow_interfaces: Option<String>,
+    disk_allowed_fs: Option<String>,
+    disk_exclude_paths: Option<String>,
+}
+
+/// Nothing changes
+const UNCHANGED: Reload = Reload {
+    realtime_info_interval: None,
+    sample_interval: None,
+    fake: None,
+    log_level: None,
+    net_filter_keywords: None,
+    net_allow_interfaces: None,
+    disk_allowed_fs: None,
+    disk_exclude_paths: None,
+};
+
+static PENDING: Mutex<Option<Reload>> = Mutex::new(None);
+
+fn parse_log_level(level: &str) -> Result<LogLevel, String> {
+    match level.trim().to_lowercase().as_str() {
+        "error" => Ok(LogLevel::Error),
+        "warn" => Ok(LogLevel::Warn),
+        "info" => Ok(LogLevel::Info),
+        "debug" => Ok(LogLevel::Debug),
+        "trace" => Ok(LogLevel::Trace),
+        _ => Err(format!("Invalid log level '{level}'")),
+    }
+}
+
+fn filter(value: &str) -> Option<String> {
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+impl Reload {
+    pub fn read(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read reload file {path}: {e}"))?;
+
+        let mut reload = UNCHANGED;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Format error: expected key=value, got '{line}'"))?;
+            let key = key.trim();
+            let value = value.trim();
+            let parse_err = |type_name: &str| format!("Invalid {type_name} for key '{key}'");
+
+            match key.trim_start_matches("--").replace('_', "-").as_str() {
+                "realtime-info-interval" => {
+                    reload.realtime_info_interval =
+                        Some(value.parse().map_err(|_| parse_err("u64"))?);
+                }
+                "sample-interval" => {
+                    reload.sample_interval = Some(value.parse().map_err(|_| parse_err("u64"))?);
+                }
+                "fake" => reload.fake = Some(value.parse().map_err(|_| parse_err("f64"))?),
+                "log-level" => reload.log_level = Some(value.to_string()),
+                "net-filter-keywords" => reload.net_filter_keywords = Some(value.to_string()),
+                "net-allow-interfaces" => reload.net_allow_interfaces = Some(value.to_string()),
+                "disk-allowed-fs" => reload.disk_allowed_fs = Some(value.to_string()),
+                "disk-exclude-paths" => reload.disk_exclude_paths = Some(value.to_string()),
+                _ => return Err(format!("Unknown or not reloadable key '{key}'")),
+            }
+        }
+        reload.validate()?;
+        Ok(reload)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(level) = &self.log_level {
+            parse_log_level(level)?;
+        }
+        if self
+            .fake
+            .is_some_and(|fake| !fake.is_finite() || fake <= 0.0)
+        {
+            return Err(String::from("The fake multiplier must be positive"));
+        }
+        if self.realtime_info_interval == Some(0) {
+            return Err(String::from("The upload interval must be positive"));
+        }
+        Ok(())
+    }
+
+    /// The settings present as `key=value` lines, in the order of the fields, as a signed
+    /// `reload` message covers them
+    pub fn signed_settings(&self) -> String {
+        let mut lines = Vec::new();
+        macro_rules! lines {
+            ($($field:ident),*) => {
+                $(if let Some(value) = &self.$field {
+                    lines.push(format!("{}={value}", stringify!($field)));
+                })*
+            };
+        }
+        lines!(
+            realtime_info_interval,
+            sample_interval,
+            fake,
+            log_level,
+            net_filter_keywords,
+            net_allow_interfaces,
+            disk_allowed_fs,
+            disk_exclude_paths
+        );
+        lines.join("\n")
+    }
+
+    /// Later settings win, the ones they leave out are kept
+    fn merge(&mut self, other: Self) {
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field;
+                })*
+            };
+        }
+        merge!(
+            realtime_info_interval,
+            sample_interval,
+            fake,
+            log_level,
+            net_filter_keywords,
+            net_allow_interfaces,
+            disk_allowed_fs,
+            disk_exclude_paths
+        );
+    }
+
+    /// Write the settings into `args` and reconfigure the logger and filters, `true` if
+    /// the sampling intervals changed
+    pub fn apply(self, args: &mut Args) -> bool {
+        let intervals = (args.realtime_info_interval, args.sample_interval);
+        if let Some(interval) = self.realtime_info_interval {
+            args.realtime_info_interval = interval;
+        }
+        if let Some(interval) = self.sample_interval {
+            args.sample_interval = interval;
+        }
+        if let Some(fake) = self.fake {
+            args.fake = fake;
+        }
+        if let Some(level) = self
+            .log_level
+            .as_deref()
+            .and_then(|l| parse_log_level(l).ok())
+        {
+            set_log_level(&level);
+            args.log_level = level;
+        }
+
+        if self.net_filter_keywords.is_some() || self.net_allow_interfaces.is_some() {
+            if let Some(keywords) = &self.net_filter_keywords {
+                args.net_filter_keywords = filter(keywords);
+            }
+            if let Some(allow) = &self.net_allow_interfaces {
+                args.net_allow_interfaces = filter(allow);
+            }
+            set_net_filter(
+                args.net_filter_keywords.as_deref(),
+                args.net_allow_interfaces.as_deref(),
+            );
+        }
+        if self.disk_allowed_fs.is_some() || self.disk_exclude_paths.is_some() {
+            if let Some(allowed_fs) = &self.disk_allowed_fs {
+                args.disk_allowed_fs = filter(allowed_fs);
+            }
+            if let Some(exclude_paths) = &self.disk_exclude_paths {
+                args.disk_exclude_paths = filter(exclude_paths);
+            }
+            set_disk_filter(
+                args.disk_allowed_fs.as_deref(),
+                args.disk_exclude_paths.as_deref(),
+            );
+        }
+
+        info!("Configuration reloaded: {self:?}");
+        intervals != (args.realtime_info_interval, args.sample_interval)
+    }
+}
+
+/// Queue settings for the upload loop
+pub fn request_reload(reload: Reload) {
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.get_or_insert(UNCHANGED).merge(reload);
+    }
+}
+
+pub fn take_reload() -> Option<Reload> {
+    PENDING.lock().ok()?.take()
+}
+
+/// Re-read the reload file on every SIGHUP. Without a reload file SIGHUP keeps its
+/// default action.
+#[cfg(unix)]
+pub fn spawn_sighup_listener(path: String) {
+    use log::error;
+    use tokio::signal::unix::{SignalKind, signal};
+
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!("Failed to listen for SIGHUP: {e}");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading {path}");
+            match Reload::read(&path) {
+                Ok(reload) => request_reload(reload),
+                Err(e) => error!("Configuration not reloaded: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn spawn_sighup_listener(_path: String) {}
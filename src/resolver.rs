@@ -0,0 +1,334 @@
+//! Resolution of the server name past the system resolver (`--dns-server`, `--resolve`).
+//!
+//! Some providers hijack DNS, so the panel name has to be resolved elsewhere. With
+//! `--dns-server` names are looked up at that server instead of the system resolver: plain
+//! DNS over UDP for `IP[:PORT]`, DNS-over-HTTPS (RFC 8484, POST) for an `https://` URL, with
+//! the IPv4 addresses tried before the IPv6 ones. `--resolve HOST:IP` pins a name to an
+//! address without any lookup, also the name of the DNS-over-HTTPS server itself, which the
+//! system resolves otherwise. Both apply to the WebSocket connections and the HTTP requests
+//! of the agent; with `nyquest-support` HTTP requests keep the system resolver.
+
+use crate::utils::timeouts;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+const DNS_PORT: u16 = 53;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const MAX_RESPONSE: usize = 4096;
+/// What ureq takes of a resolver
+#[cfg(feature = "ureq-support")]
+const MAX_ADDRESSES: usize = 16;
+
+#[derive(Debug, Clone)]
+pub enum DnsServer {
+    Udp(SocketAddr),
+    Https(String),
+}
+
+struct Settings {
+    dns_server: Option<DnsServer>,
+    pins: Vec<(String, IpAddr)>,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// `IP[:PORT]` or an `https://` URL
+pub fn parse_dns_server(server: &str) -> Result<DnsServer, String> {
+    if server.starts_with("https://") {
+        return Ok(DnsServer::Https(server.to_string()));
+    }
+    if let Ok(address) = server.parse::<SocketAddr>() {
+        return Ok(DnsServer::Udp(address));
+    }
+    server
+        .trim_matches(['[', ']'])
+        .parse::<IpAddr>()
+        .map(|ip| DnsServer::Udp(SocketAddr::new(ip, DNS_PORT)))
+        .map_err(|_| format!("expected IP[:PORT] or an https:// URL, got '{server}'"))
+}
+
+/// `HOST:IP` pins, the address may be an IPv6 one in brackets or not
+pub fn parse_pins(pins: &[String]) -> Result<Vec<(String, IpAddr)>, String> {
+    pins.iter()
+        .map(|pin| {
+            let (host, ip) = pin
+                .split_once(':')
+                .filter(|(host, _)| !host.is_empty())
+                .ok_or_else(|| format!("expected HOST:IP, got '{pin}'"))?;
+            let ip = ip
+                .trim_matches(['[', ']'])
+                .parse::<IpAddr>()
+                .map_err(|e| format!("invalid address in '{pin}': {e}"))?;
+            Ok((host.to_ascii_lowercase(), ip))
+        })
+        .collect()
+}
+
+pub fn set_resolver(dns_server: Option<DnsServer>, pins: Vec<(String, IpAddr)>) {
+    if dns_server.is_some() || !pins.is_empty() {
+        let _ = SETTINGS.set(Settings { dns_server, pins });
+    }
+}
+
+/// Whether names have to go through `resolve` rather than the system resolver
+pub fn custom_resolver() -> bool {
+    SETTINGS.get().is_some()
+}
+
+/// Addresses of `host`, blocking
+pub fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    lookup(host, port, true)
+}
+
+/// `use_dns_server` is off for the name of the DNS-over-HTTPS server
+fn lookup(host: &str, port: u16, use_dns_server: bool) -> Result<Vec<SocketAddr>, String> {
+    let host = host.trim_matches(['[', ']']);
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    let settings = SETTINGS.get();
+    let pinned = settings
+        .into_iter()
+        .flat_map(|settings| &settings.pins)
+        .filter(|(name, _)| name.eq_ignore_ascii_case(host))
+        .map(|(_, ip)| SocketAddr::new(*ip, port))
+        .collect::<Vec<_>>();
+    if !pinned.is_empty() {
+        return Ok(pinned);
+    }
+
+    let Some(server) = settings
+        .and_then(|settings| settings.dns_server.as_ref())
+        .filter(|_| use_dns_server)
+    else {
+        return (host, port)
+            .to_socket_addrs()
+            .map(Iterator::collect)
+            .map_err(|e| format!("Failed to resolve {host}: {e}"));
+    };
+    let mut addresses = Vec::new();
+    for kind in [TYPE_A, TYPE_AAAA] {
+        let ips = match server {
+            DnsServer::Udp(address) => query_udp(*address, host, kind),
+            DnsServer::Https(url) => query_https(url, host, kind),
+        }
+        .map_err(|e| format!("Failed to resolve {host}: {e}"))?;
+        addresses.extend(ips.into_iter().map(|ip| SocketAddr::new(ip, port)));
+    }
+    if addresses.is_empty() {
+        return Err(format!("Failed to resolve {host}: no address"));
+    }
+    Ok(addresses)
+}
+
+fn question(id: u16, host: &str, kind: u16) -> Result<Vec<u8>, String> {
+    let mut packet = Vec::with_capacity(64);
+    packet.extend(id.to_be_bytes());
+    // Recursion desired, one question
+    packet.extend([0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    for label in host.trim_end_matches('.').split('.') {
+        let length = u8::try_from(label.len())
+            .ok()
+            .filter(|length| (1..64).contains(length))
+            .ok_or_else(|| format!("invalid name {host}"))?;
+        packet.push(length);
+        packet.extend(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend(kind.to_be_bytes());
+    // Class IN
+    packet.extend(1u16.to_be_bytes());
+    Ok(packet)
+}
+
+/// Position after the name at `position`, compressed or not
+fn skip_name(packet: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        let length = *packet.get(position)?;
+        if length & 0xc0 == 0xc0 {
+            return Some(position + 2);
+        }
+        position += 1 + usize::from(length);
+        if length == 0 {
+            return Some(position);
+        }
+    }
+}
+
+fn read_u16(packet: &[u8], position: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *packet.get(position)?,
+        *packet.get(position + 1)?,
+    ]))
+}
+
+/// The addresses of type `kind` in the answer section, CNAMEs are followed by the server
+fn answers(packet: &[u8], id: u16, kind: u16) -> Result<Vec<IpAddr>, String> {
+    if read_u16(packet, 0) != Some(id) || packet.len() < 12 {
+        return Err("unexpected answer".to_string());
+    }
+    match packet[3] & 0x0f {
+        0 => {}
+        // NXDOMAIN
+        3 => return Ok(Vec::new()),
+        code => return Err(format!("DNS error code {code}")),
+    }
+    let malformed = || "malformed answer".to_string();
+    let questions = read_u16(packet, 4).ok_or_else(malformed)?;
+    let records = read_u16(packet, 6).ok_or_else(malformed)?;
+
+    let mut position = 12;
+    for _ in 0..questions {
+        position = skip_name(packet, position).ok_or_else(malformed)? + 4;
+    }
+    let mut ips = Vec::new();
+    for _ in 0..records {
+        position = skip_name(packet, position).ok_or_else(malformed)?;
+        let record_kind = read_u16(packet, position).ok_or_else(malformed)?;
+        let length = usize::from(read_u16(packet, position + 8).ok_or_else(malformed)?);
+        position += 10;
+        let data = packet
+            .get(position..position + length)
+            .ok_or_else(malformed)?;
+        if record_kind == kind {
+            if let Ok(octets) = <[u8; 4]>::try_from(data) {
+                ips.push(IpAddr::from(octets));
+            } else if let Ok(octets) = <[u8; 16]>::try_from(data) {
+                ips.push(IpAddr::from(octets));
+            }
+        }
+        position += length;
+    }
+    Ok(ips)
+}
+
+fn query_udp(server: SocketAddr, host: &str, kind: u16) -> Result<Vec<IpAddr>, String> {
+    let mut id = [0u8; 2];
+    SystemRandom::new()
+        .fill(&mut id)
+        .map_err(|_| "no randomness for the query id".to_string())?;
+    let id = u16::from_be_bytes(id);
+
+    let socket = UdpSocket::bind(if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })
+    .map_err(|e| e.to_string())?;
+    socket.connect(server).map_err(|e| e.to_string())?;
+    socket
+        .send(&question(id, host, kind)?)
+        .map_err(|e| format!("failed to query {server}: {e}"))?;
+
+    // Answers to other ids are spoofed or late, the deadline still holds
+    let deadline = Instant::now() + timeouts().connect;
+    let mut response = [0u8; MAX_RESPONSE];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(format!("no answer from {server}"));
+        }
+        socket
+            .set_read_timeout(Some(left))
+            .map_err(|e| e.to_string())?;
+        let read = socket
+            .recv(&mut response)
+            .map_err(|e| format!("no answer from {server}: {e}"))?;
+        if read_u16(&response[..read], 0) == Some(id) {
+            return answers(&response[..read], id, kind);
+        }
+    }
+}
+
+/// Query id 0, as RFC 8484 recommends for caching
+#[cfg(feature = "ureq-support")]
+fn query_https(url: &str, host: &str, kind: u16) -> Result<Vec<IpAddr>, String> {
+    use crate::utils::ureq_config;
+    use ureq::unversioned::transport::DefaultConnector;
+
+    let agent = ureq::Agent::with_parts(
+        ureq_config(false),
+        DefaultConnector::new(),
+        PanelResolver {
+            use_dns_server: false,
+        },
+    );
+    let mut response = agent
+        .post(url)
+        .header("Content-Type", "application/dns-message")
+        .header("Accept", "application/dns-message")
+        .send(&question(0, host, kind)?[..])
+        .map_err(|e| format!("DNS-over-HTTPS request failed: {e}"))?;
+    let packet = response
+        .body_mut()
+        .with_config()
+        .limit(MAX_RESPONSE as u64)
+        .read_to_vec()
+        .map_err(|e| format!("DNS-over-HTTPS request failed: {e}"))?;
+    answers(&packet, 0, kind)
+}
+
+#[cfg(feature = "nyquest-support")]
+fn query_https(url: &str, host: &str, kind: u16) -> Result<Vec<IpAddr>, String> {
+    use nyquest::{Body, Request};
+
+    let client = crate::utils::create_nyquest_client(false);
+    let packet = client
+        .request(
+            Request::post(url.to_string())
+                .with_header("Accept", "application/dns-message")
+                .with_body(Body::bytes(
+                    question(0, host, kind)?,
+                    "application/dns-message",
+                )),
+        )
+        .and_then(nyquest::blocking::Response::bytes)
+        .map_err(|e| format!("DNS-over-HTTPS request failed: {e}"))?;
+    answers(&packet, 0, kind)
+}
+
+/// `resolve` for ureq
+#[cfg(feature = "ureq-support")]
+#[derive(Debug)]
+pub struct PanelResolver {
+    pub use_dns_server: bool,
+}
+
+#[cfg(feature = "ureq-support")]
+impl ureq::unversioned::resolver::Resolver for PanelResolver {
+    fn resolve(
+        &self,
+        uri: &ureq::http::Uri,
+        config: &ureq::config::Config,
+        _timeout: ureq::unversioned::transport::NextTimeout,
+    ) -> Result<ureq::unversioned::resolver::ResolvedSocketAddrs, ureq::Error> {
+        let host = uri.host().ok_or(ureq::Error::HostNotFound)?;
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("https") {
+                443
+            } else {
+                80
+            });
+        let addresses = lookup(host, port, self.use_dns_server).map_err(|e| {
+            log::warn!("{e}");
+            ureq::Error::HostNotFound
+        })?;
+
+        let mut resolved = self.empty();
+        for address in config
+            .ip_family()
+            .keep_wanted(addresses.into_iter())
+            .take(MAX_ADDRESSES)
+        {
+            resolved.push(address);
+        }
+        if resolved.is_empty() {
+            return Err(ureq::Error::HostNotFound);
+        }
+        Ok(resolved)
+    }
+}
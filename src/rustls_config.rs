@@ -1,8 +1,14 @@
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
-use rustls::{ClientConfig, DigitallySignedStruct, DistinguishedName, Error, SignatureScheme};
+use rustls::{
+    ClientConfig, DigitallySignedStruct, DistinguishedName, Error, RootCertStore, SignatureScheme,
+};
+use rustls_pki_types::pem::PemObject;
 use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
 use std::fmt::{Debug, Formatter};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+
+/// Certificates of `--ca-cert`, trusted on top of the built-in roots
+static CA_CERTS: OnceLock<Vec<CertificateDer<'static>>> = OnceLock::new();
 
 struct NoVerification;
 
@@ -77,3 +83,37 @@ pub fn create_dangerous_config() -> ClientConfig {
         .with_custom_certificate_verifier(verifier)
         .with_no_client_auth()
 }
+
+/// Load the PEM bundle at `path`, every later TLS connection trusts its certificates
+pub fn set_ca_cert(path: &str) -> Result<(), String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(|e| format!("Failed to read CA certificates {path}: {e}"))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to parse CA certificates {path}: {e}"))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {path}"));
+    }
+
+    // Rejected here rather than on every connection
+    let mut store = RootCertStore::empty();
+    for cert in &certs {
+        store
+            .add(cert.clone())
+            .map_err(|e| format!("Invalid CA certificate in {path}: {e}"))?;
+    }
+    let _ = CA_CERTS.set(certs);
+    Ok(())
+}
+
+/// Certificates of `--ca-cert`, empty without it
+pub fn ca_certs() -> &'static [CertificateDer<'static>] {
+    CA_CERTS.get().map_or(&[], Vec::as_slice)
+}
+
+/// The built-in web PKI roots plus `--ca-cert`
+pub fn root_store() -> RootCertStore {
+    let mut root_store = RootCertStore::empty();
+    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    root_store.add_parsable_certificates(ca_certs().iter().cloned());
+    root_store
+}
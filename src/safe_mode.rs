@@ -0,0 +1,87 @@
+//! Crash-loop detection (`--crash-loop-starts`).
+//!
+//! Every start is appended to a file next to the version file. When the agent started
+//! `--crash-loop-starts` times within `--crash-loop-window` seconds, something it enables
+//! is likely taking it down, so it runs in safe mode: the optional collectors and the
+//! remote control features stay off and a `[safe-mode]` event is queued. Monitoring
+//! itself keeps working. Safe mode ends once the starts fall out of the window.
+
+use crate::command_parser::Args;
+use crate::events::push_event;
+use log::{error, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed)
+}
+
+/// Record this start in `starts_file` and tell whether the agent is crash-looping
+pub fn record_start(starts_file: &str, starts: u32, window: u64) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    let mut recent: Vec<u64> = std::fs::read_to_string(starts_file)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .filter(|start| now.saturating_sub(*start) < window)
+        .collect();
+    recent.push(now);
+
+    let content = recent
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if let Err(e) = std::fs::write(starts_file, content) {
+        warn!("Failed to save the start time to {starts_file}: {e}");
+    }
+    starts > 0 && recent.len() >= starts as usize
+}
+
+/// Turn off what a crash loop is most likely caused by
+pub fn enter_safe_mode(args: &mut Args) {
+    SAFE_MODE.store(true, Ordering::Relaxed);
+    let window = args.crash_loop_window;
+    error!(
+        "Started {} times within {window} s, entering safe mode: optional collectors, terminal, exec, speed tests, downloads, alerts, syslog and log forwarding are disabled",
+        args.crash_loop_starts
+    );
+    push_event(format!(
+        "[safe-mode] {} starts within {window} s, optional collectors and remote control disabled",
+        args.crash_loop_starts
+    ));
+
+    // Remote control
+    args.terminal = false;
+    args.speedtest = false;
+    args.fetch_dir.clear();
+    // Optional collectors
+    args.ebpf_process_network = false;
+    args.ebpf_connections = false;
+    args.vpn_stats = false;
+    args.openvpn_status = None;
+    args.ip_family_stats = false;
+    args.temperatures = false;
+    args.power_stats = false;
+    args.nut_ups.clear();
+    args.thermal_throttle = false;
+    args.sbc_stats = false;
+    args.wifi_stats = false;
+    args.watch_process.clear();
+    args.watch_service.clear();
+    args.cgroup.clear();
+    args.traffic_account.clear();
+    args.interface_history = 0;
+    args.mesh_listen = None;
+    args.ping_target.clear();
+    args.http_check.clear();
+    args.clock_check = None;
+    // Local listeners and actions
+    args.syslog_listen = None;
+    args.tail_file.clear();
+    args.alert.clear();
+}
@@ -0,0 +1,188 @@
+//! Windows Service integration (install / uninstall / service control handler)
+
+#[cfg(target_os = "windows")]
+mod imp {
+    use crate::command_parser::Args;
+    use log::{error, info};
+    use std::ffi::OsString;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    const SERVICE_NAME: &str = "komari-monitor-rs";
+    const SERVICE_DISPLAY_NAME: &str = "Komari Monitor Agent";
+    const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+    /// Register the agent as an auto-start service, reusing the current command line
+    pub fn install_service() -> Result<(), String> {
+        let manager = ServiceManager::local_computer(
+            None::<&str>,
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+        )
+        .map_err(|e| format!("Failed to connect to Service Manager: {e}"))?;
+
+        let executable_path =
+            std::env::current_exe().map_err(|e| format!("Failed to get executable path: {e}"))?;
+
+        let mut launch_arguments = Args::service_arguments();
+        launch_arguments.push(OsString::from("--run-as-service"));
+        launch_arguments.push(OsString::from("--disable-toast-notify"));
+
+        let service_info = ServiceInfo {
+            name: OsString::from(SERVICE_NAME),
+            display_name: OsString::from(SERVICE_DISPLAY_NAME),
+            service_type: SERVICE_TYPE,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path,
+            launch_arguments,
+            dependencies: vec![],
+            account_name: None, // LocalSystem
+            account_password: None,
+        };
+
+        let service = manager
+            .create_service(
+                &service_info,
+                ServiceAccess::CHANGE_CONFIG | ServiceAccess::START,
+            )
+            .map_err(|e| format!("Failed to create service: {e}"))?;
+        service
+            .set_description("Komari Monitor Agent in Rust")
+            .map_err(|e| format!("Failed to set service description: {e}"))?;
+        service
+            .start::<&str>(&[])
+            .map_err(|e| format!("Failed to start service: {e}"))?;
+
+        info!("Service '{SERVICE_NAME}' installed and started");
+        Ok(())
+    }
+
+    /// Stop the service if it is running and mark it for deletion
+    pub fn uninstall_service() -> Result<(), String> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .map_err(|e| format!("Failed to connect to Service Manager: {e}"))?;
+
+        let service = manager
+            .open_service(
+                SERVICE_NAME,
+                ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+            )
+            .map_err(|e| format!("Failed to open service: {e}"))?;
+
+        service
+            .delete()
+            .map_err(|e| format!("Failed to delete service: {e}"))?;
+
+        let is_stopped = service
+            .query_status()
+            .is_ok_and(|status| status.current_state == ServiceState::Stopped);
+        if !is_stopped {
+            service
+                .stop()
+                .map_err(|e| format!("Failed to stop service: {e}"))?;
+        }
+        drop(service);
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if manager
+                .open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS)
+                .is_err()
+            {
+                info!("Service '{SERVICE_NAME}' uninstalled");
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+
+        info!("Service '{SERVICE_NAME}' is marked for deletion");
+        Ok(())
+    }
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Connect to the Service Control Manager on a dedicated thread.
+    ///
+    /// The agent itself keeps running on the tokio runtime, the control handler only
+    /// reports status and terminates the process on Stop / Shutdown.
+    pub fn start_service_dispatcher() {
+        std::thread::spawn(|| {
+            if let Err(e) = service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
+                error!("Failed to connect to Service Control Manager: {e}");
+                std::process::exit(1);
+            }
+        });
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            error!("Windows Service error: {e}");
+        }
+        std::process::exit(0);
+    }
+
+    fn run_service() -> windows_service::Result<()> {
+        let (shutdown_tx, shutdown_rx) = mpsc::channel();
+
+        let event_handler = move |control_event| -> ServiceControlHandlerResult {
+            match control_event {
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    let _ = shutdown_tx.send(());
+                    ServiceControlHandlerResult::NoError
+                }
+                _ => ServiceControlHandlerResult::NotImplemented,
+            }
+        };
+
+        let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)?;
+
+        let set_state = |current_state, controls_accepted| {
+            status_handle.set_service_status(ServiceStatus {
+                service_type: SERVICE_TYPE,
+                current_state,
+                controls_accepted,
+                exit_code: ServiceExitCode::Win32(0),
+                checkpoint: 0,
+                wait_hint: Duration::from_secs(5),
+                process_id: None,
+            })
+        };
+
+        set_state(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        )?;
+        info!("Running as Windows Service '{SERVICE_NAME}'");
+
+        let _ = shutdown_rx.recv();
+
+        info!("Windows Service stop requested, shutting down");
+        set_state(ServiceState::StopPending, ServiceControlAccept::empty())?;
+        set_state(ServiceState::Stopped, ServiceControlAccept::empty())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+mod imp {
+    pub fn install_service() -> Result<(), String> {
+        Err("Windows Service is only supported on Windows".to_string())
+    }
+
+    pub fn uninstall_service() -> Result<(), String> {
+        Err("Windows Service is only supported on Windows".to_string())
+    }
+
+    pub fn start_service_dispatcher() {}
+}
+
+pub use imp::{install_service, start_service_dispatcher, uninstall_service};
@@ -0,0 +1,240 @@
+//! Synthetic load test (`simulate` subcommand).
+//!
+//! Runs `--agents` virtual agents from one process to load-test a panel before rolling out
+//! real nodes. Agent `i` (from 1) authenticates with `<token>-<i>` in the query string, so
+//! the panel needs one node per suffixed token. Each one pushes a generated Basic Info,
+//! then Real-Time Info with random-walk metrics every `--realtime-info-interval` over a
+//! WebSocket of its own, reconnecting when it drops. Connections are opened at
+//! `--ramp-up` per second, a summary is logged every 10 seconds.
+
+use crate::auth::panel_headers;
+use crate::callbacks::message::PROTOCOL_VERSION;
+use crate::data_struct::{BasicInfo, RealTimeInfo};
+use crate::get_info::collector::MetricsCollector;
+use crate::get_info::network::TrafficOffset;
+use crate::utils::{build_urls, connect_ws};
+use futures::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use miniserde::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const MEM_TOTAL: u64 = 8 * 1024 * 1024 * 1024;
+const SWAP_TOTAL: u64 = 2 * 1024 * 1024 * 1024;
+const DISK_TOTAL: u64 = 100 * 1024 * 1024 * 1024;
+
+#[derive(Default)]
+struct Counters {
+    connected: AtomicU64,
+    sent: AtomicU64,
+    failures: AtomicU64,
+}
+
+pub struct Simulation {
+    pub http_server: String,
+    pub ws_server: Option<String>,
+    pub token: String,
+    pub agents: u32,
+    pub ramp_up: u32,
+    pub interval_ms: u64,
+    pub tls: bool,
+    pub ignore_unsafe_cert: bool,
+}
+
+/// xorshift, one per virtual agent
+struct Rng(u64);
+
+impl Rng {
+    /// Uniform in 0..1
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// `value` moved by at most `step` in either direction, kept within `0..=max`
+    fn walk(&mut self, value: f64, step: f64, max: f64) -> f64 {
+        (value + (self.next() * 2.0 - 1.0) * step).clamp(0.0, max)
+    }
+}
+
+fn basic_info(index: u32) -> BasicInfo {
+    BasicInfo {
+        arch: std::env::consts::ARCH.to_string(),
+        cpu_cores: 4,
+        cpu_name: "Simulated CPU".to_string(),
+        gpu_name: String::new(),
+        disk_total: DISK_TOTAL,
+        swap_total: SWAP_TOTAL,
+        mem_total: MEM_TOTAL,
+        mem_balloon: None,
+        ipv4: None,
+        ipv6: None,
+        os: format!("Simulated agent {index}"),
+        version: format!("komari-monitor-rs {} (simulate)", env!("CARGO_PKG_VERSION")),
+        kernel_version: String::new(),
+        virtualization: "simulated".to_string(),
+        interfaces: Vec::new(),
+        geo: None,
+        hostname: Some(format!("simulated-{index}")),
+        labels: BTreeMap::new(),
+        capabilities: Vec::new(),
+        protocol: PROTOCOL_VERSION,
+    }
+}
+
+/// The next sample of a virtual agent, moved a little from `previous`
+fn next_sample(previous: &RealTimeInfo, rng: &mut Rng, interval_s: f64) -> RealTimeInfo {
+    let mut sample = previous.clone();
+    sample.cpu.usage = rng.walk(previous.cpu.usage, 5.0, 100.0);
+    sample.ram.total = MEM_TOTAL;
+    sample.ram.used = rng.walk(previous.ram.used as f64, 64e6, MEM_TOTAL as f64) as u64;
+    sample.swap.total = SWAP_TOTAL;
+    sample.swap.used = rng.walk(previous.swap.used as f64, 8e6, SWAP_TOTAL as f64) as u64;
+    sample.disk.total = DISK_TOTAL;
+    sample.disk.used = rng.walk(previous.disk.used as f64, 1e6, DISK_TOTAL as f64) as u64;
+    sample.load.load1 = rng.walk(previous.load.load1, 0.3, 16.0);
+    sample.load.load5 = (previous.load.load5 * 4.0 + sample.load.load1) / 5.0;
+    sample.load.load15 = (previous.load.load15 * 14.0 + sample.load.load1) / 15.0;
+    sample.network.up = rng.walk(previous.network.up as f64, 2e5, 1.25e8) as u64;
+    sample.network.down = rng.walk(previous.network.down as f64, 2e5, 1.25e8) as u64;
+    sample.network.total_up += (sample.network.up as f64 * interval_s) as u64;
+    sample.network.total_down += (sample.network.down as f64 * interval_s) as u64;
+    sample.connections.tcp = rng.walk(previous.connections.tcp as f64, 5.0, 2000.0) as u64;
+    sample.connections.udp = rng.walk(previous.connections.udp as f64, 2.0, 500.0) as u64;
+    sample.process = rng.walk(previous.process as f64, 3.0, 1000.0) as u64;
+    sample.uptime += interval_s as u64;
+    sample
+}
+
+/// Run the virtual agents until the process is stopped
+pub async fn simulate(simulation: Simulation) {
+    let simulation = Arc::new(simulation);
+    let counters = Arc::new(Counters::default());
+    // The walks start from the values of this host
+    let template = RealTimeInfo::build_core(
+        &MetricsCollector::new(Arc::new(TrafficOffset::default())),
+        1.0,
+    );
+    info!(
+        "Simulating {} agents against {}, {} new connections per second",
+        simulation.agents,
+        simulation.http_server,
+        simulation.ramp_up.max(1)
+    );
+
+    let ramp_up = Duration::from_secs(1) / simulation.ramp_up.max(1);
+    for index in 1..=simulation.agents {
+        tokio::spawn(virtual_agent(
+            simulation.clone(),
+            index,
+            template.clone(),
+            counters.clone(),
+        ));
+        tokio::time::sleep(ramp_up).await;
+    }
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(10));
+    loop {
+        ticker.tick().await;
+        info!(
+            "Simulated agents: {} / {} connected, {} samples sent, {} failures",
+            counters.connected.load(Ordering::Relaxed),
+            simulation.agents,
+            counters.sent.load(Ordering::Relaxed),
+            counters.failures.load(Ordering::Relaxed)
+        );
+    }
+}
+
+async fn virtual_agent(
+    simulation: Arc<Simulation>,
+    index: u32,
+    template: RealTimeInfo,
+    counters: Arc<Counters>,
+) {
+    let token = format!("{}-{index}", simulation.token);
+    let urls = match build_urls(
+        &simulation.http_server,
+        simulation.ws_server.as_ref(),
+        &token,
+    ) {
+        Ok(urls) => urls,
+        Err(e) => {
+            error!("Simulated agent {index}: failed to parse server address: {e}");
+            return;
+        }
+    };
+
+    let basic_info_url = urls.basic_info.clone();
+    let ignore_unsafe_cert = simulation.ignore_unsafe_cert;
+    let basic_info_counters = counters.clone();
+    tokio::spawn(async move {
+        if basic_info(index)
+            .push_with_retry(
+                basic_info_url,
+                panel_headers(),
+                false,
+                ignore_unsafe_cert,
+                3,
+            )
+            .await
+            .is_err()
+        {
+            basic_info_counters.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+
+    let mut rng = Rng(u64::from(index).wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1);
+    let interval = Duration::from_millis(simulation.interval_ms.max(100));
+    let mut sample = template;
+    loop {
+        let ws_stream = match connect_ws(
+            &urls.ws_real_time,
+            simulation.tls,
+            simulation.ignore_unsafe_cert,
+            false,
+        )
+        .await
+        {
+            Ok(ws_stream) => ws_stream,
+            Err(e) => {
+                counters.failures.fetch_add(1, Ordering::Relaxed);
+                warn!("Simulated agent {index}: failed to connect: {e}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        counters.connected.fetch_add(1, Ordering::Relaxed);
+        let (mut write, mut read) = ws_stream.split();
+        // Reading answers the server's pings, the commands themselves are ignored
+        let reader = tokio::spawn(async move { while let Some(Ok(_)) = read.next().await {} });
+
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if reader.is_finished() {
+                break;
+            }
+            sample = next_sample(&sample, &mut rng, interval.as_secs_f64());
+            let json = json::to_string(&sample);
+            if let Err(e) = write.send(Message::Text(Utf8Bytes::from(json))).await {
+                warn!("Simulated agent {index}: connection lost: {e}");
+                break;
+            }
+            counters.sent.fetch_add(1, Ordering::Relaxed);
+        }
+
+        reader.abort();
+        counters.connected.fetch_sub(1, Ordering::Relaxed);
+        counters.failures.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
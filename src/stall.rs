@@ -0,0 +1,134 @@
+//! Watchdog of the collection loop itself (`--stall-intervals`).
+//!
+//! A sysinfo refresh or a send that blocks, e.g. on a hung NFS mount, stops the loop without
+//! any error and the agent silently stops reporting. The loop beats once per cycle and tells
+//! which phase it is in; a thread of its own checks the beats. Without one for
+//! `--stall-intervals` Real-Time Info intervals, never less than 3 minutes so that slow
+//! reconnects do not count, the stall is logged with its phase and, on Linux, the threads
+//! stuck in uninterruptible sleep with their kernel wait channel. A blocked call cannot be
+//! interrupted from the outside, so the agent then restarts: it executes itself again with
+//! the same arguments on Unix and exits with status 1 elsewhere, for the service manager to
+//! start it again.
+
+use log::error;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const STALL_MIN: Duration = Duration::from_mins(3);
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    Connecting,
+    Refreshing,
+    BuildingBasicInfo,
+    Sending,
+    Sleeping,
+}
+
+const PHASES: [&str; 5] = [
+    "connecting",
+    "refreshing the metrics",
+    "building the Basic Info",
+    "sending Real-Time Info",
+    "sleeping",
+];
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+/// Since `STARTED` (ms)
+static LAST_BEAT: AtomicU64 = AtomicU64::new(0);
+static PHASE: AtomicUsize = AtomicUsize::new(0);
+
+fn since_start() -> u64 {
+    let started = STARTED.get_or_init(Instant::now);
+    u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+/// The loop made progress
+pub fn beat() {
+    LAST_BEAT.store(since_start(), Ordering::Relaxed);
+}
+
+/// The loop moves on to `phase`
+pub fn enter(phase: Phase) {
+    PHASE.store(phase as usize, Ordering::Relaxed);
+}
+
+/// Threads in uninterruptible sleep (`D`) with their wait channel
+#[cfg(target_os = "linux")]
+fn blocked_threads() -> Vec<String> {
+    let Ok(tasks) = std::fs::read_dir("/proc/self/task") else {
+        return Vec::new();
+    };
+    tasks
+        .flatten()
+        .filter_map(|task| {
+            let path = task.path();
+            let stat = std::fs::read_to_string(path.join("stat")).ok()?;
+            // "tid (comm) state ...", the name may contain spaces and parentheses
+            let (head, rest) = stat.rsplit_once(')')?;
+            if rest.split_whitespace().next()? != "D" {
+                return None;
+            }
+            let name = head.split_once('(').map_or("?", |(_, name)| name);
+            let wchan = std::fs::read_to_string(path.join("wchan")).unwrap_or_default();
+            Some(format!("{name} ({})", wchan.trim()))
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn blocked_threads() -> Vec<String> {
+    Vec::new()
+}
+
+fn restart() -> ! {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        if let Ok(exe) = std::env::current_exe() {
+            let e = std::process::Command::new(exe)
+                .args(std::env::args_os().skip(1))
+                .exec();
+            error!("Failed to restart the agent: {e}");
+        }
+    }
+    std::process::exit(1);
+}
+
+/// Watch the beats from a thread of its own, `intervals` of `interval_ms` at the least
+pub fn spawn_stall_watchdog(intervals: u32, interval_ms: u64) {
+    if intervals == 0 {
+        return;
+    }
+    let limit =
+        Duration::from_millis(interval_ms.saturating_mul(u64::from(intervals))).max(STALL_MIN);
+    beat();
+    let spawned = std::thread::Builder::new()
+        .name("stall-watchdog".to_string())
+        .spawn(move || {
+            loop {
+                std::thread::sleep(CHECK_INTERVAL);
+                let silent = Duration::from_millis(
+                    since_start().saturating_sub(LAST_BEAT.load(Ordering::Relaxed)),
+                );
+                if silent < limit {
+                    continue;
+                }
+                let phase = PHASES[PHASE.load(Ordering::Relaxed)];
+                error!(
+                    "The collection loop is stuck {phase} for {} s, restarting the agent",
+                    silent.as_secs()
+                );
+                for thread in blocked_threads() {
+                    error!("Thread in uninterruptible sleep: {thread}");
+                }
+                restart();
+            }
+        });
+    if let Err(e) = spawned {
+        error!("Failed to start the stall watchdog: {e}");
+    }
+}
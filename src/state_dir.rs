@@ -0,0 +1,132 @@
+//! Relocation of state files away from read-only filesystems.
+//!
+//! On images with a read-only root, `/etc` or `/var` cannot be written and every save
+//! interval would log the same error. The directory of each state file is probed once at
+//! startup, the file moves to `--state-dir` when it is not writable. The state files are
+//! trusted when read back, so on Unix the directory is created private to the agent and an
+//! existing one is refused unless the agent's user owns it and nobody else can write to it.
+
+use log::{info, warn};
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+/// The first directory of systemd's `StateDirectory=`, else `/var/lib/komari-monitor`, a
+/// directory in the temp dir on Windows
+pub fn default_state_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("STATE_DIRECTORY")
+        .and_then(|dirs| dirs.to_string_lossy().split(':').next().map(PathBuf::from))
+        .filter(|dir| !dir.as_os_str().is_empty())
+    {
+        return dir;
+    }
+    if cfg!(unix) {
+        PathBuf::from("/var/lib/komari-monitor")
+    } else {
+        std::env::temp_dir().join("komari-monitor")
+    }
+}
+
+/// Fine when `path` does not exist or belongs to the agent's user with no write access for
+/// group or others. A symbolic link is judged by itself and always refused
+#[cfg(unix)]
+pub fn check_private(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if metadata.uid() != unsafe { libc::geteuid() } {
+        return Err(format!(
+            "{} is owned by uid {}, not by the agent's user",
+            path.display(),
+            metadata.uid()
+        ));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(format!(
+            "{} is writable by group or others (mode {:o})",
+            path.display(),
+            metadata.mode() & 0o7777
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_private(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// Fine when `dir` does not exist yet or is a directory passing `check_private`
+pub fn check_state_dir(dir: &Path) -> Result<(), String> {
+    check_private(dir)?;
+    if dir.exists() && !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+    Ok(())
+}
+
+/// Create `dir` with mode 0700, checked again in case it appeared meanwhile
+fn create_state_dir(dir: &Path) -> Result<(), String> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(dir)
+        .map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+    check_state_dir(dir)
+}
+
+/// Create and remove a probe file, the only check that also catches read-only mounts
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".komari-write-test-{}", std::process::id()));
+    match OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Path `what` should be written to: `path` itself when its directory is writable,
+/// otherwise the same file name inside `state_dir`
+pub fn writable_path(path: &str, state_dir: &Path, what: &str) -> String {
+    let file = Path::new(path);
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // An existing file that still opens for writing is fine even if the directory is not
+    if is_writable(dir) || (file.exists() && OpenOptions::new().append(true).open(file).is_ok()) {
+        return path.to_string();
+    }
+
+    let Some(name) = file.file_name() else {
+        return path.to_string();
+    };
+    if let Err(e) = create_state_dir(state_dir) {
+        warn!(
+            "{what} directory {} is not writable and the state directory is refused: {e}, saving will fail",
+            dir.display()
+        );
+        return path.to_string();
+    }
+    if !is_writable(state_dir) {
+        warn!(
+            "{what} directory {} is not writable and neither is {}, saving will fail",
+            dir.display(),
+            state_dir.display()
+        );
+        return path.to_string();
+    }
+
+    let relocated = state_dir.join(name);
+    info!(
+        "{what} directory {} is not writable, using {} instead",
+        dir.display(),
+        relocated.display()
+    );
+    relocated.to_string_lossy().to_string()
+}
@@ -0,0 +1,133 @@
+//! Opt-in UDP syslog receiver forwarding messages as events
+
+use crate::events::push_event;
+use crate::privacy::{privacy_mode, public_ip};
+use log::{error, info, trace, warn};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+const MAX_MESSAGE_LEN: usize = 512;
+
+/// Split `<PRI>message` (RFC 3164 / RFC 5424) into severity and message
+fn parse_syslog(datagram: &str) -> (u8, &str) {
+    let datagram = datagram.trim_end_matches(['\r', '\n', '\0']);
+    if let Some(rest) = datagram.strip_prefix('<')
+        && let Some((pri, message)) = rest.split_once('>')
+        && let Ok(pri) = pri.parse::<u16>()
+    {
+        // RFC 5424 messages carry a version after PRI
+        let message = message.strip_prefix("1 ").unwrap_or(message);
+        return ((pri % 8) as u8, message);
+    }
+    // No PRI part means user.notice
+    (5, datagram)
+}
+
+/// `message` without the HOSTNAME of its header, which follows the timestamp: one field in
+/// RFC 5424, `Mmm dd hh:mm:ss` in RFC 3164
+fn without_hostname(message: &str) -> String {
+    let fields = message
+        .split_whitespace()
+        .take(4)
+        .map(|field| (field.as_ptr() as usize - message.as_ptr() as usize, field))
+        .collect::<Vec<_>>();
+    let rfc5424 = fields
+        .first()
+        .is_some_and(|(_, timestamp)| *timestamp == "-" || timestamp.contains('T'));
+    let rfc3164 = fields
+        .get(2)
+        .is_some_and(|(_, time)| time.len() == 8 && time.matches(':').count() == 2);
+    let hostname = match (rfc5424, rfc3164) {
+        (true, _) => fields.get(1),
+        (_, true) => fields.get(3),
+        _ => None,
+    };
+    let Some((start, hostname)) = hostname else {
+        return message.to_string();
+    };
+    let rest = message[start + hostname.len()..].trim_start();
+    format!("{}{rest}", &message[..*start])
+}
+
+/// The socket passed by systemd, a new one bound to `listen` otherwise
+async fn bind(listen: &str) -> std::io::Result<UdpSocket> {
+    #[cfg(unix)]
+    if let Some(fd) = crate::systemd::take_listen_fd("syslog") {
+        let socket = std::net::UdpSocket::from(fd);
+        socket.set_nonblocking(true)?;
+        return UdpSocket::from_std(socket);
+    }
+    UdpSocket::bind(listen).await
+}
+
+/// Receive syslog datagrams on `listen` and forward those at most `min_severity`
+/// (0 = emerg ... 7 = debug), at most `rate_limit` messages per second
+pub async fn syslog_receiver(listen: String, min_severity: u8, rate_limit: u32) {
+    let socket = match bind(&listen).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Failed to bind syslog receiver on {listen}: {e}");
+            return;
+        }
+    };
+    match socket.local_addr() {
+        Ok(addr) => info!("Syslog receiver listening on {addr}"),
+        Err(_) => info!("Syslog receiver listening on {listen}"),
+    }
+
+    let mut buf = vec![0u8; 8192];
+    let mut window_start = Instant::now();
+    let mut forwarded = 0u32;
+    let mut dropped = 0u64;
+
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Syslog receiver error: {e}");
+                continue;
+            }
+        };
+
+        let datagram = String::from_utf8_lossy(&buf[..len]);
+        let (severity, message) = parse_syslog(&datagram);
+        trace!("Syslog from {peer}: <{severity}> {message}");
+        if severity > min_severity {
+            continue;
+        }
+
+        if window_start.elapsed() >= Duration::from_secs(1) {
+            if dropped > 0 {
+                warn!("Syslog receiver rate limited, dropped {dropped} message(s)");
+                dropped = 0;
+            }
+            window_start = Instant::now();
+            forwarded = 0;
+        }
+        if forwarded >= rate_limit {
+            dropped += 1;
+            continue;
+        }
+        forwarded += 1;
+
+        let mut message = message.to_string();
+        if message.len() > MAX_MESSAGE_LEN {
+            let mut end = MAX_MESSAGE_LEN;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+        if privacy_mode() {
+            message = without_hostname(&message);
+        }
+        push_event(format!(
+            "[syslog {} {}] {message}",
+            public_ip(peer.ip()),
+            SEVERITIES[usize::from(severity)]
+        ));
+    }
+}
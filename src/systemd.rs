@@ -0,0 +1,219 @@
+//! systemd integration: `sd_notify` readiness / watchdog, socket activation and unit file
+//! generation
+//!
+//! Sockets passed by systemd (`LISTEN_FDS`) are matched by their `FileDescriptorName=`:
+//! `control` for the control socket, `syslog` for the syslog receiver. A passed socket
+//! enables its listener even without `--control-socket` / `--syslog-listen`, so the
+//! agent can run unprivileged while systemd owns `/run/komari-agent.sock` or port 514.
+
+use crate::command_parser::Args;
+use log::{debug, info};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static READY_SENT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    use std::sync::OnceLock;
+
+    static NOTIFY: OnceLock<Option<(UnixDatagram, SocketAddr)>> = OnceLock::new();
+
+    fn notify_socket() -> Option<&'static (UnixDatagram, SocketAddr)> {
+        NOTIFY
+            .get_or_init(|| {
+                let path = std::env::var("NOTIFY_SOCKET").ok()?;
+                let addr = if let Some(name) = path.strip_prefix('@') {
+                    SocketAddr::from_abstract_name(name).ok()?
+                } else {
+                    SocketAddr::from_pathname(&path).ok()?
+                };
+                Some((UnixDatagram::unbound().ok()?, addr))
+            })
+            .as_ref()
+    }
+
+    pub fn sd_notify(state: &str) -> bool {
+        let Some((socket, addr)) = notify_socket() else {
+            return false;
+        };
+        socket.send_to_addr(state.as_bytes(), addr).is_ok()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    pub fn sd_notify(_state: &str) -> bool {
+        false
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod activation {
+    use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+    use std::sync::Mutex;
+
+    /// First passed descriptor, `SD_LISTEN_FDS_START`
+    const LISTEN_FDS_START: RawFd = 3;
+
+    static PASSED: Mutex<Option<Vec<(String, OwnedFd)>>> = Mutex::new(None);
+
+    /// The descriptors passed to this process with their names, read once
+    fn passed() -> Vec<(String, OwnedFd)> {
+        let env = |key| std::env::var(key).ok();
+        if env("LISTEN_PID").and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+            return Vec::new();
+        }
+        let Some(count) = env("LISTEN_FDS").and_then(|count| count.parse::<RawFd>().ok()) else {
+            return Vec::new();
+        };
+        let names = env("LISTEN_FDNAMES").unwrap_or_default();
+        let mut names = names.split(':');
+
+        (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                // Commands and terminals started by the agent must not inherit them
+                unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+                let name = names.next().unwrap_or_default().to_string();
+                // SAFETY: systemd hands these descriptors to this process only
+                (name, unsafe { OwnedFd::from_raw_fd(fd) })
+            })
+            .collect()
+    }
+
+    pub fn is_passed(name: &str) -> bool {
+        PASSED.lock().is_ok_and(|mut guard| {
+            guard
+                .get_or_insert_with(passed)
+                .iter()
+                .any(|(passed, _)| passed == name)
+        })
+    }
+
+    pub fn take_listen_fd(name: &str) -> Option<OwnedFd> {
+        let mut guard = PASSED.lock().ok()?;
+        let passed = guard.get_or_insert_with(passed);
+        let index = passed.iter().position(|(passed, _)| passed == name)?;
+        Some(passed.swap_remove(index).1)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod activation {
+    pub fn is_passed(_name: &str) -> bool {
+        false
+    }
+
+    #[cfg(unix)]
+    pub fn take_listen_fd(_name: &str) -> Option<std::os::fd::OwnedFd> {
+        None
+    }
+}
+
+/// Whether systemd passed a socket named `name`
+pub fn socket_passed(name: &str) -> bool {
+    activation::is_passed(name)
+}
+
+/// The socket systemd passed under `FileDescriptorName=name`, each one is handed out once
+#[cfg(unix)]
+pub fn take_listen_fd(name: &str) -> Option<std::os::fd::OwnedFd> {
+    let fd = activation::take_listen_fd(name);
+    if fd.is_some() {
+        info!("Using the `{name}` socket passed by systemd");
+    }
+    fd
+}
+
+/// Report `READY=1` once, after the first successful WebSocket connection
+pub fn notify_ready(status: &str) {
+    if !READY_SENT.swap(true, Ordering::Relaxed)
+        && imp::sd_notify(&format!("READY=1\nSTATUS={status}"))
+    {
+        debug!("Notified systemd: READY=1");
+    }
+}
+
+/// Report `WATCHDOG=1`, called on each push cycle
+pub fn notify_watchdog() {
+    imp::sd_notify("WATCHDOG=1");
+}
+
+/// Quote an argument for `ExecStart=`, escaping specifiers and variables
+fn quote_exec_arg(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    if escaped.is_empty() || escaped.contains(char::is_whitespace) || escaped != arg {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+/// Write a `Type=notify` unit that runs the agent with the arguments given before `install`
+pub fn install_systemd_unit(unit_path: &str, watchdog_sec: u64) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to get executable path: {e}"))?;
+
+    let mut exec_start = quote_exec_arg(&exe.to_string_lossy());
+    for arg in Args::service_arguments() {
+        exec_start.push(' ');
+        exec_start.push_str(&quote_exec_arg(&arg.to_string_lossy()));
+    }
+
+    let watchdog = if watchdog_sec > 0 {
+        format!("WatchdogSec={watchdog_sec}\n")
+    } else {
+        String::new()
+    };
+
+    let unit = format!(
+        "[Unit]\n\
+         Description=Komari Monitor Agent\n\
+         After=network-online.target\n\
+         Wants=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         NotifyAccess=main\n\
+         ExecStart={exec_start}\n\
+         Restart=always\n\
+         RestartSec=5\n\
+         {watchdog}\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n"
+    );
+
+    std::fs::write(unit_path, unit).map_err(|e| format!("Failed to write {unit_path}: {e}"))?;
+
+    let unit_name = std::path::Path::new(unit_path).file_name().map_or_else(
+        || unit_path.to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    info!("systemd unit written to {unit_path}");
+    info!("Run `systemctl daemon-reload && systemctl enable --now {unit_name}` to start it");
+    Ok(())
+}
+
+/// Stop and disable the unit written by `install_systemd_unit`, then remove it
+pub fn uninstall_systemd_unit(unit_path: &str) -> Result<(), String> {
+    let unit_name = std::path::Path::new(unit_path).file_name().map_or_else(
+        || unit_path.to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    // Best effort, the unit may never have been enabled or systemd may not be running
+    let _ = std::process::Command::new("systemctl")
+        .args(["disable", "--now", &unit_name])
+        .status();
+
+    std::fs::remove_file(unit_path).map_err(|e| format!("Failed to remove {unit_path}: {e}"))?;
+    let _ = std::process::Command::new("systemctl")
+        .arg("daemon-reload")
+        .status();
+    info!("systemd unit {unit_path} removed");
+    Ok(())
+}
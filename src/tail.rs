@@ -0,0 +1,183 @@
+//! Opt-in log file forwarder (`--tail-file`).
+//!
+//! The files are polled every second from their end at start, lines written since are
+//! forwarded as events when they contain one of the `--tail-match` patterns (all lines
+//! without any). A file that is rotated (replaced) or truncated is read again from its
+//! start, one that does not exist yet is picked up once it appears.
+
+use crate::events::push_event;
+use log::{info, trace, warn};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_LINE_LEN: usize = 512;
+/// Bytes read from one file per poll, the rest waits for the next one
+const MAX_READ: u64 = 1024 * 1024;
+
+struct Tailer {
+    path: String,
+    /// Short name in the events
+    label: String,
+    file: Option<File>,
+    position: u64,
+    /// Identity of the open file, to notice it was replaced
+    id: Option<(u64, u64)>,
+    /// Start of a line whose end was not written yet
+    partial: Vec<u8>,
+    /// Whether the file existed when tailing started, those are read from the end
+    from_end: bool,
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    // Some network filesystems report no inodes
+    (metadata.ino() != 0).then_some((metadata.dev(), metadata.ino()))
+}
+
+/// Without an inode a replaced file is only noticed once it is shorter than the position
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+impl Tailer {
+    fn new(path: String) -> Self {
+        let label = Path::new(&path)
+            .file_name()
+            .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned());
+        Self {
+            path,
+            label,
+            file: None,
+            position: 0,
+            id: None,
+            partial: Vec::new(),
+            from_end: true,
+        }
+    }
+
+    /// Open the file at the path, at its end the first time
+    fn open(&mut self) {
+        let Ok(mut file) = File::open(&self.path) else {
+            self.from_end = false;
+            return;
+        };
+        let Ok(metadata) = file.metadata() else {
+            return;
+        };
+        let position = if self.from_end { metadata.len() } else { 0 };
+        if file.seek(SeekFrom::Start(position)).is_err() {
+            return;
+        }
+        trace!("Tailing {} from byte {position}", self.path);
+        self.file = Some(file);
+        self.position = position;
+        self.id = file_id(&metadata);
+        self.partial.clear();
+        self.from_end = false;
+    }
+
+    /// Complete lines written since the last poll
+    fn poll(&mut self) -> Vec<String> {
+        if self.file.is_none() {
+            self.open();
+        }
+        let mut lines = self.read();
+
+        // Rotated: the rest of the old file was just read, continue with the new one
+        let current = std::fs::metadata(&self.path).ok();
+        let replaced = current
+            .as_ref()
+            .is_some_and(|metadata| file_id(metadata).is_some() && file_id(metadata) != self.id);
+        let truncated = current
+            .as_ref()
+            .is_some_and(|metadata| metadata.len() < self.position);
+        if self.file.is_some() && (replaced || truncated) {
+            trace!(
+                "{} was rotated or truncated, reading it from the start",
+                self.path
+            );
+            self.file = None;
+            self.open();
+            lines.extend(self.read());
+        }
+        lines
+    }
+
+    fn read(&mut self) -> Vec<String> {
+        let Some(file) = self.file.as_mut() else {
+            return Vec::new();
+        };
+        let mut buf = Vec::new();
+        match file.take(MAX_READ).read_to_end(&mut buf) {
+            Ok(read) => self.position += read as u64,
+            Err(e) => {
+                warn!("Failed to read {}: {e}", self.path);
+                self.file = None;
+                return Vec::new();
+            }
+        }
+
+        self.partial.extend_from_slice(&buf);
+        let Some(end) = self.partial.iter().rposition(|byte| *byte == b'\n') else {
+            return Vec::new();
+        };
+        let complete = self.partial.drain(..=end).collect::<Vec<_>>();
+        String::from_utf8_lossy(&complete)
+            .lines()
+            .map(|line| line.trim_end_matches('\r').to_string())
+            .filter(|line| !line.trim().is_empty())
+            .collect()
+    }
+}
+
+/// Forward new lines of `paths` containing one of `patterns`, at most `rate_limit` per second
+pub async fn tail_files(paths: Vec<String>, patterns: Vec<String>, rate_limit: u32) {
+    let mut tailers = paths.into_iter().map(Tailer::new).collect::<Vec<_>>();
+    for tailer in &mut tailers {
+        tailer.open();
+        if tailer.file.is_some() {
+            info!("Tailing {}", tailer.path);
+        } else {
+            warn!(
+                "{} does not exist yet, tailing it once it appears",
+                tailer.path
+            );
+        }
+    }
+
+    let mut ticker = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let mut forwarded = 0u32;
+        let mut dropped = 0u64;
+        for tailer in &mut tailers {
+            for mut line in tailer.poll() {
+                if !patterns.is_empty() && !patterns.iter().any(|pattern| line.contains(pattern)) {
+                    continue;
+                }
+                if forwarded >= rate_limit {
+                    dropped += 1;
+                    continue;
+                }
+                forwarded += 1;
+
+                if line.len() > MAX_LINE_LEN {
+                    let mut end = MAX_LINE_LEN;
+                    while !line.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    line.truncate(end);
+                }
+                push_event(format!("[log {}] {line}", tailer.label));
+            }
+        }
+        if dropped > 0 {
+            warn!("Log forwarder rate limited, dropped {dropped} line(s)");
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! More panels reported to from the same process (`--tenant TOKEN@URL`).
+//!
+//! Every tenant is a panel with a token of its own, e.g. one per customer when a host has
+//! to show up in several panels. The collection loop stays the same: each Real-Time Info the
+//! main connection sends goes to every tenant over a WebSocket of its own and every Basic
+//! Info pushed to the main panel is pushed to every tenant too. Tenants get the token in the
+//! query string whatever `--auth-mode`, so the main token never reaches them, the `--header`
+//! ones do. Tenants only report: the tasks they send are ignored, remote control stays with
+//! the main panel, and nothing is sent to them while the main loop is not running.
+
+use crate::auth::tenant_headers;
+use crate::data_struct::{BasicInfo, RealTimeInfo};
+use crate::utils::{ConnectionUrls, build_urls_with, connect_ws_with_headers};
+use futures::{SinkExt, StreamExt};
+use log::{info, warn};
+use miniserde::json;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+use url::Url;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Real-Time Info a slow tenant may lag behind before it skips some
+const BACKLOG: usize = 16;
+
+/// A `--tenant`
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    /// Host of the panel, for the logs
+    pub name: String,
+    pub urls: ConnectionUrls,
+}
+
+struct Shared {
+    real_time: broadcast::Sender<RealTimeInfo>,
+    basic_info: watch::Sender<Option<BasicInfo>>,
+}
+
+static SHARED: OnceLock<Shared> = OnceLock::new();
+
+/// Parse `TOKEN@URL` specs, the WebSocket URL follows from the HTTP one
+pub fn parse_tenants(specs: &[String]) -> Result<Vec<Tenant>, String> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (token, server) = spec
+                .split_once('@')
+                .filter(|(token, server)| !token.is_empty() && !server.is_empty())
+                .ok_or_else(|| format!("Invalid tenant '{spec}', expected TOKEN@URL"))?;
+            let url = Url::parse(server).map_err(|e| format!("Invalid URL '{server}': {e}"))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(format!(
+                    "Invalid URL '{server}', expected http:// or https://"
+                ));
+            }
+            let name = url.host_str().unwrap_or(server).to_string();
+            let urls = build_urls_with(server, None, token, true)
+                .map_err(|e| format!("Invalid URL '{server}': {e}"))?;
+            Ok(Tenant { name, urls })
+        })
+        .collect()
+}
+
+/// Connect to every tenant in the background
+pub fn spawn_tenants(tenants: Vec<Tenant>, tls: bool, ignore_unsafe_cert: bool) {
+    if tenants.is_empty() {
+        return;
+    }
+    let shared = SHARED.get_or_init(|| Shared {
+        real_time: broadcast::channel(BACKLOG).0,
+        basic_info: watch::channel(None).0,
+    });
+    info!("Reporting to {} more panel(s)", tenants.len());
+    for tenant in tenants {
+        tokio::spawn(run_tenant(
+            tenant,
+            tls,
+            ignore_unsafe_cert,
+            shared.real_time.clone(),
+            shared.basic_info.subscribe(),
+        ));
+    }
+}
+
+/// Send a Real-Time Info to every tenant
+pub fn share_real_time(real_time: &RealTimeInfo) {
+    if let Some(shared) = SHARED.get() {
+        let _ = shared.real_time.send(real_time.clone());
+    }
+}
+
+/// Push a Basic Info to every tenant
+pub fn share_basic_info(basic_info: &BasicInfo) {
+    if let Some(shared) = SHARED.get() {
+        shared.basic_info.send_replace(Some(basic_info.clone()));
+    }
+}
+
+async fn run_tenant(
+    tenant: Tenant,
+    tls: bool,
+    ignore_unsafe_cert: bool,
+    real_time: broadcast::Sender<RealTimeInfo>,
+    mut basic_info: watch::Receiver<Option<BasicInfo>>,
+) {
+    let name = tenant.name;
+    loop {
+        let ws_stream = match connect_ws_with_headers(
+            &tenant.urls.ws_real_time,
+            tls,
+            ignore_unsafe_cert,
+            false,
+            tenant_headers(),
+        )
+        .await
+        {
+            Ok(ws_stream) => ws_stream,
+            Err(e) => {
+                warn!("Tenant {name}: failed to connect: {e}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        info!("Tenant {name}: connected");
+        let (mut write, mut read) = ws_stream.split();
+        // Reading answers the server's pings, the tasks themselves are ignored
+        let mut reader = tokio::spawn(async move { while let Some(Ok(_)) = read.next().await {} });
+        let mut frames = real_time.subscribe();
+        // The latest Basic Info goes out again with every connection
+        basic_info.mark_changed();
+
+        loop {
+            tokio::select! {
+                _ = &mut reader => {
+                    warn!("Tenant {name}: connection closed");
+                    break;
+                }
+                changed = basic_info.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    let Some(info) = basic_info.borrow_and_update().clone() else {
+                        continue;
+                    };
+                    let url = tenant.urls.basic_info.clone();
+                    let name = name.clone();
+                    tokio::spawn(async move {
+                        if info
+                            .push_with_retry(url, tenant_headers(), false, ignore_unsafe_cert, 5)
+                            .await
+                            .is_err()
+                        {
+                            warn!("Tenant {name}: failed to push Basic Info");
+                        }
+                    });
+                }
+                frame = frames.recv() => {
+                    let frame = match frame {
+                        Ok(frame) => frame,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Tenant {name}: too slow, skipped {skipped} Real-Time Info");
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
+                    };
+                    let json = json::to_string(&frame);
+                    if let Err(e) = write.send(Message::Text(Utf8Bytes::from(json))).await {
+                        warn!("Tenant {name}: connection lost: {e}");
+                        break;
+                    }
+                }
+            }
+        }
+
+        reader.abort();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
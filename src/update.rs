@@ -0,0 +1,190 @@
+//! Self-update (`update` subcommand).
+//!
+//! Downloads the release asset built for this platform and puts it in place of the running
+//! executable. The new version runs from the next start, a service has to be restarted.
+//! Only the `x86_64`, `aarch64` and `i686` Linux builds and the `arm64` macOS build are
+//! recognised, the other platforms name their asset with `--asset`. The download only
+//! replaces the executable when its SHA-256 matches `--sha256`, taken from the release page,
+//! and never over a connection with `--ignore-unsafe-cert`.
+
+use log::info;
+use ring::digest::{SHA256, digest};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+pub const RELEASE_URL: &str =
+    "https://github.com/GenshinMinecraft/komari-monitor-rs/releases/download/latest";
+/// Anything larger is not an agent binary
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+/// Upper bound of the download, `--request-timeout` is meant for small requests
+const MAX_DURATION: Duration = Duration::from_mins(10);
+
+/// Name of the release asset matching this build, `None` when it cannot be told apart
+fn default_asset() -> Option<String> {
+    let libc = if cfg!(target_env = "musl") {
+        "musl"
+    } else {
+        "gnu"
+    };
+    let nyquest = if cfg!(feature = "nyquest-support") {
+        "-nyquest"
+    } else {
+        ""
+    };
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", arch @ ("x86_64" | "aarch64" | "i686")) => {
+            Some(format!("komari-monitor-rs-linux-{arch}-{libc}"))
+        }
+        ("macos", "aarch64") => Some(format!("komari-monitor-rs-macos-arm64{nyquest}")),
+        _ => None,
+    }
+}
+
+/// Whether `binary` starts like an executable of this platform, guards against error pages
+fn is_executable(binary: &[u8]) -> bool {
+    if cfg!(target_os = "windows") {
+        binary.starts_with(b"MZ")
+    } else if cfg!(target_os = "macos") {
+        binary.starts_with(&[0xcf, 0xfa, 0xed, 0xfe])
+            || binary.starts_with(&[0xca, 0xfe, 0xba, 0xbe])
+    } else {
+        binary.starts_with(b"\x7fELF")
+    }
+}
+
+#[cfg(feature = "ureq-support")]
+fn download(url: &str, ignore_unsafe_cert: bool) -> Result<Vec<u8>, String> {
+    use crate::utils::timeouts;
+
+    let agent = ureq::Agent::config_builder()
+        .tls_config(
+            ureq::tls::TlsConfig::builder()
+                .disable_verification(ignore_unsafe_cert)
+                .build(),
+        )
+        .timeout_connect(Some(timeouts().connect))
+        .timeout_global(Some(MAX_DURATION))
+        .build()
+        .new_agent();
+    let mut response = agent
+        .get(url)
+        .header("User-Agent", "curl/11.45.14-rs")
+        .call()
+        .map_err(|e| format!("Failed to download {url}: {e}"))?;
+    response
+        .body_mut()
+        .with_config()
+        .limit(MAX_SIZE)
+        .read_to_vec()
+        .map_err(|e| format!("Failed to download {url}: {e}"))
+}
+
+#[cfg(feature = "nyquest-support")]
+fn download(url: &str, ignore_unsafe_cert: bool) -> Result<Vec<u8>, String> {
+    use nyquest::Request;
+
+    let mut client = nyquest::ClientBuilder::default()
+        .request_timeout(MAX_DURATION)
+        .user_agent("curl/8.7.1");
+    if ignore_unsafe_cert {
+        client = client.dangerously_ignore_certificate_errors();
+    }
+    let response = client
+        .build_blocking()
+        .map_err(|e| e.to_string())?
+        .request(Request::get(url.to_string()))
+        .map_err(|e| format!("Failed to download {url}: {e}"))?;
+    if !response.status().is_successful() {
+        return Err(format!(
+            "Failed to download {url}, HTTP status code: {}",
+            response.status()
+        ));
+    }
+    let binary = response
+        .bytes()
+        .map_err(|e| format!("Failed to download {url}: {e}"))?;
+    if binary.len() as u64 > MAX_SIZE {
+        return Err(format!("Download exceeds {MAX_SIZE} bytes"));
+    }
+    Ok(binary)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+/// Replace `exe` by `binary`, through a file next to it so a failed write leaves it intact
+fn replace(exe: &Path, binary: &[u8]) -> Result<(), String> {
+    let staged = exe.with_extension("new");
+    std::fs::write(&staged, binary)
+        .map_err(|e| format!("Failed to write {}: {e}", staged.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("Failed to make {} executable: {e}", staged.display()))?;
+    }
+
+    // A running executable cannot be overwritten on Windows, but it can be renamed
+    #[cfg(windows)]
+    {
+        let old = exe.with_extension("old");
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old)
+            .map_err(|e| format!("Failed to move {} aside: {e}", exe.display()))?;
+    }
+
+    std::fs::rename(&staged, exe).map_err(|e| format!("Failed to replace {}: {e}", exe.display()))
+}
+
+/// Download `asset` (or the one of this platform) from `url` and install it over this
+/// executable if its digest is `sha256`
+pub fn update(
+    url: &str,
+    asset: Option<&str>,
+    sha256: &str,
+    ignore_unsafe_cert: bool,
+) -> Result<(), String> {
+    if ignore_unsafe_cert {
+        return Err("Refusing to update with `--ignore-unsafe-cert`".to_string());
+    }
+    let asset = match asset {
+        Some(asset) => asset.to_string(),
+        None => default_asset().ok_or_else(|| {
+            format!(
+                "No release asset known for {}-{}, pass one with `--asset`",
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            )
+        })?,
+    };
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to get executable path: {e}"))?;
+
+    let url = format!("{}/{asset}", url.trim_end_matches('/'));
+    info!("Downloading {url}");
+    let binary = download(&url, ignore_unsafe_cert)?;
+    if !is_executable(&binary) {
+        return Err(format!(
+            "{url} is not an executable for this platform, nothing was replaced"
+        ));
+    }
+    let actual = hex(digest(&SHA256, &binary).as_ref());
+    if !sha256.trim().eq_ignore_ascii_case(&actual) {
+        return Err(format!(
+            "Checksum mismatch: expected {sha256}, got {actual}, nothing was replaced"
+        ));
+    }
+
+    replace(&exe, &binary)?;
+    info!(
+        "Updated {} ({} bytes), restart the agent to run the new version",
+        exe.display(),
+        binary.len()
+    );
+    Ok(())
+}
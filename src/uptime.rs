@@ -0,0 +1,231 @@
+//! HTTP uptime checks (`--http-check`).
+//!
+//! Each configured URL gets a GET every interval, redirects followed. A check is up when
+//! the final status code is below 400. Every Real-Time Info carries the latest status code
+//! and response time per URL with the share of successful checks over the last `WINDOW`,
+//! going down or coming back up is also queued as an event.
+
+use crate::data_struct::HttpCheck;
+use crate::events::push_event;
+use log::{info, trace, warn};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+
+/// Checks per URL the reported uptime is computed over
+const WINDOW: usize = 60;
+const DEFAULT_INTERVAL: u64 = 60;
+
+#[derive(Debug, Clone)]
+pub struct CheckTarget {
+    pub name: String,
+    pub url: String,
+    pub interval: Duration,
+}
+
+/// Outcome of one check
+struct Outcome {
+    status: Option<u16>,
+    /// ms until the response headers
+    response_time: Option<f64>,
+    error: Option<String>,
+}
+
+impl Outcome {
+    fn up(&self) -> bool {
+        self.status.is_some_and(|status| status < 400)
+    }
+}
+
+struct CheckState {
+    target: CheckTarget,
+    latest: Option<Outcome>,
+    /// Whether the latest checks were up
+    history: VecDeque<bool>,
+}
+
+static CHECKS: Mutex<Vec<CheckState>> = Mutex::new(Vec::new());
+
+/// Parse `[NAME=]URL[@SECONDS]` specs
+pub fn parse_http_checks(specs: &[String]) -> Result<Vec<CheckTarget>, String> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (name, rest) = match spec.split_once('=') {
+                Some((name, rest)) if !name.contains(':') && !name.trim().is_empty() => {
+                    (Some(name.trim()), rest)
+                }
+                _ => (None, spec.as_str()),
+            };
+            let (url, interval) = match rest.rsplit_once('@') {
+                Some((url, seconds)) if seconds.chars().all(|c| c.is_ascii_digit()) => {
+                    let seconds = seconds
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|seconds| *seconds > 0)
+                        .ok_or_else(|| format!("Invalid HTTP check interval in '{spec}'"))?;
+                    (url, seconds)
+                }
+                _ => (rest, DEFAULT_INTERVAL),
+            };
+            let url = url.trim();
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!(
+                    "Invalid HTTP check '{spec}', expected [NAME=]URL[@SECONDS] with an http(s) URL"
+                ));
+            }
+            Ok(CheckTarget {
+                name: name.unwrap_or(url).to_string(),
+                url: url.to_string(),
+                interval: Duration::from_secs(interval),
+            })
+        })
+        .collect()
+}
+
+#[cfg(feature = "ureq-support")]
+fn get(url: &str, ignore_unsafe_cert: bool) -> Result<u16, String> {
+    use crate::utils::create_ureq_agent;
+    let resp = create_ureq_agent(ignore_unsafe_cert)
+        .get(url)
+        .header("User-Agent", "curl/11.45.14-rs")
+        .config()
+        .http_status_as_error(false)
+        .build()
+        .call()
+        .map_err(|e| e.to_string())?;
+    Ok(resp.status().as_u16())
+}
+
+#[cfg(feature = "nyquest-support")]
+fn get(url: &str, ignore_unsafe_cert: bool) -> Result<u16, String> {
+    use nyquest::Request;
+    let client = crate::utils::create_nyquest_client(ignore_unsafe_cert);
+    let resp = client
+        .request(Request::get(url.to_string()))
+        .map_err(|e| e.to_string())?;
+    Ok(resp.status().code())
+}
+
+fn check(url: &str, ignore_unsafe_cert: bool) -> Outcome {
+    let started = Instant::now();
+    match get(url, ignore_unsafe_cert) {
+        Ok(status) => Outcome {
+            status: Some(status),
+            response_time: Some(started.elapsed().as_secs_f64() * 1000.0),
+            error: None,
+        },
+        Err(e) => Outcome {
+            status: None,
+            response_time: None,
+            error: Some(e),
+        },
+    }
+}
+
+/// Check every URL on its own interval until the process stops
+pub fn spawn_http_checks(targets: Vec<CheckTarget>, ignore_unsafe_cert: bool) {
+    if let Ok(mut states) = CHECKS.lock() {
+        *states = targets
+            .iter()
+            .map(|target| CheckState {
+                target: target.clone(),
+                latest: None,
+                history: VecDeque::with_capacity(WINDOW),
+            })
+            .collect();
+    }
+
+    for (index, target) in targets.into_iter().enumerate() {
+        info!(
+            "Checking {} ({}) every {} s",
+            target.name,
+            target.url,
+            target.interval.as_secs()
+        );
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(target.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let url = target.url.clone();
+                let Ok(outcome) =
+                    tokio::task::spawn_blocking(move || check(&url, ignore_unsafe_cert)).await
+                else {
+                    continue;
+                };
+                trace!(
+                    "HTTP check {}: {:?} {:?}",
+                    target.name, outcome.status, outcome.error
+                );
+                record(index, &target, outcome);
+            }
+        });
+    }
+}
+
+fn record(index: usize, target: &CheckTarget, outcome: Outcome) {
+    let Ok(mut states) = CHECKS.lock() else {
+        return;
+    };
+    let Some(state) = states.get_mut(index) else {
+        return;
+    };
+
+    let up = outcome.up();
+    // The first check only reports a service that is down
+    let was_up = state.latest.as_ref().is_none_or(Outcome::up);
+    if up != was_up {
+        let reason = outcome
+            .error
+            .clone()
+            .unwrap_or_else(|| format!("HTTP status code {}", outcome.status.unwrap_or_default()));
+        if up {
+            info!("HTTP check {} is up again", target.name);
+            push_event(format!("[http-check] {} up", target.name));
+        } else {
+            warn!("HTTP check {} is down: {reason}", target.name);
+            push_event(format!("[http-check] {} down: {reason}", target.name));
+        }
+    }
+
+    if state.history.len() == WINDOW {
+        state.history.pop_front();
+    }
+    state.history.push_back(up);
+    state.latest = Some(outcome);
+}
+
+/// Latest result per URL, `None` without checks
+pub fn realtime_http_checks() -> Option<Vec<HttpCheck>> {
+    let states = CHECKS.lock().ok()?;
+    if states.is_empty() {
+        return None;
+    }
+
+    Some(
+        states
+            .iter()
+            .map(|state| {
+                let checks = state.history.len() as u64;
+                let successful = state.history.iter().filter(|up| **up).count() as u64;
+                let latest = state.latest.as_ref();
+                HttpCheck {
+                    name: state.target.name.clone(),
+                    url: state.target.url.clone(),
+                    up: latest.is_some_and(Outcome::up),
+                    status: latest.and_then(|latest| latest.status),
+                    response_time: latest.and_then(|latest| latest.response_time),
+                    error: latest.and_then(|latest| latest.error.clone()),
+                    checks,
+                    uptime: if checks == 0 {
+                        0.0
+                    } else {
+                        successful as f64 * 100.0 / checks as f64
+                    },
+                }
+            })
+            .collect(),
+    )
+}
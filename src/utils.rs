@@ -1,27 +1,91 @@
-use crate::command_parser::LogLevel;
-use crate::rustls_config::create_dangerous_config;
-use log::Level;
+use crate::auth::{panel_headers, token_in_query};
+use crate::command_parser::{ConnectIpFamily, LogFormat, LogLevel};
+use crate::event_log::EventLog;
+use crate::get_info::network::own::Counted;
+use crate::resolver::{custom_resolver, resolve};
+use crate::rustls_config::{create_dangerous_config, root_store};
+use crate::ws_deflate::{DeflateStream, EXTENSION_OFFER};
+use log::{Level, LevelFilter, Metadata, Record, info, warn};
+use miniserde::{Serialize, json};
+use rustls::ClientConfig;
+use rustls_pki_types::ServerName;
+use simple_logger::SimpleLogger;
 use std::fmt::Display;
-use std::sync::Arc;
+use std::net::IpAddr;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
 use tokio::net::TcpStream;
 use tokio::time::timeout;
-use tokio_tungstenite::{
-    Connector, MaybeTlsStream, WebSocketStream, connect_async, connect_async_tls_with_config,
-};
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, client_async};
 use url::{ParseError, Url};
 
-pub fn init_logger(log_level: &LogLevel) {
-    #[cfg(target_os = "windows")]
-    simple_logger::set_up_windows_color_terminal();
+pub fn init_logger(log_level: &LogLevel, log_format: &LogFormat, event_log: bool) {
+    // The logger lets everything through, the global maximum filters so that a reload can
+    // change it
+    match log_format {
+        LogFormat::Text => {
+            #[cfg(target_os = "windows")]
+            simple_logger::set_up_windows_color_terminal();
 
-    match log_level {
-        LogLevel::Error => simple_logger::init_with_level(Level::Error).unwrap(),
-        LogLevel::Warn => simple_logger::init_with_level(Level::Warn).unwrap(),
-        LogLevel::Info => simple_logger::init_with_level(Level::Info).unwrap(),
-        LogLevel::Debug => simple_logger::init_with_level(Level::Debug).unwrap(),
-        LogLevel::Trace => simple_logger::init_with_level(Level::Trace).unwrap(),
+            if event_log {
+                log::set_boxed_logger(Box::new(EventLog::new(Box::new(SimpleLogger::new()))))
+                    .unwrap();
+            } else {
+                simple_logger::init_with_level(Level::Trace).unwrap();
+            }
+        }
+        LogFormat::Json if event_log => {
+            log::set_boxed_logger(Box::new(EventLog::new(Box::new(JsonLogger)))).unwrap();
+        }
+        LogFormat::Json => log::set_logger(&JsonLogger).unwrap(),
     }
+    set_log_level(log_level);
+}
+
+#[derive(Serialize)]
+struct JsonLogLine {
+    timestamp: String,
+    level: String,
+    target: String,
+    message: String,
+}
+
+/// One JSON object per line on stderr (`--log-format json`), for Loki / ELK
+struct JsonLogger;
+
+impl log::Log for JsonLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let line = JsonLogLine {
+            timestamp: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            level: record.level().to_string(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        eprintln!("{}", json::to_string(&line));
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn set_log_level(log_level: &LogLevel) {
+    log::set_max_level(match log_level {
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Debug => LevelFilter::Debug,
+        LogLevel::Trace => LevelFilter::Trace,
+    });
 }
 
 #[derive(Debug, Clone)]
@@ -30,6 +94,10 @@ pub struct ConnectionUrls {
     pub exec_callback: String,
     pub ws_terminal: String,
     pub ws_real_time: String,
+    /// The same endpoint as `ws_real_time` over HTTP, for `--http-summary-interval`
+    pub http_real_time: String,
+    /// Long-polled for the server's messages by the HTTP fallback
+    pub http_tasks: String,
 }
 
 impl Display for ConnectionUrls {
@@ -38,7 +106,9 @@ impl Display for ConnectionUrls {
         writeln!(f, "  Basic Info URL: {}", self.basic_info)?;
         writeln!(f, "  Exec Callback URL: {}", self.exec_callback)?;
         writeln!(f, "  WebSocket Terminal URL: {}", self.ws_terminal)?;
-        writeln!(f, "  WebSocket Real-time URL: {}", self.ws_real_time)
+        writeln!(f, "  WebSocket Real-time URL: {}", self.ws_real_time)?;
+        writeln!(f, "  HTTP Real-time URL: {}", self.http_real_time)?;
+        writeln!(f, "  HTTP Tasks URL: {}", self.http_tasks)
     }
 }
 
@@ -46,6 +116,16 @@ pub fn build_urls(
     http_server: &str,
     ws_server: Option<&String>,
     token: &str,
+) -> Result<ConnectionUrls, ParseError> {
+    build_urls_with(http_server, ws_server, token, token_in_query())
+}
+
+/// `build_urls` with the token in the query string or not in the URLs at all
+pub fn build_urls_with(
+    http_server: &str,
+    ws_server: Option<&String>,
+    token: &str,
+    in_query: bool,
 ) -> Result<ConnectionUrls, ParseError> {
     // 1. Construct http_url_base
     let http_url = Url::parse(http_server)?;
@@ -65,81 +145,309 @@ pub fn build_urls(
     };
     let ws_url_base = ws_url.as_str().trim_end_matches('/').to_string();
 
-    // 3. Construct final URLs
-    let basic_info_url = format!("{http_url_base}/api/clients/uploadBasicInfo?token={token}");
-    let exec_callback_url = format!("{http_url_base}/api/clients/task/result?token={token}");
-    let ws_terminal_url = format!("{ws_url_base}/api/clients/terminal?token={token}");
-    let ws_real_time_url = format!("{ws_url_base}/api/clients/report?token={token}");
+    // 3. Construct final URLs, the token goes in a header unless `--auth-mode query`
+    let query = if in_query {
+        format!("?token={token}")
+    } else {
+        String::new()
+    };
+    let basic_info_url = format!("{http_url_base}/api/clients/uploadBasicInfo{query}");
+    let exec_callback_url = format!("{http_url_base}/api/clients/task/result{query}");
+    let ws_terminal_url = format!("{ws_url_base}/api/clients/terminal{query}");
+    let ws_real_time_url = format!("{ws_url_base}/api/clients/report{query}");
+    let http_real_time_url = format!("{http_url_base}/api/clients/report{query}");
+    let http_tasks_url = format!("{http_url_base}/api/clients/tasks{query}");
 
     let connection_urls = ConnectionUrls {
         basic_info: basic_info_url,
         exec_callback: exec_callback_url,
         ws_terminal: ws_terminal_url,
         ws_real_time: ws_real_time_url,
+        http_real_time: http_real_time_url,
+        http_tasks: http_tasks_url,
     };
 
     Ok(connection_urls)
 }
 
+/// Network timeouts from `--connect-timeout` and `--request-timeout`
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// DNS, TCP, TLS and the WebSocket handshake
+    pub connect: Duration,
+    /// Sending an HTTP request and reading its response once connected
+    pub request: Duration,
+}
+
+impl Timeouts {
+    /// Upper bound of a whole HTTP request, for clients without per-phase timeouts
+    pub fn total(self) -> Duration {
+        self.connect + self.request
+    }
+}
+
+static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+
+pub fn set_timeouts(connect_s: u64, request_s: u64) {
+    let _ = TIMEOUTS.set(Timeouts {
+        connect: Duration::from_secs(connect_s),
+        request: Duration::from_secs(request_s),
+    });
+}
+
+pub fn timeouts() -> Timeouts {
+    *TIMEOUTS.get_or_init(|| Timeouts {
+        connect: Duration::from_secs(10),
+        request: Duration::from_secs(5),
+    })
+}
+
+static CONNECT_IP_FAMILY: OnceLock<ConnectIpFamily> = OnceLock::new();
+
+/// `--connect-ip-family`, for the WebSocket and ureq connections to the server
+pub fn set_connect_ip_family(family: ConnectIpFamily) {
+    let _ = CONNECT_IP_FAMILY.set(family);
+}
+
+fn connect_ip_family() -> ConnectIpFamily {
+    CONNECT_IP_FAMILY
+        .get()
+        .copied()
+        .unwrap_or(ConnectIpFamily::Auto)
+}
+
+/// TCP connection to `host`, over the addresses of `--connect-ip-family` only, resolved by
+/// `--dns-server` / `--resolve` when given
+async fn connect_tcp(host: &str, port: u16) -> Result<TcpStream, String> {
+    let family = connect_ip_family();
+    if family == ConnectIpFamily::Auto && !custom_resolver() {
+        return TcpStream::connect((host, port))
+            .await
+            .map_err(|e| format!("Failed to connect to {host}:{port}: {e}"));
+    }
+    let addrs = if custom_resolver() {
+        let name = host.to_string();
+        tokio::task::spawn_blocking(move || resolve(&name, port))
+            .await
+            .map_err(|e| e.to_string())??
+    } else {
+        tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| format!("Failed to resolve {host}: {e}"))?
+            .collect()
+    };
+
+    let (wanted, name): (fn(&IpAddr) -> bool, _) = match family {
+        ConnectIpFamily::Auto => (|_| true, "usable"),
+        ConnectIpFamily::Ipv4 => (IpAddr::is_ipv4, "IPv4"),
+        ConnectIpFamily::Ipv6 => (IpAddr::is_ipv6, "IPv6"),
+    };
+    let mut error = format!("{host} has no {name} address");
+    for addr in addrs.into_iter().filter(|addr| wanted(&addr.ip())) {
+        match TcpStream::connect(addr).await {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => error = format!("Failed to connect to {addr}: {e}"),
+        }
+    }
+    Err(error)
+}
+
+/// Transport of every WebSocket connection, see `ws_deflate`
+pub type WsStream = DeflateStream<MaybeTlsStream<Counted<TcpStream>>>;
+
 pub async fn connect_ws(
     url: &str,
     tls: bool,
     skip_verify: bool,
-) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, String> {
-    let connection_timeout = Duration::from_secs(10);
-
-    if tls {
-        if skip_verify {
-            timeout(
-                connection_timeout,
-                connect_async_tls_with_config(
-                    url,
-                    None,
-                    false,
-                    Some(Connector::Rustls(Arc::new(create_dangerous_config()))),
-                ),
-            )
-            .await
-            .map_err(|_| "WebSocket connection timeout".to_string())?
-            .map(|ws| ws.0)
-            .map_err(|_| "Failed to establish WebSocket connection".to_string())
+    compression: bool,
+) -> Result<WebSocketStream<WsStream>, String> {
+    connect_ws_with_headers(url, tls, skip_verify, compression, panel_headers()).await
+}
+
+/// `connect_ws` with other headers than the panel's
+pub async fn connect_ws_with_headers(
+    url: &str,
+    tls: bool,
+    skip_verify: bool,
+    compression: bool,
+    headers: Vec<(String, String)>,
+) -> Result<WebSocketStream<WsStream>, String> {
+    timeout(
+        timeouts().connect,
+        open_ws(url, tls, skip_verify, compression, headers),
+    )
+    .await
+    .map_err(|_| "WebSocket connection timeout".to_string())?
+}
+
+async fn open_ws(
+    url: &str,
+    tls: bool,
+    skip_verify: bool,
+    compression: bool,
+    headers: Vec<(String, String)>,
+) -> Result<WebSocketStream<WsStream>, String> {
+    let mut request = url
+        .into_client_request()
+        .map_err(|e| format!("Invalid WebSocket URL: {e}"))?;
+    for (name, value) in headers {
+        let name = HeaderName::try_from(name.as_str())
+            .map_err(|e| format!("Invalid header {name}: {e}"))?;
+        let value =
+            HeaderValue::from_str(&value).map_err(|e| format!("Invalid header {name}: {e}"))?;
+        request.headers_mut().insert(name, value);
+    }
+    if compression {
+        request.headers_mut().insert(
+            "Sec-WebSocket-Extensions",
+            HeaderValue::from_static(EXTENSION_OFFER),
+        );
+    }
+
+    let uri = request.uri();
+    let secure = uri.scheme_str() == Some("wss");
+    let host = uri
+        .host()
+        .ok_or_else(|| "WebSocket URL has no host".to_string())?
+        .trim_matches(['[', ']'])
+        .to_string();
+    let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+
+    let tcp = connect_tcp(&host, port).await?;
+    let peer = tcp.peer_addr().ok().map(|peer| peer.ip());
+    let tcp = Counted::new(tcp, peer);
+
+    // The TLS layer sits below `DeflateStream`, so it is set up here rather than by tokio-tungstenite
+    let stream = if secure {
+        let config = if tls && skip_verify {
+            create_dangerous_config()
         } else {
-            timeout(
-                connection_timeout,
-                connect_async_tls_with_config(url, None, false, None),
-            )
+            ClientConfig::builder()
+                .with_root_certificates(root_store())
+                .with_no_client_auth()
+        };
+        let server_name = ServerName::try_from(host.clone())
+            .map_err(|e| format!("Invalid WebSocket host: {e}"))?;
+        let tls_stream = TlsConnector::from(Arc::new(config))
+            .connect(server_name, tcp)
             .await
-            .map_err(|_| "WebSocket connection timeout".to_string())?
-            .map(|ws| ws.0)
-            .map_err(|_| "Failed to establish WebSocket connection".into())
-        }
+            .map_err(|e| format!("TLS handshake with {host} failed: {e}"))?;
+        MaybeTlsStream::Rustls(tls_stream)
     } else {
-        timeout(connection_timeout, connect_async(url))
-            .await
-            .map_err(|_| "WebSocket connection timeout".to_string())?
-            .map(|ws| ws.0)
-            .map_err(|_| "Failed to establish WebSocket connection".to_string())
+        MaybeTlsStream::Plain(tcp)
+    };
+
+    client_async(request, DeflateStream::new(stream, compression))
+        .await
+        .map(|ws| ws.0)
+        .map_err(|e| format!("WebSocket handshake failed: {e}"))
+}
+
+/// Whether the kernel has a route towards the internet, a UDP connect sends nothing but
+/// fails with `ENETUNREACH` until DHCP or SLAAC has installed a default route
+fn has_default_route() -> bool {
+    let v4 = std::net::UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| socket.connect("192.0.2.1:9"))
+        .is_ok();
+    let v6 = || {
+        std::net::UdpSocket::bind("[::]:0")
+            .and_then(|socket| socket.connect("[2001:db8::1]:9"))
+            .is_ok()
+    };
+    v4 || v6()
+}
+
+/// Delay the first connection until a default route exists and the server name resolves,
+/// gives up after `timeout_s` and lets the normal retry loop take over
+pub async fn wait_for_network(url: &str, timeout_s: u64) {
+    let Ok(url) = Url::parse(url) else {
+        return;
+    };
+    let host = url.host_str().unwrap_or_default().to_string();
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let start = tokio::time::Instant::now();
+    let mut logged = false;
+    loop {
+        let route = has_default_route();
+        let dns = route
+            && tokio::net::lookup_host((host.as_str(), port))
+                .await
+                .is_ok_and(|mut addrs| addrs.next().is_some());
+        if dns {
+            if logged {
+                info!("Network is up after {} s", start.elapsed().as_secs());
+            }
+            return;
+        }
+
+        if start.elapsed() >= Duration::from_secs(timeout_s) {
+            let reason = if route {
+                "DNS not resolving"
+            } else {
+                "no default route"
+            };
+            warn!("Network still unavailable after {timeout_s} s ({reason}), connecting anyway");
+            return;
+        }
+        if !logged {
+            info!("Waiting for a default route and DNS to resolve {host}");
+            logged = true;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
     }
 }
 
+/// ureq cannot extend its built-in roots, with `--ca-cert` it trusts that bundle alone
 #[cfg(feature = "ureq-support")]
 pub fn create_ureq_agent(disable_verification: bool) -> ureq::Agent {
-    let config = ureq::Agent::config_builder()
-        .tls_config(
-            ureq::tls::TlsConfig::builder()
-                .disable_verification(disable_verification)
-                .build(),
+    use crate::get_info::network::own::counted_agent;
+    use crate::resolver::{PanelResolver, custom_resolver};
+    use ureq::unversioned::resolver::DefaultResolver;
+
+    let config = ureq_config(disable_verification);
+    if custom_resolver() {
+        counted_agent(
+            config,
+            PanelResolver {
+                use_dns_server: true,
+            },
         )
-        .timeout_global(Some(Duration::from_secs(5)))
-        .build();
-    config.new_agent()
+    } else {
+        counted_agent(config, DefaultResolver::default())
+    }
+}
+
+#[cfg(feature = "ureq-support")]
+pub fn ureq_config(disable_verification: bool) -> ureq::config::Config {
+    use crate::rustls_config::ca_certs;
+    use ureq::config::IpFamily;
+    use ureq::tls::{Certificate, RootCerts, TlsConfig};
+
+    let mut tls_config = TlsConfig::builder().disable_verification(disable_verification);
+    if !ca_certs().is_empty() {
+        let certs = ca_certs()
+            .iter()
+            .map(|cert| Certificate::from_der(cert).to_owned())
+            .collect::<Vec<_>>();
+        tls_config = tls_config.root_certs(RootCerts::new_with_certs(&certs));
+    }
+    ureq::Agent::config_builder()
+        .tls_config(tls_config.build())
+        .timeout_connect(Some(timeouts().connect))
+        .timeout_global(Some(timeouts().total()))
+        .ip_family(match connect_ip_family() {
+            ConnectIpFamily::Auto => IpFamily::Any,
+            ConnectIpFamily::Ipv4 => IpFamily::Ipv4Only,
+            ConnectIpFamily::Ipv6 => IpFamily::Ipv6Only,
+        })
+        .build()
 }
 
 #[cfg(feature = "nyquest-support")]
 pub fn create_nyquest_client(disable_verification: bool) -> nyquest::BlockingClient {
-    use std::time::Duration;
     let mut client = nyquest::ClientBuilder::default()
-        .request_timeout(Duration::from_secs(5))
+        .request_timeout(timeouts().total())
         .user_agent("curl/8.7.1");
     if disable_verification {
         client = client.dangerously_ignore_certificate_errors();
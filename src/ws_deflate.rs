@@ -0,0 +1,396 @@
+//! permessage-deflate (RFC 7692) for the WebSocket connections, see `--ws-compression`.
+//!
+//! tungstenite has no extension support, so compression happens on the byte stream below
+//! it: outgoing data frames are compressed and flagged with RSV1, incoming compressed
+//! messages are inflated into plain frames before tungstenite parses them. When the server
+//! does not accept the extension the stream is a plain pass-through.
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Offered in `Sec-WebSocket-Extensions`, without window bits since both ends keep the default 15
+pub const EXTENSION_OFFER: &str = "permessage-deflate";
+
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+const MASKED: u8 = 0x80;
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+/// Appended by the sender's sync flush, stripped on the wire (RFC 7692 7.2.1)
+const DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+pub struct DeflateStream<S> {
+    inner: S,
+    /// Whether the extension was offered at all, otherwise every call is forwarded as is
+    offered: bool,
+    /// Whether the server accepted the extension, `None` until the handshake response is read
+    negotiated: Option<bool>,
+    client_no_context_takeover: bool,
+    read_raw: Vec<u8>,
+    read_out: Vec<u8>,
+    read_pos: usize,
+    /// Opcode and payload of a fragmented compressed message being reassembled
+    fragments: Option<(u8, Vec<u8>)>,
+    write_raw: Vec<u8>,
+    write_out: Vec<u8>,
+    write_pos: usize,
+    deflater: Compress,
+    inflater: Decompress,
+}
+
+impl<S> DeflateStream<S> {
+    pub fn new(inner: S, offered: bool) -> Self {
+        Self {
+            inner,
+            offered,
+            negotiated: None,
+            client_no_context_takeover: false,
+            read_raw: Vec::new(),
+            read_out: Vec::new(),
+            read_pos: 0,
+            fragments: None,
+            write_raw: Vec::new(),
+            write_out: Vec::new(),
+            write_pos: 0,
+            deflater: Compress::new(Compression::default(), false),
+            inflater: Decompress::new(false),
+        }
+    }
+
+    /// Check the handshake response for an accepted `permessage-deflate`
+    fn read_handshake(&mut self) -> bool {
+        let Some(end) = self
+            .read_raw
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map(|pos| pos + 4)
+        else {
+            return false;
+        };
+
+        let head = String::from_utf8_lossy(&self.read_raw[..end]);
+        let mut accepted = false;
+        for line in head.lines() {
+            if let Some((name, value)) = line.split_once(':')
+                && name.trim().eq_ignore_ascii_case("sec-websocket-extensions")
+                && value.contains("permessage-deflate")
+            {
+                accepted = true;
+                self.client_no_context_takeover = value.contains("client_no_context_takeover");
+            }
+        }
+
+        self.read_out.extend(self.read_raw.drain(..end));
+        self.negotiated = Some(accepted);
+        true
+    }
+
+    /// Move every complete frame of `read_raw` to `read_out`, inflating compressed messages
+    fn process_read(&mut self) -> io::Result<()> {
+        if self.negotiated.is_none() && !self.read_handshake() {
+            return Ok(());
+        }
+        if self.negotiated == Some(false) {
+            self.read_out.append(&mut self.read_raw);
+            return Ok(());
+        }
+
+        while let Some((header_len, payload_len)) = parse_frame(&self.read_raw)? {
+            let total = header_len + payload_len;
+            let first = self.read_raw[0];
+            let opcode = first & 0x0F;
+            let is_final = first & FIN != 0;
+            // Server frames are never masked, a masked one is left for tungstenite to reject
+            let masked = self.read_raw[1] & MASKED != 0;
+            let payload = &self.read_raw[header_len..total];
+
+            if masked || opcode >= 0x8 {
+                self.read_out.extend_from_slice(&self.read_raw[..total]);
+            } else if first & RSV1 != 0 && opcode != OPCODE_CONTINUATION {
+                if is_final {
+                    let payload = payload.to_vec();
+                    let inflated = self.inflate(&payload)?;
+                    write_frame(&mut self.read_out, FIN | opcode, &inflated, None);
+                } else {
+                    self.fragments = Some((opcode, payload.to_vec()));
+                }
+            } else if opcode == OPCODE_CONTINUATION && self.fragments.is_some() {
+                if let Some((_, data)) = self.fragments.as_mut() {
+                    data.extend_from_slice(payload);
+                }
+                if is_final && let Some((opcode, data)) = self.fragments.take() {
+                    let inflated = self.inflate(&data)?;
+                    write_frame(&mut self.read_out, FIN | opcode, &inflated, None);
+                }
+            } else {
+                self.read_out.extend_from_slice(&self.read_raw[..total]);
+            }
+
+            self.read_raw.drain(..total);
+        }
+        Ok(())
+    }
+
+    /// Move every complete frame of `write_raw` to `write_out`, compressing data messages
+    fn process_write(&mut self) -> io::Result<()> {
+        while let Some((header_len, payload_len)) = parse_frame(&self.write_raw)? {
+            let total = header_len + payload_len;
+            let first = self.write_raw[0];
+            let opcode = first & 0x0F;
+            let masked = self.write_raw[1] & MASKED != 0;
+
+            // Fragmented and empty messages are sent uncompressed, which the RFC allows
+            if first & FIN != 0
+                && first & RSV1 == 0
+                && masked
+                && payload_len > 0
+                && (opcode == OPCODE_TEXT || opcode == OPCODE_BINARY)
+            {
+                let mask = [
+                    self.write_raw[header_len - 4],
+                    self.write_raw[header_len - 3],
+                    self.write_raw[header_len - 2],
+                    self.write_raw[header_len - 1],
+                ];
+                let payload = self.write_raw[header_len..total]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, b)| b ^ mask[i % 4])
+                    .collect::<Vec<_>>();
+                let compressed = self.deflate(&payload)?;
+                write_frame(&mut self.write_out, first | RSV1, &compressed, Some(mask));
+            } else {
+                self.write_out.extend_from_slice(&self.write_raw[..total]);
+            }
+
+            self.write_raw.drain(..total);
+        }
+        Ok(())
+    }
+
+    fn inflate(&mut self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let mut input = Vec::with_capacity(payload.len() + DEFLATE_TAIL.len());
+        input.extend_from_slice(payload);
+        input.extend_from_slice(&DEFLATE_TAIL);
+
+        let mut output = Vec::with_capacity(payload.len() * 4 + 64);
+        let mut consumed = 0;
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity());
+            }
+            let (before_in, before_out) = (self.inflater.total_in(), self.inflater.total_out());
+            let status = self
+                .inflater
+                .decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let read = usize::try_from(self.inflater.total_in() - before_in).unwrap_or(0);
+            consumed += read;
+
+            if status == Status::StreamEnd {
+                // The sender closed its deflate stream, the next message starts a new one
+                self.inflater.reset(false);
+                break;
+            }
+            if consumed >= input.len() && output.len() < output.capacity() {
+                break;
+            }
+            if read == 0 && self.inflater.total_out() == before_out {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Truncated deflate message",
+                ));
+            }
+        }
+        Ok(output)
+    }
+
+    fn deflate(&mut self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        if self.client_no_context_takeover {
+            self.deflater.reset();
+        }
+
+        let mut output = Vec::with_capacity(payload.len() / 2 + 64);
+        let mut consumed = 0;
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity());
+            }
+            let before_in = self.deflater.total_in();
+            self.deflater
+                .compress_vec(&payload[consumed..], &mut output, FlushCompress::Sync)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            consumed += usize::try_from(self.deflater.total_in() - before_in).unwrap_or(0);
+
+            if consumed >= payload.len() && output.len() < output.capacity() {
+                break;
+            }
+        }
+
+        if output.ends_with(&DEFLATE_TAIL) {
+            output.truncate(output.len() - DEFLATE_TAIL.len());
+        }
+        Ok(output)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> DeflateStream<S> {
+    /// Write out everything already processed
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_out.len() {
+            let written = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.write_out[self.write_pos..])
+            )?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += written;
+        }
+        self.write_out.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Header and payload length of the first frame, `None` until it is complete
+fn parse_frame(buf: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let (mut header_len, payload_len) = match buf[1] & 0x7F {
+        126 => {
+            let Some(len) = buf.get(2..4) else {
+                return Ok(None);
+            };
+            (4, u64::from(u16::from_be_bytes([len[0], len[1]])))
+        }
+        127 => {
+            let Some(len) = buf.get(2..10) else {
+                return Ok(None);
+            };
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(len);
+            (10, u64::from_be_bytes(bytes))
+        }
+        len => (2, u64::from(len)),
+    };
+    if buf[1] & MASKED != 0 {
+        header_len += 4;
+    }
+
+    let payload_len = usize::try_from(payload_len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "WebSocket frame too large"))?;
+    if buf.len() < header_len + payload_len {
+        return Ok(None);
+    }
+    Ok(Some((header_len, payload_len)))
+}
+
+fn write_frame(out: &mut Vec<u8>, first: u8, payload: &[u8], mask: Option<[u8; 4]>) {
+    out.push(first);
+    let mask_bit = if mask.is_some() { MASKED } else { 0 };
+    if let Ok(len @ 0..=125) = u8::try_from(payload.len()) {
+        out.push(mask_bit | len);
+    } else if let Ok(len) = u16::try_from(payload.len()) {
+        out.push(mask_bit | 0x7E);
+        out.extend_from_slice(&len.to_be_bytes());
+    } else {
+        out.push(mask_bit | 0x7F);
+        out.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+
+    match mask {
+        Some(mask) => {
+            out.extend_from_slice(&mask);
+            out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        }
+        None => out.extend_from_slice(payload),
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.offered {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        loop {
+            if this.read_pos < this.read_out.len() {
+                let available = &this.read_out[this.read_pos..];
+                let len = available.len().min(buf.remaining());
+                buf.put_slice(&available[..len]);
+                this.read_pos += len;
+                if this.read_pos == this.read_out.len() {
+                    this.read_out.clear();
+                    this.read_pos = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                // EOF, hand over any partial frame so tungstenite reports the truncation
+                if this.read_raw.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                this.read_out.append(&mut this.read_raw);
+                continue;
+            }
+            this.read_raw.extend_from_slice(chunk_buf.filled());
+            this.process_read()?;
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.offered {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        ready!(this.poll_drain(cx))?;
+        if this.negotiated != Some(true) {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        this.write_raw.extend_from_slice(buf);
+        this.process_write()?;
+        // Pending is fine here, the rest is written on the next write or flush
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.offered {
+            ready!(this.poll_drain(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.offered {
+            ready!(this.poll_drain(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}